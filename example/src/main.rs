@@ -4,24 +4,28 @@ include!(concat!(env!("OUT_DIR"), "/IMPsimple.rs"));
 
 use imp_core::*;
 
-fn main() {
-    let folded = expect_scalar(shouldbefolded());
+fn main() -> Result<(), ImpError> {
+    let folded = shouldbefolded()?;
     println!("shouldbefolded = {}", folded);
     assert_eq!(folded, 9);
 
-    let cat = expect_array(cat(expect_array(iota(3)), expect_array(iota(4))));
+    let cat = cat(&iota(3)?, &iota(4)?)?;
     println!("cat = {:?}", cat.data);
 
     let fold_input = ImpArray { shp: vec![4], data: vec![1i32, 2, 3, 4] };
-    let fold_sum = expect_scalar(sum(fold_input));
+    let fold_sum = sum(&fold_input)?;
     assert_eq!(fold_sum, 10);
     println!("sum = {}", fold_sum);
 
+    // Arguments are borrowed, so the same array can be passed again, also as a slice with a shape
+    let fold_square = sum(ImpArrayView::new(&[2, 2], &fold_input.data))?;
+    assert_eq!(fold_square, fold_sum);
+
     let fold2d_input = ImpArray {
         shp: vec![2, 3],
         data: vec![1i32, 2, 3, 4, 5, 6],
     };
-    let fold2d_sum = expect_scalar(sum(fold2d_input));
+    let fold2d_sum = sum(&fold2d_input)?;
     //assert_eq!(fold2d_sum, 21);
     println!("sum2d = {}", fold2d_sum);
 
@@ -29,7 +33,7 @@ fn main() {
         shp: vec![3,2],
         data: vec![1i32, 2, 3, 4, 5, 6],
     };
-    let fold2d_sum = expect_scalar(sum(fold2d_input));
+    let fold2d_sum = sum(&fold2d_input)?;
     //assert_eq!(fold2d_sum, 21);
     println!("sum2d = {}", fold2d_sum);
 
@@ -37,52 +41,68 @@ fn main() {
         shp: vec![2, 3],
         data: vec![1i32, 2, 3, 4, 5, 6],
     };
-    let fold_last = expect_array(sumlast(fold_last_input));
+    let fold_last = sumlast(&fold_last_input)?;
     assert_eq!(fold_last.shp, vec![3]);
     println!("sumlast = {:?}", fold_last.data);
 
     let ub: usize = 10;
-    let arr: ImpArray<usize> = expect_array(iota(ub));
+    let arr: ImpArray<usize> = iota(ub)?;
     assert_eq!(arr.shp, vec![ub]);
     assert_eq!(arr.data, (0..ub).collect::<Vec<usize>>());
     println!("arr.data = {:?}", arr.data);
 
-    let arr1: ImpArray<usize> = expect_array(iota(15));
-    let arr2: ImpArray<usize> = expect_array(iota(15));
-    let res: ImpArray<usize> = expect_array(my_add_after_iota(arr1, arr2));
+    let arr1: ImpArray<usize> = iota(15)?;
+    let arr2: ImpArray<usize> = iota(15)?;
+    let res: ImpArray<usize> = expect_array(my_add_after_iota(&arr1, &arr2)?);
     println!("iota + iota = {:?}", res.data);
 
-    let overldemo = expect_scalar(overload_demo_usize_usize(ImpArrayOrScalar::Scalar(4usize), ImpArrayOrScalar::Scalar(5usize)));
+    let overldemo = overload_demo((4usize, 5usize))?;
     println!("overload_demo scalar = {:?}", overldemo);
 
-    // Obviously, we should not have to write 'ovl' (overload).
-    // We should generate each variant with a unique name, and then a wrapper with the original
-    // name that dispatches to the correct variant based on argument types and shapes
-    let overldemo: ImpArray<usize> = expect_array(overload_demo_usize_usize(ImpArrayOrScalar::Array(expect_array(four())), ImpArrayOrScalar::Array(expect_array(four()))));
+    // Overloads take their arguments as a tuple, whose type selects the implementation
+    let four_arr: ImpArray<usize> = four()?;
+    let overldemo: ImpArray<usize> = overload_demo((&four_arr, &four_arr))?;
     println!("overload_demo vector = {:?}", overldemo.data);
 
-    let panic_hook = std::panic::take_hook();
-    std::panic::set_hook(Box::new(|_| {}));
-    let add_demo_mismatch = std::panic::catch_unwind(|| add_demo(expect_array(four()), expect_array(five())));
-    std::panic::set_hook(panic_hook);
-    assert!(add_demo_mismatch.is_err());
-    println!("add_demo mismatched extents rejected in Rust FFI wrapper");
+    // As for other functions, arrays may also be passed as views, e.g. of a slice with a shape
+    let overldemo_view = overload_demo((four_arr.view(), ImpArrayView::new(&[4], &four_arr.data)))?;
+    assert_eq!(overldemo_view, overldemo);
+
+    // Arguments that do not match the type patterns are rejected with an error rather than a panic
+    let add_demo_mismatch = add_demo(&four_arr, &five()?);
+    assert_eq!(add_demo_mismatch, Err(ImpError::Extent { operand: ImpOperand::Arg(1), axis: 0, expected: 4, found: 5 }));
+    println!("add_demo mismatched extents rejected: {}", add_demo_mismatch.unwrap_err());
 
-    let shp: ImpArray<usize> = expect_array(shape(arr));
+    let shp: ImpArray<usize> = shape(&arr)?;
     println!("shape(arr) = {:?}", shp.data);
 
-    let arr2: ImpArray<u32> = expect_array(arrays());
+    let arr2: ImpArray<u32> = arrays()?;
     assert_eq!(arr2.shp, vec![5]);
     println!("arr2.data = {:?}", arr2.data);
 
-    println!("sel = {}", expect_scalar(sel_demo()));
+    println!("sel = {}", sel_demo()?);
+
+    println!("scalar_add_demo = {}", scalar_add_demo()?);
 
-    println!("scalar_add_demo = {}", expect_scalar(scalar_add_demo()));
+    let fused = fused_demo(&iota(4)?, &iota(4)?)?;
+    assert_eq!(fused.data, vec![1, 3, 5, 7]);
+    println!("fused_demo = {:?}", fused.data);
 
-    let dyn_sum = add_dyn(expect_array(iota(4)), expect_array(iota(4)));
+    let shifted = shifted_demo(&ImpArray { shp: vec![2, 3], data: vec![1, 2, 3, 4, 5, 6] })?;
+    assert_eq!(shifted.data, vec![1, 1, 1, 1, 1, 7]);
+    println!("shifted_demo = {:?}", shifted.data);
+
+    let outer = outer_demo(&ImpArray { shp: vec![2], data: vec![1, 2] }, &ImpArray { shp: vec![3], data: vec![1, 2, 3] })?;
+    assert_eq!(outer.shp, vec![2, 3]);
+    assert_eq!(outer.data, vec![1, 2, 3, 2, 4, 6]);
+    println!("outer_demo = {:?}", outer.data);
+
+    let dyn_sum = add_dyn(&iota(4)?, &iota(4)?)?;
     println!("add_dyn = {:?}", dyn_sum);
 
-    // double free detected
-    // let arr = scalar_or_array(ImpArrayOrScalar::Array(ImpArray { shp: vec![6], data: vec![1,2,3,4,5,6] }));
-    // println!("scalar_or_array = {:?}", arr);
+    let arr = expect_array(scalar_or_array(&ImpArray { shp: vec![6], data: vec![1, 2, 3, 4, 5, 6] })?);
+    assert_eq!(arr.data, vec![1, 2, 3, 4, 5, 6]);
+    println!("scalar_or_array = {:?}", arr.data);
+
+    Ok(())
 }
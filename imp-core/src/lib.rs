@@ -1,6 +1,6 @@
-use std::{ffi::c_void, slice};
+use std::{alloc::Layout, error::Error, ffi::c_void, fmt, mem, ptr, slice};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ImpArrayOrScalar<T>
 where
     T: Copy,
@@ -9,7 +9,7 @@ where
     Scalar(T),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ImpArray<T>
 where
     T: Copy,
@@ -18,6 +18,19 @@ where
     pub data: Vec<T>,
 }
 
+/// A borrowed array, such that arrays need not be moved or cloned to pass them to generated functions,
+/// which never modify their arguments.
+///
+/// Example: `sum(&arr)`, or `sum(ImpArrayView::new(&[2, 3], &data))` for a row-major slice `data`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImpArrayView<'a, T>
+where
+    T: Copy,
+{
+    pub shp: &'a [usize],
+    pub data: &'a [T],
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct ImpArrayRaw {
@@ -56,42 +69,266 @@ pub type ImpDynUsize = ImpDyn<usize>;
 pub type ImpDynF32 = ImpDyn<f32>;
 pub type ImpDynF64 = ImpDyn<f64>;
 
+/// A runtime error of a generated function, as raised by C into the thread-local `imp_error`.
+///
+/// The generated C functions return a zero result after raising an error,
+/// which the generated Rust wrappers take with `imp_take_error` and turn into an [`ImpError`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ImpErrorRaw {
+    pub kind: u32,
+    /// The index of the argument, or [`IMP_RESULT`] for the result
+    pub arg: usize,
+    pub axis: usize,
+    pub expected: usize,
+    pub found: usize,
+}
+
+pub const IMP_OK: u32 = 0;
+pub const IMP_ERR_RANK: u32 = 1;
+pub const IMP_ERR_MIN_RANK: u32 = 2;
+pub const IMP_ERR_EXTENT: u32 = 3;
+pub const IMP_ERR_DISPATCH: u32 = 4;
+pub const IMP_ERR_TARGET_LENGTH: u32 = 5;
+pub const IMP_ERR_TARGET_INDEX: u32 = 6;
+pub const IMP_ERR_PERMUTATION: u32 = 7;
+pub const IMP_ERR_GAP: u32 = 8;
+pub const IMP_ERR_OVERLAP: u32 = 9;
+
+/// The operand of an error that is the result rather than an argument, `SIZE_MAX` in C.
+pub const IMP_RESULT: usize = usize::MAX;
+
+/// An argument of a generated function, or its result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImpOperand {
+    Arg(usize),
+    Result,
+}
+
+/// Why a generated function rejected its arguments, instead of aborting the process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImpError {
+    /// The operand has a different rank than its type pattern.
+    Rank { operand: ImpOperand, expected: usize, found: usize },
+    /// The operand has fewer axes than its type pattern requires.
+    MinRank { operand: ImpOperand, expected: usize, found: usize },
+    /// The operand has a different extent at `axis` than its type pattern.
+    Extent { operand: ImpOperand, axis: usize, expected: usize, found: usize },
+    /// No overload of the function matches the shapes of the arguments.
+    Dispatch,
+    /// The permutation target of a tensor does not have one index vector per element.
+    TargetLength { expected: usize, found: usize },
+    /// The permutation target of a tensor maps an element out of bounds at `axis`.
+    TargetIndex { axis: usize, index: usize, bound: usize },
+    /// The permutation target of a tensor writes the element at this flat index more than once.
+    NotAPermutation { index: usize },
+    /// No partition of a tensor selects the element at this flat index.
+    Gap { index: usize },
+    /// More than one partition of a tensor selects the element at this flat index.
+    Overlap { index: usize },
+}
+
+impl ImpErrorRaw {
+    /// The error raised by C, if any.
+    pub fn check(self) -> Result<(), ImpError> {
+        let operand = match self.arg {
+            IMP_RESULT => ImpOperand::Result,
+            arg => ImpOperand::Arg(arg),
+        };
+        let (axis, expected, found) = (self.axis, self.expected, self.found);
+
+        Err(match self.kind {
+            IMP_OK => return Ok(()),
+            IMP_ERR_RANK => ImpError::Rank { operand, expected, found },
+            IMP_ERR_MIN_RANK => ImpError::MinRank { operand, expected, found },
+            IMP_ERR_EXTENT => ImpError::Extent { operand, axis, expected, found },
+            IMP_ERR_DISPATCH => ImpError::Dispatch,
+            IMP_ERR_TARGET_LENGTH => ImpError::TargetLength { expected, found },
+            IMP_ERR_TARGET_INDEX => ImpError::TargetIndex { axis, index: found, bound: expected },
+            IMP_ERR_PERMUTATION => ImpError::NotAPermutation { index: found },
+            IMP_ERR_GAP => ImpError::Gap { index: found },
+            IMP_ERR_OVERLAP => ImpError::Overlap { index: found },
+            kind => unreachable!("unknown error kind {kind}"),
+        })
+    }
+}
+
+impl fmt::Display for ImpOperand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImpOperand::Arg(i) => write!(f, "argument {}", i),
+            ImpOperand::Result => write!(f, "result"),
+        }
+    }
+}
+
+impl fmt::Display for ImpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ImpError::*;
+        match self {
+            Rank { operand, expected, found } => write!(f, "{} has rank {}, expected {}", operand, found, expected),
+            MinRank { operand, expected, found } => write!(f, "{} has rank {}, expected at least {}", operand, found, expected),
+            Extent { operand, axis, expected, found } => {
+                write!(f, "{} has extent {} at axis {}, expected {}", operand, found, axis, expected)
+            }
+            Dispatch => write!(f, "no overload matches the shapes of the arguments"),
+            TargetLength { expected, found } => write!(f, "tensor target has {} elements, expected {}", found, expected),
+            TargetIndex { axis, index, bound } => {
+                write!(f, "tensor target index {} is out of bounds {} at axis {}", index, bound, axis)
+            }
+            NotAPermutation { index } => {
+                write!(f, "tensor target is not a permutation: flat index {} is written more than once", index)
+            }
+            Gap { index } => write!(f, "tensor partitions do not cover flat index {}", index),
+            Overlap { index } => write!(f, "tensor partitions overlap at flat index {}", index),
+        }
+    }
+}
+
+impl Error for ImpError {}
+
 unsafe extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
     fn free(ptr: *mut c_void);
 }
 
+/// Allocate a buffer that may cross the boundary between Rust and C.
+///
+/// Every buffer of which ownership moves between both sides comes from this allocator, which is
+/// the C `malloc`, such that it can be released by either [`imp_free`] or the C `free`.
+/// The generated C code allocates its results with `malloc` directly.
+#[unsafe(no_mangle)]
+pub extern "C" fn imp_alloc(size: usize) -> *mut c_void {
+    let ptr = unsafe { malloc(size) };
+    if ptr.is_null() && size > 0 {
+        std::alloc::handle_alloc_error(Layout::from_size_align(size, 1).unwrap());
+    }
+    ptr
+}
+
+/// Release a buffer allocated by [`imp_alloc`] or by the C `malloc`.
+///
+/// # Safety
+///
+/// `ptr` must be null or a buffer from `imp_alloc` that has not been released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn imp_free(ptr: *mut c_void) {
+    unsafe { free(ptr) }
+}
+
+/// Copy `elems` into a buffer from [`imp_alloc`], or null if there are none.
+fn alloc_copy<T: Copy>(elems: &[T]) -> *mut T {
+    if elems.is_empty() {
+        return ptr::null_mut();
+    }
+    let buf = imp_alloc(mem::size_of_val(elems)) as *mut T;
+    unsafe { ptr::copy_nonoverlapping(elems.as_ptr(), buf, elems.len()) };
+    buf
+}
+
 impl<T> ImpArray<T>
 where
     T: Copy,
 {
-    pub fn into_raw(mut self) -> ImpArrayRaw {
+    /// Hand the array over to C, which takes ownership of the result.
+    ///
+    /// The shape and the data are copied into buffers from [`imp_alloc`], because the buffers
+    /// of a `Vec` belong to the Rust allocator. Use [`ImpArray::as_raw`] for arguments that are only read.
+    pub fn into_raw(self) -> ImpArrayRaw {
         ImpArrayRaw {
             len: self.data.len(),
             dim: self.shp.len(),
-            shp: self.shp.as_mut_ptr(),
-            data: self.data.as_mut_ptr() as *mut c_void,
+            shp: alloc_copy(&self.shp),
+            data: alloc_copy(&self.data) as *mut c_void,
         }
     }
 
+    pub fn view(&self) -> ImpArrayView<'_, T> {
+        ImpArrayView { shp: &self.shp, data: &self.data }
+    }
+
+    /// Lend the array to C without copying, see [`ImpArrayView::as_raw`].
+    pub fn as_raw(&self) -> ImpArrayRaw {
+        self.view().as_raw()
+    }
+
+    /// Take ownership of an array allocated by C, such as the result of a generated function.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must own its buffers, which are null or allocated by [`imp_alloc`] or the C `malloc`,
+    /// and hold `raw.dim` extents and `raw.len` elements of type `T`. They are released here,
+    /// so `raw` must not be used afterwards.
     pub unsafe fn from_raw(raw: ImpArrayRaw) -> Self {
         let shp = if raw.shp.is_null() {
             Vec::new()
         } else {
-            let shp = unsafe { slice::from_raw_parts(raw.shp, raw.dim) }.to_vec();
-            unsafe { free(raw.shp as *mut c_void) };
-            shp
+            unsafe { slice::from_raw_parts(raw.shp, raw.dim) }.to_vec()
         };
 
         let data = if raw.data.is_null() {
             Vec::new()
         } else {
-            let data = unsafe { slice::from_raw_parts(raw.data as *const T, raw.len) }.to_vec();
-            unsafe { free(raw.data) };
-            data
+            unsafe { slice::from_raw_parts(raw.data as *const T, raw.len) }.to_vec()
         };
 
+        unsafe {
+            imp_free(raw.shp as *mut c_void);
+            imp_free(raw.data);
+        }
+        Self { shp, data }
+    }
+}
+
+impl<'a, T> ImpArrayView<'a, T>
+where
+    T: Copy,
+{
+    /// View `data` as an array of shape `shp`, in row-major order.
+    pub fn new(shp: &'a [usize], data: &'a [T]) -> Self {
+        assert_eq!(shp.iter().product::<usize>(), data.len(), "shape {:?} does not match {} elements", shp, data.len());
         Self { shp, data }
     }
+
+    /// Lend the array to C without copying, e.g. as the argument of a generated function.
+    ///
+    /// The result borrows the buffers of the view: it is only valid for the lifetime `'a`,
+    /// and C must neither write to it nor free it.
+    pub fn as_raw(&self) -> ImpArrayRaw {
+        ImpArrayRaw {
+            len: self.data.len(),
+            dim: self.shp.len(),
+            shp: self.shp.as_ptr() as *mut usize,
+            data: self.data.as_ptr() as *mut c_void,
+        }
+    }
+
+    pub fn to_array(&self) -> ImpArray<T> {
+        ImpArray { shp: self.shp.to_vec(), data: self.data.to_vec() }
+    }
+}
+
+impl<'a, T> From<&'a ImpArray<T>> for ImpArrayView<'a, T>
+where
+    T: Copy,
+{
+    fn from(array: &'a ImpArray<T>) -> Self {
+        array.view()
+    }
+}
+
+/// A rank-polymorphic result, which is a scalar if the array has rank zero.
+impl<T> From<ImpArray<T>> for ImpArrayOrScalar<T>
+where
+    T: Copy,
+{
+    fn from(array: ImpArray<T>) -> Self {
+        if array.shp.is_empty() {
+            ImpArrayOrScalar::Scalar(array.data[0])
+        } else {
+            ImpArrayOrScalar::Array(array)
+        }
+    }
 }
 
 impl<T> ImpDyn<T>
@@ -112,6 +349,11 @@ where
         }
     }
 
+    /// Take ownership of the array or the scalar returned by C.
+    ///
+    /// # Safety
+    ///
+    /// If `self.is_array`, the array must satisfy the requirements of [`ImpArray::from_raw`].
     pub unsafe fn into_array_or_scalar(self) -> ImpArrayOrScalar<T> {
         if self.is_array {
             let raw = unsafe { self.data.array };
@@ -1,5 +1,7 @@
 // Declarations
 mod program;
+mod import;
+mod structdef;
 mod fundef;
 mod shapefact;
 // Statements
@@ -7,6 +9,7 @@ mod body;
 mod stmt;
 mod assign;
 mod printf;
+mod free;
 // Expressions
 mod expr;
 mod cond;
@@ -16,13 +19,17 @@ mod prf;
 mod tensor;
 mod fold;
 mod array;
+mod record;
 // Terminals
 mod id;
 mod constval;
 mod typ;
+mod span;
 
 // Declarations
 pub use program::*;
+pub use import::*;
+pub use structdef::*;
 pub use fundef::*;
 pub use shapefact::*;
 // Statements
@@ -30,6 +37,7 @@ pub use body::*;
 pub use stmt::*;
 pub use assign::*;
 pub use printf::*;
+pub use free::*;
 // Expressions
 pub use expr::*;
 pub use cond::*;
@@ -39,10 +47,12 @@ pub use prf::*;
 pub use tensor::*;
 pub use fold::*;
 pub use array::*;
+pub use record::*;
 // Terminals
 pub use id::*;
 pub use constval::*;
 pub use typ::*;
+pub use span::*;
 
 pub use crate::trav::Traverse;
 
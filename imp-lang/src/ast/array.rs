@@ -3,4 +3,5 @@ use super::*;
 #[derive(Clone, Debug)]
 pub struct Array<'ast, Ast: AstConfig> {
     pub elems: Vec<Ast::Operand<'ast>>,
+    pub span: Span,
 }
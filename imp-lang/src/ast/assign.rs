@@ -4,4 +4,5 @@ use super::*;
 pub struct Assign<'ast, Ast: AstConfig> {
     pub lhs: &'ast VarInfo<'ast, Ast>,
     pub expr: &'ast Expr<'ast, Ast>,
+    pub span: Span,
 }
@@ -5,3 +5,10 @@ pub struct Body<'ast, Ast: AstConfig> {
     pub stmts: Vec<Stmt<'ast, Ast>>,
     pub ret: Ast::Operand<'ast>,
 }
+
+impl<'ast, Ast: AstConfig> Body<'ast, Ast> {
+    /// Whether `lvis` is assigned directly in this body (not in a nested body).
+    pub fn defines(&self, lvis: &VarInfo<'ast, Ast>) -> bool {
+        self.stmts.iter().any(|stmt| matches!(stmt, Stmt::Assign(assign) if std::ptr::eq(assign.lhs, lvis)))
+    }
+}
@@ -4,4 +4,5 @@ use super::*;
 pub struct Call<'ast, Ast: AstConfig> {
     pub id: Ast::Dispatch<'ast>,
     pub args: Vec<Ast::Operand<'ast>>,
+    pub span: Span,
 }
@@ -8,6 +8,26 @@ pub enum Expr<'ast, Ast: AstConfig> {
     Tensor(Tensor<'ast, Ast>),
     Fold(Fold<'ast, Ast>),
     Array(Array<'ast, Ast>),
+    Record(Record<'ast, Ast>),
+    Field(Field<'ast, Ast>),
     Id(Id<'ast, Ast>),
     Const(Const),
 }
+
+impl<'ast, Ast: AstConfig> Expr<'ast, Ast> {
+    /// The source location of this expression, if it has one.
+    /// Primitive applications, identifiers and constants do not store a span,
+    /// they take on the span of their enclosing expression or assignment.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Cond(n) => Some(n.span),
+            Expr::Call(n) => Some(n.span),
+            Expr::Tensor(n) => Some(n.span),
+            Expr::Fold(n) => Some(n.span),
+            Expr::Array(n) => Some(n.span),
+            Expr::Record(n) => Some(n.span),
+            Expr::Field(n) => Some(n.span),
+            Expr::Prf(_) | Expr::Id(_) | Expr::Const(_) => None,
+        }
+    }
+}
@@ -4,7 +4,8 @@ use super::*;
 pub struct Fold<'ast, Ast: AstConfig> {
     pub neutral: Ast::Operand<'ast>,
     pub foldfun: FoldFun<'ast, Ast>,
-    pub selection: Tensor<'ast, Ast>,
+    pub selection: Partition<'ast, Ast>,
+    pub span: Span,
 }
 
 #[derive(Clone, Debug)]
@@ -0,0 +1,9 @@
+use super::*;
+
+/// Release the heap buffers owned by a local array variable.
+///
+/// Only inserted by the memory management phase, after its last use.
+#[derive(Clone, Debug)]
+pub struct Free<'ast, Ast: AstConfig> {
+    pub id: Id<'ast, Ast>,
+}
@@ -1,8 +1,14 @@
+use std::fmt;
+
 use super::*;
 use typed_arena::Arena;
 
 pub struct Fundef<'ast, Ast: AstConfig> {
     pub name: String,
+    /// The type parameters of a generic function, e.g. `T` in `fn add<T>(T a, T b) -> T`.
+    pub type_params: Vec<String>,
+    /// The overloads that a generic function requires of its type parameters, from its `where` clause.
+    pub constraints: Vec<Constraint>,
     pub ret_type: Type,
     pub args: Vec<Farg>,
     pub shape_prelude: Vec<Assign<'ast, Ast>>,
@@ -10,12 +16,22 @@ pub struct Fundef<'ast, Ast: AstConfig> {
     pub decs: Arena<VarInfo<'ast, Ast>>,
     pub exprs: Arena<Expr<'ast, Ast>>,
     pub body: Body<'ast, Ast>,
+    /// The signature, from `fn` up to and including the return type.
+    pub span: Span,
+    /// The module this function was imported from, or `None` if it is defined in the module being compiled.
+    /// Imported functions are type checked and dispatched to, but their code is generated by their own module.
+    pub import: Option<String>,
+    /// Whether this is an instance or clone of an imported function. Every module that calls it
+    /// generates its own copy, so it gets internal linkage and is not exported.
+    pub internal: bool,
 }
 
 impl<'ast, Ast: AstConfig> Clone for Fundef<'ast, Ast> {
     fn clone(&self) -> Self {
         Self {
             name: self.name.clone(),
+            type_params: self.type_params.clone(),
+            constraints: self.constraints.clone(),
             ret_type: self.ret_type.clone(),
             args: self.args.clone(),
             shape_prelude: self.shape_prelude.clone(),
@@ -23,14 +39,30 @@ impl<'ast, Ast: AstConfig> Clone for Fundef<'ast, Ast> {
             decs: Arena::new(),
             exprs: Arena::new(),
             body: self.body.clone(),
+            span: self.span,
+            import: self.import.clone(),
+            internal: self.internal,
         }
     }
 }
 
+/// A `where` constraint of a generic function, e.g. `add(T, T) -> T`.
+///
+/// Every instantiation of the function requires an overload of `name` that accepts the
+/// argument types and returns the return type, after substituting the type parameters.
+#[derive(Clone, Debug)]
+pub struct Constraint {
+    pub name: String,
+    pub args: Vec<Type>,
+    pub ret_type: Type,
+    pub span: Span,
+}
+
 #[derive(Clone, Debug)]
 pub struct Farg {
     pub id: String,
     pub ty: Type,
+    pub span: Span,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -39,9 +71,32 @@ pub struct BaseSignature {
 }
 
 impl<'ast, Ast: AstConfig> Fundef<'ast, Ast> {
+    pub fn is_generic(&self) -> bool {
+        !self.type_params.is_empty()
+    }
+
+    /// Whether the function is part of the interface of the module being compiled.
+    pub fn is_exported(&self) -> bool {
+        self.import.is_none() && !self.internal
+    }
+
     pub fn signature(&self) -> BaseSignature {
         BaseSignature {
             base_types: self.args.iter().map(|arg| arg.ty.ty.clone()).collect(),
         }
     }
 }
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|ty| ty.to_string()).collect();
+        write!(f, "{}({}) -> {}", self.name, args.join(", "), self.ret_type)
+    }
+}
+
+impl fmt::Display for BaseSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let base_types: Vec<String> = self.base_types.iter().map(|ty| ty.to_string()).collect();
+        write!(f, "({})", base_types.join(", "))
+    }
+}
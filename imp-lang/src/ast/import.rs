@@ -0,0 +1,13 @@
+use super::*;
+
+/// A dependency on another module.
+///
+/// `import foo;` makes all functions of module `foo` available,
+/// `use foo::{add, sel};` only the listed ones.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub module: String,
+    /// The imported function names, or `None` to import all functions of the module.
+    pub names: Option<Vec<(String, Span)>>,
+    pub span: Span,
+}
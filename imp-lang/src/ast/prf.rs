@@ -19,6 +19,16 @@ pub enum Prf<'ast, Ast: AstConfig> {
     ///
     /// `A[V]`
     SelVxA(Ast::Operand<'ast>, Ast::Operand<'ast>),
+    /// @takeSxV
+    ///
+    /// The first `S` elements of a vector, as a view of its buffer rather
+    /// than a copy. Only introduced by the compiler, for index vectors.
+    TakeSxV(Ast::Operand<'ast>, Ast::Operand<'ast>),
+    /// @dropSxV
+    ///
+    /// All but the first `S` elements of a vector, as a view of its buffer
+    /// rather than a copy. Only introduced by the compiler, for index vectors.
+    DropSxV(Ast::Operand<'ast>, Ast::Operand<'ast>),
     /// @addSxS
     ///
     /// `S + S`
@@ -80,6 +90,8 @@ impl<'ast, Ast: AstConfig> Prf<'ast, Ast> {
             DimA(_) => "@dimA",
             ShapeA(_) => "@shapeA",
             SelVxA(_, _) => "@selVxA",
+            TakeSxV(_, _) => "@takeSxV",
+            DropSxV(_, _) => "@dropSxV",
             AddSxS(_, _) => "@addSxS",
             SubSxS(_, _) => "@subSxS",
             MulSxS(_, _) => "@mulSxS",
@@ -95,26 +107,28 @@ impl<'ast, Ast: AstConfig> Prf<'ast, Ast> {
         }
     }
 
-    // pub fn args(&self) -> Vec<&Ast::Operand<'ast>> {
-    //     use Prf::*;
-    //     match self {
-    //         DimA(a) => vec![a],
-    //         ShapeA(a) => vec![a],
-    //         SelVxA(a, b) => vec![a, b],
-    //         AddSxS(a, b) => vec![a, b],
-    //         SubSxS(a, b) => vec![a, b],
-    //         MulSxS(a, b) => vec![a, b],
-    //         DivSxS(a, b) => vec![a, b],
-    //         LtSxS(a, b) => vec![a, b],
-    //         LeSxS(a, b) => vec![a, b],
-    //         GtSxS(a, b) => vec![a, b],
-    //         GeSxS(a, b) => vec![a, b],
-    //         EqSxS(a, b) => vec![a, b],
-    //         NeSxS(a, b) => vec![a, b],
-    //         NegS(a) => vec![a],
-    //         NotS(a) => vec![a],
-    //     }
-    // }
+    pub fn args(&self) -> Vec<&Ast::Operand<'ast>> {
+        use Prf::*;
+        match self {
+            DimA(a) => vec![a],
+            ShapeA(a) => vec![a],
+            SelVxA(a, b) => vec![a, b],
+            TakeSxV(a, b) => vec![a, b],
+            DropSxV(a, b) => vec![a, b],
+            AddSxS(a, b) => vec![a, b],
+            SubSxS(a, b) => vec![a, b],
+            MulSxS(a, b) => vec![a, b],
+            DivSxS(a, b) => vec![a, b],
+            LtSxS(a, b) => vec![a, b],
+            LeSxS(a, b) => vec![a, b],
+            GtSxS(a, b) => vec![a, b],
+            GeSxS(a, b) => vec![a, b],
+            EqSxS(a, b) => vec![a, b],
+            NeSxS(a, b) => vec![a, b],
+            NegS(a) => vec![a],
+            NotS(a) => vec![a],
+        }
+    }
 
     pub fn args_mut(&mut self) -> Vec<&mut Ast::Operand<'ast>> {
         use Prf::*;
@@ -122,6 +136,8 @@ impl<'ast, Ast: AstConfig> Prf<'ast, Ast> {
             DimA(a) => vec![a],
             ShapeA(a) => vec![a],
             SelVxA(a, b) => vec![a, b],
+            TakeSxV(a, b) => vec![a, b],
+            DropSxV(a, b) => vec![a, b],
             AddSxS(a, b) => vec![a, b],
             SubSxS(a, b) => vec![a, b],
             MulSxS(a, b) => vec![a, b],
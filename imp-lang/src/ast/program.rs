@@ -28,4 +28,6 @@ pub struct Program<'ast, Ast: AstConfig> {
     /// ```
     pub overloads: HashMap<String, HashMap<BaseSignature, Vec<&'ast Fundef<'ast, Ast>>>>,
     pub fundefs: Arena<Fundef<'ast, Ast>>,
+    /// The record types declared in the program, in declaration order.
+    pub structs: Vec<Structdef>,
 }
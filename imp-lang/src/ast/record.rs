@@ -0,0 +1,18 @@
+use super::*;
+
+/// Record construction, e.g. `complex32 { .real = 1f32, .imag = 0f32 }`.
+#[derive(Clone, Debug)]
+pub struct Record<'ast, Ast: AstConfig> {
+    pub name: String,
+    /// The field initialisers, in source order.
+    pub fields: Vec<(String, Ast::Operand<'ast>)>,
+    pub span: Span,
+}
+
+/// Field access, e.g. `z.real`.
+#[derive(Clone, Debug)]
+pub struct Field<'ast, Ast: AstConfig> {
+    pub record: Ast::Operand<'ast>,
+    pub name: String,
+    pub span: Span,
+}
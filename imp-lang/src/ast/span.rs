@@ -0,0 +1,87 @@
+use std::{fmt, path::Path};
+
+/// A region of the source file, from the start of the first token up to
+/// (but excluding) the column after the last token. Lines and columns are 1-based.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    from_line: usize,
+    from_col: usize,
+    to_line: usize,
+    to_col: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, from: usize, to: usize) -> Self {
+        Self {
+            from_line: line,
+            from_col: from,
+            to_line: line,
+            to_col: to,
+        }
+    }
+
+    pub fn extend(&mut self, other: &Span) {
+        debug_assert!((self.from_line, self.from_col) <= (other.to_line, other.to_col));
+        self.to_line = other.to_line;
+        self.to_col = other.to_col;
+    }
+
+    pub fn to(&self, other: &Span) -> Self {
+        debug_assert!((self.from_line, self.from_col) <= (other.to_line, other.to_col));
+        Self {
+            from_line: self.from_line,
+            from_col: self.from_col,
+            to_line: other.to_line,
+            to_col: other.to_col,
+        }
+    }
+
+    /// Render a diagnostic for this span in the style of rustc:
+    ///
+    /// ```text
+    /// error: <message>
+    ///  --> <path>:<line>:<col>
+    ///   |
+    /// 3 |     x = foo(y);
+    ///   |         ^^^^^^
+    /// ```
+    ///
+    /// Multi-line spans are underlined up to the end of their first line.
+    pub fn report(&self, path: &Path, src: &str, message: impl fmt::Display) -> String {
+        let mut out = format!("error: {}\n", message);
+
+        let Some(line) = src.lines().nth(self.from_line.wrapping_sub(1)) else {
+            out.push_str(&format!("--> {}\n", path.display()));
+            return out;
+        };
+
+        let gutter = self.from_line.to_string().len();
+        let pad = " ".repeat(gutter);
+
+        let line_len = line.chars().count();
+        let from = self.from_col.max(1).min(line_len + 1);
+        let to = if self.to_line == self.from_line {
+            self.to_col.min(line_len + 1)
+        } else {
+            line_len + 1
+        };
+        let width = to.saturating_sub(from).max(1);
+        // Keep tabs so that the carets line up with the source excerpt.
+        let indent: String = line.chars()
+            .take(from - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        out.push_str(&format!("{}--> {}:{}:{}\n", pad, path.display(), self.from_line, self.from_col));
+        out.push_str(&format!("{} |\n", pad));
+        out.push_str(&format!("{} | {}\n", self.from_line, line));
+        out.push_str(&format!("{} | {}{}\n", pad, indent, "^".repeat(width)));
+        out
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.from_line, self.from_col)
+    }
+}
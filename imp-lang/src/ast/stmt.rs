@@ -4,4 +4,5 @@ use super::*;
 pub enum Stmt<'ast, Ast: AstConfig> {
     Assign(Assign<'ast, Ast>),
     Printf(Printf<'ast, Ast>),
+    Free(Free<'ast, Ast>),
 }
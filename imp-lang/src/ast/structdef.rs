@@ -0,0 +1,20 @@
+use super::*;
+
+/// A record type declaration, e.g. `struct complex32 { f32 real, f32 imag }`.
+///
+/// Values of the type have base type [`BaseType::Udf`] with the name of the struct.
+/// The fields are scalars, which are laid out in declaration order, like a C struct.
+#[derive(Clone, Debug)]
+pub struct Structdef {
+    pub name: String,
+    pub fields: Vec<Farg>,
+    pub span: Span,
+    /// The module this struct was imported from, or `None` if it is defined in the module being compiled.
+    pub import: Option<String>,
+}
+
+impl Structdef {
+    pub fn field(&self, name: &str) -> Option<&Farg> {
+        self.fields.iter().find(|field| field.id == name)
+    }
+}
@@ -1,18 +1,129 @@
 use super::*;
 
 /// ```bnf
-/// { <stmt>* <expr> | <lb> <= <iv> < <ub> }
+/// { <partition> (; <partition>)* }
+/// { <target>[<iv>] -> <partition> }
 /// ```
 ///
-/// Where <lb> and <ub> must be vectors of the same shape.
+/// The shape of the result is <ub> ++ shape(<expr>), where <ub> is the elementwise maximum of
+/// the upper bounds of the partitions, which must all have index vectors of the same length.
+///
+/// A tensor with a single partition is zero wherever its generator does not select an index.
+/// A tensor with multiple partitions must select every index below <ub> in exactly one of them.
+///
+/// Example: `{ a[iv] | [0] <= iv < [n]; b[iv - n] | [n] <= iv < [n + m] }`
+///
+/// With a <target>, the element computed for <iv> is written at index <target>[<iv>] instead,
+/// such that the body reads linearly while the writes are scattered. The target must be a
+/// permutation of the indices below <ub>, so it has shape <ub> ++ [len(<iv>)] and the tensor
+/// has a single partition that selects every index. See `doc/permutations.md`.
+///
+/// Example: `{ q[iv] -> a[iv] | iv < [n] }`
+#[derive(Clone, Debug)]
+pub struct Tensor<'ast, Ast: AstConfig> {
+    pub target: Option<Ast::Operand<'ast>>,
+    pub partitions: Vec<Partition<'ast, Ast>>,
+    pub span: Span,
+}
+
+/// ```bnf
+/// <stmt>* <expr> | <lb> <= <iv> < <ub> step <step> width <width>
+/// ```
+///
+/// Where <lb>, <ub>, <step> and <width> must be vectors of the same shape.
 /// <iv> is a variable that iterates over the range [<lb>, <ub>).
 /// Where <stmt> and <expr> can refer to the induction variable <iv>.
-/// The shape of the result is shape(<expr>) ++ <ub>.
-/// Note that <lb> has no effect on the result shape. Any values below <lb> are zeros.
+///
+/// As in SaC, `step` and `width` select a grid within the range: in every dimension, the
+/// first <width> of each <step> consecutive indices, counted from <lb>, are selected.
+/// The width defaults to 1 and may only be given together with a step.
 #[derive(Clone, Debug)]
-pub struct Tensor<'ast, Ast: AstConfig> {
+pub struct Partition<'ast, Ast: AstConfig> {
     pub body: Body<'ast, Ast>,
     pub iv: &'ast VarInfo<'ast, Ast>,
     pub lb: Option<Ast::Operand<'ast>>,
     pub ub: Ast::Operand<'ast>,
+    pub step: Option<Ast::Operand<'ast>>,
+    pub width: Option<Ast::Operand<'ast>>,
+    pub span: Span,
+}
+
+impl<'ast, Ast: AstConfig> Tensor<'ast, Ast> {
+    /// The only partition of the tensor, if it is not partitioned.
+    pub fn single(&self) -> Option<&Partition<'ast, Ast>> {
+        match &self.partitions[..] {
+            [partition] => Some(partition),
+            _ => None,
+        }
+    }
+
+    /// Whether the tensor leaves some indices below <ub> unselected, such that they are zeros.
+    pub fn has_default(&self) -> bool {
+        self.single().is_some_and(Partition::has_default)
+    }
+}
+
+impl<'ast, Ast: AstConfig> Partition<'ast, Ast> {
+    /// Whether the generator may leave some indices below <ub> unselected.
+    pub fn has_default(&self) -> bool {
+        self.lb.is_some() || self.step.is_some()
+    }
+}
+
+/// The values of the bounds of a generator, with the lower bound defaulting to zeros
+/// and the step and width to ones.
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    pub lb: Vec<usize>,
+    pub ub: Vec<usize>,
+    pub step: Vec<usize>,
+    pub width: Vec<usize>,
+}
+
+/// An index below the upper bound of a partitioned tensor that is not selected exactly once.
+#[derive(Clone, Debug)]
+pub enum Coverage {
+    /// No partition selects the index.
+    Gap(Vec<usize>),
+    /// More than one partition selects the index.
+    Overlap(Vec<usize>),
+}
+
+impl Bounds {
+    /// Whether the index vector lies in `[lb, ub)` and within the width of its step in every axis.
+    pub fn selects(&self, iv: &[usize]) -> bool {
+        (0..iv.len()).all(|d| iv[d] >= self.lb[d] && iv[d] < self.ub[d] && (iv[d] - self.lb[d]) % self.step[d] < self.width[d])
+    }
+
+    /// The upper bound of a tensor with the given partitions, i.e. the elementwise maximum of their upper bounds.
+    pub fn upper_bound(partitions: &[Bounds]) -> Vec<usize> {
+        let mut ub = partitions[0].ub.clone();
+        for partition in &partitions[1..] {
+            for (u, p) in ub.iter_mut().zip(&partition.ub) {
+                *u = (*u).max(*p);
+            }
+        }
+        ub
+    }
+
+    /// The first index in row-major order that is not selected by exactly one of the partitions, if any.
+    pub fn coverage(partitions: &[Bounds]) -> Option<Coverage> {
+        let ub = Self::upper_bound(partitions);
+        if ub.contains(&0) {
+            return None;
+        }
+
+        let mut iv = vec![0; ub.len()];
+        loop {
+            match partitions.iter().filter(|partition| partition.selects(&iv)).count() {
+                0 => return Some(Coverage::Gap(iv)),
+                1 => {}
+                _ => return Some(Coverage::Overlap(iv)),
+            }
+
+            let d = (0..iv.len()).rev().find(|&d| iv[d] + 1 < ub[d])?;
+            iv[d] += 1;
+            iv[d + 1..].fill(0);
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BaseType {
     Bool,
@@ -8,10 +10,15 @@ pub enum BaseType {
     I64,
     F32,
     F64,
-    /// User-defined type
+    /// User-defined record type, declared by a [`Structdef`](super::Structdef)
     ///
-    /// (Not actually supported yet by the syntax or the compiler)
+    /// Example: `complex32` after `struct complex32 { f32 real, f32 imag }`
     Udf(String),
+    /// Type parameter of a generic function, e.g. `T` in `fn add<T>(T a, T b) -> T`
+    ///
+    /// Generic functions are monomorphised during type inference,
+    /// so this does not occur after type checking.
+    Generic(String),
 }
 
 #[derive(Clone, Debug)]
@@ -55,13 +62,15 @@ pub enum DimPattern {
 }
 
 /// A `d:shp` rank capture — binds the rank scalar (`d`) and the shape vector (`shp`) from
-/// the runtime array descriptor, without constraining the rank at compile time
+/// the runtime array descriptor, constraining the rank only by an optional lower bound
 #[derive(Clone, Debug)]
 pub struct RankCapture {
     /// Name bound to the array's rank (`arr.dim`) as a `usize` scalar
     pub dim_name: String,
     /// Name bound to the array's shape vector (`arr.shp`) as a `usize[d]` array
     pub shp_name: String,
+    /// Smallest rank the capture matches, from a `d>0:shp` or `d>=1:shp` bound; zero if unbounded
+    pub min_rank: usize,
 }
 
 impl Type {
@@ -109,8 +118,40 @@ impl TypePattern {
         TypePattern::Axes(vec![AxisPattern::Rank(RankCapture {
             dim_name: String::new(),
             shp_name: String::new(),
+            min_rank: 0,
         })])
     }
+
+    /// Whether the pattern contains a rank capture, such that its rank is not fixed.
+    pub fn has_rank_capture(&self) -> bool {
+        match self {
+            TypePattern::Scalar => false,
+            TypePattern::Axes(axes) => axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))),
+        }
+    }
+
+    /// The smallest rank of an array matching this pattern.
+    ///
+    /// Example: 0 for `u32[d:shp]`, 2 for `u32[n,d>0:shp]`
+    pub fn min_rank(&self) -> usize {
+        match self {
+            TypePattern::Scalar => 0,
+            TypePattern::Axes(axes) => axes.iter().map(|axis| match axis {
+                AxisPattern::Dim(_) => 1,
+                AxisPattern::Rank(capture) => capture.min_rank,
+            }).sum(),
+        }
+    }
+}
+
+impl RankCapture {
+    /// The lower bound as written in a type pattern, e.g. `>0`; empty if unbounded
+    pub fn bound(&self) -> String {
+        match self.min_rank {
+            0 => String::new(),
+            n => format!(">{}", n - 1),
+        }
+    }
 }
 
 impl DimPattern {
@@ -118,3 +159,46 @@ impl DimPattern {
         DimPattern::Var(String::new())
     }
 }
+
+impl fmt::Display for BaseType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BaseType::*;
+        match self {
+            Bool => write!(f, "bool"),
+            Usize => write!(f, "usize"),
+            U32 => write!(f, "u32"),
+            U64 => write!(f, "u64"),
+            I32 => write!(f, "i32"),
+            I64 => write!(f, "i64"),
+            F32 => write!(f, "f32"),
+            F64 => write!(f, "f64"),
+            Udf(udf) => write!(f, "{}", udf),
+            Generic(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.ty, self.shape)
+    }
+}
+
+impl fmt::Display for TypePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Anonymous symbols, as created by `TypePattern::any` and `DimPattern::any`
+        let name = |s: &str| if s.is_empty() { "_".to_owned() } else { s.to_owned() };
+
+        let TypePattern::Axes(axes) = self else {
+            return Ok(());
+        };
+
+        let axes: Vec<String> = axes.iter().map(|axis| match axis {
+            AxisPattern::Dim(DimPattern::Known(n)) => n.to_string(),
+            AxisPattern::Dim(DimPattern::Var(var)) => name(var),
+            AxisPattern::Rank(capture) => format!("{}{}:{}", name(&capture.dim_name), capture.bound(), name(&capture.shp_name)),
+        }).collect();
+
+        write!(f, "[{}]", axes.join(", "))
+    }
+}
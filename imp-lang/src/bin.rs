@@ -1,8 +1,39 @@
-use clap::Parser;
-use imp_lang::Options;
+use clap::{Parser, Subcommand};
+use imp_lang::{Options, RunOptions};
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    options: Option<Options>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Call a function of the program with the interpreter
+    Run(RunOptions),
+}
 
 fn main() {
     env_logger::init();
-    let options = Options::parse();
-    imp_lang::compile(options);
+    let cli = Cli::parse();
+    match (cli.command, cli.options) {
+        (Some(Command::Run(options)), _) => match imp_lang::run(options) {
+            Ok(value) => println!("{}", imp_lang::show_value(&value)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        (None, Some(options)) => {
+            if let Err(e) = imp_lang::compile(options) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        (None, None) => unreachable!("clap requires either a subcommand or an input file"),
+    }
 }
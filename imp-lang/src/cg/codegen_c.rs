@@ -1,4 +1,6 @@
-use crate::{ast::*, cg::rename_fundefs};
+use std::collections::HashMap;
+
+use crate::{ast::*, cg::rename_fundefs, mm};
 
 pub fn emit_c(ast: &mut Program<'static, TypedAst>, module_name: String) -> String {
     let mut cg = CompileC::new(module_name);
@@ -12,11 +14,15 @@ pub struct CompileC {
     arg_names: Vec<String>,
     arg_types: Vec<Type>,
     ret_type: Option<Type>,
+    shape_facts: ShapeFacts,
     expr_stack: Vec<String>,
     lhs_target: Option<(String, Type)>,
     indent: usize,
     shp_uid: usize,
     tensor_uid: usize,
+    /// Releases the buffers that the function owns at the current point, innermost last.
+    /// They are emitted before returning early with a runtime error.
+    cleanup: Vec<Cleanup>,
 }
 
 impl CompileC {
@@ -27,11 +33,13 @@ impl CompileC {
             arg_names: Vec::new(),
             arg_types: Vec::new(),
             ret_type: None,
+            shape_facts: ShapeFacts::default(),
             expr_stack: Vec::new(),
             lhs_target: None,
             indent: 0,
             shp_uid: 0,
             tensor_uid: 0,
+            cleanup: Vec::new(),
         }
     }
 
@@ -71,22 +79,23 @@ impl CompileC {
             .map(|arg| format!("{} {}", full_ctype(&arg.ty), arg.id))
             .collect();
         self.output.push_str(&format!(
-            "{} IMP_{}({});\n",
+            "{}{} IMP_{}({});\n",
+            linkage(fundef.internal),
             full_ctype(&fundef.ret_type),
             fundef.name,
             args.join(", ")
         ));
     }
 
-    fn emit_wrapper_prototype(&mut self, base_name: &str, sig: &BaseSignature, ret_ty: &BaseType) {
+    fn emit_wrapper_prototype(&mut self, base_name: &str, sig: &BaseSignature, family: &[&Fundef<'_, TypedAst>]) {
         let sig_str = sig.base_types.iter().map(base_rstype).collect::<Vec<_>>();
         let fargs: Vec<String> = sig.base_types
             .iter()
             .enumerate()
             .map(|(i, base)| format!("{} arg{i}", dyn_ctype(base)))
             .collect();
-        self.push_line(&format!("{} IMP_{}_{}({});",
-            dyn_ctype(ret_ty), base_name, sig_str.join("_"), fargs.join(", ")));
+        self.push_line(&format!("{}{} IMP_{}_{}({});",
+            linkage(family_internal(family)), dyn_ctype(&family[0].ret_type.ty), base_name, sig_str.join("_"), fargs.join(", ")));
     }
 
     fn emit_wrapper_function(&mut self, base_name: &str, sig: &BaseSignature, family: &Vec<&Fundef<'_, TypedAst>>) {
@@ -98,8 +107,8 @@ impl CompileC {
             .collect();
 
         let first = family[0];
-        self.push_line(&format!("{} IMP_{}_{}({}) {{",
-            dyn_ctype(&first.ret_type.ty), base_name, sig_str.join("_"), fargs.join(", ")));
+        self.push_line(&format!("{}{} IMP_{}_{}({}) {{",
+            linkage(family_internal(family)), dyn_ctype(&first.ret_type.ty), base_name, sig_str.join("_"), fargs.join(", ")));
 
         self.indent += 1;
         for (idx, fundef) in family.iter().enumerate() {
@@ -143,8 +152,8 @@ impl CompileC {
             self.push_line("}");
         }
 
-        self.push_line(&format!("fprintf(stderr, \"runtime overload dispatch failed: {}\\n\");", base_name));
-        self.push_line("abort();");
+        self.push_line(&format!("imp_raise({});", c_error("IMP_ERR_DISPATCH", "0", "0", "0", "0")));
+        self.push_line(&format!("return ({}) {{ 0 }};", dyn_ctype(&first.ret_type.ty)));
 
         self.indent -= 1;
         self.push_line("}");
@@ -172,15 +181,20 @@ impl CompileC {
             if value_ty.is_array_or_scalar() {
                 self.push_line(&format!("if (!{name}.is_array) {{"));
                 self.indent += 1;
-                self.push_line("fprintf(stderr, \"return type mismatch: expected array\\n\");");
-                self.push_line("abort();");
+                let min_rank = declared_ty.shape.min_rank().max(1).to_string();
+                self.emit_raise(&c_error("IMP_ERR_MIN_RANK", "IMP_RESULT", "0", &min_rank, "0"));
                 self.indent -= 1;
                 self.push_line("}");
                 self.push_line(&format!(
                     "return imp_clone_array_raw({name}.data.array, sizeof({}));",
                     base_ctype(&declared_ty)
                 ));
+            } else if mm::owner(&ret).is_some() {
+                self.emit_ret_shape_checks(&name, &declared_ty, &value_ty);
+                // Locally allocated, so ownership moves to the caller
+                self.push_line(&format!("return {name};"));
             } else {
+                self.emit_ret_shape_checks(&name, &declared_ty, &value_ty);
                 self.push_line(&format!(
                     "return imp_clone_array_raw({}, sizeof({}));",
                     name,
@@ -191,8 +205,7 @@ impl CompileC {
             if value_ty.is_array_or_scalar() {
                 self.push_line(&format!("if ({name}.is_array) {{"));
                 self.indent += 1;
-                self.push_line("fprintf(stderr, \"return type mismatch: expected scalar\\n\");");
-                self.push_line("abort();");
+                self.emit_raise(&c_error("IMP_ERR_RANK", "IMP_RESULT", "0", "0", &format!("{name}.data.array.dim")));
                 self.indent -= 1;
                 self.push_line("}");
                 self.push_line(&format!("return {name}.data.scalar;"));
@@ -201,6 +214,556 @@ impl CompileC {
             }
         }
     }
+
+    /// Raise a runtime error and return a zero result, see `imp_error` in the header.
+    fn emit_raise(&mut self, error: &str) {
+        self.push_line(&format!("imp_raise({error});"));
+        self.emit_cleanup();
+        self.push_line(&format!("return {};", self.zero_result()));
+    }
+
+    /// Return early if the preceding call raised a runtime error.
+    fn emit_propagate(&mut self) {
+        if self.cleanup.is_empty() {
+            self.push_line(&format!("if (imp_error.kind != IMP_OK) return {};", self.zero_result()));
+            return;
+        }
+        self.push_line("if (imp_error.kind != IMP_OK) {");
+        self.indent += 1;
+        self.emit_cleanup();
+        self.push_line(&format!("return {};", self.zero_result()));
+        self.indent -= 1;
+        self.push_line("}");
+    }
+
+    /// Release every buffer that is live at this point, see [`Self::cleanup`].
+    fn emit_cleanup(&mut self) {
+        for cleanup in self.cleanup.clone().iter().rev() {
+            if cleanup.debug_only {
+                self.push_line("#ifndef NDEBUG");
+            }
+            self.push_line(&cleanup.free);
+            if cleanup.debug_only {
+                self.push_line("#endif");
+            }
+        }
+    }
+
+    /// Register a buffer that must be released if the function returns early, until [`Self::release`].
+    fn acquire(&mut self, free: String) {
+        self.cleanup.push(Cleanup { free, debug_only: false });
+    }
+
+    fn release(&mut self, free: &str) {
+        if let Some(i) = self.cleanup.iter().rposition(|cleanup| cleanup.free == free) {
+            self.cleanup.remove(i);
+        }
+    }
+
+    fn zero_result(&self) -> String {
+        let ret_type = self.ret_type.as_ref().expect("runtime errors are only raised in functions");
+        format!("({}) {{ 0 }}", full_ctype(ret_type))
+    }
+
+    /// Raise an error if the returned array `value` does not have the declared return shape.
+    ///
+    /// Type inference rejects return values that provably have a different shape, so only what it
+    /// could not prove from the type `found` of the value is checked here.
+    fn emit_ret_shape_checks(&mut self, value: &str, declared: &Type, found: &Type) {
+        for check in self.ret_shape_checks(value, &declared.shape, &found.shape) {
+            if let Some(rank) = &check.axes {
+                self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+                self.indent += 1;
+            }
+            self.push_line(&format!("if (!({})) {{", check.cond));
+            self.indent += 1;
+            self.emit_raise(&check.error);
+            self.indent -= 1;
+            self.push_line("}");
+            if check.axes.is_some() {
+                self.indent -= 1;
+                self.push_line("}");
+            }
+        }
+    }
+
+    fn ret_shape_checks(&self, value: &str, declared: &TypePattern, found: &TypePattern) -> Vec<RetCheck> {
+        let TypePattern::Axes(axes) = declared else {
+            return Vec::new();
+        };
+        let found = match found {
+            TypePattern::Scalar => &[][..],
+            TypePattern::Axes(found) => found.as_slice(),
+        };
+        if axes.len() == found.len() && axes.iter().zip(found).all(|(a, b)| same_axis(a, b)) {
+            return Vec::new();
+        }
+
+        let captures = axes.iter().filter(|axis| matches!(axis, AxisPattern::Rank(_))).count();
+        let same_layout = captures == 0 && axes.len() == found.len() && !found.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
+        let proven = |i: usize| same_layout && same_axis(&axes[i], &found[i]);
+
+        let dim = format!("{value}.dim");
+        let extent = |i: usize, expected: &str| {
+            let axis = axis_index(value, axes, i)?;
+            let found = format!("{value}.shp[{axis}]");
+            let error = c_error("IMP_ERR_EXTENT", "IMP_RESULT", &axis, expected, &found);
+            Some(RetCheck::new(format!("{found} == {expected}"), error))
+        };
+
+        let mut checks = Vec::new();
+        if captures == 0 {
+            if !same_layout {
+                let rank = axes.len().to_string();
+                checks.push(RetCheck::new(format!("{dim} == {rank}"), c_error("IMP_ERR_RANK", "IMP_RESULT", "0", &rank, &dim)));
+            }
+        } else if TypePattern::Axes(found.to_vec()).min_rank() < declared.min_rank() {
+            let rank = declared.min_rank().to_string();
+            checks.push(RetCheck::new(format!("{dim} >= {rank}"), c_error("IMP_ERR_MIN_RANK", "IMP_RESULT", "0", &rank, &dim)));
+        }
+
+        // Free symbols are compared with their first occurrence
+        let mut free: HashMap<&str, (usize, String)> = HashMap::new();
+        for (i, axis) in axes.iter().enumerate() {
+            match axis {
+                AxisPattern::Dim(DimPattern::Known(n)) => {
+                    if !proven(i) {
+                        checks.extend(extent(i, &n.to_string()));
+                    }
+                }
+                AxisPattern::Dim(DimPattern::Var(var)) if var.is_empty() => {}
+                AxisPattern::Dim(DimPattern::Var(var)) => {
+                    let Some(found) = axis_expr(value, axes, i) else {
+                        continue;
+                    };
+                    match (self.symbol_expr(var), free.get(var.as_str())) {
+                        (Some(ShapeExpr::Extent(expected)), _) => {
+                            if !proven(i) {
+                                checks.extend(extent(i, &expected));
+                            }
+                        }
+                        (_, Some((first, expected))) => {
+                            if !proven(*first) || !proven(i) {
+                                checks.extend(extent(i, expected));
+                            }
+                        }
+                        (_, None) => {
+                            free.insert(var, (i, found));
+                        }
+                    }
+                }
+                AxisPattern::Rank(capture) => {
+                    if captures > 1 {
+                        continue;
+                    }
+                    let fixed = axes.len() - 1;
+                    if let Some(ShapeExpr::Extent(rank)) = self.symbol_expr(&capture.dim_name) {
+                        let expected = match fixed {
+                            0 => rank,
+                            _ => format!("{rank} + {fixed}"),
+                        };
+                        let error = c_error("IMP_ERR_RANK", "IMP_RESULT", "0", &expected, &dim);
+                        checks.push(RetCheck::new(format!("{dim} == {expected}"), error));
+                        if let Some(ShapeExpr::Shape { start, rank }) = self.symbol_expr(&capture.shp_name) {
+                            let found = format!("{value}.shp[{i} + _d]");
+                            let error = c_error("IMP_ERR_EXTENT", "IMP_RESULT", &format!("{i} + _d"), &format!("({start})[_d]"), &found);
+                            checks.push(RetCheck {
+                                axes: Some(rank),
+                                cond: format!("{found} == ({start})[_d]"),
+                                error,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        checks
+    }
+
+    /// A C expression for a shape symbol that is bound by the arguments of the current function.
+    ///
+    /// Symbols are evaluated on the argument descriptors rather than read from the shape prelude,
+    /// as dead code removal drops the prelude assignments that the body does not use.
+    fn symbol_expr(&self, symbol: &str) -> Option<ShapeExpr> {
+        if let Some(i) = self.arg_names.iter().position(|name| name == symbol)
+            && self.arg_types[i].is_scalar()
+        {
+            return Some(ShapeExpr::Extent(symbol.to_owned()));
+        }
+
+        let binding = self.shape_facts.bindings.iter().find(|binding| binding.symbol == symbol)?;
+        let arg_index = match binding.term {
+            ShapeTerm::ArgDim { arg_index, .. }
+            | ShapeTerm::ArgRank { arg_index, .. }
+            | ShapeTerm::TailShape { arg_index, .. } => arg_index,
+            _ => return None,
+        };
+        let name = &self.arg_names[arg_index];
+        let TypePattern::Axes(axes) = &self.arg_types[arg_index].shape else {
+            return None;
+        };
+
+        // The number of axes matched by the sole rank capture of the argument
+        let rank = || match axes.iter().filter(|axis| matches!(axis, AxisPattern::Rank(_))).count() {
+            1 if axes.len() == 1 => Some(format!("{name}.dim")),
+            1 => Some(format!("({name}.dim - {})", axes.len() - 1)),
+            _ => None,
+        };
+
+        match binding.term {
+            ShapeTerm::ArgDim { axis_index, .. } => axis_expr(name, axes, axis_index).map(ShapeExpr::Extent),
+            ShapeTerm::ArgRank { .. } => rank().map(ShapeExpr::Extent),
+            ShapeTerm::TailShape { start_axis, .. } => Some(ShapeExpr::Shape {
+                start: format!("{name}.shp + {start_axis}"),
+                rank: rank()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Declare the scalar bounds of every axis of a partition whose index vector has a statically known length.
+    fn emit_static_bounds(&mut self, partition: &Partition<'_, TypedAst>, t_uid: usize, rank: usize) {
+        let iv_name = partition.iv.name.clone();
+        let bounds = [("lb", partition.lb.as_ref()), ("ub", Some(&partition.ub)), ("step", partition.step.as_ref()), ("width", partition.width.as_ref())];
+        for d in 0..rank {
+            for (kind, bound) in bounds {
+                if let Some(bound) = bound {
+                    let name = self.nameof(bound);
+                    self.push_line(&format!("size_t {iv_name}_{kind}{d}_{t_uid} = ((size_t *){name}.data)[{d}];"));
+                }
+            }
+        }
+    }
+
+    /// Open one loop per axis over the indices that the generator of a partition selects,
+    /// and build its index vector inside them, see [`Self::emit_static_bounds`].
+    fn open_static_loops(&mut self, partition: &Partition<'_, TypedAst>, t_uid: usize, rank: usize) {
+        let iv_name = partition.iv.name.clone();
+        for d in 0..rank {
+            let i = format!("{iv_name}_{d}_{t_uid}");
+            let lb = partition.lb.as_ref().map(|_| format!("{iv_name}_lb{d}_{t_uid}"));
+            let start = lb.clone().unwrap_or_else(|| "0".to_owned());
+            self.push_line(&format!("for (size_t {i} = {start}; {i} < {iv_name}_ub{d}_{t_uid}; {i} += 1) {{"));
+            self.indent += 1;
+
+            if partition.step.is_some() {
+                let offset = match lb {
+                    Some(lb) => format!("({i} - {lb})"),
+                    None => i.clone(),
+                };
+                let width = match partition.width {
+                    Some(_) => format!("{iv_name}_width{d}_{t_uid}"),
+                    None => "1".to_owned(),
+                };
+                self.push_line(&format!("if ({offset} % {iv_name}_step{d}_{t_uid} >= {width}) continue;"));
+            }
+        }
+
+        // Build iv as a stack-allocated ImpArrayRaw so that iv[i] selections work.
+        let iv_elem = base_ctype(&partition.iv.ty);
+        let iv_components: Vec<String> = (0..rank)
+            .map(|d| format!("({iv_elem}){iv_name}_{d}_{t_uid}"))
+            .collect();
+        self.push_line(&format!(
+            "{iv_elem} {iv_name}_data_{t_uid}[{rank}] = {{ {} }};",
+            iv_components.join(", ")
+        ));
+        self.push_line(&format!("size_t {iv_name}_shp_arr_{t_uid}[1] = {{ {rank} }};"));
+        self.push_line(&format!(
+            "ImpArrayRaw {iv_name} = (ImpArrayRaw) {{ .len = {rank}, .shp = {iv_name}_shp_arr_{t_uid}, .dim = 1, .data = (void *){iv_name}_data_{t_uid} }};"
+        ));
+    }
+
+    fn close_static_loops(&mut self, rank: usize) {
+        for _ in 0..rank {
+            self.indent -= 1;
+            self.push_line("}");
+        }
+    }
+
+    /// A tensor whose index vectors have a length that is only known at runtime.
+    ///
+    /// The index vector of each partition is a runtime odometer over its upper bound, such that its
+    /// elements are visited in row-major order. For a single partition, the upper bound is the shape
+    /// of the result and the flat index is simply the iteration count.
+    fn emit_dynamic_tensor(&mut self, tensor: &mut Tensor<'_, TypedAst>, target_name: &str, base: &str, uids: &[usize]) {
+        let len_name = format!("{target_name}_len");
+        let data_name = format!("{target_name}_data");
+        let shp_name = format!("{target_name}_shp");
+        let partitioned = tensor.single().is_none();
+
+        for (partition, &t_uid) in tensor.partitions.iter().zip(uids) {
+            self.emit_odometer_bounds(partition, t_uid);
+        }
+
+        let first = &tensor.partitions[0].iv.name;
+        let rank = format!("{first}_rank_{}", uids[0]);
+        self.push_line(&format!("size_t *{shp_name} = (size_t *)malloc({rank} * sizeof(size_t));"));
+        self.acquire(format!("free({shp_name});"));
+        self.push_line(&format!("memcpy({shp_name}, {first}_ub_{}, {rank} * sizeof(size_t));", uids[0]));
+        if partitioned {
+            for (partition, t_uid) in tensor.partitions.iter().zip(uids).skip(1) {
+                let iv_name = &partition.iv.name;
+                self.push_line(&format!("if ({iv_name}_rank_{t_uid} != {rank}) {{"));
+                self.indent += 1;
+                self.emit_raise(&c_error("IMP_ERR_RANK", "IMP_RESULT", "0", &rank, &format!("{iv_name}_rank_{t_uid}")));
+                self.indent -= 1;
+                self.push_line("}");
+                self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+                self.push_line(&format!("    if ({iv_name}_ub_{t_uid}[_d] > {shp_name}[_d]) {shp_name}[_d] = {iv_name}_ub_{t_uid}[_d];"));
+                self.push_line("}");
+            }
+            self.push_line(&format!("size_t {len_name} = 1;"));
+            self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+            self.push_line(&format!("    {len_name} *= {shp_name}[_d];"));
+            self.push_line("}");
+        } else {
+            self.push_line(&format!("size_t {len_name} = {first}_count_{};", uids[0]));
+        }
+        self.push_line(&format!("{base} *{data_name} = {};", alloc_elems(base, &len_name, tensor.has_default())));
+        self.acquire(format!("free({data_name});"));
+        if partitioned {
+            self.emit_coverage_counts(target_name, &len_name);
+        }
+        let target = tensor.target;
+        if let Some(target) = &target {
+            self.open_target_check(target, target_name, &len_name, &rank);
+        }
+
+        for (partition, &t_uid) in tensor.partitions.iter_mut().zip(uids) {
+            let iv_name = partition.iv.name.clone();
+            let scope = self.cleanup.len();
+            self.open_odometer(partition, t_uid);
+
+            for stmt in &mut partition.body.stmts {
+                self.trav_stmt(stmt);
+            }
+            let ret = self.render_id(partition.body.ret);
+
+            let flat = if partitioned {
+                let flat = format!("{iv_name}_at_{t_uid}");
+                self.push_line(&format!("size_t {flat} = 0;"));
+                self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+                self.push_line(&format!("    {flat} = {flat} * {shp_name}[_d] + (size_t){iv_name}_data_{t_uid}[_d];"));
+                self.push_line("}");
+                self.count_coverage(target_name, &flat);
+                flat
+            } else if let Some(target) = &target {
+                self.emit_target_index(target, partition, target_name, &rank, &shp_name)
+            } else {
+                format!("{iv_name}_flat")
+            };
+            self.push_line(&format!("{data_name}[{flat}] = {ret};"));
+
+            self.cleanup.truncate(scope);
+            self.close_odometer(partition, t_uid);
+        }
+
+        if partitioned {
+            self.check_coverage(target_name, &len_name);
+        }
+        if target.is_some() {
+            self.close_target_check(target_name);
+        }
+        self.release(&format!("free({shp_name});"));
+        self.release(&format!("free({data_name});"));
+        self.push_line(&format!(
+            "ImpArrayRaw {target_name} = (ImpArrayRaw) {{ .len = {len_name}, .shp = {shp_name}, .dim = {rank}, .data = (void *){data_name} }};"
+        ));
+    }
+
+    /// Declare the bounds, the number of index vectors and the odometer of a partition of unknown rank,
+    /// see [`Self::emit_dynamic_tensor`].
+    fn emit_odometer_bounds(&mut self, partition: &Partition<'_, TypedAst>, t_uid: usize) {
+        let iv_name = partition.iv.name.clone();
+        let iv_elem = base_ctype(&partition.iv.ty);
+        let ub_name = self.nameof(&partition.ub);
+        let rank = format!("{iv_name}_rank_{t_uid}");
+        let count = format!("{iv_name}_count_{t_uid}");
+
+        self.push_line(&format!("size_t {rank} = {ub_name}.len;"));
+        let bounds = [("lb", partition.lb.as_ref()), ("ub", Some(&partition.ub)), ("step", partition.step.as_ref()), ("width", partition.width.as_ref())];
+        for (kind, bound) in bounds {
+            if let Some(bound) = bound {
+                let name = self.nameof(bound);
+                self.push_line(&format!("size_t *{iv_name}_{kind}_{t_uid} = (size_t *){name}.data;"));
+            }
+        }
+        self.push_line(&format!("size_t {count} = 1;"));
+        self.push_line(&format!("{iv_elem} *{iv_name}_data_{t_uid} = ({iv_elem} *)calloc({rank}, sizeof({iv_elem}));"));
+        self.acquire(format!("free({iv_name}_data_{t_uid});"));
+        self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+        self.push_line(&format!("    {count} *= {iv_name}_ub_{t_uid}[_d];"));
+        self.push_line("}");
+    }
+
+    /// Open a loop over all index vectors below the upper bound of a partition of unknown rank,
+    /// and a conditional on whether its generator selects the index vector.
+    fn open_odometer(&mut self, partition: &Partition<'_, TypedAst>, t_uid: usize) {
+        let iv_name = partition.iv.name.clone();
+        let rank = format!("{iv_name}_rank_{t_uid}");
+        let count = format!("{iv_name}_count_{t_uid}");
+
+        self.push_line(&format!("for (size_t {iv_name}_flat = 0; {iv_name}_flat < {count}; {iv_name}_flat += 1) {{"));
+        self.indent += 1;
+        self.push_line(&format!("size_t {iv_name}_shp_arr_{t_uid}[1] = {{ {rank} }};"));
+        self.push_line(&format!(
+            "ImpArrayRaw {iv_name} = (ImpArrayRaw) {{ .len = {rank}, .shp = {iv_name}_shp_arr_{t_uid}, .dim = 1, .data = (void *){iv_name}_data_{t_uid} }};"
+        ));
+
+        if !partition.has_default() {
+            return;
+        }
+
+        let i = format!("(size_t){iv_name}_data_{t_uid}[_d]");
+        let mut outside = Vec::new();
+        let offset = match partition.lb {
+            Some(_) => {
+                outside.push(format!("{i} < {iv_name}_lb_{t_uid}[_d]"));
+                format!("({i} - {iv_name}_lb_{t_uid}[_d])")
+            }
+            None => i.clone(),
+        };
+        if partition.step.is_some() {
+            let width = match partition.width {
+                Some(_) => format!("{iv_name}_width_{t_uid}[_d]"),
+                None => "1".to_owned(),
+            };
+            outside.push(format!("{offset} % {iv_name}_step_{t_uid}[_d] >= {width}"));
+        }
+
+        let selected = format!("{iv_name}_selected_{t_uid}");
+        self.push_line(&format!("bool {selected} = true;"));
+        self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+        self.push_line(&format!("    if ({}) {selected} = false;", outside.join(" || ")));
+        self.push_line("}");
+        self.push_line(&format!("if ({selected}) {{"));
+        self.indent += 1;
+    }
+
+    /// Advance the odometer to the next index vector in row-major order, and close the loop.
+    fn close_odometer(&mut self, partition: &Partition<'_, TypedAst>, t_uid: usize) {
+        if partition.has_default() {
+            self.indent -= 1;
+            self.push_line("}");
+        }
+
+        let iv_name = partition.iv.name.clone();
+        let data = format!("{iv_name}_data_{t_uid}");
+        self.push_line(&format!("for (size_t _d = {iv_name}_rank_{t_uid}; _d-- > 0;) {{"));
+        self.indent += 1;
+        self.push_line(&format!("{data}[_d] += 1;"));
+        self.push_line(&format!("if ((size_t){data}[_d] < {iv_name}_ub_{t_uid}[_d]) break;"));
+        self.push_line(&format!("{data}[_d] = 0;"));
+        self.indent -= 1;
+        self.push_line("}");
+
+        self.indent -= 1;
+        self.push_line("}");
+        let free = format!("free({data});");
+        self.release(&free);
+        self.push_line(&free);
+    }
+
+    /// In debug builds, check that the target of a tensor has one index vector per element,
+    /// and track which elements are written such that [`Self::emit_target_index`] can verify that it is a permutation.
+    fn open_target_check(&mut self, target: &Id<'_, TypedAst>, target_name: &str, len: &str, rank: &str) {
+        let q = self.nameof(target);
+        self.push_line("#ifndef NDEBUG");
+        self.push_line(&format!("if ({q}.len != {len} * {rank}) {{"));
+        self.indent += 1;
+        self.emit_raise(&c_error("IMP_ERR_TARGET_LENGTH", "IMP_RESULT", "0", &format!("{len} * {rank}"), &format!("{q}.len")));
+        self.indent -= 1;
+        self.push_line("}");
+        self.push_line(&format!("unsigned char *{target_name}_written = (unsigned char *)calloc({len}, 1);"));
+        self.push_line("#endif");
+        self.cleanup.push(Cleanup { free: format!("free({target_name}_written);"), debug_only: true });
+    }
+
+    fn close_target_check(&mut self, target_name: &str) {
+        let free = format!("free({target_name}_written);");
+        self.release(&free);
+        self.push_line("#ifndef NDEBUG");
+        self.push_line(&free);
+        self.push_line("#endif");
+    }
+
+    /// The flat index at which the element with the given flat index is written, i.e. the flat index
+    /// of the index vector that the target maps it to, see [`Self::open_target_check`].
+    fn emit_target_index(&mut self, target: &Id<'_, TypedAst>, partition: &Partition<'_, TypedAst>, target_name: &str, rank: &str, shp: &str) -> String {
+        let q = self.nameof(target);
+        let iv_name = &partition.iv.name;
+        let iv_elem = base_ctype(&partition.iv.ty);
+        let at = format!("{iv_name}_at");
+        let written = format!("{target_name}_written");
+
+        self.push_line(&format!("size_t {at} = 0;"));
+        self.push_line(&format!("for (size_t _d = 0; _d < {rank}; _d += 1) {{"));
+        self.indent += 1;
+        self.push_line(&format!("size_t _i = (size_t)(({iv_elem} *){q}.data)[{iv_name}_flat * {rank} + _d];"));
+        self.push_line("#ifndef NDEBUG");
+        self.push_line(&format!("if (_i >= {shp}[_d]) {{"));
+        self.indent += 1;
+        self.emit_raise(&c_error("IMP_ERR_TARGET_INDEX", "IMP_RESULT", "_d", &format!("{shp}[_d]"), "_i"));
+        self.indent -= 1;
+        self.push_line("}");
+        self.push_line("#endif");
+        self.push_line(&format!("{at} = {at} * {shp}[_d] + _i;"));
+        self.indent -= 1;
+        self.push_line("}");
+        self.push_line("#ifndef NDEBUG");
+        self.push_line(&format!("if ({written}[{at}]) {{"));
+        self.indent += 1;
+        self.emit_raise(&c_error("IMP_ERR_PERMUTATION", "IMP_RESULT", "0", "0", &at));
+        self.indent -= 1;
+        self.push_line("}");
+        self.push_line(&format!("{written}[{at}] = 1;"));
+        self.push_line("#endif");
+        at
+    }
+
+    /// Count how often the partitions of a tensor select each element, saturating at two,
+    /// such that [`Self::check_coverage`] can verify that every element is selected exactly once.
+    fn emit_coverage_counts(&mut self, target_name: &str, len: &str) {
+        self.push_line(&format!("unsigned char *{target_name}_hits = (unsigned char *)calloc({len}, 1);"));
+        self.acquire(format!("free({target_name}_hits);"));
+    }
+
+    fn count_coverage(&mut self, target_name: &str, flat: &str) {
+        let hits = format!("{target_name}_hits");
+        self.push_line(&format!("if ({hits}[{flat}] < 2) {hits}[{flat}] += 1;"));
+    }
+
+    fn check_coverage(&mut self, target_name: &str, len: &str) {
+        let hits = format!("{target_name}_hits");
+        self.push_line(&format!("for (size_t _i = 0; _i < {len}; _i += 1) {{"));
+        self.indent += 1;
+        self.push_line(&format!("if ({hits}[_i] != 1) {{"));
+        self.indent += 1;
+        let kind = format!("{hits}[_i] ? IMP_ERR_OVERLAP : IMP_ERR_GAP");
+        self.emit_raise(&c_error(&kind, "IMP_RESULT", "0", "1", "_i"));
+        self.indent -= 1;
+        self.push_line("}");
+        self.indent -= 1;
+        self.push_line("}");
+        let free = format!("free({hits});");
+        self.release(&free);
+        self.push_line(&free);
+    }
+
+    /// The value returned by a conditional branch.
+    /// Arrays that the branch did not allocate itself are cloned, such that the result is always owned.
+    fn branch_value(&mut self, body: &Body<'_, TypedAst>) -> String {
+        let name = self.nameof(&body.ret);
+        let ty = self.id_type(&body.ret);
+        let owned = mm::owner(&body.ret).is_some_and(|lvis| body.defines(lvis));
+        if ty.is_array() && !ty.is_array_or_scalar() && !owned {
+            format!("imp_clone_array_raw({name}, sizeof({}))", base_ctype(&ty))
+        } else {
+            name
+        }
+    }
 }
 
 const HEADER: &str = r#"
@@ -222,6 +785,27 @@ static ImpArrayRaw imp_clone_array_raw(ImpArrayRaw src, size_t elem_size) {
     if (src.len > 0) { memcpy(data, src.data, src.len * elem_size); }
     return (ImpArrayRaw) { .len = src.len, .dim = src.dim, .shp = shp, .data = data };
 }
+
+static void imp_free_array_raw(ImpArrayRaw arr) {
+    free(arr.shp);
+    free(arr.data);
+}
+
+/* Every module defines the error slot, of which the linker keeps one */
+__attribute__((weak)) _Thread_local ImpErrorRaw imp_error;
+
+__attribute__((weak)) ImpErrorRaw imp_take_error(void) {
+    ImpErrorRaw error = imp_error;
+    imp_error = (ImpErrorRaw) { 0 };
+    return error;
+}
+
+/* Only the first error is kept, as later ones may be caused by it */
+static void imp_raise(ImpErrorRaw error) {
+    if (imp_error.kind == IMP_OK) {
+        imp_error = error;
+    }
+}
 "#;
 
 impl<'ast> Traverse<'ast> for CompileC {
@@ -235,32 +819,30 @@ impl<'ast> Traverse<'ast> for CompileC {
         self.output.push_str(&format!("#include \"{}.h\"\n", self.module_name));
         self.output.push_str(HEADER);
 
-        for (_name, overloads) in &program.overloads {
-            for (_sig, fundefs) in overloads {
-                for fundef in fundefs {
-                    self.output.push('\n');
-                    self.emit_function_prototype(fundef);
-                }
-            }
+        // Including the clones made by specialisation, which are not overloads
+        for fundef in program.fundefs.iter_mut() {
+            self.output.push('\n');
+            self.emit_function_prototype(fundef);
         }
 
         for (name, overloads) in &program.overloads {
             for (sig, fundefs) in overloads {
                 if overloads.len() > 1 || fundefs.len() > 1 {
                     self.output.push('\n');
-                    let first = fundefs[0];
-                    self.emit_wrapper_prototype(&name, sig, &first.ret_type.ty);
+                    self.emit_wrapper_prototype(&name, sig, fundefs);
                 }
             }
         }
 
-        for fundef in program.fundefs.iter_mut() {
+        // Imported functions are only declared, their definitions are linked from their own module
+        for fundef in program.fundefs.iter_mut().filter(|fundef| fundef.import.is_none()) {
             self.trav_fundef(fundef);
         }
 
         for (name, overloads) in &program.overloads {
             for (sig, fundefs) in overloads {
-                if overloads.len() > 1 || fundefs.len() > 1 {
+                // Instances of imported functions are generated by every module that calls them, and so are their wrappers
+                if (overloads.len() > 1 || fundefs.len() > 1) && (fundefs[0].import.is_none() || family_internal(fundefs)) {
                     self.output.push('\n');
                     self.emit_wrapper_function(&name, sig, fundefs);
                 }
@@ -272,13 +854,15 @@ impl<'ast> Traverse<'ast> for CompileC {
         self.arg_names = fundef.args.iter().map(|arg| arg.id.clone()).collect();
         self.arg_types = fundef.args.iter().map(|arg| arg.ty.clone()).collect();
         self.ret_type = Some(fundef.ret_type.clone());
+        self.shape_facts = fundef.shape_facts.clone();
+        self.cleanup.clear();
         let args: Vec<String> = fundef.args.iter()
             .map(|arg| format!("{} {}", full_ctype(&arg.ty), arg.id))
             .collect();
 
         self.push_line(&format!(
-            "{} IMP_{}({}) {{",
-            full_ctype(&fundef.ret_type), fundef.name, args.join(", ")
+            "{}{} IMP_{}({}) {{",
+            linkage(fundef.internal), full_ctype(&fundef.ret_type), fundef.name, args.join(", ")
         ));
 
         self.indent += 1;
@@ -311,6 +895,12 @@ impl<'ast> Traverse<'ast> for CompileC {
             let rhs = self.expr_stack.pop().expect("expression stack underflow");
             self.push_line(&format!("{} {} = {};", full_ctype(&ty), name, rhs));
         }
+        if matches!(assign.expr, Expr::Call(_)) {
+            self.emit_propagate();
+        }
+        if mm::owns(assign.lhs) {
+            self.acquire(format!("imp_free_array_raw({name});"));
+        }
 
         self.lhs_target = prev_lhs_target;
     }
@@ -320,11 +910,18 @@ impl<'ast> Traverse<'ast> for CompileC {
         self.push_line(&format!("printf(\"Hello, {}\\n\");", id));
     }
 
+    fn trav_free(&mut self, free: &mut Free<'ast, Self::Ast>) {
+        let id = self.nameof(&free.id);
+        let free = format!("imp_free_array_raw({id});");
+        self.release(&free);
+        self.push_line(&free);
+    }
+
     fn trav_cond(&mut self, cond: &mut Cond<'ast, Self::Ast>) {
         if cond.then_branch.stmts.is_empty() && cond.else_branch.stmts.is_empty() {
             let c = self.nameof(&cond.cond);
-            let t = self.nameof(&cond.then_branch.ret);
-            let f = self.nameof(&cond.else_branch.ret);
+            let t = self.branch_value(&cond.then_branch);
+            let f = self.branch_value(&cond.else_branch);
             self.expr_stack.push(format!("{} ? {} : {}", c, t, f));
         } else {
             self.push_line(&format!("{} cond_ret;", full_ctype(&self.id_type(&cond.then_branch.ret))));
@@ -333,20 +930,24 @@ impl<'ast> Traverse<'ast> for CompileC {
             self.push_line(&format!("if ({}) {{", c));
             self.indent += 1;
 
+            let scope = self.cleanup.len();
             for stmt in &mut cond.then_branch.stmts {
                 self.trav_stmt(stmt);
             }
-            let t = self.nameof(&cond.then_branch.ret);
+            self.cleanup.truncate(scope);
+            let t = self.branch_value(&cond.then_branch);
             self.push_line(&format!("cond_ret = {};", t));
 
             self.indent -= 1;
             self.push_line("} else {");
             self.indent += 1;
 
+            let scope = self.cleanup.len();
             for stmt in &mut cond.else_branch.stmts {
                 self.trav_stmt(stmt);
             }
-            let f = self.nameof(&cond.else_branch.ret);
+            self.cleanup.truncate(scope);
+            let f = self.branch_value(&cond.else_branch);
             self.push_line(&format!("cond_ret = {};", f));
 
             self.indent -= 1;
@@ -359,116 +960,109 @@ impl<'ast> Traverse<'ast> for CompileC {
     fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, Self::Ast>) {
         let (target_name, target_ty) = self.lhs_target.clone().expect("tensor target must be set");
         let base = base_ctype(&target_ty);
-        let iv_name = tensor.iv.name.clone();
+        let partitioned = tensor.single().is_none();
 
-        let rank = tensor.iv.ty.rank()
-            .expect("tensor iv must have a statically-known rank for C codegen") as usize;
+        let uids: Vec<usize> = tensor.partitions.iter()
+            .map(|_| {
+                self.tensor_uid += 1;
+                self.tensor_uid
+            })
+            .collect();
 
-        self.tensor_uid += 1;
-        let t_uid = self.tensor_uid;
+        let Some(rank) = iv_len(&tensor.partitions[0].iv.ty) else {
+            return self.emit_dynamic_tensor(tensor, &target_name, &base, &uids);
+        };
 
-        // Extract scalar lower/upper bound per dimension.
-        for d in 0..rank {
-            if let Some(lb) = &tensor.lb {
-                let lb_name = self.nameof(lb);
-                self.push_line(&format!("size_t {iv_name}_lb{d}_{t_uid} = ((size_t *){lb_name}.data)[{d}];"));
-            }
-            let ub_name = self.nameof(&tensor.ub);
-            self.push_line(&format!("size_t {iv_name}_ub{d}_{t_uid} = ((size_t *){ub_name}.data)[{d}];"));
+        for (partition, &t_uid) in tensor.partitions.iter().zip(&uids) {
+            self.emit_static_bounds(partition, t_uid, rank);
         }
 
-        // Total element count in the result (product of extents).
+        // The result takes the full extent of the upper bounds, unselected elements are zeros.
         let len_name  = format!("{target_name}_len");
         let data_name = format!("{target_name}_data");
         let shp_name  = format!("{target_name}_shp");
         let extents: Vec<String> = (0..rank)
             .map(|d| {
-                if tensor.lb.is_some() {
-                    format!("({iv_name}_ub{d}_{t_uid} - {iv_name}_lb{d}_{t_uid})")
-                } else {
-                    format!("{iv_name}_ub{d}_{t_uid}")
+                let ubs: Vec<String> = tensor.partitions.iter().zip(&uids)
+                    .map(|(partition, t_uid)| format!("{}_ub{d}_{t_uid}", partition.iv.name))
+                    .collect();
+                if !partitioned {
+                    return ubs[0].clone();
+                }
+                let extent = format!("{target_name}_ub{d}");
+                self.push_line(&format!("size_t {extent} = {};", ubs[0]));
+                for ub in &ubs[1..] {
+                    self.push_line(&format!("if ({ub} > {extent}) {extent} = {ub};"));
                 }
+                extent
             })
             .collect();
         let total_len = if extents.is_empty() { "1".to_owned() } else { extents.join(" * ") };
         self.push_line(&format!("size_t {len_name} = {total_len};"));
-        self.push_line(&format!("{base} *{data_name} = ({base} *)malloc({len_name} * sizeof({base}));"));
+        self.push_line(&format!("{base} *{data_name} = {};", alloc_elems(&base, &len_name, tensor.has_default())));
+        self.acquire(format!("free({data_name});"));
+        if partitioned {
+            self.emit_coverage_counts(&target_name, &len_name);
+        }
 
         // Heap-allocate the result shape array.
         self.push_line(&format!("size_t *{shp_name} = (size_t *)malloc({rank} * sizeof(size_t));"));
-        for d in 0..rank {
-            if tensor.lb.is_some() {
-                self.push_line(&format!("{shp_name}[{d}] = {iv_name}_ub{d}_{t_uid} - {iv_name}_lb{d}_{t_uid};"));
-            } else {
-                self.push_line(&format!("{shp_name}[{d}] = {iv_name}_ub{d}_{t_uid};"));
-            }
+        self.acquire(format!("free({shp_name});"));
+        for (d, extent) in extents.iter().enumerate() {
+            self.push_line(&format!("{shp_name}[{d}] = {extent};"));
         }
-
-        // Generate k nested for-loops.
-        for d in 0..rank {
-            if tensor.lb.is_some() {
-                self.push_line(&format!("for (size_t {iv_name}_{d}_{t_uid} = {iv_name}_lb{d}_{t_uid}; {iv_name}_{d}_{t_uid} < {iv_name}_ub{d}_{t_uid}; {iv_name}_{d}_{t_uid} += 1) {{"));
-            } else {
-                self.push_line(&format!("for (size_t {iv_name}_{d}_{t_uid} = 0; {iv_name}_{d}_{t_uid} < {iv_name}_ub{d}_{t_uid}; {iv_name}_{d}_{t_uid} += 1) {{"));
-            }
-            self.indent += 1;
+        let target = tensor.target;
+        if let Some(target) = &target {
+            self.open_target_check(target, &target_name, &len_name, &rank.to_string());
         }
 
-        // Build iv as a stack-allocated ImpArrayRaw so that iv[i] selections work.
-        let iv_elem = base_ctype(&tensor.iv.ty);
-        let iv_components: Vec<String> = (0..rank)
-            .map(|d| format!("({iv_elem}){iv_name}_{d}_{t_uid}"))
-            .collect();
-        self.push_line(&format!(
-            "{iv_elem} {iv_name}_data_{t_uid}[{rank}] = {{ {} }};",
-            iv_components.join(", ")
-        ));
-        self.push_line(&format!("size_t {iv_name}_shp_arr_{t_uid}[1] = {{ {rank} }};"));
-        self.push_line(&format!(
-            "ImpArrayRaw {iv_name} = (ImpArrayRaw) {{ .len = {rank}, .shp = {iv_name}_shp_arr_{t_uid}, .dim = 1, .data = (void *){iv_name}_data_{t_uid} }};"
-        ));
-
-        // Row-major flat index: Σ (iv_d - lb_d) * stride_d
-        let flat_terms: Vec<String> = (0..rank).map(|d| {
-            let stride: Vec<String> = (d + 1..rank)
-                .map(|j| {
-                    if tensor.lb.is_some() {
-                        format!("({iv_name}_ub{j}_{t_uid} - {iv_name}_lb{j}_{t_uid})")
-                    } else {
-                        format!("{iv_name}_ub{j}_{t_uid}")
-                    }
-                })
-                .collect();
-
-            let stride_expr = if stride.is_empty() { "1".to_owned() } else { stride.join(" * ") };
+        // One loop nest per partition
+        for (partition, &t_uid) in tensor.partitions.iter_mut().zip(&uids) {
+            let iv_name = partition.iv.name.clone();
+            let scope = self.cleanup.len();
+            self.open_static_loops(partition, t_uid, rank);
 
-            if tensor.lb.is_some() {
-                format!("({iv_name}_{d}_{t_uid} - {iv_name}_lb{d}_{t_uid}) * {stride_expr}")
-            } else {
+            // Row-major flat index: Σ iv_d * stride_d
+            let flat_terms: Vec<String> = (0..rank).map(|d| {
+                let stride = &extents[d + 1..];
+                let stride_expr = if stride.is_empty() { "1".to_owned() } else { stride.join(" * ") };
                 format!("{iv_name}_{d}_{t_uid} * {stride_expr}")
+            }).collect();
+            let flat_expr = if flat_terms.is_empty() { "0".to_owned() } else { flat_terms.join(" + ") };
+            self.push_line(&format!("size_t {iv_name}_flat = {flat_expr};"));
+
+            // Body statements.
+            for stmt in &mut partition.body.stmts {
+                self.trav_stmt(stmt);
             }
-        }).collect();
-        let flat_expr = if flat_terms.is_empty() { "0".to_owned() } else { flat_terms.join(" + ") };
-        self.push_line(&format!("size_t {iv_name}_flat = {flat_expr};"));
 
-        // Body statements.
-        for stmt in &mut tensor.body.stmts {
-            self.trav_stmt(stmt);
+            // Store element into the flat result buffer.
+            let mut ret = self.render_id(partition.body.ret);
+            if rank == 1 && ret == iv_name {
+                let iv_elem = base_ctype(&partition.iv.ty);
+                ret = format!("(({iv_elem}*){iv_name}.data)[0]");
+            }
+            if partitioned {
+                self.count_coverage(&target_name, &format!("{iv_name}_flat"));
+            }
+            let at = match &target {
+                Some(target) => self.emit_target_index(target, partition, &target_name, &rank.to_string(), &shp_name),
+                None => format!("{iv_name}_flat"),
+            };
+            self.push_line(&format!("{data_name}[{at}] = {ret};"));
+
+            self.cleanup.truncate(scope);
+            self.close_static_loops(rank);
         }
 
-        // Store element into the flat result buffer.
-        let mut ret = self.render_id(tensor.body.ret);
-        if rank == 1 && ret == iv_name {
-            ret = format!("(({iv_elem}*){iv_name}.data)[0]");
+        if partitioned {
+            self.check_coverage(&target_name, &len_name);
         }
-        self.push_line(&format!("{data_name}[{iv_name}_flat] = {ret};"));
-
-        // Close nested loops.
-        for _ in 0..rank {
-            self.indent -= 1;
-            self.push_line("}");
+        if target.is_some() {
+            self.close_target_check(&target_name);
         }
-
+        self.release(&format!("free({shp_name});"));
+        self.release(&format!("free({data_name});"));
         self.push_line(&format!(
             "ImpArrayRaw {target_name} = (ImpArrayRaw) {{ .len = {len_name}, .shp = {shp_name}, .dim = {rank}, .data = (void *){data_name} }};"
         ));
@@ -482,47 +1076,31 @@ impl<'ast> Traverse<'ast> for CompileC {
             (format!("_fold_{}", self.tensor_uid), self.id_type(&fold.neutral), true)
         };
 
-        let iv_name = fold.selection.iv.name.clone();
-        let rank = fold.selection.iv.ty.rank()
-            .expect("fold selection iv must have a statically-known rank for C codegen") as usize;
+        let rank = iv_len(&fold.selection.iv.ty);
 
         self.tensor_uid += 1;
         let t_uid = self.tensor_uid;
 
+        // An array accumulator is replaced every iteration, so it must not alias the neutral element
+        let owned_acc = target_ty.is_array() && !target_ty.is_array_or_scalar();
         let neutral_expr = self.render_id(fold.neutral);
-        self.push_line(&format!("{} {} = {};", full_ctype(&target_ty), target_name, neutral_expr));
-
-        for d in 0..rank {
-            if let Some(lb) = &fold.selection.lb {
-                let lb_name = self.nameof(lb);
-                self.push_line(&format!("size_t {iv_name}_lb{d}_{t_uid} = ((size_t *){lb_name}.data)[{d}];"));
-            }
-            let ub_name = self.nameof(&fold.selection.ub);
-            self.push_line(&format!("size_t {iv_name}_ub{d}_{t_uid} = ((size_t *){ub_name}.data)[{d}];"));
+        if owned_acc {
+            self.push_line(&format!("{} {} = imp_clone_array_raw({}, sizeof({}));",
+                full_ctype(&target_ty), target_name, neutral_expr, base_ctype(&target_ty)));
+            self.acquire(format!("imp_free_array_raw({target_name});"));
+        } else {
+            self.push_line(&format!("{} {} = {};", full_ctype(&target_ty), target_name, neutral_expr));
         }
 
-        for d in 0..rank {
-            if fold.selection.lb.is_some() {
-                self.push_line(&format!("for (size_t {iv_name}_{d}_{t_uid} = {iv_name}_lb{d}_{t_uid}; {iv_name}_{d}_{t_uid} < {iv_name}_ub{d}_{t_uid}; {iv_name}_{d}_{t_uid} += 1) {{"));
-            } else {
-                self.push_line(&format!("for (size_t {iv_name}_{d}_{t_uid} = 0; {iv_name}_{d}_{t_uid} < {iv_name}_ub{d}_{t_uid}; {iv_name}_{d}_{t_uid} += 1) {{"));
-            }
-            self.indent += 1;
+        let scope = self.cleanup.len();
+        if let Some(rank) = rank {
+            self.emit_static_bounds(&fold.selection, t_uid, rank);
+            self.open_static_loops(&fold.selection, t_uid, rank);
+        } else {
+            self.emit_odometer_bounds(&fold.selection, t_uid);
+            self.open_odometer(&fold.selection, t_uid);
         }
 
-        let iv_elem = base_ctype(&fold.selection.iv.ty);
-        let iv_components: Vec<String> = (0..rank)
-            .map(|d| format!("({iv_elem}){iv_name}_{d}_{t_uid}"))
-            .collect();
-        self.push_line(&format!(
-            "{iv_elem} {iv_name}_data_{t_uid}[{rank}] = {{ {} }};",
-            iv_components.join(", ")
-        ));
-        self.push_line(&format!("size_t {iv_name}_shp_arr_{t_uid}[1] = {{ {rank} }};"));
-        self.push_line(&format!(
-            "ImpArrayRaw {iv_name} = (ImpArrayRaw) {{ .len = {rank}, .shp = {iv_name}_shp_arr_{t_uid}, .dim = 1, .data = (void *){iv_name}_data_{t_uid} }};"
-        ));
-
         for stmt in &mut fold.selection.body.stmts {
             self.trav_stmt(stmt);
         }
@@ -561,11 +1139,33 @@ impl<'ast> Traverse<'ast> for CompileC {
             }
         };
 
-        self.push_line(&format!("{} = IMP_{}({});", target_name, fold_name, call_args.join(", ")));
+        if owned_acc {
+            self.push_line(&format!("{} {}_next = IMP_{}({});",
+                full_ctype(&target_ty), target_name, fold_name, call_args.join(", ")));
+            self.push_line(&format!("imp_free_array_raw({target_name});"));
+            self.push_line(&format!("{target_name} = {target_name}_next;"));
+        } else {
+            self.push_line(&format!("{} = IMP_{}({});", target_name, fold_name, call_args.join(", ")));
+        }
+        self.emit_propagate();
+
+        let sel = fold.selection.body.ret;
+        if mm::owner(&sel).is_some_and(|lvis| fold.selection.body.defines(lvis)) {
+            let sel_name = self.nameof(&sel);
+            let free = format!("imp_free_array_raw({sel_name});");
+            self.release(&free);
+            self.push_line(&free);
+        }
 
-        for _ in 0..rank {
-            self.indent -= 1;
-            self.push_line("}");
+        self.cleanup.truncate(scope);
+        if let Some(rank) = rank {
+            self.close_static_loops(rank);
+        } else {
+            self.close_odometer(&fold.selection, t_uid);
+        }
+        // The accumulator is owned by the variable it is assigned to from here on
+        if owned_acc {
+            self.release(&format!("imp_free_array_raw({target_name});"));
         }
 
         if push_result {
@@ -629,9 +1229,23 @@ impl<'ast> Traverse<'ast> for CompileC {
             SelVxA(idx, arr) => {
                 let arr_name = self.render_id(*arr);
                 let idx_name = self.render_id(*idx);
-                let elem_base = elem_ctype_of_id(arr);
+                let elem_base = base_ctype(&self.id_type(arr));
                 format!("(({elem_base} *){arr_name}.data)[imp_flat_index({arr_name}, {idx_name})]")
             }
+            // Views share the buffer of the vector, and their shape lives until the end of the enclosing block
+            TakeSxV(n, vec) => {
+                let n = self.render_id(*n);
+                let vec = self.render_id(*vec);
+                format!("(ImpArrayRaw) {{ .len = {n}, .shp = (size_t[]) {{ {n} }}, .dim = 1, .data = {vec}.data }}")
+            }
+            DropSxV(n, vec) => {
+                let n = self.render_id(*n);
+                let vec_name = self.render_id(*vec);
+                let elem_base = base_ctype(&self.id_type(vec));
+                format!(
+                    "(ImpArrayRaw) {{ .len = {vec_name}.len - {n}, .shp = (size_t[]) {{ {vec_name}.len - {n} }}, .dim = 1, .data = (void *)(({elem_base} *){vec_name}.data + {n}) }}",
+                )
+            }
             AddSxS(a, b) => format!("{} + {}", self.render_id(*a), self.render_id(*b)),
             SubSxS(a, b) => format!("{} - {}", self.render_id(*a), self.render_id(*b)),
             MulSxS(a, b) => format!("{} * {}", self.render_id(*a), self.render_id(*b)),
@@ -672,6 +1286,18 @@ impl<'ast> Traverse<'ast> for CompileC {
         ));
     }
 
+    fn trav_record(&mut self, record: &mut Record<'ast, Self::Ast>) {
+        let fields: Vec<String> = record.fields.iter()
+            .map(|(name, value)| format!(".{} = {}", name, self.render_id(*value)))
+            .collect();
+        self.expr_stack.push(format!("({}) {{ {} }}", record.name, fields.join(", ")));
+    }
+
+    fn trav_field(&mut self, field: &mut Field<'ast, Self::Ast>) {
+        let record = self.render_id(field.record);
+        self.expr_stack.push(format!("{}.{}", record, field.name));
+    }
+
     fn trav_id(&mut self, id: &mut Id<'ast, Self::Ast>) {
         match id {
             Id::Arg(i) => self.expr_stack.push(self.arg_names[*i].clone()),
@@ -695,6 +1321,26 @@ impl<'ast> Traverse<'ast> for CompileC {
     }
 }
 
+/// The number of axes a tensor iterates over, which is the length of its index vector.
+fn iv_len(ty: &Type) -> Option<usize> {
+    match &ty.shape {
+        TypePattern::Axes(axes) => match axes.as_slice() {
+            [AxisPattern::Dim(DimPattern::Known(n))] => Some(*n),
+            _ => None,
+        },
+        TypePattern::Scalar => None,
+    }
+}
+
+/// Allocate `len` elements of C type `base`, which are zeros if `zeroed`.
+fn alloc_elems(base: &str, len: &str, zeroed: bool) -> String {
+    if zeroed {
+        format!("({base} *)calloc({len}, sizeof({base}))")
+    } else {
+        format!("({base} *)malloc({len} * sizeof({base}))")
+    }
+}
+
 fn base_rstype(ty: &BaseType) -> String {
     use BaseType::*;
     match ty {
@@ -707,6 +1353,7 @@ fn base_rstype(ty: &BaseType) -> String {
         F32 => "f32".to_owned(),
         F64 => "f64".to_owned(),
         Udf(udf) => udf.to_owned(),
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
     }
 }
 
@@ -722,23 +1369,13 @@ fn base_ctype(ty: &Type) -> String {
         F32 => "float".to_owned(),
         F64 => "double".to_owned(),
         Udf(udf) => udf.to_owned(),
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
     }
 }
 
 fn full_ctype(ty: &Type) -> String {
     if ty.is_array_or_scalar() {
-        use BaseType::*;
-        return match &ty.ty {
-            Bool => "ImpDynBool".to_owned(),
-            Usize => "ImpDynUsize".to_owned(),
-            U32 => "ImpDynU32".to_owned(),
-            U64 => "ImpDynU64".to_owned(),
-            I32 => "ImpDynI32".to_owned(),
-            I64 => "ImpDynI64".to_owned(),
-            F32 => "ImpDynF32".to_owned(),
-            F64 => "ImpDynF64".to_owned(),
-            Udf(udf) => format!("ImpDyn{}", udf),
-        }
+        dyn_ctype(&ty.ty)
     } else if ty.is_array() {
         "ImpArrayRaw".to_owned()
     } else {
@@ -746,11 +1383,24 @@ fn full_ctype(ty: &Type) -> String {
     }
 }
 
+/// The tagged union of a scalar and an array, which the overload dispatchers take and return.
+///
+/// Inside generated functions, a value whose rank is not known is an `ImpArrayRaw`
+/// that may have rank zero, so this is only used at the dispatch boundary.
 fn dyn_ctype(base: &BaseType) -> String {
-    full_ctype(&Type {
-        ty: base.clone(),
-        shape: TypePattern::any(),
-    })
+    use BaseType::*;
+    match base {
+        Bool => "ImpDynBool".to_owned(),
+        Usize => "ImpDynUsize".to_owned(),
+        U32 => "ImpDynU32".to_owned(),
+        U64 => "ImpDynU64".to_owned(),
+        I32 => "ImpDynI32".to_owned(),
+        I64 => "ImpDynI64".to_owned(),
+        F32 => "ImpDynF32".to_owned(),
+        F64 => "ImpDynF64".to_owned(),
+        Udf(udf) => format!("ImpDyn{}", udf),
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
+    }
 }
 
 fn shape_match_condition(shape: &TypePattern, arg: &str) -> String {
@@ -759,8 +1409,11 @@ fn shape_match_condition(shape: &TypePattern, arg: &str) -> String {
             format!("!{arg}.is_array")
         }
         TypePattern::Axes(axes) => {
-            if axes.iter().any(|ax| matches!(ax, AxisPattern::Rank(_))) {
-                return format!("{arg}.is_array");
+            if shape.has_rank_capture() {
+                return match shape.min_rank() {
+                    0 => format!("{arg}.is_array"),
+                    min => format!("{arg}.is_array && {arg}.data.array.dim >= {min}"),
+                };
             }
 
             let mut checks = vec![
@@ -777,6 +1430,82 @@ fn shape_match_condition(shape: &TypePattern, arg: &str) -> String {
     }
 }
 
+/// A shape symbol of the current function, in terms of its arguments.
+enum ShapeExpr {
+    /// An extent or a rank
+    Extent(String),
+    /// A shape vector of `rank` extents, starting at `start`
+    Shape { start: String, rank: String },
+}
+
+/// The extent of axis `i` of pattern `axes` in the array descriptor `arr`.
+///
+/// Axes before a rank capture are counted from the front, those after it from the back.
+fn axis_expr(arr: &str, axes: &[AxisPattern], i: usize) -> Option<String> {
+    axis_index(arr, axes, i).map(|axis| format!("{arr}.shp[{axis}]"))
+}
+
+/// The index in the array descriptor `arr` of axis `i` of pattern `axes`, see [`axis_expr`].
+fn axis_index(arr: &str, axes: &[AxisPattern], i: usize) -> Option<String> {
+    let is_rank = |axis: &AxisPattern| matches!(axis, AxisPattern::Rank(_));
+    match axes.iter().position(is_rank) {
+        Some(first) if i > first => {
+            (axes.iter().rposition(is_rank) == Some(first)).then(|| format!("{arr}.dim - {}", axes.len() - i))
+        }
+        _ => Some(i.to_string()),
+    }
+}
+
+/// The storage class of a function, which is `static` for functions that are not exported, see [`Fundef::internal`].
+fn linkage(internal: bool) -> &'static str {
+    if internal { "static " } else { "" }
+}
+
+/// Whether the dispatch wrapper of an overload family is not exported, as it calls an instance of an imported function.
+fn family_internal(family: &[&Fundef<'_, TypedAst>]) -> bool {
+    family.iter().any(|fundef| fundef.internal)
+}
+
+/// A C statement releasing a buffer, see [`CompileC::emit_cleanup`].
+#[derive(Clone)]
+struct Cleanup {
+    free: String,
+    /// Whether the buffer only exists in debug builds
+    debug_only: bool,
+}
+
+/// A runtime check of a returned array, see [`CompileC::emit_ret_shape_checks`].
+struct RetCheck {
+    /// The number of axes `_d` to check, if the condition depends on it
+    axes: Option<String>,
+    cond: String,
+    /// The error raised if the condition does not hold
+    error: String,
+}
+
+impl RetCheck {
+    fn new(cond: String, error: String) -> Self {
+        Self { axes: None, cond, error }
+    }
+}
+
+/// A C compound literal of an `ImpErrorRaw`, whose fields are described by `imp_core::ImpError`.
+fn c_error(kind: &str, arg: &str, axis: &str, expected: &str, found: &str) -> String {
+    format!("(ImpErrorRaw) {{ .kind = {kind}, .arg = {arg}, .axis = {axis}, .expected = {expected}, .found = {found} }}")
+}
+
+/// Whether both axes statically have the same extent, or match the same axes.
+fn same_axis(a: &AxisPattern, b: &AxisPattern) -> bool {
+    match (a, b) {
+        (AxisPattern::Dim(DimPattern::Known(a)), AxisPattern::Dim(DimPattern::Known(b))) => a == b,
+        (AxisPattern::Dim(DimPattern::Var(a)), AxisPattern::Dim(DimPattern::Var(b))) => !a.is_empty() && a == b,
+        (AxisPattern::Rank(a), AxisPattern::Rank(b)) => {
+            !a.dim_name.is_empty() && a.dim_name == b.dim_name && a.shp_name == b.shp_name
+        }
+        _ => false,
+    }
+}
+
 fn wrapper_call_arg(shape: &TypePattern, arg: &str) -> String {
     match shape {
         TypePattern::Scalar => format!("{arg}.data.scalar"),
@@ -784,11 +1513,3 @@ fn wrapper_call_arg(shape: &TypePattern, arg: &str) -> String {
     }
 }
 
-fn elem_ctype_of_id(id: &Id<'_, TypedAst>) -> String {
-    match id {
-        Id::Arg(_) => "uint32_t".to_owned(),  // args used directly as array bounds are uncommon
-        Id::Var(v) => {
-            base_ctype(&v.ty)
-        }
-    }
-}
@@ -24,6 +24,14 @@ impl CompileFfi {
     fn push(&mut self, s: &str) {
         self.output.push_str(s);
     }
+
+    fn push_lines(&mut self, s: &str, indent: usize) {
+        for line in s.lines() {
+            self.push(&"    ".repeat(indent));
+            self.push(line);
+            self.push("\n");
+        }
+    }
 }
 
 impl<'ast> Traverse<'ast> for CompileFfi {
@@ -38,26 +46,49 @@ impl<'ast> Traverse<'ast> for CompileFfi {
         self.push("use imp_core::*;\n");
         self.push("\n");
 
+        // Imported structs are defined by the bindings of their own module
+        for structdef in program.structs.iter().filter(|structdef| structdef.import.is_none()) {
+            self.push("#[repr(C)]\n");
+            self.push("#[allow(non_camel_case_types)]\n");
+            self.push("#[derive(Clone, Copy, Debug, PartialEq)]\n");
+            self.push(&format!("pub struct {} {{\n", structdef.name));
+            for field in &structdef.fields {
+                self.push(&format!("    pub {}: {},\n", field.id, rust_base_type(&field.ty.ty)));
+            }
+            self.push("}\n");
+            self.push("\n");
+        }
+
         self.push("unsafe extern \"C\" {\n");
         for (_name, overloads) in &program.overloads {
             for (_sig, fundefs) in overloads {
-                for fundef in fundefs {
+                for fundef in fundefs.iter().filter(|fundef| fundef.is_exported()) {
                     self.push(&format!("    fn IMP_{}(", fundef.name));
                     self.push(&join_args(&fundef.args, rust_ffi_type));
                     self.push(&format!(") -> {};\n", rust_ffi_type(&fundef.ret_type)));
                 }
             }
         }
+        self.push("    fn imp_take_error() -> ImpErrorRaw;\n");
         self.push("}\n");
 
+        // Imported functions are exposed by the bindings of their own module
         for (name, overloads) in &program.overloads {
-            for (sig, fundefs) in overloads {
-                self.push("\n");
-                if overloads.len() > 1 || fundefs.len() > 1 {
-                    self.emit_family_wrapper(&name, sig, fundefs);
-                } else {
-                    let fundef = fundefs[0];
-                    self.emit_direct_wrapper(&name, fundef);
+            let fundefs: Vec<&Fundef<'_, TypedAst>> = overloads.values()
+                .flatten()
+                .copied()
+                .filter(|fundef| fundef.is_exported())
+                .collect();
+
+            match fundefs[..] {
+                [] => {}
+                [fundef] => {
+                    self.push("\n");
+                    self.emit_direct_wrapper(name, fundef);
+                }
+                _ => {
+                    self.push("\n");
+                    self.emit_overloaded_wrapper(name, &fundefs);
                 }
             }
         }
@@ -66,9 +97,16 @@ impl<'ast> Traverse<'ast> for CompileFfi {
 
 impl CompileFfi {
     fn emit_direct_wrapper(&mut self, base_name: &str, fundef: &Fundef<'_, TypedAst>) {
-        self.push(&format!("fn {}(", base_name));
+        // The lifetime of the borrowed array arguments
+        let lifetime = if fundef.args.iter().any(|arg| is_static_array(&arg.ty)) { "<'a>" } else { "" };
+        self.push(&format!("fn {}{}(", base_name, lifetime));
         self.push(&join_args(&fundef.args, rust_api_arg_type));
-        self.push(&format!(") -> {} {{\n", rust_api_ret_type(&fundef.ret_type)));
+        self.push(&format!(") -> Result<{}, ImpError> {{\n", rust_api_ret_type(&fundef.ret_type)));
+
+        // Arrays are borrowed, as generated functions never modify their arguments
+        for arg in fundef.args.iter().filter(|arg| is_static_array(&arg.ty)) {
+            self.push(&format!("    let {}: ImpArrayView<{}> = {}.into();\n", arg.id, rust_base_type(&arg.ty.ty), arg.id));
+        }
 
         let shape_checks = generate_shape_checks(&fundef.args);
         if !shape_checks.is_empty() {
@@ -85,56 +123,128 @@ impl CompileFfi {
         self.push("}\n");
     }
 
-    fn emit_family_wrapper(&mut self, base_name: &str, sig: &BaseSignature, fundefs: &Vec<&Fundef<'_, TypedAst>>) {
-        let sig_str = sig.base_types.iter().map(rust_base_type).collect::<Vec<_>>();
-        let fargs = sig.base_types.iter()
-            .enumerate()
-            .map(|(i, base)| format!("arg{}: ImpArrayOrScalar<{}>", i, rust_base_type(base)))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        self.push(&format!("fn {}_{}(", base_name, sig_str.join("_")));
-        self.push(&fargs);
-        let first = fundefs[0];
-        self.push(&format!(") -> {} {{\n", rust_api_ret_type(&first.ret_type)));
-
-        let match_args = &(0..sig.base_types.len())
-            .map(|i| format!("arg{i}"))
-            .collect::<Vec<_>>()
-            .join(", ");
-        self.push("    match (");
-        self.push(&match_args);
-        self.push(") {\n");
-
-        for fundef in fundefs {
-            let pattern = fundef.args.iter()
-                .enumerate()
-                .map(|(i, arg)| family_match_pattern(i, &arg.ty))
-                .collect::<Vec<_>>()
-                .join(", ");
-            let guard = family_match_guard(&fundef.args);
-            self.push("        (");
-            self.push(&pattern);
-            self.push(")");
-
-            if !guard.is_empty() {
-                self.push(" if ");
-                self.push(&guard);
+    /// Overloads of a function are called through a single generic function, that takes the
+    /// arguments as a tuple and dispatches on its type through a trait, e.g.
+    ///
+    /// ```ignore
+    /// trait OverloadDemoArgs { type Out; fn call(self) -> Result<Self::Out, ImpError>; }
+    /// impl OverloadDemoArgs for (usize, usize) { ... }
+    /// impl OverloadDemoArgs for (ImpArrayView<'_, usize>, ImpArrayView<'_, usize>) { ... }
+    /// fn overload_demo<A: OverloadDemoArgs>(args: A) -> Result<A::Out, ImpError> { args.call() }
+    /// ```
+    ///
+    /// Overloads that only differ in their shapes have the same Rust types,
+    /// so their implementation selects one of them at runtime, or fails with `ImpError::Dispatch`.
+    fn emit_overloaded_wrapper(&mut self, base_name: &str, fundefs: &[&Fundef<'_, TypedAst>]) {
+        let trait_name = format!("{}Args", camel_case(base_name));
+        self.push(&format!("trait {} {{\n", trait_name));
+        self.push("    type Out;\n");
+        self.push("    fn call(self) -> Result<Self::Out, ImpError>;\n");
+        self.push("}\n\n");
+        self.push(&format!("fn {}<A: {}>(args: A) -> Result<A::Out, ImpError> {{\n", base_name, trait_name));
+        self.push(&format!("    {}::call(args)\n", trait_name));
+        self.push("}\n");
+
+        let mut impls: Vec<(Vec<String>, Vec<&Fundef<'_, TypedAst>>)> = Vec::new();
+        for &fundef in fundefs {
+            let arg_types = fundef.args.iter().map(|arg| rust_tuple_arg_type(&arg.ty)).collect::<Vec<_>>();
+            match impls.iter_mut().find(|(types, _)| *types == arg_types) {
+                Some((_, overloads)) => overloads.push(fundef),
+                None => impls.push((arg_types, vec![fundef])),
             }
-            self.push(" => {\n");
+        }
+
+        for (arg_types, overloads) in &impls {
+            self.push("\n");
+            self.emit_overload_impl(&trait_name, arg_types, overloads);
+            self.emit_borrowed_impls(&trait_name, arg_types, overloads[0]);
+        }
+    }
 
-            let branch_args = fundef.args.iter().enumerate().map(|(i, _)| format!("arg{i}")).collect::<Vec<_>>();
-            let marshaled = emit_marshaled_branch_args(&mut self.output, &fundef.args, &branch_args, 3);
-            let ret = emit_return_conversion(&fundef.name, &fundef.ret_type, &marshaled);
-            for line in ret.lines() {
-                self.push("            ");
+    /// As the wrappers of functions that are not overloaded, the arrays may be passed either as
+    /// `&ImpArray<T>` or as `ImpArrayView<T>`, for which these implementations take views of the former.
+    fn emit_borrowed_impls(&mut self, trait_name: &str, arg_types: &[String], fundef: &Fundef<'_, TypedAst>) {
+        let args = &fundef.args;
+        let arrays = args.iter()
+            .enumerate()
+            .filter(|(_, arg)| is_static_array(&arg.ty))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let names = (0..args.len()).map(|i| format!("arg{i}")).collect::<Vec<_>>();
+
+        // Every combination of borrowed arrays, except the one without any
+        for mask in 1..1usize << arrays.len() {
+            let borrowed = |i: &usize| arrays.iter().position(|a| a == i).is_some_and(|bit| mask & (1 << bit) != 0);
+            let types = (0..args.len())
+                .map(|i| if borrowed(&i) { format!("&ImpArray<{}>", rust_base_type(&args[i].ty.ty)) } else { arg_types[i].clone() })
+                .collect::<Vec<_>>();
+            let views = (0..args.len())
+                .map(|i| if borrowed(&i) { format!("{}.view()", names[i]) } else { names[i].clone() })
+                .collect::<Vec<_>>();
+
+            self.push("\n");
+            self.push(&format!("impl {} for {} {{\n", trait_name, tuple_of(&types)));
+            self.push(&format!("    type Out = {};\n\n", rust_api_ret_type(&fundef.ret_type)));
+            self.push("    fn call(self) -> Result<Self::Out, ImpError> {\n");
+            self.push(&format!("        let {} = self;\n", tuple_of(&names)));
+            self.push(&format!("        {}::call({})\n", trait_name, tuple_of(&views)));
+            self.push("    }\n");
+            self.push("}\n");
+        }
+    }
+
+    fn emit_overload_impl(&mut self, trait_name: &str, arg_types: &[String], overloads: &[&Fundef<'_, TypedAst>]) {
+        let first = overloads[0];
+        self.push(&format!("impl {} for {} {{\n", trait_name, tuple_of(arg_types)));
+        self.push(&format!("    type Out = {};\n\n", rust_api_ret_type(&first.ret_type)));
+        self.push("    fn call(self) -> Result<Self::Out, ImpError> {\n");
+
+        if let [fundef] = overloads {
+            let names = fundef.args.iter().map(|arg| arg.id.clone()).collect::<Vec<_>>();
+            self.push(&format!("        let {} = self;\n", tuple_of(&names)));
+
+            let mut body = generate_shape_checks(&fundef.args);
+            let call_args = emit_marshaled_call_args(&mut body, &fundef.args);
+            for line in body.lines() {
+                self.push("    ");
                 self.push(line);
                 self.push("\n");
             }
-            self.push("        }\n");
+            self.push_lines(&emit_return_conversion(&fundef.name, &fundef.ret_type, &call_args), 2);
+        } else {
+            let names = (0..arg_types.len()).map(|i| format!("arg{i}")).collect::<Vec<_>>();
+            self.push(&format!("        let {} = self;\n", tuple_of(&names)));
+
+            let mut exhaustive = false;
+            for (i, fundef) in overloads.iter().enumerate() {
+                let guard = family_match_guard(&fundef.args);
+                match (i, guard.is_empty()) {
+                    (0, true) => self.push("        {\n"),
+                    (0, false) => self.push(&format!("        if {} {{\n", guard)),
+                    (_, false) => self.push(&format!(" else if {} {{\n", guard)),
+                    (_, true) => self.push(" else {\n"),
+                }
+
+                let call_args = emit_marshaled_branch_args(&mut self.output, &fundef.args, &names, 3);
+                self.push_lines(&emit_return_conversion(&fundef.name, &fundef.ret_type, &call_args), 3);
+                self.push("        }");
+
+                // Later overloads are unreachable after one without a guard
+                if guard.is_empty() {
+                    exhaustive = true;
+                    break;
+                }
+            }
+
+            if exhaustive {
+                self.push("\n");
+            } else {
+                self.push(" else {\n");
+                self.push("            Err(ImpError::Dispatch)\n");
+                self.push("        }\n");
+            }
         }
 
-        self.push("        _ => panic!(\"runtime overload dispatch failed\"),\n");
         self.push("    }\n");
         self.push("}\n");
     }
@@ -164,13 +274,52 @@ fn rust_api_type(ty: &Type) -> String {
 fn rust_api_arg_type(ty: &Type) -> String {
     if ty.is_array_or_scalar() {
         format!("ImpArrayOrScalar<{}>", rust_base_type(&ty.ty))
+    } else if ty.is_array() {
+        format!("impl Into<ImpArrayView<'a, {}>>", rust_base_type(&ty.ty))
     } else {
         rust_api_type(ty)
     }
 }
 
+/// The type of an argument in the tuple that is passed to an overloaded function.
+fn rust_tuple_arg_type(ty: &Type) -> String {
+    if ty.is_array_or_scalar() {
+        format!("ImpArrayOrScalar<{}>", rust_base_type(&ty.ty))
+    } else if ty.is_array() {
+        format!("ImpArrayView<'_, {}>", rust_base_type(&ty.ty))
+    } else {
+        rust_api_type(ty)
+    }
+}
+
+/// A tuple of the given types or patterns, where a single one is not wrapped.
+fn tuple_of(items: &[String]) -> String {
+    match items {
+        [item] => item.clone(),
+        _ => format!("({})", items.join(", ")),
+    }
+}
+
+/// The name of a trait derived from a function name, e.g. `OverloadDemo` for `overload_demo`.
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Only a rank-polymorphic result may be either an array or a scalar.
 fn rust_api_ret_type(ty: &Type) -> String {
-    format!("ImpArrayOrScalar<{}>", rust_base_type(&ty.ty))
+    if ty.is_array_or_scalar() || ty.shape.has_rank_capture() {
+        format!("ImpArrayOrScalar<{}>", rust_base_type(&ty.ty))
+    } else {
+        rust_api_type(ty)
+    }
 }
 
 fn rust_ffi_type(ty: &Type) -> String {
@@ -195,6 +344,7 @@ fn rust_base_type(ty: &BaseType) -> String {
         F32 => "f32".to_owned(),
         F64 => "f64".to_owned(),
         Udf(udf) => udf.to_owned(),
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
     }
 }
 
@@ -202,13 +352,13 @@ fn emit_marshaled_call_args(out: &mut String, args: &[Farg]) -> Vec<String> {
     let mut call_args = Vec::with_capacity(args.len());
     for arg in args {
         if is_static_array(&arg.ty) {
-            out.push_str(&format!("    let {}_raw = {}.into_raw();\n", arg.id, arg.id));
+            out.push_str(&format!("    let {}_raw = {}.as_raw();\n", arg.id, arg.id));
             call_args.push(format!("{}_raw", arg.id));
         } else if arg.ty.is_array_or_scalar() {
-            out.push_str(&format!("    let mut {}_dyn = {};\n", arg.id, arg.id));
-            out.push_str(&format!("    let {}_ffi = match &mut {}_dyn {{\n", arg.id, arg.id));
+            out.push_str(&format!("    let {}_dyn = {};\n", arg.id, arg.id));
+            out.push_str(&format!("    let {}_ffi = match &{}_dyn {{\n", arg.id, arg.id));
             out.push_str("        ImpArrayOrScalar::Scalar(v) => ImpDyn::from_scalar(*v),\n");
-            out.push_str("        ImpArrayOrScalar::Array(a) => ImpDyn::from_array_raw(a.into_raw()),\n");
+            out.push_str("        ImpArrayOrScalar::Array(a) => ImpDyn::from_array_raw(a.as_raw()),\n");
             out.push_str("    };\n");
             call_args.push(format!("{}_ffi", arg.id));
         } else {
@@ -223,13 +373,13 @@ fn emit_marshaled_branch_args(out: &mut String, args: &[Farg], branch_names: &[S
     let mut call_args = Vec::with_capacity(args.len());
     for (arg, branch_name) in args.iter().zip(branch_names.iter()) {
         if is_static_array(&arg.ty) {
-            out.push_str(&format!("{pad}let {}_raw = {}.into_raw();\n", branch_name, branch_name));
+            out.push_str(&format!("{pad}let {}_raw = {}.as_raw();\n", branch_name, branch_name));
             call_args.push(format!("{}_raw", branch_name));
         } else if arg.ty.is_array_or_scalar() {
-            out.push_str(&format!("{pad}let mut {}_dyn = {};\n", branch_name, branch_name));
-            out.push_str(&format!("{pad}let {}_ffi = match &mut {}_dyn {{\n", branch_name, branch_name));
+            out.push_str(&format!("{pad}let {}_dyn = {};\n", branch_name, branch_name));
+            out.push_str(&format!("{pad}let {}_ffi = match &{}_dyn {{\n", branch_name, branch_name));
             out.push_str(&format!("{pad}    ImpArrayOrScalar::Scalar(v) => ImpDyn::from_scalar(*v),\n"));
-            out.push_str(&format!("{pad}    ImpArrayOrScalar::Array(a) => ImpDyn::from_array_raw(a.into_raw()),\n"));
+            out.push_str(&format!("{pad}    ImpArrayOrScalar::Array(a) => ImpDyn::from_array_raw(a.as_raw()),\n"));
             out.push_str(&format!("{pad}}};\n"));
             call_args.push(format!("{}_ffi", branch_name));
         } else {
@@ -239,62 +389,58 @@ fn emit_marshaled_branch_args(out: &mut String, args: &[Farg], branch_names: &[S
     call_args
 }
 
+/// The call of a generated function, which fails with the runtime error that it raised, if any.
 fn emit_return_conversion(symbol_name: &str, ret_type: &Type, call_args: &[String]) -> String {
+    let mut out = format!("let res0 = unsafe {{ IMP_{}({}) }};\n", symbol_name, call_args.join(", "));
+    out.push_str("unsafe { imp_take_error() }.check()?;\n");
+
     if ret_type.is_array_or_scalar() {
-        format!("let res0_dyn = unsafe {{ IMP_{}({}) }};\nunsafe {{ res0_dyn.into_array_or_scalar() }}",
-            symbol_name, call_args.join(", ") )
+        out.push_str("Ok(unsafe { res0.into_array_or_scalar() })");
+    } else if ret_type.shape.has_rank_capture() {
+        out.push_str(&format!("Ok(ImpArrayOrScalar::from(unsafe {{ ImpArray::<{}>::from_raw(res0) }}))", rust_base_type(&ret_type.ty)));
     } else if is_static_array(ret_type) {
-        format!("let res0_raw = unsafe {{ IMP_{}({}) }};\nImpArrayOrScalar::Array(unsafe {{ ImpArray::<{}>::from_raw(res0_raw) }})",
-            symbol_name, call_args.join(", "), rust_base_type(&ret_type.ty)
-        )
+        out.push_str(&format!("Ok(unsafe {{ ImpArray::<{}>::from_raw(res0) }})", rust_base_type(&ret_type.ty)));
     } else {
-        format!("ImpArrayOrScalar::Scalar(unsafe {{ IMP_{}({}) }})",
-            symbol_name, call_args.join(", "))
-    }
-}
-
-fn family_match_pattern(arg_index: usize, ty: &Type) -> String {
-    match ty.shape {
-        TypePattern::Scalar => format!("ImpArrayOrScalar::Scalar(arg{arg_index})"),
-        _ => format!("ImpArrayOrScalar::Array(arg{arg_index})"),
+        out.push_str("Ok(res0)");
     }
+    out
 }
 
 fn family_match_guard(args: &[Farg]) -> String {
     let mut checks = Vec::new();
-    let mut bound_dims: Vec<(String, String)> = Vec::new();
-    let mut bound_ranks: Vec<(String, String)> = Vec::new();
+    // Extents and ranks share one namespace, as in `sel(usize[n] idx, usize[n:shp] arr)`
+    let mut bound: Vec<(String, String)> = Vec::new();
 
     for (arg_index, arg) in args.iter().enumerate() {
         let TypePattern::Axes(axes) = &arg.ty.shape else {
             continue;
         };
+        let arr = format!("arg{arg_index}");
 
-        if !axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))) {
-            checks.push(format!("arg{arg_index}.shp.len() == {}", axes.len()));
+        if !arg.ty.shape.has_rank_capture() {
+            checks.push(format!("{arr}.shp.len() == {}", axes.len()));
+        } else if arg.ty.shape.min_rank() > 0 {
+            checks.push(format!("{arr}.shp.len() >= {}", arg.ty.shape.min_rank()));
         }
 
         for (axis_index, axis) in axes.iter().enumerate() {
-            match axis {
+            let (name, expr) = match axis {
                 AxisPattern::Dim(DimPattern::Known(v)) => {
-                    checks.push(format!("arg{arg_index}.shp[{axis_index}] == {v}"));
-                }
-                AxisPattern::Dim(DimPattern::Var(extent)) => {
-                    let expr = format!("arg{arg_index}.shp[{axis_index}]");
-                    if let Some((_, bound_expr)) = bound_dims.iter().find(|(name, _)| name == extent) {
-                        checks.push(format!("{expr} == {bound_expr}"));
-                    } else {
-                        bound_dims.push((extent.clone(), expr));
-                    }
-                }
-                AxisPattern::Rank(capture) => {
-                    let expr = format!("arg{arg_index}.shp.len()");
-                    if let Some((_, bound_expr)) = bound_ranks.iter().find(|(name, _)| name == &capture.dim_name) {
-                        checks.push(format!("{expr} == {bound_expr}"));
-                    } else {
-                        bound_ranks.push((capture.dim_name.clone(), expr));
+                    if let Some(expr) = rust_axis_expr(&arr, axes, axis_index) {
+                        checks.push(format!("{expr} == {v}"));
                     }
+                    continue;
                 }
+                AxisPattern::Dim(DimPattern::Var(extent)) => (extent, rust_axis_expr(&arr, axes, axis_index)),
+                AxisPattern::Rank(capture) => (&capture.dim_name, rust_captured_rank(&arr, axes)),
+            };
+            let Some(expr) = expr.filter(|_| !name.is_empty()) else {
+                continue;
+            };
+            if let Some((_, bound_expr)) = bound.iter().find(|(bound_name, _)| bound_name == name) {
+                checks.push(format!("{expr} == {bound_expr}"));
+            } else {
+                bound.push((name.clone(), expr));
             }
         }
     }
@@ -302,55 +448,80 @@ fn family_match_guard(args: &[Farg]) -> String {
     checks.join(" && ")
 }
 
+/// Reject arguments that do not match the shapes of their type patterns, before they are passed to C.
 fn generate_shape_checks(args: &[Farg]) -> String {
     let mut out = String::new();
-    let mut bound_dims: Vec<String> = Vec::new();
-    let mut bound_ranks: Vec<String> = Vec::new();
+    // Extents and ranks share one namespace, as in `sel(usize[n] idx, usize[n:shp] arr)`
+    let mut bound: Vec<String> = Vec::new();
 
-    for arg in args {
+    let fail_if = |out: &mut String, cond: String, error: String| {
+        out.push_str(&format!("    if {} {{\n", cond));
+        out.push_str(&format!("        return Err(ImpError::{});\n", error));
+        out.push_str("    }\n");
+    };
+
+    for (i, arg) in args.iter().enumerate() {
         let TypePattern::Axes(axes) = &arg.ty.shape else {
             continue;
         };
-
-        if !axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))) {
-            out.push_str(&format!(
-                "    assert_eq!({}.shp.len(), {}, \"{} rank mismatch\");\n",
-                arg.id,
-                axes.len(),
-                arg.id,
-            ));
+        let operand = format!("operand: ImpOperand::Arg({})", i);
+        let rank = format!("{}.shp.len()", arg.id);
+
+        if !arg.ty.shape.has_rank_capture() {
+            fail_if(&mut out,
+                format!("{} != {}", rank, axes.len()),
+                format!("Rank {{ {}, expected: {}, found: {} }}", operand, axes.len(), rank));
+        } else if arg.ty.shape.min_rank() > 0 {
+            let too_small = match arg.ty.shape.min_rank() {
+                1 => format!("{}.shp.is_empty()", arg.id),
+                min_rank => format!("{} < {}", rank, min_rank),
+            };
+            fail_if(&mut out,
+                too_small,
+                format!("MinRank {{ {}, expected: {}, found: {} }}", operand, arg.ty.shape.min_rank(), rank));
         }
 
         for (idx, axis) in axes.iter().enumerate() {
             match axis {
-                AxisPattern::Dim(DimPattern::Known(v)) => {
-                    out.push_str(&format!(
-                        "    assert_eq!({}.shp[{}], {}, \"{} extent mismatch at axis {}\");\n",
-                        arg.id,
-                        idx,
-                        v,
-                        arg.id,
-                        idx,
-                    ));
-                }
-                AxisPattern::Dim(DimPattern::Var(extent)) => {
-                    let binding = format!("_imp_extent_{}", extent);
-                    if bound_dims.iter().any(|existing| existing == &binding) {
-                        out.push_str(&format!("    assert_eq!({}.shp[{}], {}, \"extent {} mismatch\");\n",
-                            arg.id, idx, binding, extent));
-                    } else {
-                        out.push_str(&format!("    let {} = {}.shp[{}];\n", binding, arg.id, idx));
-                        bound_dims.push(binding);
+                AxisPattern::Dim(dim) => {
+                    let Some(axis_index) = rust_axis_index(&arg.id, axes, idx) else {
+                        continue;
+                    };
+                    let extent = format!("{}.shp[{}]", arg.id, axis_index);
+                    let mismatch = |expected: &str| format!("Extent {{ {}, axis: {}, expected: {}, found: {} }}", operand, axis_index, expected, extent);
+                    match dim {
+                        DimPattern::Known(v) => {
+                            fail_if(&mut out, format!("{} != {}", extent, v), mismatch(&v.to_string()));
+                        }
+                        DimPattern::Var(var) if var.is_empty() => {}
+                        DimPattern::Var(var) => {
+                            let binding = format!("_imp_{}", var);
+                            if bound.contains(&binding) {
+                                fail_if(&mut out, format!("{} != {}", extent, binding), mismatch(&binding));
+                            } else {
+                                out.push_str(&format!("    let {} = {};\n", binding, extent));
+                                bound.push(binding);
+                            }
+                        }
                     }
                 }
                 AxisPattern::Rank(capture) => {
-                    let binding = format!("_imp_rank_{}", capture.dim_name);
-                    if bound_ranks.iter().any(|existing| existing == &binding) {
-                        out.push_str(&format!("    assert_eq!({}.shp.len(), {}, \"rank {} mismatch\");\n",
-                            arg.id, binding, capture.dim_name));
+                    let Some(captured) = rust_captured_rank(&arg.id, axes).filter(|_| !capture.dim_name.is_empty()) else {
+                        continue;
+                    };
+                    let binding = format!("_imp_{}", capture.dim_name);
+                    if bound.contains(&binding) {
+                        // The other axes of the pattern are fixed, so the rank follows from the capture
+                        let expected = match axes.len() - 1 {
+                            0 => binding.clone(),
+                            fixed => format!("{} + {}", binding, fixed),
+                        };
+                        fail_if(&mut out,
+                            format!("{} != {}", captured, binding),
+                            format!("Rank {{ {}, expected: {}, found: {} }}", operand, expected, rank));
                     } else {
-                        out.push_str(&format!("    let {} = {}.shp.len();\n", binding, arg.id));
-                        bound_ranks.push(binding);
+                        out.push_str(&format!("    let {} = {};\n", binding, captured));
+                        bound.push(binding);
                     }
                 }
             }
@@ -359,3 +530,29 @@ fn generate_shape_checks(args: &[Farg]) -> String {
 
     out
 }
+
+/// The extent of axis `i` of pattern `axes` in the Rust array `arr`, see [`rust_axis_index`].
+fn rust_axis_expr(arr: &str, axes: &[AxisPattern], i: usize) -> Option<String> {
+    rust_axis_index(arr, axes, i).map(|axis| format!("{arr}.shp[{axis}]"))
+}
+
+/// The index in the shape of the Rust array `arr` of axis `i` of pattern `axes`. Axes after a rank
+/// capture are counted from the end, and have no fixed position after several captures.
+fn rust_axis_index(arr: &str, axes: &[AxisPattern], i: usize) -> Option<String> {
+    let is_rank = |axis: &AxisPattern| matches!(axis, AxisPattern::Rank(_));
+    match axes.iter().position(is_rank) {
+        Some(first) if i > first => {
+            (axes.iter().rposition(is_rank) == Some(first)).then(|| format!("{arr}.shp.len() - {}", axes.len() - i))
+        }
+        _ => Some(i.to_string()),
+    }
+}
+
+/// The number of axes that the single rank capture of `axes` covers in the Rust array `arr`.
+fn rust_captured_rank(arr: &str, axes: &[AxisPattern]) -> Option<String> {
+    let captures = axes.iter().filter(|axis| matches!(axis, AxisPattern::Rank(_))).count();
+    (captures == 1).then(|| match axes.len() - 1 {
+        0 => format!("{arr}.shp.len()"),
+        fixed => format!("({arr}.shp.len() - {fixed})"),
+    })
+}
@@ -26,6 +26,10 @@ r#"#pragma once
 #include <stdbool.h>
 #include <stdint.h>
 
+/* Shared by the headers of all modules, such that they can be included together. */
+#ifndef IMP_PRELUDE_H
+#define IMP_PRELUDE_H
+
 typedef struct {
     size_t len;
     size_t dim;
@@ -104,6 +108,37 @@ typedef struct {
     bool is_array;
     ImpDynDataF64 data;
 } ImpDynF64;
+
+enum {
+    IMP_OK,
+    IMP_ERR_RANK,
+    IMP_ERR_MIN_RANK,
+    IMP_ERR_EXTENT,
+    IMP_ERR_DISPATCH,
+    IMP_ERR_TARGET_LENGTH,
+    IMP_ERR_TARGET_INDEX,
+    IMP_ERR_PERMUTATION,
+    IMP_ERR_GAP,
+    IMP_ERR_OVERLAP,
+};
+
+/* The operand of an error that is the result rather than an argument */
+#define IMP_RESULT SIZE_MAX
+
+typedef struct {
+    uint32_t kind;
+    size_t arg;
+    size_t axis;
+    size_t expected;
+    size_t found;
+} ImpErrorRaw;
+
+/* The first runtime error on this thread. A function that raises or encounters an error returns
+   a zero result, so callers must check imp_error.kind and clear it with imp_take_error. */
+extern _Thread_local ImpErrorRaw imp_error;
+ImpErrorRaw imp_take_error(void);
+
+#endif
 "#;
 
 impl<'ast> Traverse<'ast> for CompileH {
@@ -116,7 +151,29 @@ impl<'ast> Traverse<'ast> for CompileH {
     fn trav_program(&mut self, program: &mut Program<'ast, TypedAst>) {
         self.output.push_str(HEADER);
 
-        for fundef in program.fundefs.iter_mut() {
+        // Imported structs are included as well, as the signatures below may use them.
+        // The guards keep them from being defined twice when the headers of both modules are included.
+        for structdef in declaration_order(&program.structs) {
+            self.output.push_str(&format!("\n#ifndef IMP_STRUCT_{}\n", structdef.name));
+            self.output.push_str(&format!("#define IMP_STRUCT_{}\n", structdef.name));
+            self.output.push_str("typedef struct {\n");
+            for field in &structdef.fields {
+                self.output.push_str(&format!("    {} {};\n", base_ctype(&field.ty), field.id));
+            }
+            self.output.push_str(&format!("}} {};\n", structdef.name));
+            self.output.push_str(&format!(
+                "typedef union {{\n    {0} scalar;\n    ImpArrayRaw array;\n}} ImpDynData{0};\n",
+                structdef.name
+            ));
+            self.output.push_str(&format!(
+                "typedef struct {{\n    bool is_array;\n    ImpDynData{0} data;\n}} ImpDyn{0};\n",
+                structdef.name
+            ));
+            self.output.push_str("#endif\n");
+        }
+        self.output.push('\n');
+
+        for fundef in program.fundefs.iter_mut().filter(|fundef| fundef.is_exported()) {
             self.trav_fundef(fundef);
         }
     }
@@ -131,6 +188,29 @@ impl<'ast> Traverse<'ast> for CompileH {
     }
 }
 
+/// The structs ordered such that each comes after the structs of its fields, as C requires.
+fn declaration_order(structs: &[Structdef]) -> Vec<&Structdef> {
+    fn visit<'a>(structs: &'a [Structdef], structdef: &'a Structdef, order: &mut Vec<&'a Structdef>) {
+        if order.iter().any(|s| s.name == structdef.name) {
+            return;
+        }
+        for field in &structdef.fields {
+            if let BaseType::Udf(name) = &field.ty.ty
+                && let Some(inner) = structs.iter().find(|s| &s.name == name)
+            {
+                visit(structs, inner, order);
+            }
+        }
+        order.push(structdef);
+    }
+
+    let mut order = Vec::with_capacity(structs.len());
+    for structdef in structs {
+        visit(structs, structdef, &mut order);
+    }
+    order
+}
+
 fn base_ctype(ty: &Type) -> String {
     use BaseType::*;
     match &ty.ty {
@@ -143,6 +223,7 @@ fn base_ctype(ty: &Type) -> String {
         F32 => "float".to_owned(),
         F64 => "double".to_owned(),
         Udf(udf) => udf.to_owned(),
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
     }
 }
 
@@ -159,6 +240,7 @@ fn dyn_ctype(ty: &Type) -> String {
             F32 => "ImpDynF32".to_owned(),
             F64 => "ImpDynF64".to_owned(),
             Udf(udf) => format!("ImpDyn{}", udf),
+            Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
         }
     } else if ty.is_array() {
         "ImpArrayRaw".to_owned()
@@ -113,6 +113,7 @@ pub fn mangle_type(ty: &Type) -> String {
         F32 => "f32",
         F64 => "f64",
         Udf(udf) => udf,
+        Generic(_) => unreachable!("generic functions are monomorphised before code generation"),
     };
     format!("{}_{}", base, mangle_shape(&ty.shape))
 }
@@ -135,6 +136,9 @@ fn mangle_axis(axis: &AxisPattern) -> String {
             DimPattern::Known(v) => v.to_string(),
             DimPattern::Var(ext) => ext.clone(),
         },
+        AxisPattern::Rank(capture) if capture.min_rank > 0 => {
+            format!("{}_ge{}_{}", capture.dim_name, capture.min_rank, capture.shp_name)
+        }
         AxisPattern::Rank(capture) => format!("{}_{}", capture.dim_name, capture.shp_name),
     }
 }
@@ -0,0 +1,166 @@
+use std::{error, fmt, io, path::{Path, PathBuf}};
+
+use crate::{ast::Span, interp::RuntimeError, scp::{ModuleError, ParseError}, tc::{DispatchError, InferenceError, OverloadError}, tp::TypePatternError};
+
+/// Error returned by [`compile`](crate::compile).
+#[derive(Debug)]
+pub enum CompileError {
+    /// Reading the source file or writing one of the generated files failed.
+    Io { path: PathBuf, error: io::Error },
+    /// The program was rejected by one of the compiler phases.
+    /// Contains every error found by that phase, in source order.
+    Invalid { path: PathBuf, src: String, diagnostics: Vec<Diagnostic> },
+}
+
+/// Error returned by [`run`](crate::run).
+#[derive(Debug)]
+pub enum RunError {
+    /// The program could not be compiled.
+    Compile(CompileError),
+    /// The program was aborted while being interpreted.
+    Runtime(RuntimeError),
+}
+
+/// A single error in the source program.
+#[derive(Debug)]
+pub enum Diagnostic {
+    Parse(ParseError),
+    Module(ModuleError),
+    TypePattern(TypePatternError),
+    Overload(OverloadError),
+    Inference(InferenceError),
+    Dispatch(DispatchError),
+}
+
+impl CompileError {
+    pub(crate) fn invalid<E: Into<Diagnostic>>(path: &Path, src: &str, errors: Vec<E>) -> Self {
+        let mut diagnostics: Vec<Diagnostic> = errors.into_iter().map(Into::into).collect();
+        diagnostics.sort_by_key(|d| d.span());
+        Self::Invalid {
+            path: path.to_owned(),
+            src: src.to_owned(),
+            diagnostics,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn span(&self) -> Span {
+        match self {
+            Diagnostic::Parse(e) => e.span(),
+            Diagnostic::Module(e) => e.span(),
+            Diagnostic::TypePattern(e) => e.span(),
+            Diagnostic::Overload(e) => e.span(),
+            Diagnostic::Inference(e) => e.span(),
+            Diagnostic::Dispatch(e) => e.span(),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Io { path, error } => {
+                write!(f, "error: {}: {}", path.display(), error)
+            }
+            CompileError::Invalid { path, src, diagnostics } => {
+                for diagnostic in diagnostics {
+                    writeln!(f, "{}", diagnostic.span().report(path, src, diagnostic))?;
+                }
+                let plural = if diagnostics.len() == 1 { "" } else { "s" };
+                write!(f, "error: could not compile `{}` due to {} previous error{}",
+                    path.display(), diagnostics.len(), plural)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Compile(e) => e.fmt(f),
+            RunError::Runtime(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Parse(e) => e.fmt(f),
+            Diagnostic::Module(e) => e.fmt(f),
+            Diagnostic::TypePattern(e) => e.fmt(f),
+            Diagnostic::Overload(e) => e.fmt(f),
+            Diagnostic::Inference(e) => e.fmt(f),
+            Diagnostic::Dispatch(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CompileError::Io { error, .. } => Some(error),
+            CompileError::Invalid { .. } => None,
+        }
+    }
+}
+
+impl error::Error for RunError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RunError::Compile(e) => Some(e),
+            RunError::Runtime(e) => Some(e),
+        }
+    }
+}
+
+impl error::Error for Diagnostic {}
+
+impl From<CompileError> for RunError {
+    fn from(e: CompileError) -> Self {
+        RunError::Compile(e)
+    }
+}
+
+impl From<RuntimeError> for RunError {
+    fn from(e: RuntimeError) -> Self {
+        RunError::Runtime(e)
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Self {
+        Diagnostic::Parse(e)
+    }
+}
+
+impl From<ModuleError> for Diagnostic {
+    fn from(e: ModuleError) -> Self {
+        Diagnostic::Module(e)
+    }
+}
+
+impl From<TypePatternError> for Diagnostic {
+    fn from(e: TypePatternError) -> Self {
+        Diagnostic::TypePattern(e)
+    }
+}
+
+impl From<OverloadError> for Diagnostic {
+    fn from(e: OverloadError) -> Self {
+        Diagnostic::Overload(e)
+    }
+}
+
+impl From<InferenceError> for Diagnostic {
+    fn from(e: InferenceError) -> Self {
+        Diagnostic::Inference(e)
+    }
+}
+
+impl From<DispatchError> for Diagnostic {
+    fn from(e: DispatchError) -> Self {
+        Diagnostic::Dispatch(e)
+    }
+}
@@ -0,0 +1,8 @@
+//! # Interpreter (`interp`)
+mod evaluate;
+mod value;
+
+use value::stack;
+
+pub use evaluate::{interpret, RuntimeError};
+pub use value::{parse_value, show_value, Value};
@@ -0,0 +1,675 @@
+use std::{cmp::Ordering, collections::HashMap, fmt};
+
+use imp_core::{ImpArray, ImpArrayOrScalar};
+
+use crate::ast::*;
+
+use super::{stack, Value};
+
+/// Call function `name` of the program with the given arguments.
+///
+/// The overload is selected by the base types and shapes of the arguments, as is done
+/// by the runtime dispatch of the generated C code. Functions called from within the
+/// program are dispatched at runtime in the same way.
+pub fn interpret<'ast>(program: &Program<'ast, TypedAst>, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    let Some(group) = program.overloads.get(name) else {
+        return Err(RuntimeError::UndefinedFunction { name: name.to_owned() });
+    };
+
+    let candidates: Vec<&Fundef<'ast, TypedAst>> = group.iter()
+        .filter(|(sig, _)| sig.base_types.len() == args.len()
+            && sig.base_types.iter().zip(&args).all(|(base, arg)| base_of_value(arg).is_none_or(|b| b == *base)))
+        .flat_map(|(_, fundefs)| fundefs.iter().copied())
+        .collect();
+
+    let interp = Interpreter { program };
+    let fundef = interp.select_overload(name, &candidates, &args)?;
+    interp.call_fundef(fundef, args)
+}
+
+#[derive(Clone, Debug)]
+pub enum RuntimeError {
+    UndefinedFunction {
+        name: String,
+    },
+    NoMatchingOverload {
+        name: String,
+        args: Vec<String>,
+    },
+    InvalidIndex {
+        index: Vec<usize>,
+        shape: Vec<usize>,
+    },
+    InhomogeneousArray {
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
+    InvalidOperands {
+        prf: &'static str,
+        args: Vec<String>,
+    },
+    DivisionByZero,
+    /// The partitions of a tensor do not select every index exactly once.
+    PartitionCoverage {
+        coverage: Coverage,
+    },
+    /// The target of a tensor maps two index vectors to the same index.
+    NotAPermutation {
+        index: Vec<usize>,
+    },
+    /// The program uses a feature that only the compiler supports.
+    Unsupported {
+        feature: &'static str,
+    },
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RuntimeError::*;
+        match self {
+            UndefinedFunction { name } => {
+                write!(f, "function `{}` is not defined", name)
+            }
+            NoMatchingOverload { name, args } => {
+                write!(f, "no overload of `{}` accepts arguments ({})", name, args.join(", "))
+            }
+            InvalidIndex { index, shape } => {
+                write!(f, "index {:?} is out of bounds for an array of shape {:?}", index, shape)
+            }
+            InhomogeneousArray { expected, found } => {
+                write!(f, "inhomogeneous array: expected elements of shape {:?}, found {:?}", expected, found)
+            }
+            InvalidOperands { prf, args } => {
+                write!(f, "`{}` cannot be applied to ({})", prf, args.join(", "))
+            }
+            DivisionByZero => {
+                write!(f, "division by zero")
+            }
+            PartitionCoverage { coverage: Coverage::Gap(index) } => {
+                write!(f, "no partition of tensor selects index {:?}", index)
+            }
+            PartitionCoverage { coverage: Coverage::Overlap(index) } => {
+                write!(f, "partitions of tensor overlap at index {:?}", index)
+            }
+            NotAPermutation { index } => {
+                write!(f, "the target of a tensor is not a permutation: index {:?} is written more than once", index)
+            }
+            Unsupported { feature } => {
+                write!(f, "{} are not supported by the interpreter", feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+struct Interpreter<'a, 'ast> {
+    program: &'a Program<'ast, TypedAst>,
+}
+
+/// Values of the arguments and local variables of a function call.
+struct Frame<'ast> {
+    fundef: &'ast Fundef<'ast, TypedAst>,
+    args: Vec<Value>,
+    vars: HashMap<*const VarInfo<'ast, TypedAst>, Value>,
+}
+
+impl<'a, 'ast> Interpreter<'a, 'ast> {
+    /// The most specific of the `candidates` whose argument shapes match `args`.
+    fn select_overload(&self, name: &str, candidates: &[&'ast Fundef<'ast, TypedAst>], args: &[Value]) -> Result<&'ast Fundef<'ast, TypedAst>, RuntimeError> {
+        let matches: Vec<&Fundef<'ast, TypedAst>> = candidates.iter()
+            .copied()
+            .filter(|fundef| accepts(fundef, args))
+            .collect();
+
+        matches.iter()
+            .find(|a| matches.iter().all(|b| at_least_as_specific(&a.args, &b.args)))
+            .or(matches.first())
+            .copied()
+            .ok_or_else(|| RuntimeError::NoMatchingOverload {
+                name: name.to_owned(),
+                args: args.iter().map(describe).collect(),
+            })
+    }
+
+    fn call(&self, target: &CallTarget<'ast, TypedAst>, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let CallTarget::Function(fundef) = target;
+        let family = self.program.overloads
+            .get(&fundef.name)
+            .and_then(|group| group.get(&fundef.signature()));
+
+        let fundef = match family {
+            Some(family) => self.select_overload(&fundef.name, family, &args)?,
+            None => *fundef,
+        };
+
+        self.call_fundef(fundef, args)
+    }
+
+    fn call_fundef(&self, fundef: &'ast Fundef<'ast, TypedAst>, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let mut frame = Frame { fundef, args, vars: HashMap::new() };
+
+        for assign in &fundef.shape_prelude {
+            self.eval_assign(assign, &mut frame)?;
+        }
+
+        self.eval_body(&fundef.body, &mut frame)
+    }
+
+    fn eval_body(&self, body: &Body<'ast, TypedAst>, frame: &mut Frame<'ast>) -> Result<Value, RuntimeError> {
+        for stmt in &body.stmts {
+            match stmt {
+                Stmt::Assign(assign) => self.eval_assign(assign, frame)?,
+                Stmt::Printf(printf) => {
+                    let name = match printf.id {
+                        Id::Arg(i) => frame.fundef.args[i].id.clone(),
+                        Id::Var(lvis) => lvis.name.clone(),
+                    };
+                    println!("Hello, {}", name);
+                }
+                // Values are dropped along with their frame
+                Stmt::Free(_) => {}
+            }
+        }
+
+        Ok(self.eval_id(&body.ret, frame))
+    }
+
+    fn eval_assign(&self, assign: &Assign<'ast, TypedAst>, frame: &mut Frame<'ast>) -> Result<(), RuntimeError> {
+        let value = self.eval_expr(assign.expr, &assign.lhs.ty, frame)?;
+        frame.vars.insert(assign.lhs as *const _, value);
+        Ok(())
+    }
+
+    fn eval_id(&self, id: &Id<'ast, TypedAst>, frame: &Frame<'ast>) -> Value {
+        match id {
+            Id::Arg(i) => frame.args[*i].clone(),
+            Id::Var(lvis) => frame.vars
+                .get(&(*lvis as *const _))
+                .expect("variable referenced before its assignment was evaluated")
+                .clone(),
+        }
+    }
+
+    /// Evaluate `expr`, whose result is of type `ty`.
+    fn eval_expr(&self, expr: &Expr<'ast, TypedAst>, ty: &Type, frame: &mut Frame<'ast>) -> Result<Value, RuntimeError> {
+        match expr {
+            Expr::Cond(cond) => {
+                let branch = match self.eval_id(&cond.cond, frame) {
+                    ImpArrayOrScalar::Scalar(Const::Bool(true)) => &cond.then_branch,
+                    ImpArrayOrScalar::Scalar(Const::Bool(false)) => &cond.else_branch,
+                    value => {
+                        return Err(RuntimeError::InvalidOperands { prf: "if", args: vec![describe(&value)] });
+                    }
+                };
+                self.eval_body(branch, frame)
+            }
+            Expr::Call(call) => {
+                let args = call.args.iter().map(|arg| self.eval_id(arg, frame)).collect();
+                self.call(&call.id, args)
+            }
+            Expr::Prf(prf) => self.eval_prf(prf, frame),
+            Expr::Tensor(tensor) => self.eval_tensor(tensor, &ty.ty, frame),
+            Expr::Fold(fold) => self.eval_fold(fold, frame),
+            Expr::Array(array) => {
+                let elems = array.elems.iter().map(|elem| self.eval_id(elem, frame)).collect();
+                stack(elems)
+            }
+            Expr::Record(_) | Expr::Field(_) => Err(RuntimeError::Unsupported { feature: "records" }),
+            Expr::Id(id) => Ok(self.eval_id(id, frame)),
+            Expr::Const(c) => Ok(ImpArrayOrScalar::Scalar(*c)),
+        }
+    }
+
+    /// Evaluate the bounds of a partition, defaulting the lower bound to zeros and the step and width to ones.
+    fn eval_bounds(&self, partition: &Partition<'ast, TypedAst>, frame: &Frame<'ast>) -> Result<Bounds, RuntimeError> {
+        let ub = index_vector(self.eval_id(&partition.ub, frame), "tensor")?;
+        let or_fill = |id: &Option<Id<'ast, TypedAst>>, fill: usize| match id {
+            Some(id) => index_vector(self.eval_id(id, frame), "tensor"),
+            None => Ok(vec![fill; ub.len()]),
+        };
+        let lb = or_fill(&partition.lb, 0)?;
+        let step = or_fill(&partition.step, 1)?;
+        let width = or_fill(&partition.width, 1)?;
+
+        if [&lb, &step, &width].iter().any(|v| v.len() != ub.len()) || step.contains(&0) {
+            let operands = partition.lb.iter().chain([&partition.ub]).chain(&partition.step).chain(&partition.width);
+            return Err(RuntimeError::InvalidOperands {
+                prf: "tensor",
+                args: operands.map(|id| describe(&self.eval_id(id, frame))).collect(),
+            });
+        }
+
+        Ok(Bounds { lb, ub, step, width })
+    }
+
+    /// Bind the index vector of a partition and evaluate its body.
+    fn eval_at(&self, partition: &Partition<'ast, TypedAst>, iv: &[usize], frame: &mut Frame<'ast>) -> Result<Value, RuntimeError> {
+        let data = iv.iter().map(|i| const_from_usize(&partition.iv.ty.ty, *i)).collect();
+        let iv_value = ImpArrayOrScalar::Array(ImpArray { shp: vec![iv.len()], data });
+        frame.vars.insert(partition.iv as *const _, iv_value);
+        self.eval_body(&partition.body, frame)
+    }
+
+    /// The result has shape `ub ++ shape(expr)`, where every element is computed by the partition that selects it.
+    /// The elements that the generator of a single partition does not select are zeros.
+    fn eval_tensor(&self, tensor: &Tensor<'ast, TypedAst>, base: &BaseType, frame: &mut Frame<'ast>) -> Result<Value, RuntimeError> {
+        let bounds = tensor.partitions.iter()
+            .map(|partition| self.eval_bounds(partition, frame))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if bounds.iter().any(|b| b.ub.len() != bounds[0].ub.len()) {
+            return Err(RuntimeError::InvalidOperands {
+                prf: "tensor",
+                args: tensor.partitions.iter().map(|p| describe(&self.eval_id(&p.ub, frame))).collect(),
+            });
+        }
+        if tensor.single().is_none()
+            && let Some(coverage) = Bounds::coverage(&bounds)
+        {
+            return Err(RuntimeError::PartitionCoverage { coverage });
+        }
+
+        let ub = Bounds::upper_bound(&bounds);
+        let mut elems = Vec::new();
+        for iv in indices(&ub) {
+            match tensor.partitions.iter().zip(&bounds).find(|(_, b)| b.selects(&iv)) {
+                Some((partition, _)) => elems.push(Some(self.eval_at(partition, &iv, frame)?)),
+                None => elems.push(None),
+            }
+        }
+
+        let elem_shp = elems.iter()
+            .flatten()
+            .next()
+            .map(shape_of)
+            .unwrap_or_default();
+
+        if ub.is_empty() {
+            return Ok(elems.pop().flatten().expect("empty ub selects exactly one element"));
+        }
+        if let Some(target) = &tensor.target {
+            elems = self.permute(elems, &ub, target, frame)?;
+        }
+
+        let zero = const_from_usize(base, 0);
+        let elem_len: usize = elem_shp.iter().product();
+        let mut data = Vec::with_capacity(elems.len() * elem_len);
+        for elem in elems {
+            match elem {
+                None => data.extend(std::iter::repeat_n(zero, elem_len)),
+                Some(value) => {
+                    let found = shape_of(&value);
+                    if found != elem_shp {
+                        return Err(RuntimeError::InhomogeneousArray { expected: elem_shp, found });
+                    }
+                    data.extend(elements(value));
+                }
+            }
+        }
+
+        let mut shp = ub;
+        shp.extend(elem_shp);
+        Ok(ImpArrayOrScalar::Array(ImpArray { shp, data }))
+    }
+
+    /// Move the element computed for each index vector to the index that the target maps it to.
+    fn permute(&self, elems: Vec<Option<Value>>, ub: &[usize], target: &Id<'ast, TypedAst>, frame: &Frame<'ast>) -> Result<Vec<Option<Value>>, RuntimeError> {
+        let target = self.eval_id(target, frame);
+        let mut expected = ub.to_vec();
+        expected.push(ub.len());
+        let ImpArrayOrScalar::Array(map) = &target else {
+            return Err(RuntimeError::InvalidOperands { prf: "tensor", args: vec![describe(&target)] });
+        };
+        if map.shp != expected {
+            return Err(RuntimeError::InvalidOperands { prf: "tensor", args: vec![describe(&target)] });
+        }
+
+        let mut permuted = vec![None; elems.len()];
+        for (elem, to) in elems.into_iter().zip(map.data.chunks(ub.len())) {
+            let index = to.iter()
+                .map(|c| int_value(*c).and_then(|v| usize::try_from(v).ok()))
+                .collect::<Option<Vec<usize>>>()
+                .ok_or_else(|| RuntimeError::InvalidOperands { prf: "tensor", args: vec![describe(&target)] })?;
+            if index.iter().zip(ub).any(|(i, n)| i >= n) {
+                return Err(RuntimeError::InvalidIndex { index, shape: ub.to_vec() });
+            }
+
+            let flat = index.iter().zip(ub).fold(0, |flat, (i, n)| flat * n + i);
+            if permuted[flat].is_some() {
+                return Err(RuntimeError::NotAPermutation { index });
+            }
+            permuted[flat] = elem;
+        }
+        Ok(permuted)
+    }
+
+    /// Fold the selected elements into the neutral element, in row-major order.
+    fn eval_fold(&self, fold: &Fold<'ast, TypedAst>, frame: &mut Frame<'ast>) -> Result<Value, RuntimeError> {
+        let mut acc = self.eval_id(&fold.neutral, frame);
+
+        let generator = self.eval_bounds(&fold.selection, frame)?;
+        let extents: Vec<usize> = generator.lb.iter().zip(&generator.ub).map(|(l, u)| u.saturating_sub(*l)).collect();
+
+        for offset in indices(&extents) {
+            let iv: Vec<usize> = offset.iter().zip(&generator.lb).map(|(o, l)| o + l).collect();
+            if !generator.selects(&iv) {
+                continue;
+            }
+            let elem = self.eval_at(&fold.selection, &iv, frame)?;
+
+            acc = match &fold.foldfun {
+                FoldFun::Name(target) => self.call(target, vec![acc, elem])?,
+                FoldFun::Apply { id, args } => {
+                    // The first placeholder receives the accumulator, the second the element
+                    let mut holes = vec![acc, elem].into_iter();
+                    let args = args.iter()
+                        .map(|arg| match arg {
+                            FoldFunArg::Placeholder => holes.next().expect("too many placeholders"),
+                            FoldFunArg::Bound(bound) => self.eval_id(bound, frame),
+                        })
+                        .collect();
+                    self.call(id, args)?
+                }
+            };
+        }
+
+        Ok(acc)
+    }
+
+    fn eval_prf(&self, prf: &Prf<'ast, TypedAst>, frame: &Frame<'ast>) -> Result<Value, RuntimeError> {
+        use Prf::*;
+        let args: Vec<Value> = prf.args().into_iter().map(|arg| self.eval_id(arg, frame)).collect();
+        let invalid = || RuntimeError::InvalidOperands {
+            prf: prf.nameof(),
+            args: args.iter().map(describe).collect(),
+        };
+
+        let scalars: Vec<Const> = args.iter()
+            .filter_map(|arg| match arg {
+                ImpArrayOrScalar::Scalar(c) => Some(*c),
+                ImpArrayOrScalar::Array(_) => None,
+            })
+            .collect();
+
+        let result = match prf {
+            DimA(_) => Const::Usize(shape_of(&args[0]).len()),
+            ShapeA(_) => {
+                let shp = shape_of(&args[0]);
+                return Ok(ImpArrayOrScalar::Array(ImpArray {
+                    shp: vec![shp.len()],
+                    data: shp.into_iter().map(Const::Usize).collect(),
+                }));
+            }
+            SelVxA(_, _) => {
+                let index = index_vector(args[0].clone(), prf.nameof())?;
+                return select(&index, &args[1]).map(ImpArrayOrScalar::Scalar);
+            }
+            TakeSxV(_, _) | DropSxV(_, _) => {
+                let (ImpArrayOrScalar::Scalar(n), ImpArrayOrScalar::Array(vec)) = (&args[0], &args[1]) else {
+                    return Err(invalid());
+                };
+                let n = int_value(*n)
+                    .and_then(|n| usize::try_from(n).ok())
+                    .filter(|n| vec.shp.len() == 1 && *n <= vec.data.len())
+                    .ok_or_else(invalid)?;
+                let data = match prf {
+                    TakeSxV(_, _) => vec.data[..n].to_vec(),
+                    _ => vec.data[n..].to_vec(),
+                };
+                return Ok(ImpArrayOrScalar::Array(ImpArray { shp: vec![data.len()], data }));
+            }
+            _ if scalars.len() != args.len() => return Err(invalid()),
+            AddSxS(_, _) => arith(scalars[0], scalars[1], i128::wrapping_add, |a, b| a + b).ok_or_else(invalid)?,
+            SubSxS(_, _) => arith(scalars[0], scalars[1], i128::wrapping_sub, |a, b| a - b).ok_or_else(invalid)?,
+            MulSxS(_, _) => arith(scalars[0], scalars[1], i128::wrapping_mul, |a, b| a * b).ok_or_else(invalid)?,
+            DivSxS(_, _) => {
+                if int_value(scalars[1]) == Some(0) {
+                    return Err(RuntimeError::DivisionByZero);
+                }
+                arith(scalars[0], scalars[1], i128::wrapping_div, |a, b| a / b).ok_or_else(invalid)?
+            }
+            LtSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_some_and(Ordering::is_lt)),
+            LeSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_some_and(Ordering::is_le)),
+            GtSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_some_and(Ordering::is_gt)),
+            GeSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_some_and(Ordering::is_ge)),
+            EqSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_some_and(Ordering::is_eq)),
+            NeSxS(_, _) => Const::Bool(compare(scalars[0], scalars[1]).ok_or_else(invalid)?.is_none_or(Ordering::is_ne)),
+            NegS(_) => arith(scalars[0], scalars[0], |_, b| b.wrapping_neg(), |_, b| -b).ok_or_else(invalid)?,
+            NotS(_) => match scalars[0] {
+                Const::Bool(v) => Const::Bool(!v),
+                c => Const::Bool(int_value(c).ok_or_else(invalid)? == 0),
+            },
+        };
+
+        Ok(ImpArrayOrScalar::Scalar(result))
+    }
+}
+
+/// Whether the argument shapes of `fundef` match the shapes of `args`.
+/// Named dimensions must match the same extent everywhere they occur.
+fn accepts(fundef: &Fundef<'_, TypedAst>, args: &[Value]) -> bool {
+    if fundef.args.len() != args.len() {
+        return false;
+    }
+
+    let mut dims = HashMap::new();
+    fundef.args.iter()
+        .zip(args)
+        .all(|(farg, arg)| shape_matches(&farg.ty.shape, &shape_of(arg), &mut dims))
+}
+
+fn shape_matches(pattern: &TypePattern, shp: &[usize], dims: &mut HashMap<String, usize>) -> bool {
+    let mut bind = |name: &str, extent: usize| {
+        name.is_empty() || *dims.entry(name.to_owned()).or_insert(extent) == extent
+    };
+
+    let dim_matches = |dim: &DimPattern, extent: usize, bind: &mut dyn FnMut(&str, usize) -> bool| match dim {
+        DimPattern::Known(n) => *n == extent,
+        DimPattern::Var(name) => bind(name, extent),
+    };
+
+    let axes = match pattern {
+        TypePattern::Scalar => return shp.is_empty(),
+        TypePattern::Axes(axes) => axes,
+    };
+
+    let Some(first_rank) = axes.iter().position(|axis| matches!(axis, AxisPattern::Rank(_))) else {
+        return axes.len() == shp.len() && axes.iter().zip(shp).all(|(axis, extent)| match axis {
+            AxisPattern::Dim(dim) => dim_matches(dim, *extent, &mut bind),
+            AxisPattern::Rank(_) => unreachable!(),
+        });
+    };
+
+    let last_rank = axes.iter().rposition(|axis| matches!(axis, AxisPattern::Rank(_))).unwrap();
+    let prefix = &axes[..first_rank];
+    let suffix = &axes[last_rank + 1..];
+    if pattern.min_rank() > shp.len() {
+        return false;
+    }
+
+    let captured = shp.len() - prefix.len() - suffix.len();
+    let prefix_ok = prefix.iter().zip(shp).all(|(axis, extent)| match axis {
+        AxisPattern::Dim(dim) => dim_matches(dim, *extent, &mut bind),
+        AxisPattern::Rank(_) => unreachable!(),
+    });
+    let suffix_ok = suffix.iter().zip(&shp[shp.len() - suffix.len()..]).all(|(axis, extent)| match axis {
+        AxisPattern::Dim(dim) => dim_matches(dim, *extent, &mut bind),
+        AxisPattern::Rank(_) => unreachable!(),
+    });
+    // A single capture binds the rank of the axes it covers
+    let rank_ok = first_rank != last_rank || match &axes[first_rank] {
+        AxisPattern::Rank(capture) => bind(&capture.dim_name, captured),
+        AxisPattern::Dim(_) => unreachable!(),
+    };
+
+    prefix_ok && suffix_ok && rank_ok
+}
+
+/// Whether every argument pattern of `a` is at least as specific as that of `b`.
+fn at_least_as_specific(a: &[Farg], b: &[Farg]) -> bool {
+    a.iter().zip(b).all(|(a, b)| pattern_at_least_as_specific(&a.ty.shape, &b.ty.shape))
+}
+
+fn pattern_at_least_as_specific(a: &TypePattern, b: &TypePattern) -> bool {
+    let has_rank = |axes: &[AxisPattern]| axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
+    let (a_min, b_min) = (a.min_rank(), b.min_rank());
+    match (a, b) {
+        (TypePattern::Scalar, _) => true,
+        (TypePattern::Axes(_), TypePattern::Scalar) => false,
+        (TypePattern::Axes(a), TypePattern::Axes(b)) => match (has_rank(a), has_rank(b)) {
+            (false, true) => true,
+            (true, false) => false,
+            (true, true) => a.len() >= b.len() && a_min >= b_min,
+            (false, false) => a.iter().zip(b).all(|(a, b)| {
+                !matches!((a, b), (AxisPattern::Dim(DimPattern::Var(_)), AxisPattern::Dim(DimPattern::Known(_))))
+            }),
+        },
+    }
+}
+
+/// All index vectors below `ub`, in row-major order.
+fn indices(ub: &[usize]) -> Vec<Vec<usize>> {
+    let len: usize = ub.iter().product();
+    (0..len)
+        .map(|mut flat| {
+            let mut iv = vec![0; ub.len()];
+            for d in (0..ub.len()).rev() {
+                iv[d] = flat % ub[d];
+                flat /= ub[d];
+            }
+            iv
+        })
+        .collect()
+}
+
+fn select(index: &[usize], arr: &Value) -> Result<Const, RuntimeError> {
+    let shp = shape_of(arr);
+    let invalid = || RuntimeError::InvalidIndex { index: index.to_vec(), shape: shp.clone() };
+
+    if index.len() != shp.len() || index.iter().zip(&shp).any(|(i, n)| i >= n) {
+        return Err(invalid());
+    }
+
+    match arr {
+        ImpArrayOrScalar::Scalar(c) => Ok(*c),
+        ImpArrayOrScalar::Array(arr) => {
+            let flat = index.iter().zip(&arr.shp).fold(0, |flat, (i, n)| flat * n + i);
+            Ok(arr.data[flat])
+        }
+    }
+}
+
+fn index_vector(value: Value, prf: &'static str) -> Result<Vec<usize>, RuntimeError> {
+    let invalid = |value: &Value| RuntimeError::InvalidOperands { prf, args: vec![describe(value)] };
+    match &value {
+        ImpArrayOrScalar::Array(arr) if arr.shp.len() == 1 => arr.data.iter()
+            .map(|c| int_value(*c).and_then(|v| usize::try_from(v).ok()))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(|| invalid(&value)),
+        _ => Err(invalid(&value)),
+    }
+}
+
+fn shape_of(value: &Value) -> Vec<usize> {
+    match value {
+        ImpArrayOrScalar::Scalar(_) => Vec::new(),
+        ImpArrayOrScalar::Array(arr) => arr.shp.clone(),
+    }
+}
+
+fn elements(value: Value) -> Vec<Const> {
+    match value {
+        ImpArrayOrScalar::Scalar(c) => vec![c],
+        ImpArrayOrScalar::Array(arr) => arr.data,
+    }
+}
+
+/// The base type of a value, which is unknown for empty arrays.
+fn base_of_value(value: &Value) -> Option<BaseType> {
+    let c = match value {
+        ImpArrayOrScalar::Scalar(c) => *c,
+        ImpArrayOrScalar::Array(arr) => *arr.data.first()?,
+    };
+
+    Some(match c {
+        Const::Bool(_) => BaseType::Bool,
+        Const::Usize(_) => BaseType::Usize,
+        Const::U32(_) => BaseType::U32,
+        Const::U64(_) => BaseType::U64,
+        Const::I32(_) => BaseType::I32,
+        Const::I64(_) => BaseType::I64,
+        Const::F32(_) => BaseType::F32,
+        Const::F64(_) => BaseType::F64,
+    })
+}
+
+/// The type of a value, e.g. `i32[2, 3]`.
+fn describe(value: &Value) -> String {
+    let shape = match value {
+        ImpArrayOrScalar::Scalar(_) => TypePattern::Scalar,
+        ImpArrayOrScalar::Array(arr) => TypePattern::Axes(
+            arr.shp.iter().map(|n| AxisPattern::Dim(DimPattern::Known(*n))).collect()
+        ),
+    };
+
+    match base_of_value(value) {
+        Some(ty) => Type { ty, shape }.to_string(),
+        None => format!("_{}", shape),
+    }
+}
+
+fn const_from_usize(base: &BaseType, v: usize) -> Const {
+    match base {
+        BaseType::Bool => Const::Bool(v != 0),
+        BaseType::U32 => Const::U32(v as u32),
+        BaseType::U64 => Const::U64(v as u64),
+        BaseType::I32 => Const::I32(v as i32),
+        BaseType::I64 => Const::I64(v as i64),
+        BaseType::F32 => Const::F32(v as f32),
+        BaseType::F64 => Const::F64(v as f64),
+        BaseType::Usize | BaseType::Udf(_) => Const::Usize(v),
+        BaseType::Generic(_) => unreachable!("generic functions are monomorphised before interpretation"),
+    }
+}
+
+fn int_value(c: Const) -> Option<i128> {
+    match c {
+        Const::Usize(v) => Some(v as i128),
+        Const::U32(v) => Some(v as i128),
+        Const::U64(v) => Some(v as i128),
+        Const::I32(v) => Some(v as i128),
+        Const::I64(v) => Some(v as i128),
+        Const::Bool(_) | Const::F32(_) | Const::F64(_) => None,
+    }
+}
+
+/// Apply an arithmetic operation to two constants of the same type.
+/// Integer results wrap around, like they do in the generated C code.
+fn arith(a: Const, b: Const, int: impl Fn(i128, i128) -> i128, float: impl Fn(f64, f64) -> f64) -> Option<Const> {
+    use Const::*;
+    match (a, b) {
+        (Usize(x), Usize(y)) => Some(Usize(int(x as i128, y as i128) as usize)),
+        (U32(x), U32(y)) => Some(U32(int(x as i128, y as i128) as u32)),
+        (U64(x), U64(y)) => Some(U64(int(x as i128, y as i128) as u64)),
+        (I32(x), I32(y)) => Some(I32(int(x as i128, y as i128) as i32)),
+        (I64(x), I64(y)) => Some(I64(int(x as i128, y as i128) as i64)),
+        (F32(x), F32(y)) => Some(F32(float(x as f64, y as f64) as f32)),
+        (F64(x), F64(y)) => Some(F64(float(x, y))),
+        _ => None,
+    }
+}
+
+/// Compare two constants of the same type, where the inner option is `None` for unordered floats.
+fn compare(a: Const, b: Const) -> Option<Option<Ordering>> {
+    use Const::*;
+    match (a, b) {
+        (Bool(x), Bool(y)) => Some(Some(x.cmp(&y))),
+        (F32(x), F32(y)) => Some(x.partial_cmp(&y)),
+        (F64(x), F64(y)) => Some(x.partial_cmp(&y)),
+        _ if base_of_value(&ImpArrayOrScalar::Scalar(a)) == base_of_value(&ImpArrayOrScalar::Scalar(b)) => {
+            Some(Some(int_value(a)?.cmp(&int_value(b)?)))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,138 @@
+use std::iter::Peekable;
+
+use imp_core::{ImpArray, ImpArrayOrScalar};
+
+use crate::{ast::*, scp::{Lexer, Token}};
+
+use super::RuntimeError;
+
+/// A runtime value of the interpreter.
+pub type Value = ImpArrayOrScalar<Const>;
+
+/// Parse a literal value, using the same syntax as constants in imp programs.
+///
+/// Example: `true`, `42`, `-1i32`, `3.14f64`, `[[1, 2], [3, 4]]`
+pub fn parse_value(literal: &str) -> Result<Value, String> {
+    let mut tokens = Lexer::new(literal).map(|(token, _)| token).peekable();
+    let value = parse_literal(&mut tokens)?;
+    match tokens.next() {
+        None => Ok(value),
+        Some(token) => Err(format!("unexpected {:?} after literal", token)),
+    }
+}
+
+fn parse_literal(tokens: &mut Peekable<impl Iterator<Item = Token>>) -> Result<Value, String> {
+    match tokens.next() {
+        Some(Token::LSquare) => {
+            let mut elems = Vec::new();
+            if tokens.peek() != Some(&Token::RSquare) {
+                loop {
+                    elems.push(parse_literal(tokens)?);
+                    if tokens.peek() == Some(&Token::Comma) {
+                        tokens.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            match tokens.next() {
+                Some(Token::RSquare) => stack(elems).map_err(|e| e.to_string()),
+                Some(token) => Err(format!("expected `,` or `]`, found {:?}", token)),
+                None => Err("expected `]`, found end of input".to_owned()),
+            }
+        }
+        Some(Token::Sub) => match parse_literal(tokens)? {
+            ImpArrayOrScalar::Scalar(c) => negate(c).map(ImpArrayOrScalar::Scalar),
+            ImpArrayOrScalar::Array(_) => Err("cannot negate an array literal".to_owned()),
+        },
+        Some(token) => scalar_literal(token).map(ImpArrayOrScalar::Scalar),
+        None => Err("expected a literal, found end of input".to_owned()),
+    }
+}
+
+fn scalar_literal(token: Token) -> Result<Const, String> {
+    use Token::*;
+    match token {
+        BoolValue(v) => Ok(Const::Bool(v)),
+        NatValue(v) | UsizeValue(v) => Ok(Const::Usize(v)),
+        U32Value(v) => Ok(Const::U32(v)),
+        U64Value(v) => Ok(Const::U64(v)),
+        I32Value(v) => Ok(Const::I32(v)),
+        I64Value(v) => Ok(Const::I64(v)),
+        RealValue(v) | F32Value(v) => Ok(Const::F32(v)),
+        F64Value(v) => Ok(Const::F64(v)),
+        token => Err(format!("expected a literal, found {:?}", token)),
+    }
+}
+
+fn negate(c: Const) -> Result<Const, String> {
+    match c {
+        Const::I32(v) => Ok(Const::I32(-v)),
+        Const::I64(v) => Ok(Const::I64(-v)),
+        Const::F32(v) => Ok(Const::F32(-v)),
+        Const::F64(v) => Ok(Const::F64(-v)),
+        c => Err(format!("cannot negate unsigned literal {}", show_const(c))),
+    }
+}
+
+/// Combine values of the same shape into an array with one more leading axis.
+pub fn stack(elems: Vec<Value>) -> Result<Value, RuntimeError> {
+    let mut shp = vec![elems.len()];
+    let mut data = Vec::new();
+    let mut elem_shp = None;
+
+    for elem in elems {
+        let (shape, values) = match elem {
+            ImpArrayOrScalar::Scalar(c) => (Vec::new(), vec![c]),
+            ImpArrayOrScalar::Array(arr) => (arr.shp, arr.data),
+        };
+
+        match &elem_shp {
+            None => elem_shp = Some(shape),
+            Some(expected) if *expected != shape => {
+                return Err(RuntimeError::InhomogeneousArray { expected: expected.clone(), found: shape });
+            }
+            Some(_) => {}
+        }
+
+        data.extend(values);
+    }
+
+    shp.extend(elem_shp.unwrap_or_default());
+    Ok(ImpArrayOrScalar::Array(ImpArray { shp, data }))
+}
+
+/// Render a value using the literal syntax accepted by [`parse_value`].
+pub fn show_value(value: &Value) -> String {
+    match value {
+        ImpArrayOrScalar::Scalar(c) => show_const(*c),
+        ImpArrayOrScalar::Array(arr) => show_axes(&arr.shp, &arr.data),
+    }
+}
+
+fn show_axes(shp: &[usize], data: &[Const]) -> String {
+    let Some((&len, rest)) = shp.split_first() else {
+        return show_const(data[0]);
+    };
+
+    let stride: usize = rest.iter().product();
+    let elems: Vec<String> = (0..len)
+        .map(|i| show_axes(rest, &data[i * stride..(i + 1) * stride]))
+        .collect();
+    format!("[{}]", elems.join(", "))
+}
+
+/// Constants other than `usize` and `bool` carry a type suffix, such that they parse back to the same type.
+fn show_const(c: Const) -> String {
+    use Const::*;
+    match c {
+        Bool(v) => v.to_string(),
+        Usize(v) => v.to_string(),
+        U32(v) => format!("{v}u32"),
+        U64(v) => format!("{v}u64"),
+        I32(v) => format!("{v}i32"),
+        I64(v) => format!("{v}i64"),
+        F32(v) => format!("{v:?}f32"),
+        F64(v) => format!("{v:?}f64"),
+    }
+}
@@ -7,105 +7,208 @@ mod tp;
 mod pre;
 mod tc;
 mod opt;
+mod mm;
 mod cg;
+mod interp;
+mod error;
 
-use std::{fs, path::PathBuf};
+use std::{fs, path::{Path, PathBuf}};
 
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, ValueEnum};
 
-pub fn compile(options: Options) {
-    let src = fs::read_to_string(&options.infile).unwrap();
+use ast::{ParsedAst, Program, TypedAst};
+
+pub use ast::{Const, Span};
+pub use error::{CompileError, Diagnostic, RunError};
+pub use interp::{parse_value, show_value, RuntimeError, Value};
+pub use scp::{ModuleError, ParseError};
+pub use tp::TypePatternError;
+pub use tc::{DispatchError, InferenceError, OverloadError};
+
+/// The generated sources of a compiled program.
+///
+/// When compilation is stopped early with `-b`, the intermediate result is
+/// printed instead and these are left empty.
+#[derive(Clone, Debug, Default)]
+pub struct Artifacts {
+    pub c: String,
+    pub h: String,
+    pub rs: String,
+}
+
+pub fn compile(options: Options) -> Result<Artifacts, CompileError> {
+    let src = fs::read_to_string(&options.infile)
+        .map_err(|error| CompileError::Io { path: options.infile.clone(), error })?;
     if matches!(options.b, Some(Phase::RD)) {
         println!("{}", src.trim_end_matches('\n'));
-        return;
+        return Ok(Artifacts::default());
     }
 
-    let mut ast = scp::scanparse(&src).unwrap();
+    let (mut ast, imports) = scp::load_modules(&options.infile, &options.include)?;
+    check_imports(&imports, &options.include)?;
     if matches!(options.b, Some(Phase::SCP)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
-    let mut ast = tp::check_tp(ast).unwrap();
+    let mut ast = tp::check_tp(ast)
+        .map_err(|errs| CompileError::invalid(&options.infile, &src, errs))?;
     if matches!(options.b, Some(Phase::CTP)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
     tp::analyse_tp(&mut ast);
     if matches!(options.b, Some(Phase::ATP)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
     pre::flatten(&mut ast);
     if matches!(options.b, Some(Phase::FLT)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
-    let mut ast = pre::to_ssa(ast);
+    let mut ast = pre::to_ssa(ast)
+        .map_err(|errs| CompileError::invalid(&options.infile, &src, errs))?;
     if matches!(options.b, Some(Phase::SSA)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
-    tc::type_infer(&mut ast).unwrap();
+    tc::check_overloads(&ast)
+        .map_err(|errs| CompileError::invalid(&options.infile, &src, errs))?;
+    tc::type_infer(&mut ast)
+        .map_err(|errs| CompileError::invalid(&options.infile, &src, errs))?;
     if matches!(options.b, Some(Phase::TI)) {
         let mut ast = ast;
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
-    let mut ast = tc::resolve_dispatch(ast).unwrap();
+    let mut ast = tc::resolve_dispatch(ast)
+        .map_err(|errs| CompileError::invalid(&options.infile, &src, errs))?;
     if matches!(options.b, Some(Phase::DR)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
+    }
+
+    tc::specialise(&mut ast);
+    if matches!(options.b, Some(Phase::SP)) {
+        print!("{}", show::show(&mut ast));
+        return Ok(Artifacts::default());
     }
 
     opt::constant_fold(&mut ast);
     if matches!(options.b, Some(Phase::CF)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
+    }
+
+    opt::with_loop_folding(&mut ast);
+    if matches!(options.b, Some(Phase::WLF)) {
+        print!("{}", show::show(&mut ast));
+        return Ok(Artifacts::default());
+    }
+
+    opt::with_loop_scalarisation(&mut ast);
+    if matches!(options.b, Some(Phase::WLS)) {
+        print!("{}", show::show(&mut ast));
+        return Ok(Artifacts::default());
     }
 
     opt::dead_code_removal(&mut ast);
     if matches!(options.b, Some(Phase::DCR)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
+    }
+
+    mm::insert_frees(&mut ast);
+    if matches!(options.b, Some(Phase::IFR)) {
+        print!("{}", show::show(&mut ast));
+        return Ok(Artifacts::default());
     }
 
     cg::rename_fundefs(&mut ast);
     if matches!(options.b, Some(Phase::RNF)) {
         print!("{}", show::show(&mut ast));
-        return;
+        return Ok(Artifacts::default());
     }
 
     let c_str = cg::emit_c(&mut ast, options.module_name());
     if matches!(options.b, Some(Phase::CGC)) {
         print!("{}", c_str);
-        return;
+        return Ok(Artifacts::default());
     }
 
     let h_str = cg::emit_h(&mut ast);
     if matches!(options.b, Some(Phase::CGH)) {
         print!("{}", h_str);
-        return;
+        return Ok(Artifacts::default());
     }
 
     let rs_str = cg::emit_ffi(&mut ast);
     if matches!(options.b, Some(Phase::CGRS)) {
         print!("{}", rs_str);
-        return;
+        return Ok(Artifacts::default());
     }
 
+    let artifacts = Artifacts {
+        c: c_str,
+        h: h_str,
+        rs: rs_str,
+    };
+
     if let Some(c_path) = options.c_path() {
         let h_path = options.h_path().unwrap();
         let rs_path = options.rs_path().unwrap();
-        fs::write(c_path, c_str).unwrap();
-        fs::write(h_path, h_str).unwrap();
-        fs::write(rs_path, rs_str).unwrap();
+        for (path, contents) in [(c_path, &artifacts.c), (h_path, &artifacts.h), (rs_path, &artifacts.rs)] {
+            fs::write(&path, contents)
+                .map_err(|error| CompileError::Io { path, error })?;
+        }
     }
+
+    Ok(artifacts)
+}
+
+/// Call a function of the program with the interpreter, without generating any code.
+pub fn run(options: RunOptions) -> Result<Value, RunError> {
+    let (ast, imports) = scp::load_modules(&options.infile, &options.include)?;
+    check_imports(&imports, &options.include)?;
+    let ast = front_end(ast, &options.infile)?;
+
+    let value = interp::interpret(&ast, &options.function, options.args)?;
+    Ok(value)
+}
+
+/// Run all phases after scanning-parsing, up to and including dispatch resolution.
+fn front_end(ast: Program<'static, ParsedAst>, infile: &Path) -> Result<Program<'static, TypedAst>, CompileError> {
+    let src = fs::read_to_string(infile)
+        .map_err(|error| CompileError::Io { path: infile.to_owned(), error })?;
+
+    let mut ast = tp::check_tp(ast)
+        .map_err(|errs| CompileError::invalid(infile, &src, errs))?;
+    tp::analyse_tp(&mut ast);
+    pre::flatten(&mut ast);
+    let mut ast = pre::to_ssa(ast)
+        .map_err(|errs| CompileError::invalid(infile, &src, errs))?;
+    tc::check_overloads(&ast)
+        .map_err(|errs| CompileError::invalid(infile, &src, errs))?;
+    tc::type_infer(&mut ast)
+        .map_err(|errs| CompileError::invalid(infile, &src, errs))?;
+    tc::resolve_dispatch(ast)
+        .map_err(|errs| CompileError::invalid(infile, &src, errs))
+}
+
+/// Check every imported module by itself, such that its errors are reported against its own source.
+/// The imported functions are still checked again as part of the importing program.
+fn check_imports(imports: &[PathBuf], include: &[PathBuf]) -> Result<(), CompileError> {
+    for path in imports {
+        let (ast, _) = scp::load_modules(path, include)?;
+        front_end(ast, path)?;
+    }
+    Ok(())
 }
 
 #[derive(Parser)]
@@ -117,6 +220,11 @@ pub struct Options {
     #[arg(short('o'), long("out"))]
     pub outdir: Option<PathBuf>,
 
+    /// Directories to search for imported modules,
+    /// after the directory of the importing module
+    #[arg(short('I'), long("include"))]
+    pub include: Vec<PathBuf>,
+
     pub infile: PathBuf,
 }
 
@@ -152,6 +260,23 @@ impl Options {
     }
 }
 
+#[derive(Args)]
+pub struct RunOptions {
+    /// Directories to search for imported modules,
+    /// after the directory of the importing module
+    #[arg(short('I'), long("include"))]
+    pub include: Vec<PathBuf>,
+
+    pub infile: PathBuf,
+
+    /// Name of the function to call
+    pub function: String,
+
+    /// Arguments of the function, as literals (e.g. `3`, `-1i32`, `[[1, 2], [3, 4]]`)
+    #[arg(value_parser = parse_value, allow_hyphen_values = true)]
+    pub args: Vec<Value>,
+}
+
 #[derive(ValueEnum)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Phase {
@@ -171,10 +296,18 @@ pub enum Phase {
     TI,
     /// Function dispatch resolution
     DR,
+    /// Specialisation
+    SP,
     /// Constant folding
     CF,
+    /// With-loop folding
+    WLF,
+    /// With-loop scalarisation
+    WLS,
     /// Dead code removal
     DCR,
+    /// Insert frees
+    IFR,
     /// Rename fundefs
     RNF,
     /// C code generation
@@ -0,0 +1,4 @@
+//! # Memory management (`mm`)
+mod insert_frees;
+
+pub use insert_frees::{insert_frees, owner, owns};
@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+
+use crate::ast::*;
+
+/// Arrays are values: every allocation has exactly one owning variable,
+/// and aliases (`x = y`) are resolved through the SSA links.
+///
+/// This pass inserts a `free` directly after the last use of each owned array,
+/// in the body that allocated it. Uses inside nested tensor, fold, and conditional
+/// bodies count as a use of the statement containing them, so a buffer is never released
+/// halfway through a loop. Returned values are moved out of their body and are not freed.
+pub fn insert_frees<'ast>(program: &mut Program<'ast, TypedAst>) {
+    InsertFrees.trav_program(program);
+}
+
+/// Local variable owning the heap buffer that `id` refers to, if any.
+///
+/// Arguments are owned by the caller, and index vectors and the views of them live
+/// on the stack, thus neither they nor their aliases have an owner.
+pub fn owner<'ast>(id: &Id<'ast, TypedAst>) -> Option<&'ast VarInfo<'ast, TypedAst>> {
+    let Id::Var(lvis) = id else {
+        return None;
+    };
+
+    match lvis.ssa {
+        Some(Expr::Id(alias)) => owner(alias),
+        Some(expr) if lvis.ty.is_array() && allocates(expr) => Some(lvis),
+        _ => None,
+    }
+}
+
+/// Whether `lvis` is the owner of its buffer, rather than an alias.
+pub fn owns<'ast>(lvis: &'ast VarInfo<'ast, TypedAst>) -> bool {
+    owner(&Id::Var(lvis)).is_some_and(|owner| std::ptr::eq(owner, lvis))
+}
+
+fn allocates(expr: &Expr<'_, TypedAst>) -> bool {
+    matches!(
+        expr,
+        Expr::Cond(_) | Expr::Call(_) | Expr::Tensor(_) | Expr::Fold(_) | Expr::Array(_) | Expr::Prf(Prf::ShapeA(_))
+    )
+}
+
+fn ptr<'ast>(lvis: &VarInfo<'ast, TypedAst>) -> *const () {
+    lvis as *const _ as *const ()
+}
+
+struct InsertFrees;
+
+impl InsertFrees {
+    fn insert_frees<'ast>(&mut self, prelude: &[Assign<'ast, TypedAst>], body: &mut Body<'ast, TypedAst>) {
+        // Nested bodies release their own allocations first
+        for stmt in &mut body.stmts {
+            self.trav_stmt(stmt);
+        }
+
+        let mut defs = Vec::new();
+        let mut uses = Vec::with_capacity(prelude.len() + body.stmts.len());
+
+        for (i, assign) in prelude.iter().enumerate() {
+            if owns(assign.lhs) {
+                defs.push((i, assign.lhs));
+            }
+            let mut collect = CollectOwners::new();
+            collect.trav_assign(&mut assign.clone());
+            uses.push(collect.owners);
+        }
+
+        for (i, stmt) in body.stmts.iter_mut().enumerate() {
+            if let Stmt::Assign(assign) = stmt
+                && owns(assign.lhs)
+            {
+                defs.push((prelude.len() + i, assign.lhs));
+            }
+            let mut collect = CollectOwners::new();
+            collect.trav_stmt(stmt);
+            uses.push(collect.owners);
+        }
+
+        let escaping = owner(&body.ret).map(ptr);
+
+        // Index of the statement after which each buffer can be released
+        let mut frees: Vec<Vec<&'ast VarInfo<'ast, TypedAst>>> = vec![Vec::new(); uses.len()];
+        for (def, lvis) in defs {
+            if escaping == Some(ptr(lvis)) {
+                continue;
+            }
+
+            let last_use = (def..uses.len())
+                .rev()
+                .find(|i| uses[*i].contains(&ptr(lvis)))
+                .unwrap_or(def);
+            frees[last_use].push(lvis);
+        }
+
+        let mut stmts = Vec::with_capacity(body.stmts.len());
+        for &lvis in frees[..prelude.len()].iter().flatten() {
+            stmts.push(Stmt::Free(Free { id: Id::Var(lvis) }));
+        }
+        for (stmt, frees) in body.stmts.drain(..).zip(&frees[prelude.len()..]) {
+            stmts.push(stmt);
+            for &lvis in frees {
+                stmts.push(Stmt::Free(Free { id: Id::Var(lvis) }));
+            }
+        }
+        body.stmts = stmts;
+    }
+}
+
+impl<'ast> Traverse<'ast> for InsertFrees {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_fundef(&mut self, fundef: &mut Fundef<'ast, Self::Ast>) {
+        let prelude = fundef.shape_prelude.clone();
+        self.insert_frees(&prelude, &mut fundef.body);
+    }
+
+    fn trav_body(&mut self, body: &mut Body<'ast, Self::Ast>) {
+        self.insert_frees(&[], body);
+    }
+}
+
+/// Collects the owners of all buffers used by a statement, including nested bodies.
+struct CollectOwners {
+    owners: HashSet<*const ()>,
+}
+
+impl CollectOwners {
+    fn new() -> Self {
+        Self {
+            owners: HashSet::new(),
+        }
+    }
+}
+
+impl<'ast> Traverse<'ast> for CollectOwners {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_id(&mut self, id: &mut Id<'ast, Self::Ast>) {
+        if let Some(lvis) = owner(id) {
+            self.owners.insert(ptr(lvis));
+        }
+    }
+}
@@ -1,6 +1,10 @@
 //! # Optimisation cycle (`opt`)
 mod constant_fold;
 mod dead_code_removal;
+mod with_loop_folding;
+mod with_loop_scalarisation;
 
 pub use constant_fold::constant_fold;
 pub use dead_code_removal::dead_code_removal;
+pub use with_loop_folding::with_loop_folding;
+pub use with_loop_scalarisation::with_loop_scalarisation;
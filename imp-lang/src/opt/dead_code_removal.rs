@@ -65,6 +65,10 @@ impl<'ast> Traverse<'ast> for DeadCodeRemoval {
                     self.trav_printf(&mut printf);
                     kept_rev.push(Stmt::Printf(printf));
                 }
+                Stmt::Free(mut free) => {
+                    self.trav_free(&mut free);
+                    kept_rev.push(Stmt::Free(free));
+                }
             }
         }
 
@@ -72,22 +76,22 @@ impl<'ast> Traverse<'ast> for DeadCodeRemoval {
         body.stmts = kept_rev;
     }
 
-    fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, Self::Ast>) {
+    fn trav_partition(&mut self, partition: &mut Partition<'ast, Self::Ast>) {
         let outer_used = mem::take(&mut self.used);
 
-        if let Some(lb) = &mut tensor.lb {
-            self.trav_id(lb);
+        for id in partition.lb.iter_mut().chain([&mut partition.ub]).chain(&mut partition.step).chain(&mut partition.width) {
+            self.trav_id(id);
         }
-        self.trav_id(&mut tensor.ub);
-        self.used.insert(Self::ptr(tensor.iv));
+        self.used.insert(Self::ptr(partition.iv));
 
-        self.trav_body(&mut tensor.body);
+        self.trav_body(&mut partition.body);
 
-        self.used = outer_used;
-        if let Some(lb) = &mut tensor.lb {
-            self.trav_id(lb);
+        // Variables of the enclosing bodies remain used by this partition
+        let inner_used = mem::replace(&mut self.used, outer_used);
+        self.used.extend(inner_used);
+        for id in partition.lb.iter_mut().chain([&mut partition.ub]).chain(&mut partition.step).chain(&mut partition.width) {
+            self.trav_id(id);
         }
-        self.trav_id(&mut tensor.ub);
     }
 
     fn trav_fold(&mut self, fold: &mut Fold<'ast, Self::Ast>) {
@@ -101,7 +105,7 @@ impl<'ast> Traverse<'ast> for DeadCodeRemoval {
             }
         }
 
-        self.trav_tensor(&mut fold.selection);
+        self.trav_partition(&mut fold.selection);
     }
 
     fn trav_array(&mut self, array: &mut Array<'ast, Self::Ast>) {
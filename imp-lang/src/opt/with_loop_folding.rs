@@ -0,0 +1,537 @@
+use std::{collections::HashMap, mem, ptr};
+
+use typed_arena::Arena;
+
+use crate::ast::*;
+
+/// Fuse tensors into the tensors that consume them element by element.
+///
+/// A consumer whose body selects `x = @selVxA(iv, p)` with its own index vector, where `p` is
+/// a tensor with scalar elements, computes `x` with a copy of the body of `p` instead.
+/// Functions that consist of nothing but such a selection are treated the same.
+///
+/// If `p` has a lower bound that the consumer does not share, the partition of the consumer that
+/// selects `x` is split at that bound, into the part inside it that computes `x` with the body of `p`,
+/// and one part per axis below it in which `x` is zero. This needs the rank to be known, and is only
+/// done where the selection is in the body of the partition itself, which has no `step`, of a tensor
+/// without a target that selects every index. Elsewhere, `x` becomes a conditional that yields zero
+/// below the bound and the body of `p` otherwise. Upper bounds need no such treatment, as the consumer
+/// only selects below the upper bound of `p`, which is its shape.
+///
+/// Producers with a `step`, a target or several partitions are not fused. The producer itself is left
+/// for dead code removal.
+pub fn with_loop_folding<'ast>(program: &mut Program<'ast, TypedAst>) {
+    // Clones made by specialisation are not in the overloads, but belong to the family of
+    // the function they were made of, which has the same name and base types
+    let mut selections = HashMap::new();
+    for fundef in program.fundefs.iter_mut() {
+        let family = program.overloads.get(&fundef.name).and_then(|group| group.get(&fundef.signature()));
+        // Calls into larger families might be dispatched elsewhere at runtime
+        if family.is_some_and(|family| family.len() == 1)
+            && let Some(positions) = selection_positions(fundef)
+        {
+            selections.insert(&*fundef as *const _, positions);
+        }
+    }
+
+    let mut wlf = WithLoopFolding::new(selections);
+    for fundef in program.fundefs.iter_mut() {
+        wlf.trav_fundef(fundef);
+    }
+}
+
+struct WithLoopFolding<'ast> {
+    /// Functions that only select a scalar using `@selVxA`,
+    /// mapped to the positions of the index vector and the array in their arguments.
+    selections: HashMap<*const Fundef<'ast, TypedAst>, (usize, usize)>,
+    decs: Option<&'ast Arena<VarInfo<'ast, TypedAst>>>,
+    exprs: Option<&'ast Arena<Expr<'ast, TypedAst>>>,
+    args: Vec<Farg>,
+    /// Index vectors and lower bounds of the enclosing tensors.
+    generators: Vec<(&'ast VarInfo<'ast, TypedAst>, Option<Id<'ast, TypedAst>>)>,
+    /// Copies of the variables of the producer that is being fused, or of the partition that is being split.
+    copies: HashMap<*const VarInfo<'ast, TypedAst>, &'ast VarInfo<'ast, TypedAst>>,
+    /// Index vectors of partitions that were split, mapped to the lower bound they lie within.
+    splits: HashMap<*const VarInfo<'ast, TypedAst>, Id<'ast, TypedAst>>,
+    /// Statements that compute the bounds of split partitions, to insert before their tensor.
+    bounds: Vec<Stmt<'ast, TypedAst>>,
+    uid: usize,
+}
+
+impl<'ast> WithLoopFolding<'ast> {
+    fn new(selections: HashMap<*const Fundef<'ast, TypedAst>, (usize, usize)>) -> Self {
+        Self {
+            selections,
+            decs: None,
+            exprs: None,
+            args: Vec::new(),
+            generators: Vec::new(),
+            copies: HashMap::new(),
+            splits: HashMap::new(),
+            bounds: Vec::new(),
+            uid: 0,
+        }
+    }
+
+    fn alloc_lvis(&mut self, name: &str, ty: Type, ssa: Option<&'ast Expr<'ast, TypedAst>>) -> &'ast VarInfo<'ast, TypedAst> {
+        self.uid += 1;
+        let name = format!("{}_wlf{}", name, self.uid);
+        self.decs.unwrap().alloc(VarInfo { name, ty, ssa })
+    }
+
+    fn alloc_expr(&self, expr: Expr<'ast, TypedAst>) -> &'ast Expr<'ast, TypedAst> {
+        self.exprs.unwrap().alloc(expr)
+    }
+
+    fn id_type(&self, id: &Id<'ast, TypedAst>) -> Type {
+        match id {
+            Id::Arg(i) => self.args[*i].ty.clone(),
+            Id::Var(v) => v.ty.clone(),
+        }
+    }
+
+    /// Assign `expr` to a new variable, returning its identifier.
+    fn define(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, name: &str, ty: Type, expr: Expr<'ast, TypedAst>, span: Span) -> Id<'ast, TypedAst> {
+        let expr = self.alloc_expr(expr);
+        let lhs = self.alloc_lvis(name, ty, Some(expr));
+        stmts.push(Stmt::Assign(Assign { lhs, expr, span }));
+        Id::Var(lhs)
+    }
+
+    /// The index vector and array of a scalar selection.
+    fn selection(&self, expr: &Expr<'ast, TypedAst>) -> Option<(Id<'ast, TypedAst>, Id<'ast, TypedAst>)> {
+        match expr {
+            Expr::Prf(Prf::SelVxA(idx, arr)) => Some((*idx, *arr)),
+            Expr::Call(call) => {
+                let CallTarget::Function(fundef) = call.id;
+                let (idx, arr) = self.selections.get(&(fundef as *const _))?;
+                Some((call.args[*idx], call.args[*arr]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace `assign` by the body of the tensor it selects from, if possible.
+    fn fuse(&mut self, assign: &Assign<'ast, TypedAst>) -> Option<Vec<Stmt<'ast, TypedAst>>> {
+        let (Id::Var(iv), arr) = self.selection(assign.expr)? else {
+            return None;
+        };
+        let &(iv, consumer_lb) = self.generators.iter().rev().find(|(v, _)| ptr::eq(*v, iv))?;
+        let producer = self.producer(iv, &arr)?;
+
+        let split_lb = self.splits.get(&(iv as *const _));
+        let guard_lb = match (producer.lb, consumer_lb) {
+            (Some(p), Some(c)) if same_id(&p, &c) => None,
+            (Some(p), _) if split_lb.is_some_and(|lb| same_id(&p, lb)) => None,
+            (lb, _) => lb,
+        };
+        let guard = match guard_lb {
+            Some(lb) => Some((lb, iv_len(producer.iv)?, zero(&assign.lhs.ty.ty)?)),
+            None => None,
+        };
+
+        self.copies.clear();
+        self.copies.insert(producer.iv as *const _, iv);
+        let mut body = self.copy_body(&producer.body);
+
+        let (mut stmts, expr) = match guard {
+            None => (mem::take(&mut body.stmts), Expr::Id(body.ret)),
+            Some((lb, rank, zero)) => {
+                let mut stmts = Vec::new();
+                let inside = self.lower_bound_check(&mut stmts, iv, lb, rank, assign.span);
+                let zero = self.define(&mut stmts, "zero", Type::scalar(assign.lhs.ty.ty.clone()), Expr::Const(zero), assign.span);
+                let cond = Cond {
+                    cond: inside,
+                    then_branch: body,
+                    else_branch: Body { stmts: Vec::new(), ret: zero },
+                    span: assign.span,
+                };
+                (stmts, Expr::Cond(cond))
+            }
+        };
+
+        let expr = self.alloc_expr(expr);
+        unsafe {
+            let lhs = assign.lhs as *const VarInfo<'ast, TypedAst> as *mut VarInfo<'ast, TypedAst>;
+            (*lhs).ssa = Some(expr);
+        }
+        stmts.push(Stmt::Assign(Assign { lhs: assign.lhs, expr, span: assign.span }));
+        Some(stmts)
+    }
+
+    /// The only partition of the tensor `arr`, if its body can replace a selection from it with index vector `iv`.
+    fn producer(&self, iv: &VarInfo<'ast, TypedAst>, arr: &Id<'ast, TypedAst>) -> Option<&'ast Partition<'ast, TypedAst>> {
+        let producer = producer_of(arr)?;
+
+        if !self.id_type(&producer.body.ret).is_scalar() || producer.iv.ty.ty != iv.ty.ty || producer.step.is_some() {
+            return None;
+        }
+        if let (Some(p), Some(c)) = (iv_len(producer.iv), iv_len(iv)) && p != c {
+            return None;
+        }
+        Some(producer)
+    }
+
+    /// Split `partition` at the lower bound of a producer that its body selects from, if it does not share it.
+    ///
+    /// The partition itself keeps the part within that bound, the returned partitions cover the rest of
+    /// its generator, with the selection replaced by zero. Partition `d` of those selects the indices that
+    /// are within the bound in the axes before `d`, below it in axis `d`, and anywhere in the axes after `d`.
+    fn split(&mut self, partition: &mut Partition<'ast, TypedAst>, stmts: &mut Vec<Stmt<'ast, TypedAst>>) -> Vec<Partition<'ast, TypedAst>> {
+        let rank = match iv_len(partition.iv) {
+            Some(rank) if rank > 0 && partition.step.is_none() => rank,
+            _ => return Vec::new(),
+        };
+        let found = partition.body.stmts.iter().find_map(|stmt| {
+            let Stmt::Assign(assign) = stmt else {
+                return None;
+            };
+            let (Id::Var(iv), arr) = self.selection(assign.expr)? else {
+                return None;
+            };
+            if !ptr::eq(iv, partition.iv) {
+                return None;
+            }
+            let lb = self.producer(iv, &arr)?.lb?;
+            // The bound must be known before the tensor, outside of the body
+            let outside = match lb {
+                Id::Var(v) => !partition.body.defines(v),
+                Id::Arg(_) => true,
+            };
+            let shared = partition.lb.is_some_and(|c| same_id(&c, &lb));
+            (outside && !shared).then_some((assign.lhs, lb, zero(&assign.lhs.ty.ty)?))
+        });
+        let Some((x, producer_lb, x_zero)) = found else {
+            return Vec::new();
+        };
+        let Some(bound_zero) = zero(&self.id_type(&partition.ub).ty) else {
+            return Vec::new();
+        };
+
+        let span = partition.span;
+        let bound_ty = self.id_type(&partition.ub).ty;
+        let vector_ty = Type::vector_dim(bound_ty.clone(), DimPattern::Known(rank));
+        let mut lower = Vec::with_capacity(rank);
+        let mut inner = Vec::with_capacity(rank);
+        let mut upper = Vec::with_capacity(rank);
+        for d in 0..rank {
+            let l = match partition.lb {
+                Some(lb) => self.component(stmts, lb, d, &bound_ty, span),
+                None => self.define(stmts, "split", Type::scalar(bound_ty.clone()), Expr::Const(bound_zero), span),
+            };
+            let p = self.component(stmts, producer_lb, d, &bound_ty, span);
+            let u = self.component(stmts, partition.ub, d, &bound_ty, span);
+            let max = self.choose(stmts, Prf::GtSxS(l, p), l, p, span);
+            let m = self.choose(stmts, Prf::LtSxS(max, u), max, u, span);
+            lower.push(l);
+            inner.push(m);
+            upper.push(u);
+        }
+
+        let mut pieces = Vec::with_capacity(rank);
+        for d in 0..rank {
+            let lb: Vec<Id<'ast, TypedAst>> = inner[..d].iter().chain(&lower[d..]).copied().collect();
+            let ub: Vec<Id<'ast, TypedAst>> = upper[..d].iter().chain([&inner[d]]).chain(&upper[d + 1..]).copied().collect();
+
+            self.copies.clear();
+            let mut piece = self.copy_partition(partition);
+            piece.lb = Some(self.define(stmts, "split", vector_ty.clone(), Expr::Array(Array { elems: lb, span }), span));
+            piece.ub = self.define(stmts, "split", vector_ty.clone(), Expr::Array(Array { elems: ub, span }), span);
+
+            let x = self.copies[&(x as *const _)];
+            let expr = self.alloc_expr(Expr::Const(x_zero));
+            unsafe {
+                let lhs = x as *const VarInfo<'ast, TypedAst> as *mut VarInfo<'ast, TypedAst>;
+                (*lhs).ssa = Some(expr);
+            }
+            for stmt in &mut piece.body.stmts {
+                if let Stmt::Assign(assign) = stmt && ptr::eq(assign.lhs, x) {
+                    assign.expr = expr;
+                }
+            }
+            pieces.push(piece);
+        }
+
+        partition.lb = Some(self.define(stmts, "split", vector_ty, Expr::Array(Array { elems: inner, span }), span));
+        self.splits.insert(partition.iv as *const _, producer_lb);
+        pieces
+    }
+
+    /// Component `d` of the vector `v`.
+    fn component(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, v: Id<'ast, TypedAst>, d: usize, ty: &BaseType, span: Span) -> Id<'ast, TypedAst> {
+        let axis = self.define(stmts, "split", Type::scalar(BaseType::Usize), Expr::Const(Const::Usize(d)), span);
+        let idx = self.define(stmts, "split", Type::vector_dim(BaseType::Usize, DimPattern::Known(1)), Expr::Array(Array { elems: vec![axis], span }), span);
+        self.define(stmts, "split", Type::scalar(ty.clone()), Expr::Prf(Prf::SelVxA(idx, v)), span)
+    }
+
+    /// `a` if the comparison `cmp` holds, `b` otherwise.
+    fn choose(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, cmp: Prf<'ast, TypedAst>, a: Id<'ast, TypedAst>, b: Id<'ast, TypedAst>, span: Span) -> Id<'ast, TypedAst> {
+        let ty = self.id_type(&a);
+        let holds = self.define(stmts, "split", Type::scalar(BaseType::Bool), Expr::Prf(cmp), span);
+        let cond = Cond {
+            cond: holds,
+            then_branch: Body { stmts: Vec::new(), ret: a },
+            else_branch: Body { stmts: Vec::new(), ret: b },
+            span,
+        };
+        self.define(stmts, "split", ty, Expr::Cond(cond), span)
+    }
+
+    /// Whether every component of `iv` is at least the corresponding component of `lb`.
+    fn lower_bound_check(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, iv: &'ast VarInfo<'ast, TypedAst>, lb: Id<'ast, TypedAst>, rank: usize, span: Span) -> Id<'ast, TypedAst> {
+        let bool_ty = Type::scalar(BaseType::Bool);
+        let no = self.define(stmts, "lb", bool_ty.clone(), Expr::Const(Const::Bool(false)), span);
+        let lb_ty = Type::scalar(self.id_type(&lb).ty);
+
+        let mut inside = None;
+        for d in 0..rank {
+            let axis = self.define(stmts, "lb", Type::scalar(BaseType::Usize), Expr::Const(Const::Usize(d)), span);
+            let idx = self.define(stmts, "lb", Type::vector_dim(BaseType::Usize, DimPattern::Known(1)), Expr::Array(Array { elems: vec![axis], span }), span);
+            let i = self.define(stmts, "lb", Type::scalar(iv.ty.ty.clone()), Expr::Prf(Prf::SelVxA(idx, Id::Var(iv))), span);
+            let l = self.define(stmts, "lb", lb_ty.clone(), Expr::Prf(Prf::SelVxA(idx, lb)), span);
+            let ge = self.define(stmts, "lb", bool_ty.clone(), Expr::Prf(Prf::GeSxS(i, l)), span);
+
+            inside = Some(match inside {
+                None => ge,
+                Some(prev) => {
+                    let both = Cond {
+                        cond: prev,
+                        then_branch: Body { stmts: Vec::new(), ret: ge },
+                        else_branch: Body { stmts: Vec::new(), ret: no },
+                        span,
+                    };
+                    self.define(stmts, "lb", bool_ty.clone(), Expr::Cond(both), span)
+                }
+            });
+        }
+
+        inside.unwrap_or(no)
+    }
+
+    fn copy_id(&self, id: &Id<'ast, TypedAst>) -> Id<'ast, TypedAst> {
+        match id {
+            Id::Var(v) => Id::Var(self.copies.get(&(*v as *const _)).copied().unwrap_or(v)),
+            Id::Arg(i) => Id::Arg(*i),
+        }
+    }
+
+    fn copy_body(&mut self, body: &Body<'ast, TypedAst>) -> Body<'ast, TypedAst> {
+        let stmts = body.stmts.iter().map(|stmt| match stmt {
+            Stmt::Assign(assign) => {
+                let expr = self.copy_expr(assign.expr);
+                let expr = self.alloc_expr(expr);
+                let lhs = self.alloc_lvis(&assign.lhs.name, assign.lhs.ty.clone(), Some(expr));
+                self.copies.insert(assign.lhs as *const _, lhs);
+                Stmt::Assign(Assign { lhs, expr, span: assign.span })
+            }
+            Stmt::Printf(printf) => Stmt::Printf(Printf { id: self.copy_id(&printf.id) }),
+            Stmt::Free(free) => Stmt::Free(Free { id: self.copy_id(&free.id) }),
+        }).collect();
+
+        Body { stmts, ret: self.copy_id(&body.ret) }
+    }
+
+    fn copy_tensor(&mut self, tensor: &Tensor<'ast, TypedAst>) -> Tensor<'ast, TypedAst> {
+        Tensor {
+            target: tensor.target.map(|target| self.copy_id(&target)),
+            partitions: tensor.partitions.iter().map(|partition| self.copy_partition(partition)).collect(),
+            span: tensor.span,
+        }
+    }
+
+    fn copy_partition(&mut self, partition: &Partition<'ast, TypedAst>) -> Partition<'ast, TypedAst> {
+        let iv = self.alloc_lvis(&partition.iv.name, partition.iv.ty.clone(), None);
+        self.copies.insert(partition.iv as *const _, iv);
+        Partition {
+            body: self.copy_body(&partition.body),
+            iv,
+            lb: partition.lb.map(|lb| self.copy_id(&lb)),
+            ub: self.copy_id(&partition.ub),
+            step: partition.step.map(|step| self.copy_id(&step)),
+            width: partition.width.map(|width| self.copy_id(&width)),
+            span: partition.span,
+        }
+    }
+
+    fn copy_expr(&mut self, expr: &Expr<'ast, TypedAst>) -> Expr<'ast, TypedAst> {
+        match expr {
+            Expr::Cond(cond) => Expr::Cond(Cond {
+                cond: self.copy_id(&cond.cond),
+                then_branch: self.copy_body(&cond.then_branch),
+                else_branch: self.copy_body(&cond.else_branch),
+                span: cond.span,
+            }),
+            Expr::Call(call) => Expr::Call(Call {
+                id: call.id.clone(),
+                args: call.args.iter().map(|arg| self.copy_id(arg)).collect(),
+                span: call.span,
+            }),
+            Expr::Prf(prf) => {
+                let mut prf = prf.clone();
+                for arg in prf.args_mut() {
+                    *arg = self.copy_id(arg);
+                }
+                Expr::Prf(prf)
+            }
+            Expr::Tensor(tensor) => Expr::Tensor(self.copy_tensor(tensor)),
+            Expr::Fold(fold) => {
+                let foldfun = match &fold.foldfun {
+                    FoldFun::Name(id) => FoldFun::Name(id.clone()),
+                    FoldFun::Apply { id, args } => FoldFun::Apply {
+                        id: id.clone(),
+                        args: args.iter().map(|arg| match arg {
+                            FoldFunArg::Placeholder => FoldFunArg::Placeholder,
+                            FoldFunArg::Bound(bound) => FoldFunArg::Bound(self.copy_id(bound)),
+                        }).collect(),
+                    },
+                };
+                Expr::Fold(Fold {
+                    neutral: self.copy_id(&fold.neutral),
+                    foldfun,
+                    selection: self.copy_partition(&fold.selection),
+                    span: fold.span,
+                })
+            }
+            Expr::Array(array) => Expr::Array(Array {
+                elems: array.elems.iter().map(|elem| self.copy_id(elem)).collect(),
+                span: array.span,
+            }),
+            Expr::Record(record) => Expr::Record(Record {
+                name: record.name.clone(),
+                fields: record.fields.iter().map(|(name, value)| (name.clone(), self.copy_id(value))).collect(),
+                span: record.span,
+            }),
+            Expr::Field(field) => Expr::Field(Field {
+                record: self.copy_id(&field.record),
+                name: field.name.clone(),
+                span: field.span,
+            }),
+            Expr::Id(id) => Expr::Id(self.copy_id(id)),
+            Expr::Const(c) => Expr::Const(*c),
+        }
+    }
+}
+
+impl<'ast> Traverse<'ast> for WithLoopFolding<'ast> {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_fundef(&mut self, fundef: &mut Fundef<'ast, TypedAst>) {
+        // SAFETY: allocation arenas are stored in the owning Fundef.
+        self.decs = Some(unsafe { mem::transmute::<&Arena<VarInfo<'ast, TypedAst>>, &'ast Arena<VarInfo<'ast, TypedAst>>>(&fundef.decs) });
+        self.exprs = Some(unsafe { mem::transmute::<&Arena<Expr<'ast, TypedAst>>, &'ast Arena<Expr<'ast, TypedAst>>>(&fundef.exprs) });
+        self.args = fundef.args.clone();
+
+        self.trav_body(&mut fundef.body);
+
+        self.decs = None;
+        self.exprs = None;
+    }
+
+    fn trav_body(&mut self, body: &mut Body<'ast, TypedAst>) {
+        let mut stmts = Vec::with_capacity(body.stmts.len());
+        for mut stmt in mem::take(&mut body.stmts) {
+            self.trav_stmt(&mut stmt);
+            stmts.append(&mut self.bounds);
+            match &stmt {
+                Stmt::Assign(assign) => match self.fuse(assign) {
+                    Some(fused) => stmts.extend(fused),
+                    None => stmts.push(stmt),
+                },
+                _ => stmts.push(stmt),
+            }
+        }
+        body.stmts = stmts;
+    }
+
+    fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, TypedAst>) {
+        // Splitting leaves no index unselected, so it would not keep the zeros of a tensor that has them
+        let mut bounds = Vec::new();
+        if tensor.target.is_none() && !tensor.has_default() {
+            let mut pieces = Vec::new();
+            for partition in &mut tensor.partitions {
+                pieces.extend(self.split(partition, &mut bounds));
+            }
+            tensor.partitions.extend(pieces);
+        }
+
+        for partition in &mut tensor.partitions {
+            self.trav_partition(partition);
+        }
+        self.bounds.extend(bounds);
+    }
+
+    fn trav_partition(&mut self, partition: &mut Partition<'ast, TypedAst>) {
+        self.generators.push((partition.iv, partition.lb));
+        self.trav_body(&mut partition.body);
+        self.generators.pop();
+    }
+}
+
+/// The positions of the index vector and array arguments, if `fundef` only selects a scalar from an array.
+pub(super) fn selection_positions(fundef: &Fundef<'_, TypedAst>) -> Option<(usize, usize)> {
+    if !fundef.ret_type.is_scalar() || !fundef.body.stmts.iter().all(|stmt| matches!(stmt, Stmt::Assign(_))) {
+        return None;
+    }
+
+    let mut ret = fundef.body.ret;
+    loop {
+        match ret {
+            Id::Var(VarInfo { ssa: Some(Expr::Id(alias)), .. }) => ret = *alias,
+            Id::Var(VarInfo { ssa: Some(Expr::Prf(Prf::SelVxA(Id::Arg(idx), Id::Arg(arr)))), .. }) => {
+                return Some((*idx, *arr));
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The only partition of the tensor that defines `id`, looking through aliases,
+/// unless the tensor has a target such that its elements are not in the order of its body.
+fn producer_of<'ast>(id: &Id<'ast, TypedAst>) -> Option<&'ast Partition<'ast, TypedAst>> {
+    let Id::Var(lvis) = id else {
+        return None;
+    };
+
+    match lvis.ssa {
+        Some(Expr::Id(alias)) => producer_of(alias),
+        Some(Expr::Tensor(tensor)) if tensor.target.is_none() => tensor.single(),
+        _ => None,
+    }
+}
+
+/// The statically known length of an index vector.
+fn iv_len(iv: &VarInfo<'_, TypedAst>) -> Option<usize> {
+    match &iv.ty.shape {
+        TypePattern::Axes(axes) => match axes.as_slice() {
+            [AxisPattern::Dim(DimPattern::Known(n))] => Some(*n),
+            _ => None,
+        },
+        TypePattern::Scalar => None,
+    }
+}
+
+fn same_id<'ast>(a: &Id<'ast, TypedAst>, b: &Id<'ast, TypedAst>) -> bool {
+    match (a, b) {
+        (Id::Arg(a), Id::Arg(b)) => a == b,
+        (Id::Var(a), Id::Var(b)) => ptr::eq(*a, *b),
+        _ => false,
+    }
+}
+
+fn zero(ty: &BaseType) -> Option<Const> {
+    match ty {
+        BaseType::Bool => Some(Const::Bool(false)),
+        BaseType::Usize => Some(Const::Usize(0)),
+        BaseType::U32 => Some(Const::U32(0)),
+        BaseType::U64 => Some(Const::U64(0)),
+        BaseType::I32 => Some(Const::I32(0)),
+        BaseType::I64 => Some(Const::I64(0)),
+        BaseType::F32 => Some(Const::F32(0.0)),
+        BaseType::F64 => Some(Const::F64(0.0)),
+        BaseType::Udf(_) | BaseType::Generic(_) => None,
+    }
+}
@@ -0,0 +1,383 @@
+use std::{collections::HashSet, mem, ptr};
+
+use typed_arena::Arena;
+
+use crate::ast::*;
+
+use super::with_loop_folding::selection_positions;
+
+/// Merge tensors whose elements are tensors themselves into a single tensor.
+///
+/// `{ { e | jv < ub_j } | iv < ub_i }` becomes `{ e | kv < ub_i ++ ub_j }`, where `iv` and `jv`
+/// are views of the leading and trailing components of `kv`. This replaces the allocation
+/// of one inner array per outer index by a single allocation of the result.
+///
+/// The remaining statements of the outer body are evaluated once per element rather than once per
+/// outer index, so the tensors are only merged if none of them costs more than a selection.
+///
+/// Neither tensor may be partitioned or have a target. The bounds of the inner tensor must not depend on the outer
+/// index, and the lengths of both index vectors must be known statically.
+pub fn with_loop_scalarisation<'ast>(program: &mut Program<'ast, TypedAst>) {
+    let mut wls = WithLoopScalarisation::new();
+    for fundef in program.fundefs.iter_mut() {
+        wls.trav_fundef(fundef);
+    }
+}
+
+struct WithLoopScalarisation<'ast> {
+    decs: Option<&'ast Arena<VarInfo<'ast, TypedAst>>>,
+    exprs: Option<&'ast Arena<Expr<'ast, TypedAst>>>,
+    args: Vec<Farg>,
+    uid: usize,
+}
+
+/// An outer tensor whose body ends in an inner tensor, with the statements of the outer body split up.
+struct Nesting<'ast> {
+    inner: &'ast Partition<'ast, TypedAst>,
+    /// Definitions of the inner bounds, which can be moved in front of the outer tensor.
+    hoisted: Vec<Stmt<'ast, TypedAst>>,
+    /// The remaining statements of the outer body, without the definition of the inner tensor.
+    stmts: Vec<Stmt<'ast, TypedAst>>,
+    outer_len: usize,
+    inner_len: usize,
+}
+
+impl<'ast> WithLoopScalarisation<'ast> {
+    fn new() -> Self {
+        Self {
+            decs: None,
+            exprs: None,
+            args: Vec::new(),
+            uid: 0,
+        }
+    }
+
+    fn alloc_lvis(&mut self, name: &str, ty: Type, ssa: Option<&'ast Expr<'ast, TypedAst>>) -> &'ast VarInfo<'ast, TypedAst> {
+        self.uid += 1;
+        let name = format!("{}_wls{}", name, self.uid);
+        self.decs.unwrap().alloc(VarInfo { name, ty, ssa })
+    }
+
+    fn alloc_expr(&self, expr: Expr<'ast, TypedAst>) -> &'ast Expr<'ast, TypedAst> {
+        self.exprs.unwrap().alloc(expr)
+    }
+
+    fn id_type(&self, id: &Id<'ast, TypedAst>) -> Type {
+        match id {
+            Id::Arg(i) => self.args[*i].ty.clone(),
+            Id::Var(v) => v.ty.clone(),
+        }
+    }
+
+    /// Assign `expr` to a new variable, returning its identifier.
+    fn define(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, name: &str, ty: Type, expr: Expr<'ast, TypedAst>, span: Span) -> Id<'ast, TypedAst> {
+        let expr = self.alloc_expr(expr);
+        let lhs = self.alloc_lvis(name, ty, Some(expr));
+        stmts.push(Stmt::Assign(Assign { lhs, expr, span }));
+        Id::Var(lhs)
+    }
+
+    /// Assign `expr` to the existing variable `lhs`.
+    fn redefine(&self, lhs: &'ast VarInfo<'ast, TypedAst>, expr: Expr<'ast, TypedAst>, span: Span) -> Stmt<'ast, TypedAst> {
+        let expr = self.alloc_expr(expr);
+        unsafe {
+            let ptr = lhs as *const VarInfo<'ast, TypedAst> as *mut VarInfo<'ast, TypedAst>;
+            (*ptr).ssa = Some(expr);
+        }
+        Stmt::Assign(Assign { lhs, expr, span })
+    }
+
+    /// The scalar component `d` of vector `vec`.
+    fn component(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, vec: Id<'ast, TypedAst>, d: usize, span: Span) -> Id<'ast, TypedAst> {
+        let axis = self.define(stmts, "axis", Type::scalar(BaseType::Usize), Expr::Const(Const::Usize(d)), span);
+        let idx = self.define(stmts, "idx", Type::vector_dim(BaseType::Usize, DimPattern::Known(1)), Expr::Array(Array { elems: vec![axis], span }), span);
+        let ty = Type::scalar(self.id_type(&vec).ty);
+        self.define(stmts, "elem", ty, Expr::Prf(Prf::SelVxA(idx, vec)), span)
+    }
+
+    /// Concatenate two vectors of statically known lengths.
+    /// A missing vector, as for an absent lower bound or step, consists of `fill`.
+    fn concat(&mut self, stmts: &mut Vec<Stmt<'ast, TypedAst>>, name: &str, parts: [(Option<Id<'ast, TypedAst>>, usize); 2], fill: usize, span: Span) -> Id<'ast, TypedAst> {
+        let mut elems = Vec::new();
+        for (vec, len) in parts {
+            match vec {
+                Some(vec) => {
+                    for d in 0..len {
+                        elems.push(self.component(stmts, vec, d, span));
+                    }
+                }
+                None if len > 0 => {
+                    let fill = self.define(stmts, name, Type::scalar(BaseType::Usize), Expr::Const(Const::Usize(fill)), span);
+                    elems.extend(std::iter::repeat_n(fill, len));
+                }
+                None => {}
+            }
+        }
+
+        let ty = Type::vector_dim(BaseType::Usize, DimPattern::Known(elems.len()));
+        self.define(stmts, name, ty, Expr::Array(Array { elems, span }), span)
+    }
+
+    /// Whether the body of `outer` ends in a tensor that can be merged into it.
+    fn nesting(&self, outer: &Partition<'ast, TypedAst>) -> Option<Nesting<'ast>> {
+        let (inner_lvis, aliases) = resolve_aliases(&outer.body.ret)?;
+        let Some(Expr::Tensor(inner @ Tensor { target: None, .. })) = inner_lvis.ssa else {
+            return None;
+        };
+        let inner = inner.single()?;
+
+        let outer_len = iv_len(outer.iv)?;
+        let inner_len = iv_len(inner.iv)?;
+        let bounds = [outer, inner].into_iter()
+            .flat_map(|tensor| [Some(&tensor.ub), tensor.lb.as_ref(), tensor.step.as_ref(), tensor.width.as_ref()]);
+        if bounds.flatten().any(|id| self.id_type(id).ty != BaseType::Usize) {
+            return None;
+        }
+
+        let defs: HashSet<*const VarInfo<'ast, TypedAst>> = outer.body.stmts.iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Assign(assign) => Some(assign.lhs as *const _),
+                _ => None,
+            })
+            .collect();
+
+        // The inner tensor must be the only use of its result, and be defined in the outer body itself
+        let chain: HashSet<*const VarInfo<'ast, TypedAst>> = aliases.iter().chain([&inner_lvis]).map(|v| *v as *const _).collect();
+        if !defs.contains(&(inner_lvis as *const _)) {
+            return None;
+        }
+
+        let mut hoist = HashSet::new();
+        for id in inner.lb.iter().chain([&inner.ub]).chain(&inner.step).chain(&inner.width) {
+            if !collect_invariant(id, outer.iv, &defs, &mut hoist) {
+                return None;
+            }
+        }
+
+        let mut hoisted = Vec::new();
+        let mut stmts = Vec::new();
+        for stmt in &outer.body.stmts {
+            match stmt {
+                Stmt::Assign(assign) if chain.contains(&(assign.lhs as *const _)) => {}
+                Stmt::Assign(assign) if hoist.contains(&(assign.lhs as *const _)) => hoisted.push(stmt.clone()),
+                _ => stmts.push(stmt.clone()),
+            }
+        }
+
+        // Re-evaluating the outer body per inner index must not repeat any side effects or allocations
+        if !stmts.iter().all(is_cheap) {
+            return None;
+        }
+
+        let mut used = UsedVars::new();
+        for mut stmt in stmts.clone() {
+            used.trav_stmt(&mut stmt);
+        }
+        if chain.iter().any(|v| used.vars.contains(v)) {
+            return None;
+        }
+
+        Some(Nesting { inner, hoisted, stmts, outer_len, inner_len })
+    }
+
+    /// Replace the nested tensor defined by `assign` by a single tensor.
+    fn merge(&mut self, assign: &Assign<'ast, TypedAst>) -> Option<Vec<Stmt<'ast, TypedAst>>> {
+        let Expr::Tensor(outer @ Tensor { target: None, .. }) = assign.expr else {
+            return None;
+        };
+        let outer = outer.single()?;
+        let Nesting { inner, hoisted, stmts, outer_len, inner_len } = self.nesting(outer)?;
+        let span = outer.span;
+
+        let mut pre = hoisted;
+        let ub = self.concat(&mut pre, "ub", [(Some(outer.ub), outer_len), (Some(inner.ub), inner_len)], 0, span);
+        let lb = if outer.lb.is_some() || inner.lb.is_some() {
+            Some(self.concat(&mut pre, "lb", [(outer.lb, outer_len), (inner.lb, inner_len)], 0, span))
+        } else {
+            None
+        };
+        // Both generators select independently per axis, so a missing step or width selects every index
+        let (step, width) = if outer.step.is_some() || inner.step.is_some() {
+            let step = self.concat(&mut pre, "step", [(outer.step, outer_len), (inner.step, inner_len)], 1, span);
+            let width = self.concat(&mut pre, "width", [(outer.width, outer_len), (inner.width, inner_len)], 1, span);
+            (Some(step), Some(width))
+        } else {
+            (None, None)
+        };
+
+        let iv_base = outer.iv.ty.ty.clone();
+        let kv = self.alloc_lvis(&outer.iv.name, Type::vector_dim(iv_base, DimPattern::Known(outer_len + inner_len)), None);
+
+        // The original index vectors are views of the merged one, which need no allocation
+        let mut body = Vec::new();
+        let split = self.define(&mut body, "axis", Type::scalar(BaseType::Usize), Expr::Const(Const::Usize(outer_len)), span);
+        body.push(self.redefine(outer.iv, Expr::Prf(Prf::TakeSxV(split, Id::Var(kv))), span));
+        body.push(self.redefine(inner.iv, Expr::Prf(Prf::DropSxV(split, Id::Var(kv))), span));
+        body.extend(stmts);
+        body.extend(inner.body.stmts.iter().cloned());
+
+        let merged = Partition {
+            body: Body { stmts: body, ret: inner.body.ret },
+            iv: kv,
+            lb,
+            ub,
+            step,
+            width,
+            span,
+        };
+
+        let merged = Tensor { target: None, partitions: vec![merged], span };
+        pre.push(self.redefine(assign.lhs, Expr::Tensor(merged), assign.span));
+        Some(pre)
+    }
+}
+
+impl<'ast> Traverse<'ast> for WithLoopScalarisation<'ast> {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_fundef(&mut self, fundef: &mut Fundef<'ast, TypedAst>) {
+        // SAFETY: allocation arenas are stored in the owning Fundef.
+        self.decs = Some(unsafe { mem::transmute::<&Arena<VarInfo<'ast, TypedAst>>, &'ast Arena<VarInfo<'ast, TypedAst>>>(&fundef.decs) });
+        self.exprs = Some(unsafe { mem::transmute::<&Arena<Expr<'ast, TypedAst>>, &'ast Arena<Expr<'ast, TypedAst>>>(&fundef.exprs) });
+        self.args = fundef.args.clone();
+
+        self.trav_body(&mut fundef.body);
+
+        self.decs = None;
+        self.exprs = None;
+    }
+
+    fn trav_body(&mut self, body: &mut Body<'ast, TypedAst>) {
+        let mut stmts = Vec::with_capacity(body.stmts.len());
+        let mut pending: Vec<Stmt<'ast, TypedAst>> = mem::take(&mut body.stmts);
+        pending.reverse();
+
+        while let Some(mut stmt) = pending.pop() {
+            self.trav_stmt(&mut stmt);
+            match &stmt {
+                // Deeper nestings are merged one level at a time
+                Stmt::Assign(assign) => match self.merge(assign) {
+                    Some(merged) => {
+                        let (last, pre) = merged.split_last().unwrap();
+                        stmts.extend(pre.iter().cloned());
+                        pending.push(last.clone());
+                    }
+                    None => stmts.push(stmt),
+                },
+                _ => stmts.push(stmt),
+            }
+        }
+
+        body.stmts = stmts;
+    }
+}
+
+/// Follow `id` through aliases, returning the variable and the aliases that were passed.
+fn resolve_aliases<'ast>(id: &Id<'ast, TypedAst>) -> Option<(&'ast VarInfo<'ast, TypedAst>, Vec<&'ast VarInfo<'ast, TypedAst>>)> {
+    let Id::Var(mut lvis) = *id else {
+        return None;
+    };
+
+    let mut aliases = Vec::new();
+    while let Some(Expr::Id(Id::Var(alias))) = lvis.ssa {
+        aliases.push(lvis);
+        lvis = alias;
+    }
+
+    Some((lvis, aliases))
+}
+
+/// Whether `id` does not depend on the index vector `iv`, collecting the definitions
+/// in the body of the tensor of `iv` that it does depend on.
+fn collect_invariant<'ast>(
+    id: &Id<'ast, TypedAst>,
+    iv: &'ast VarInfo<'ast, TypedAst>,
+    defs: &HashSet<*const VarInfo<'ast, TypedAst>>,
+    hoist: &mut HashSet<*const VarInfo<'ast, TypedAst>>,
+) -> bool {
+    let Id::Var(lvis) = id else {
+        return true;
+    };
+
+    if ptr::eq(*lvis, iv) {
+        return false;
+    }
+
+    // Variables of the enclosing scopes cannot depend on the index vector
+    let key = *lvis as *const _;
+    if hoist.contains(&key) || !defs.contains(&key) {
+        return true;
+    }
+
+    let mut used = UsedVars::new();
+    used.trav_expr(lvis.ssa.unwrap());
+    if used.vars.contains(&(iv as *const _)) {
+        return false;
+    }
+
+    hoist.insert(key);
+    used.ids.iter().all(|id| collect_invariant(id, iv, defs, hoist))
+}
+
+/// Collect the variables that are used.
+struct UsedVars<'ast> {
+    vars: HashSet<*const VarInfo<'ast, TypedAst>>,
+    ids: Vec<Id<'ast, TypedAst>>,
+}
+
+impl<'ast> UsedVars<'ast> {
+    fn new() -> Self {
+        Self {
+            vars: HashSet::new(),
+            ids: Vec::new(),
+        }
+    }
+}
+
+impl<'ast> Traverse<'ast> for UsedVars<'ast> {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_id(&mut self, id: &mut Id<'ast, TypedAst>) {
+        if let Id::Var(lvis) = id
+            && self.vars.insert(*lvis as *const _)
+        {
+            self.ids.push(*id);
+        }
+    }
+}
+
+/// Whether `stmt` costs no more than a selection, and has no side effects.
+fn is_cheap(stmt: &Stmt<'_, TypedAst>) -> bool {
+    let Stmt::Assign(assign) = stmt else {
+        return false;
+    };
+
+    match assign.expr {
+        Expr::Id(_) | Expr::Const(_) | Expr::Field(_) => true,
+        Expr::Prf(prf) => !matches!(prf, Prf::ShapeA(_)),
+        Expr::Call(call) => {
+            let CallTarget::Function(fundef) = call.id;
+            selection_positions(fundef).is_some()
+        }
+        Expr::Cond(_) | Expr::Tensor(_) | Expr::Fold(_) | Expr::Array(_) | Expr::Record(_) => false,
+    }
+}
+
+/// The statically known length of an index vector.
+fn iv_len(iv: &VarInfo<'_, TypedAst>) -> Option<usize> {
+    match &iv.ty.shape {
+        TypePattern::Axes(axes) => match axes.as_slice() {
+            [AxisPattern::Dim(DimPattern::Known(n))] => Some(*n),
+            _ => None,
+        },
+        TypePattern::Scalar => None,
+    }
+}
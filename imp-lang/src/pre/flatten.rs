@@ -13,6 +13,8 @@ struct Flatten<'ast> {
     decs: Arena<VarInfo<'ast, ParsedAst>>,
     exprs: Arena<Expr<'ast, ParsedAst>>,
     new_assigns: Vec<Assign<'ast, ParsedAst>>,
+    /// Span of the innermost expression being flattened, given to emitted assignments.
+    span: Span,
 }
 
 impl<'ast> Flatten<'ast> {
@@ -22,6 +24,7 @@ impl<'ast> Flatten<'ast> {
             decs: Arena::new(),
             exprs: Arena::new(),
             new_assigns: Vec::new(),
+            span: Span::default(),
         }
     }
 
@@ -37,7 +40,7 @@ impl<'ast> Flatten<'ast> {
         let name = self.trav_name.next();
         let lvis = self.alloc_lvis(name.clone(), None);
         let rhs = self.alloc_expr(expr);
-        self.new_assigns.push(Assign { lhs: lvis, expr: rhs });
+        self.new_assigns.push(Assign { lhs: lvis, expr: rhs, span: self.span });
         Expr::Id(Id::Var(name))
     }
 }
@@ -56,6 +59,7 @@ impl<'ast> Traverse<'ast> for Flatten<'ast> {
 
         self.decs = mem::take(&mut fundef.decs);
         self.exprs = mem::take(&mut fundef.exprs);
+        self.span = fundef.span;
 
         let mut shape_prelude = Vec::new();
         for mut assign in fundef.shape_prelude.drain(..) {
@@ -73,6 +77,7 @@ impl<'ast> Traverse<'ast> for Flatten<'ast> {
 
     fn trav_body(&mut self, body: &mut Body<'ast, ParsedAst>) {
         let old_assigns = mem::take(&mut self.new_assigns);
+        let old_span = self.span;
 
         let mut stmts = Vec::new();
         for mut stmt in body.stmts.drain(..) {
@@ -87,10 +92,19 @@ impl<'ast> Traverse<'ast> for Flatten<'ast> {
         body.stmts = stmts;
 
         self.new_assigns = old_assigns;
+        self.span = old_span;
+    }
+
+    fn trav_assign(&mut self, assign: &mut Assign<'ast, ParsedAst>) {
+        self.span = assign.span;
+        self.trav_expr(assign.expr);
     }
 
     fn trav_expr_value(&mut self, expr: Expr<'ast, Self::Ast>) -> (Expr<'ast, Self::Ast>, Self::ExprOut) {
         use Expr::*;
+        let old_span = self.span;
+        self.span = expr.span().unwrap_or(old_span);
+
         let (expr, _) = match expr {
             Id(n) => {
                 return (Id(n), Self::EXPR_DEFAULT);
@@ -101,10 +115,13 @@ impl<'ast> Traverse<'ast> for Flatten<'ast> {
             Tensor(n) => self.trav_tensor_expr(n),
             Fold(n) => self.trav_fold_expr(n),
             Array(n) => self.trav_array_expr(n),
+            Record(n) => self.trav_record_expr(n),
+            Field(n) => self.trav_field_expr(n),
             Const(n) => self.trav_const_expr(n),
         };
 
         let id = self.emit_expr(expr);
+        self.span = old_span;
         (id, Self::EXPR_DEFAULT)
     }
 }
@@ -2,11 +2,13 @@ use std::{collections::HashMap, mem};
 
 use typed_arena::Arena;
 
-use crate::{ast::*, trav_name::TravName};
+use crate::{ast::*, tc::InferenceError, trav_name::TravName};
 
-pub fn to_ssa<'ast>(program: Program<'ast, ParsedAst>) -> Program<'ast, UntypedAst> {
+/// Fails with every use of an undefined variable.
+pub fn to_ssa<'ast>(program: Program<'ast, ParsedAst>) -> Result<Program<'ast, UntypedAst>, Vec<InferenceError>> {
     let mut overloads = HashMap::new();
     let fundefs_arena: Arena<Fundef<'ast, UntypedAst>> = Arena::new();
+    let mut errors = Vec::new();
 
     for (name, groups) in program.overloads {
         let mut new_groups = HashMap::new();
@@ -15,7 +17,9 @@ pub fn to_ssa<'ast>(program: Program<'ast, ParsedAst>) -> Program<'ast, UntypedA
             let mut new_fundefs = Vec::new();
 
             for fundef in fundefs {
-                let out_fundef = ToSsa::new().trav_fundef(fundef);
+                let mut to_ssa = ToSsa::new();
+                let out_fundef = to_ssa.trav_fundef(fundef);
+                errors.append(&mut to_ssa.errors);
                 let out_ref = fundefs_arena.alloc(out_fundef);
                 let out_ref: &'ast Fundef<'ast, UntypedAst> = unsafe { std::mem::transmute(out_ref) };
                 new_fundefs.push(out_ref);
@@ -27,10 +31,15 @@ pub fn to_ssa<'ast>(program: Program<'ast, ParsedAst>) -> Program<'ast, UntypedA
         overloads.insert(name, new_groups);
     }
 
-    Program {
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Program {
         overloads,
         fundefs: fundefs_arena,
-    }
+        structs: program.structs,
+    })
 }
 
 pub struct ToSsa<'ast> {
@@ -39,6 +48,9 @@ pub struct ToSsa<'ast> {
     expr_arena: Arena<Expr<'ast, UntypedAst>>,
     new_assigns: Vec<Stmt<'ast, UntypedAst>>,
     env_stack: Vec<HashMap<String, Id<'ast, UntypedAst>>>,
+    /// Span of the innermost enclosing node that has one, see [`Expr::span`]
+    span: Span,
+    errors: Vec<InferenceError>,
 }
 
 impl<'ast> ToSsa<'ast> {
@@ -49,6 +61,8 @@ impl<'ast> ToSsa<'ast> {
             expr_arena: Arena::new(),
             new_assigns: Vec::new(),
             env_stack: Vec::new(),
+            span: Span::default(),
+            errors: Vec::new(),
         }
     }
 
@@ -93,6 +107,7 @@ impl<'ast> ToSsa<'ast> {
         self.expr_arena = Arena::new();
 
         self.push_env();
+        self.span = fundef.span;
 
         let args = self.trav_fargs(fundef.args.clone());
 
@@ -103,7 +118,7 @@ impl<'ast> ToSsa<'ast> {
             for stmt in new_assigns {
                 match stmt {
                     Stmt::Assign(n) => shape_prelude.push(n),
-                    Stmt::Printf(_) => unreachable!(),
+                    Stmt::Printf(_) | Stmt::Free(_) => unreachable!(),
                 }
             }
             shape_prelude.push(assign);
@@ -118,6 +133,8 @@ impl<'ast> ToSsa<'ast> {
 
         Fundef {
             name: fundef.name.clone(),
+            type_params: fundef.type_params.clone(),
+            constraints: fundef.constraints.clone(),
             args,
             shape_prelude,
             shape_facts: fundef.shape_facts.clone(),
@@ -125,6 +142,9 @@ impl<'ast> ToSsa<'ast> {
             exprs,
             body,
             ret_type: fundef.ret_type.clone(),
+            span: fundef.span,
+            import: fundef.import.clone(),
+            internal: fundef.internal,
         }
     }
 
@@ -157,6 +177,7 @@ impl<'ast> ToSsa<'ast> {
         match stmt {
             Assign(n) => Assign(self.trav_assign(n)),
             Printf(n) => Printf(self.trav_printf(n)),
+            Free(n) => Free(self.trav_free(n)),
         }
     }
 
@@ -164,12 +185,14 @@ impl<'ast> ToSsa<'ast> {
         let old_name = assign.lhs.name.clone();
         let new_name = self.trav_name.next();
 
+        let outer_span = mem::replace(&mut self.span, assign.span);
         let expr = self.trav_expr((*assign.expr).clone());
+        self.span = outer_span;
         let expr = self.alloc_expr(expr);
         let lvis = self.alloc_lvis(new_name, Some(expr));
         self.bind_env(old_name, Id::Var(lvis));
 
-        Assign { lhs: lvis, expr }
+        Assign { lhs: lvis, expr, span: assign.span }
     }
 
     fn trav_printf(&mut self, printf: Printf<'ast, ParsedAst>) -> Printf<'ast, UntypedAst> {
@@ -177,6 +200,11 @@ impl<'ast> ToSsa<'ast> {
         Printf { id }
     }
 
+    fn trav_free(&mut self, free: Free<'ast, ParsedAst>) -> Free<'ast, UntypedAst> {
+        let id = self.trav_id(free.id);
+        Free { id }
+    }
+
     fn trav_expr(&mut self, expr: Expr<'ast, ParsedAst>) -> Expr<'ast, UntypedAst> {
         use Expr::*;
         match expr {
@@ -186,19 +214,24 @@ impl<'ast> ToSsa<'ast> {
             Fold(n) => Fold(self.trav_fold(n)),
             Tensor(n) => Tensor(self.trav_tensor(n)),
             Array(n) => Array(self.trav_array(n)),
+            Record(n) => Record(self.trav_record(n)),
+            Field(n) => Field(self.trav_field(n)),
             Id(n) => Id(self.trav_id(n)),
             Const(c) => Const(c),
         }
     }
 
     fn trav_cond(&mut self, cond: Cond<'ast, ParsedAst>) -> Cond<'ast, UntypedAst> {
+        let outer_span = mem::replace(&mut self.span, cond.span);
         let c = self.unwrap_id_operand(cond.cond);
         let t = self.trav_body(cond.then_branch);
         let e = self.trav_body(cond.else_branch);
+        self.span = outer_span;
         Cond {
             cond: c,
             then_branch: t,
             else_branch: e,
+            span: cond.span,
         }
     }
 
@@ -208,7 +241,7 @@ impl<'ast> ToSsa<'ast> {
             .into_iter()
             .map(|arg| self.unwrap_id_operand(arg))
             .collect();
-        Call { id: call.id, args }
+        Call { id: call.id, args, span: call.span }
     }
 
     fn trav_prf_call(&mut self, prf: Prf<'ast, ParsedAst>) -> Prf<'ast, UntypedAst> {
@@ -217,6 +250,8 @@ impl<'ast> ToSsa<'ast> {
             DimA(a) => DimA(self.unwrap_id_operand(a)),
             ShapeA(a) => ShapeA(self.unwrap_id_operand(a)),
             SelVxA(a, b) => SelVxA(self.unwrap_id_operand(a), self.unwrap_id_operand(b)),
+            TakeSxV(a, b) => TakeSxV(self.unwrap_id_operand(a), self.unwrap_id_operand(b)),
+            DropSxV(a, b) => DropSxV(self.unwrap_id_operand(a), self.unwrap_id_operand(b)),
             AddSxS(l, r) => AddSxS(self.unwrap_id_operand(l), self.unwrap_id_operand(r)),
             SubSxS(l, r) => SubSxS(self.unwrap_id_operand(l), self.unwrap_id_operand(r)),
             MulSxS(l, r) => MulSxS(self.unwrap_id_operand(l), self.unwrap_id_operand(r)),
@@ -233,23 +268,38 @@ impl<'ast> ToSsa<'ast> {
     }
 
     fn trav_tensor(&mut self, tensor: Tensor<'ast, ParsedAst>) -> Tensor<'ast, UntypedAst> {
-        let lb = tensor.lb.map(|lb| self.unwrap_id_operand(lb));
-        let ub = self.unwrap_id_operand(tensor.ub);
+        Tensor {
+            target: tensor.target.map(|target| self.unwrap_id_operand(target)),
+            partitions: tensor.partitions.into_iter().map(|partition| self.trav_partition(partition)).collect(),
+            span: tensor.span,
+        }
+    }
 
-        let iv_lvis = self.alloc_lvis(tensor.iv.name.clone(), None);
+    fn trav_partition(&mut self, partition: Partition<'ast, ParsedAst>) -> Partition<'ast, UntypedAst> {
+        let outer_span = mem::replace(&mut self.span, partition.span);
+        let lb = partition.lb.map(|lb| self.unwrap_id_operand(lb));
+        let ub = self.unwrap_id_operand(partition.ub);
+        let step = partition.step.map(|step| self.unwrap_id_operand(step));
+        let width = partition.width.map(|width| self.unwrap_id_operand(width));
+
+        let iv_lvis = self.alloc_lvis(partition.iv.name.clone(), None);
 
         self.push_env();
-        self.bind_env(tensor.iv.name.clone(), Id::Var(iv_lvis));
+        self.bind_env(partition.iv.name.clone(), Id::Var(iv_lvis));
 
-        let body = self.trav_body(tensor.body);
+        let body = self.trav_body(partition.body);
 
         self.pop_env();
+        self.span = outer_span;
 
-        Tensor {
+        Partition {
             body,
             iv: iv_lvis,
             lb,
             ub,
+            step,
+            width,
+            span: partition.span,
         }
     }
 
@@ -270,12 +320,13 @@ impl<'ast> ToSsa<'ast> {
             }
         };
 
-        let selection = self.trav_tensor(fold.selection);
+        let selection = self.trav_partition(fold.selection);
 
         Fold {
             neutral,
             foldfun,
             selection,
+            span: fold.span,
         }
     }
 
@@ -285,15 +336,33 @@ impl<'ast> ToSsa<'ast> {
             .into_iter()
             .map(|value| self.unwrap_id_operand(value))
             .collect();
-        Array { elems }
+        Array { elems, span: array.span }
+    }
+
+    fn trav_record(&mut self, record: Record<'ast, ParsedAst>) -> Record<'ast, UntypedAst> {
+        let fields = record
+            .fields
+            .into_iter()
+            .map(|(name, value)| (name, self.unwrap_id_operand(value)))
+            .collect();
+        Record { name: record.name, fields, span: record.span }
+    }
+
+    fn trav_field(&mut self, field: Field<'ast, ParsedAst>) -> Field<'ast, UntypedAst> {
+        let record = self.unwrap_id_operand(field.record);
+        Field { record, name: field.name, span: field.span }
     }
 
     fn trav_id(&mut self, id: Id<'ast, ParsedAst>) -> Id<'ast, UntypedAst> {
         match id {
             Id::Arg(i) => Id::Arg(i),
-            Id::Var(v) => self
-                .lookup_env(&v)
-                .unwrap_or_else(|| panic!("could not resolve id {v}")),
+            Id::Var(v) => self.lookup_env(&v).unwrap_or_else(|| {
+                self.errors.push(InferenceError::UndefinedVariable { name: v.clone(), span: self.span });
+                // Bind a placeholder, such that later uses are not reported again
+                let id = Id::Var(self.alloc_lvis(v.clone(), None));
+                self.bind_env(v, id);
+                id
+            }),
         }
     }
 }
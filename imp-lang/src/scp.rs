@@ -1,16 +1,18 @@
 //! # Scanning-parsing (`scp`)
-mod span;
 mod operator;
 mod lexer;
 mod parser;
+mod load_modules;
 
-use lexer::Lexer;
+pub(crate) use lexer::{Lexer, Token};
 use parser::Parser;
-use crate::ast::{Program, ParsedAst};
+use crate::ast::{Import, Program, ParsedAst};
 
-pub fn scanparse(src: &str) -> Result<Program<'static, ParsedAst>, String> {
+pub use parser::ParseError;
+pub use load_modules::{load_modules, ModuleError};
+
+pub fn scanparse(src: &str) -> Result<(Program<'static, ParsedAst>, Vec<Import>), Vec<ParseError>> {
     let lexer = Lexer::new(src);
     let mut parser = Parser::new(lexer);
     parser.parse_program()
-        .map_err(|e| format!("{:?}", e))
 }
@@ -1,4 +1,6 @@
-use super::span::Span;
+use std::str::FromStr;
+
+use crate::ast::Span;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
@@ -24,10 +26,15 @@ pub enum Token {
     Dot,
     Comma,
     Colon,
+    PathSep,
     Semicolon,
     // Keywords
     Fn,
     Fold,
+    Import,
+    Use,
+    Where,
+    Struct,
     If, Else,
     Printf,
     // Operators
@@ -51,6 +58,9 @@ pub enum Token {
     /// Error: natural number specifier on a real numbered value
     /// Example: `42.0i32`, `3.14usize`
     NotANaturalNumber(String),
+    /// Error: integer literal that does not fit in its type
+    /// Example: `99999999999i32`
+    OutOfRange(String, &'static str),
     /// Error: unexpected token during lexing
     UnexpectedCharacter(char),
 }
@@ -117,6 +127,14 @@ impl<'src> Lexer<'src> {
         }
     }
 
+    /// Like `match_str`, but only matches if the keyword is not the start of a longer identifier.
+    fn match_keyword(&mut self, keyword: &str) -> bool {
+        let rest = &self.src[self.current..];
+        let is_word = rest.strip_prefix(keyword)
+            .is_some_and(|after| !after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'));
+        is_word && self.match_str(keyword)
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek_char() {
             match c {
@@ -183,6 +201,14 @@ impl<'source> Iterator for Lexer<'source> {
             Fn
         } else if self.match_str("fold") {
             Fold
+        } else if self.match_keyword("import") {
+            Import
+        } else if self.match_keyword("use") {
+            Use
+        } else if self.match_keyword("where") {
+            Where
+        } else if self.match_keyword("struct") {
+            Struct
         } else if self.match_str("if") {
             If
         } else if self.match_str("else") {
@@ -202,6 +228,7 @@ impl<'source> Iterator for Lexer<'source> {
                 '|' => Bar,
                 '.' => Dot,
                 ',' => Comma,
+                ':' if self.match_char(':') => PathSep,
                 ':' => Colon,
                 ';' => Semicolon,
                 // Operators
@@ -250,31 +277,31 @@ impl<'source> Iterator for Lexer<'source> {
                         if is_real {
                             NotANaturalNumber(s.to_string())
                         } else {
-                            UsizeValue(s.parse().unwrap())
+                            int_literal(s, "usize", UsizeValue)
                         }
                     } else if self.match_str("u32") {
                         if is_real {
                             NotANaturalNumber(s.to_string())
                         } else {
-                            U32Value(s.parse().unwrap())
+                            int_literal(s, "u32", U32Value)
                         }
                     } else if self.match_str("u64") {
                         if is_real {
                             NotANaturalNumber(s.to_string())
                         } else {
-                            U64Value(s.parse().unwrap())
+                            int_literal(s, "u64", U64Value)
                         }
                     } else if self.match_str("i32") {
                         if is_real {
                             NotANaturalNumber(s.to_string())
                         } else {
-                            I32Value(s.parse().unwrap())
+                            int_literal(s, "i32", I32Value)
                         }
                     } else if self.match_str("i64") {
                         if is_real {
                             NotANaturalNumber(s.to_string())
                         } else {
-                            I64Value(s.parse().unwrap())
+                            int_literal(s, "i64", I64Value)
                         }
                     } else if self.match_str("f32") {
                         F32Value(s.parse().unwrap())
@@ -284,7 +311,7 @@ impl<'source> Iterator for Lexer<'source> {
                         if is_real {
                             RealValue(s.parse().unwrap())
                         } else {
-                            NatValue(s.parse().unwrap())
+                            int_literal(s, "usize", NatValue)
                         }
                     }
                 }
@@ -307,3 +334,8 @@ impl<'source> Iterator for Lexer<'source> {
         Some((token, span))
     }
 }
+
+/// The token of an integer literal of type `ty`, or an error token if it does not fit.
+fn int_literal<T: FromStr>(s: &str, ty: &'static str, token: fn(T) -> Token) -> Token {
+    s.parse().map_or_else(|_| Token::OutOfRange(s.to_string(), ty), token)
+}
@@ -0,0 +1,294 @@
+use std::{collections::{HashMap, HashSet}, fmt, fs, mem, path::{Path, PathBuf}};
+
+use crate::{ast::*, error::CompileError};
+
+use super::scanparse;
+
+/// Parse a program together with all modules it imports, directly or indirectly.
+///
+/// Modules are looked up as `<module>.imp`, first in the directory of the importing module and
+/// then in each directory of `search_path`. The functions of imported modules are added to the program with
+/// [`Fundef::import`] set, and the paths of those modules are returned alongside it.
+///
+/// A function and all of its overloads must be defined in a single module,
+/// such that importing a module can never change which overload is called elsewhere.
+/// Structs need not be imported by name, those of every loaded module are visible everywhere.
+pub fn load_modules(infile: &Path, search_path: &[PathBuf]) -> Result<(Program<'static, ParsedAst>, Vec<PathBuf>), CompileError> {
+    let mut modules = vec![Module::parse(infile)?];
+    let mut indices = HashMap::from([(modules[0].name.clone(), 0)]);
+    // The module and import declaration that caused each module to be loaded
+    let mut origins = vec![None];
+
+    let mut i = 0;
+    while i < modules.len() {
+        let dir = modules[i].path.parent().unwrap_or(Path::new("")).to_owned();
+        let dirs: Vec<&Path> = [dir.as_path()].into_iter().chain(search_path.iter().map(PathBuf::as_path)).collect();
+
+        let mut errors = Vec::new();
+        for import in modules[i].imports.clone() {
+            if indices.contains_key(&import.module) {
+                continue;
+            }
+
+            let file = format!("{}.imp", import.module);
+            match dirs.iter().map(|dir| dir.join(&file)).find(|path| path.is_file()) {
+                Some(path) => {
+                    indices.insert(import.module.clone(), modules.len());
+                    modules.push(Module::parse(&path)?);
+                    origins.push(Some((i, import.span)));
+                }
+                None => errors.push(ModuleError::NotFound { module: import.module, span: import.span }),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(modules[i].invalid(errors));
+        }
+        i += 1;
+    }
+
+    // Every function name belongs to exactly one module.
+    // A clash is reported at the import that brought the second module in.
+    let mut owners: HashMap<String, usize> = HashMap::new();
+    let mut struct_owners: HashMap<String, usize> = HashMap::new();
+    for (i, module) in modules.iter().enumerate() {
+        let mut errors = Vec::new();
+        for structdef in &module.program.structs {
+            match struct_owners.get(&structdef.name) {
+                // Redefinitions within a single module are reported by type inference
+                Some(&owner) if owner != i => errors.push(ModuleError::RedefinedStruct {
+                    name: structdef.name.clone(),
+                    module: module.name.clone(),
+                    owner: modules[owner].name.clone(),
+                    span: origins[i].map_or(structdef.span, |(_, span)| span),
+                }),
+                Some(_) => {}
+                None => {
+                    struct_owners.insert(structdef.name.clone(), i);
+                }
+            }
+        }
+
+        for (name, span) in module.functions() {
+            match owners.get(&name) {
+                Some(&owner) => errors.push(ModuleError::Redefined {
+                    name,
+                    module: module.name.clone(),
+                    owner: modules[owner].name.clone(),
+                    span: origins[i].map_or(span, |(_, span)| span),
+                }),
+                None => {
+                    owners.insert(name, i);
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            let reporter = origins[i].map_or(i, |(importer, _)| importer);
+            return Err(modules[reporter].invalid(errors));
+        }
+    }
+
+    let names: Vec<String> = modules.iter().map(|module| module.name.clone()).collect();
+    for (i, module) in modules.iter_mut().enumerate() {
+        let mut visible: HashSet<String> = owners.iter()
+            .filter(|(_, owner)| **owner == i)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut errors = Vec::new();
+        for import in &module.imports {
+            let index = indices[&import.module];
+            match &import.names {
+                None => {
+                    visible.extend(owners.iter()
+                        .filter(|(_, owner)| **owner == index)
+                        .map(|(name, _)| name.clone()));
+                }
+                Some(names) => {
+                    for (name, span) in names {
+                        if owners.get(name) == Some(&index) {
+                            visible.insert(name.clone());
+                        } else {
+                            errors.push(ModuleError::UndefinedImport {
+                                module: import.module.clone(),
+                                name: name.clone(),
+                                span: *span,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Calls of functions that exist nowhere are reported by type inference instead
+        let mut calls = CollectCalls::new();
+        calls.trav_program(&mut module.program);
+        for (name, span) in calls.calls {
+            if !visible.contains(&name)
+                && let Some(&owner) = owners.get(&name)
+            {
+                errors.push(ModuleError::NotImported { name, module: names[owner].clone(), span });
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(module.invalid(errors));
+        }
+    }
+
+    let mut modules = modules.into_iter();
+    let mut program = modules.next().unwrap().program;
+    let mut paths = Vec::new();
+
+    for module in modules {
+        // The overloads of the module refer into its arena, so they are dropped before moving the fundefs out
+        let Program { fundefs, structs, .. } = module.program;
+        for mut structdef in structs {
+            structdef.import = Some(module.name.clone());
+            program.structs.push(structdef);
+        }
+        for mut fundef in fundefs.into_vec() {
+            fundef.import = Some(module.name.clone());
+            let fundef = program.fundefs.alloc(fundef);
+            // SAFETY: the arena is owned by the program, which outlives its overloads.
+            let fundef: &'static Fundef<'static, ParsedAst> = unsafe { mem::transmute(fundef) };
+            program.overloads
+                .entry(fundef.name.clone())
+                .or_default()
+                .entry(fundef.signature())
+                .or_default()
+                .push(fundef);
+        }
+        paths.push(module.path);
+    }
+
+    Ok((program, paths))
+}
+
+#[derive(Debug)]
+pub enum ModuleError {
+    /// No file for the imported module exists in the search path.
+    NotFound { module: String, span: Span },
+    /// A `use` declaration names a function that the module does not define.
+    UndefinedImport { module: String, name: String, span: Span },
+    /// A function of an imported module is also defined in another module.
+    Redefined { name: String, module: String, owner: String, span: Span },
+    /// A struct of an imported module is also defined in another module.
+    RedefinedStruct { name: String, module: String, owner: String, span: Span },
+    /// A function of another module is called without being imported.
+    NotImported { name: String, module: String, span: Span },
+}
+
+impl ModuleError {
+    pub fn span(&self) -> Span {
+        use ModuleError::*;
+        match self {
+            NotFound { span, .. }
+            | UndefinedImport { span, .. }
+            | Redefined { span, .. }
+            | RedefinedStruct { span, .. }
+            | NotImported { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ModuleError::*;
+        match self {
+            NotFound { module, .. } => write!(f, "module `{}` not found", module),
+            UndefinedImport { module, name, .. } => write!(f, "module `{}` does not define a function `{}`", module, name),
+            Redefined { name, module, owner, .. } => write!(f,
+                "module `{}` defines function `{}`, which is already defined in module `{}`; all overloads of a function must be defined in a single module",
+                module, name, owner
+            ),
+            RedefinedStruct { name, module, owner, .. } => write!(f,
+                "module `{}` defines struct `{}`, which is already defined in module `{}`",
+                module, name, owner
+            ),
+            NotImported { name, module, .. } => write!(f,
+                "function `{}` is defined in module `{}`, which is not imported here",
+                name, module
+            ),
+        }
+    }
+}
+
+struct Module {
+    name: String,
+    path: PathBuf,
+    src: String,
+    program: Program<'static, ParsedAst>,
+    imports: Vec<Import>,
+}
+
+impl Module {
+    fn parse(path: &Path) -> Result<Self, CompileError> {
+        let src = fs::read_to_string(path)
+            .map_err(|error| CompileError::Io { path: path.to_owned(), error })?;
+        let (program, imports) = scanparse(&src)
+            .map_err(|errs| CompileError::invalid(path, &src, errs))?;
+        let name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+        Ok(Self { name, path: path.to_owned(), src, program, imports })
+    }
+
+    /// The names of the functions defined in this module, in source order.
+    fn functions(&self) -> Vec<(String, Span)> {
+        let mut functions: Vec<(String, Span)> = self.program.overloads.iter()
+            .map(|(name, groups)| {
+                let span = groups.values().flatten().map(|fundef| fundef.span).min().unwrap();
+                (name.clone(), span)
+            })
+            .collect();
+        functions.sort_by_key(|(_, span)| *span);
+        functions
+    }
+
+    fn invalid(&self, errors: Vec<ModuleError>) -> CompileError {
+        CompileError::invalid(&self.path, &self.src, errors)
+    }
+}
+
+/// Collect the names of all called functions, including those of folds.
+struct CollectCalls {
+    calls: Vec<(String, Span)>,
+}
+
+impl CollectCalls {
+    fn new() -> Self {
+        Self { calls: Vec::new() }
+    }
+}
+
+impl<'ast> Traverse<'ast> for CollectCalls {
+    type Ast = ParsedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_call(&mut self, call: &mut Call<'ast, ParsedAst>) {
+        self.calls.push((call.id.clone(), call.span));
+        for arg in &mut call.args {
+            self.trav_expr(arg);
+        }
+    }
+
+    fn trav_fold(&mut self, fold: &mut Fold<'ast, ParsedAst>) {
+        match &mut fold.foldfun {
+            FoldFun::Name(name) => self.calls.push((name.clone(), fold.span)),
+            FoldFun::Apply { id, args } => {
+                self.calls.push((id.clone(), fold.span));
+                for arg in args {
+                    if let FoldFunArg::Bound(bound) = arg {
+                        self.trav_expr(bound);
+                    }
+                }
+            }
+        }
+
+        self.trav_expr(fold.neutral);
+        self.trav_partition(&mut fold.selection);
+    }
+}
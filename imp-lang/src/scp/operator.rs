@@ -1,4 +1,5 @@
 use super::{lexer::Token, parser::ParseError};
+use crate::ast::Span;
 
 #[derive(Clone, Copy)]
 pub(super) enum Bop {
@@ -137,11 +138,11 @@ impl Operator for Uop {
     }
 }
 
-pub(super) fn precedes(l: &Option<impl Operator>, r: &impl Operator) -> Result<bool, ParseError> {
+pub(super) fn precedes(l: &Option<impl Operator>, r: &impl Operator, span: Span) -> Result<bool, ParseError> {
     if let Some(l) = l {
         use Assoc::*;
         match (l.associativity(), r.associativity()) {
-            (NonAssoc, NonAssoc) => Err(ParseError::NonAssociative),
+            (NonAssoc, NonAssoc) => Err(ParseError::NonAssociative(span)),
             (_, RightToLeft) => Ok(l.precedence() <= r.precedence()),
             _ => Ok(l.precedence() < r.precedence()),
         }
@@ -1,8 +1,8 @@
-use std::{collections::HashMap, iter::Peekable, mem};
+use std::{collections::HashMap, fmt, iter::Peekable, mem};
 
 use typed_arena::Arena;
 
-use super::{lexer::*, operator::*, span::*};
+use super::{lexer::*, operator::*};
 
 use crate::ast::*;
 
@@ -10,18 +10,63 @@ pub struct Parser<'src, 'ast> {
     lexer: Peekable<Lexer<'src>>,
     decs_arena: Arena<VarInfo<'ast, ParsedAst>>,
     expr_arena: Arena<Expr<'ast, ParsedAst>>,
+    /// Span of the most recently consumed token.
+    prev_span: Span,
+    /// Type parameters of the function being parsed, which are parsed as generic base types.
+    type_params: Vec<String>,
 }
 
 #[derive(Debug)]
-#[allow(unused)]
 pub enum ParseError {
-    NonAssociative,
-    DuplicateFunctionSignature(String),
+    NonAssociative(Span),
     UnknownPrimitive(String, Span),
-    FoldSelectionMustBeTensor,
+    FoldSelectionMustBeTensor(Span),
+    FoldSelectionPartitioned(Span),
+    FoldSelectionPermuted(Span),
+    PermutationPartitioned(Span),
+    PermutationIndex(String, Span),
     ExpectedStatement(Token, Span),
     UnexpectedToken(String, Token, Span),
-    UnexpectedEof,
+    UnexpectedEof(Span),
+    LiteralOutOfRange(String, &'static str, Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        use ParseError::*;
+        match self {
+            NonAssociative(span)
+            | UnknownPrimitive(_, span)
+            | FoldSelectionMustBeTensor(span)
+            | FoldSelectionPartitioned(span)
+            | FoldSelectionPermuted(span)
+            | PermutationPartitioned(span)
+            | PermutationIndex(_, span)
+            | ExpectedStatement(_, span)
+            | UnexpectedToken(_, _, span)
+            | UnexpectedEof(span)
+            | LiteralOutOfRange(_, _, span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseError::*;
+        match self {
+            NonAssociative(_) => write!(f, "non-associative operators cannot be chained"),
+            UnknownPrimitive(id, _) => write!(f, "unknown primitive function `@{}`", id),
+            FoldSelectionMustBeTensor(_) => write!(f, "the selection of a fold must be a tensor comprehension"),
+            FoldSelectionPartitioned(_) => write!(f, "the selection of a fold must have a single partition"),
+            FoldSelectionPermuted(_) => write!(f, "the selection of a fold cannot have a target"),
+            PermutationPartitioned(_) => write!(f, "a tensor with a target must have a single partition"),
+            PermutationIndex(iv, _) => write!(f, "the target of a tensor must be indexed by its index vector `{}`", iv),
+            ExpectedStatement(token, _) => write!(f, "expected a statement, found {:?}", token),
+            UnexpectedToken(expected, token, _) => write!(f, "expected {}, found {:?}", expected, token),
+            UnexpectedEof(_) => write!(f, "unexpected end of file"),
+            LiteralOutOfRange(literal, ty, _) => write!(f, "literal `{}` does not fit in type `{}`", literal, ty),
+        }
+    }
 }
 
 type ParseResult<T> = Result<T, ParseError>;
@@ -32,6 +77,8 @@ impl<'src, 'ast> Parser<'src, 'ast> {
             lexer: lexer.peekable(),
             decs_arena: Arena::new(),
             expr_arena: Arena::new(),
+            prev_span: Span::default(),
+            type_params: Vec::new(),
         }
     }
 
@@ -44,8 +91,9 @@ impl<'src, 'ast> Parser<'src, 'ast> {
     }
 
     fn matches(&mut self, expected: &Token) -> Option<Span> {
-        self.lexer.next_if(|(token, _)| token == expected)
-            .map(|(_, span)| span)
+        let (_, span) = self.lexer.next_if(|(token, _)| token == expected)?;
+        self.prev_span = span;
+        Some(span)
     }
 
     fn expect(&mut self, expected: Token) -> ParseResult<Span> {
@@ -62,11 +110,33 @@ impl<'src, 'ast> Parser<'src, 'ast> {
     }
 
     fn peek(&mut self) -> ParseResult<&(Token, Span)> {
-        self.lexer.peek().ok_or(ParseError::UnexpectedEof)
+        self.lexer.peek().ok_or(ParseError::UnexpectedEof(self.prev_span))
     }
 
     fn next(&mut self) -> ParseResult<(Token, Span)> {
-        self.lexer.next().ok_or(ParseError::UnexpectedEof)
+        let (token, span) = self.lexer.next().ok_or(ParseError::UnexpectedEof(self.prev_span))?;
+        self.prev_span = span;
+        if let Token::OutOfRange(literal, ty) = token {
+            return Err(ParseError::LiteralOutOfRange(literal, ty, span));
+        }
+        Ok((token, span))
+    }
+
+    /// Consume the next token if it is the identifier `word`.
+    ///
+    /// Used for words that are only keywords in a specific position, like the `step` of a tensor,
+    /// such that they remain valid identifiers elsewhere.
+    fn eat_word(&mut self, word: &str) -> ParseResult<bool> {
+        let found = matches!(self.peek()?, (Token::Identifier(id), _) if id == word);
+        if found {
+            self.next()?;
+        }
+        Ok(found)
+    }
+
+    /// Span from `from` up to and including the most recently consumed token.
+    fn span_from(&self, from: Span) -> Span {
+        from.to(&self.prev_span)
     }
 
     /// ```bnf
@@ -103,16 +173,29 @@ impl<'src, 'ast> Parser<'src, 'ast> {
     }
 
     /// ```bnf
-    /// <program> = <fundef>*
+    /// <program> = (<import> | <structdef> | <fundef>)*
     /// ```
-    pub fn parse_program(&mut self) -> ParseResult<Program<'ast, ParsedAst>> {
+    ///
+    /// A syntax error skips ahead to the next `fn`, `struct`, `import` or `use`, so that the errors in
+    /// all function definitions are reported at once.
+    pub fn parse_program(&mut self) -> Result<(Program<'ast, ParsedAst>, Vec<Import>), Vec<ParseError>> {
         let mut overloads = HashMap::new();
         let fundefs_arena: Arena<Fundef<'ast, ParsedAst>> = Arena::new();
+        let mut imports = Vec::new();
+        let mut structs = Vec::new();
+        let mut errors = Vec::new();
 
         while let Some((token, _)) = self.lexer.peek() {
             match token {
                 Token::Fn => {
-                    let (fundef, _) = self.parse_fundef()?;
+                    let fundef = match self.parse_fundef() {
+                        Ok((fundef, _)) => fundef,
+                        Err(e) => {
+                            errors.push(e);
+                            self.recover();
+                            continue;
+                        }
+                    };
                     let name = fundef.name.clone();
                     let sig = fundef.signature();
                     let fundef_ref = fundefs_arena.alloc(fundef);
@@ -122,21 +205,92 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                     let fundefs = group.entry(sig).or_insert(Vec::new());
                     fundefs.push(fundef_ref);
                 }
+                Token::Struct => {
+                    match self.parse_structdef() {
+                        Ok(structdef) => structs.push(structdef),
+                        Err(e) => {
+                            errors.push(e);
+                            self.recover();
+                        }
+                    }
+                }
+                Token::Import | Token::Use => {
+                    match self.parse_import() {
+                        Ok(import) => imports.push(import),
+                        Err(e) => {
+                            errors.push(e);
+                            self.recover();
+                        }
+                    }
+                }
                 _ => {
-                    let (token, span) = self.next()?;
-                    return Err(ParseError::UnexpectedToken("top-level item".to_owned(), token, span));
+                    let (token, span) = self.lexer.next().unwrap();
+                    errors.push(ParseError::UnexpectedToken("top-level item".to_owned(), token, span));
+                    self.recover();
                 }
             }
         }
 
-        Ok(Program {
-            overloads,
-            fundefs: fundefs_arena,
-        })
+        if errors.is_empty() {
+            let program = Program {
+                overloads,
+                fundefs: fundefs_arena,
+                structs,
+            };
+            Ok((program, imports))
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Skip tokens up to the start of the next top-level item.
+    fn recover(&mut self) {
+        while self.lexer.next_if(|(token, _)| !matches!(token, Token::Fn | Token::Struct | Token::Import | Token::Use)).is_some() {}
+    }
+
+    /// ```bnf
+    /// <import> = "import" <id> ";"
+    ///          | "use" <id> "::" <id> ";"
+    ///          | "use" <id> "::" "{" <id> ("," <id>)* "}" ";"
+    /// ```
+    fn parse_import(&mut self) -> ParseResult<Import> {
+        let (token, span_from) = self.next()?;
+        let (module, _) = self.parse_id()?;
+
+        let names = if token == Token::Use {
+            self.expect(Token::PathSep)?;
+            if self.peek()?.0 == Token::LBrace {
+                let (names, _) = self.parse_items_enclosed(
+                    Token::LBrace, Token::RBrace, Token::Comma,
+                    |p| p.parse_id().map(|(id, span)| ((id, span), span)))?;
+                Some(names)
+            } else {
+                Some(vec![self.parse_id()?])
+            }
+        } else {
+            None
+        };
+
+        let span_to = self.expect(Token::Semicolon)?;
+        Ok(Import { module, names, span: span_from.to(&span_to) })
+    }
+
+    /// ```bnf
+    /// <structdef> = "struct" <id> "{" <farg> ("," <farg>)* "}"
+    /// ```
+    fn parse_structdef(&mut self) -> ParseResult<Structdef> {
+        let span_from = self.expect(Token::Struct)?;
+        let (name, _) = self.parse_id()?;
+        self.expect(Token::LBrace)?;
+        let (fields, _) = self.parse_items(Token::Comma, |p| p.parse_farg())?;
+        let span_to = self.expect(Token::RBrace)?;
+        Ok(Structdef { name, fields, span: span_from.to(&span_to), import: None })
     }
 
     /// ```bnf
-    /// <fundef> = "fn" <id> "(" <fargs>? ")" "->" <type> "{" <body> "}"
+    /// <fundef> = "fn" <id> <type_params>? "(" <fargs>? ")" "->" <type> <where>? "{" <body> "}"
+    /// <type_params> = "<" <id> ("," <id>)* ">"
+    /// <where> = "where" <constraint> ("," <constraint>)*
     /// ```
     fn parse_fundef(&mut self) -> ParseResult<(Fundef<'ast, ParsedAst>, Span)> {
         self.decs_arena = Arena::new();
@@ -145,13 +299,30 @@ impl<'src, 'ast> Parser<'src, 'ast> {
         let span_from = self.expect(Token::Fn)?;
         let (name, _) = self.parse_id()?;
 
+        self.type_params = if self.peek()?.0 == Token::Lt {
+            let (type_params, _) = self.parse_items_enclosed(
+                Token::Lt, Token::Gt, Token::Comma,
+                |p| p.parse_id())?;
+            type_params
+        } else {
+            Vec::new()
+        };
+
         let (args, _) = self.parse_items_enclosed(
             Token::LParen, Token::RParen, Token::Comma,
             |p| p.parse_farg())?;
 
         self.expect(Token::Arrow)?;
 
-        let (ret_type, _) = self.parse_type()?;
+        let (ret_type, ret_span) = self.parse_type()?;
+        let span = span_from.to(&ret_span);
+
+        let constraints = if self.matches(&Token::Where).is_some() {
+            let (constraints, _) = self.parse_items(Token::Comma, |p| p.parse_constraint())?;
+            constraints
+        } else {
+            Vec::new()
+        };
 
         self.expect(Token::LBrace)?;
         let body = self.parse_body()?;
@@ -162,6 +333,8 @@ impl<'src, 'ast> Parser<'src, 'ast> {
 
         Ok((Fundef {
             name,
+            type_params: mem::take(&mut self.type_params),
+            constraints,
             args,
             shape_prelude: Vec::new(),
             shape_facts: ShapeFacts::default(),
@@ -169,16 +342,34 @@ impl<'src, 'ast> Parser<'src, 'ast> {
             exprs,
             body,
             ret_type,
+            span,
+            import: None,
+            internal: false,
         }, span_from.to(&span_to)))
     }
 
+    /// ```bnf
+    /// <constraint> = <id> "(" (<type> ("," <type>)*)? ")" "->" <type>
+    /// ```
+    fn parse_constraint(&mut self) -> ParseResult<(Constraint, Span)> {
+        let (name, span_from) = self.parse_id()?;
+        let (args, _) = self.parse_items_enclosed(
+            Token::LParen, Token::RParen, Token::Comma,
+            |p| p.parse_type())?;
+        self.expect(Token::Arrow)?;
+        let (ret_type, span_to) = self.parse_type()?;
+        let span = span_from.to(&span_to);
+        Ok((Constraint { name, args, ret_type, span }, span))
+    }
+
     /// ```bnf
     /// <farg> = <type> <id>
     /// ```
     fn parse_farg(&mut self) -> ParseResult<(Farg, Span)> {
         let (ty, ty_span) = self.parse_type()?;
         let (id, id_span) = self.parse_id()?;
-        Ok((Farg { id, ty }, ty_span.to(&id_span)))
+        let span = ty_span.to(&id_span);
+        Ok((Farg { id, ty, span }, span))
     }
 
     /// ```bnf
@@ -212,9 +403,9 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                 self.expect(Token::Assign)
                     .map_err(|_| ParseError::ExpectedStatement(err_token, err_loc))?;
 
-                let (expr, _) = self.parse_expr(None::<Bop>)?;
+                let (expr, expr_span) = self.parse_expr(None::<Bop>)?;
                 let lhs = self.alloc_lvis(lhs, None);
-                Stmt::Assign(Assign { lhs, expr })
+                Stmt::Assign(Assign { lhs, expr, span: span.to(&expr_span) })
             }
             Token::Printf => {
                 self.expect(Token::LParen)?;
@@ -258,13 +449,52 @@ impl<'src, 'ast> Parser<'src, 'ast> {
         let else_branch = self.parse_body()?;
         let span_to = self.expect(Token::RBrace)?;
 
-        let expr = self.alloc_expr(Expr::Cond(Cond { cond, then_branch, else_branch }));
-        Ok((expr, span_from.to(&span_to)))
+        let span = span_from.to(&span_to);
+        let expr = self.alloc_expr(Expr::Cond(Cond { cond, then_branch, else_branch, span }));
+        Ok((expr, span))
     }
 
     fn parse_tensor(&mut self) -> ParseResult<(&'ast Expr<'ast, ParsedAst>, Span)> {
         let span_from = self.expect(Token::LBrace)?;
 
+        let target = if self.at_target() {
+            let (target, target_span) = self.parse_id()?;
+            self.expect(Token::LSquare)?;
+            let (index, index_span) = self.parse_id()?;
+            self.expect(Token::RSquare)?;
+            self.expect(Token::Arrow)?;
+            Some((self.alloc_expr(Expr::Id(Id::Var(target))), index, target_span.to(&index_span)))
+        } else {
+            None
+        };
+
+        let mut partitions = vec![self.parse_partition()?];
+        while self.peek()?.0 == Token::Semicolon {
+            let (_, span) = self.next()?;
+            if target.is_some() {
+                return Err(ParseError::PermutationPartitioned(span));
+            }
+            partitions.push(self.parse_partition()?);
+        }
+
+        let span_to = self.expect(Token::RBrace)?;
+        let span = span_from.to(&span_to);
+
+        let target = match target {
+            Some((_, index, index_span)) if index != partitions[0].iv.name => {
+                return Err(ParseError::PermutationIndex(partitions[0].iv.name.clone(), index_span));
+            }
+            Some((target, _, _)) => Some(target),
+            None => None,
+        };
+
+        let tensor = self.alloc_expr(Expr::Tensor(Tensor { target, partitions, span }));
+        Ok((tensor, span))
+    }
+
+    fn parse_partition(&mut self) -> ParseResult<Partition<'ast, ParsedAst>> {
+        let span_from = self.peek()?.1;
+
         let body = self.parse_body()?;
 
         self.expect(Token::Bar)?;
@@ -287,32 +517,44 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                 (None, iv.clone(), ub)
             }
             _ => {
-                return Err(ParseError::UnexpectedToken("expected '<' or '<='".to_owned(), token, span));
+                return Err(ParseError::UnexpectedToken("'<' or '<='".to_owned(), token, span));
             }
         };
 
-        let span_to = self.expect(Token::RBrace)?;
+        let step = if self.eat_word("step")? {
+            Some(self.parse_expr(Some(PrecedenceFloor(2)))?.0)
+        } else {
+            None
+        };
+        let width = if step.is_some() && self.eat_word("width")? {
+            Some(self.parse_expr(Some(PrecedenceFloor(2)))?.0)
+        } else {
+            None
+        };
 
         let iv = self.alloc_lvis(iv, None);
-        let tensor = self.alloc_expr(Expr::Tensor(Tensor {
+        Ok(Partition {
             body,
             iv,
             lb,
             ub,
-        }));
-
-        Ok((tensor, span_from.to(&span_to)))
+            step,
+            width,
+            span: self.span_from(span_from),
+        })
     }
 
     fn parse_binary(&mut self, prev_op: Option<impl Operator>) -> ParseResult<(&'ast Expr<'ast, ParsedAst>, Span)> {
         let (token, span_from) = self.next()?;
 
         let mut left = match token {
-            Token::Fold => self.parse_fold()?,
+            Token::Fold => self.parse_fold(span_from)?,
             Token::Prf(id) => self.parse_prf_call(id, span_from)?,
             Token::Identifier(id) => {
                 if let Some((Token::LParen, _)) = self.lexer.peek() {
-                    self.parse_call(id)?
+                    self.parse_call(id, span_from)?
+                } else if self.at_record() {
+                    self.parse_record(id, span_from)?
                 } else {
                     self.alloc_expr(Expr::Id(Id::Var(id)))
                 }
@@ -333,7 +575,7 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                 let (token, rloc) = self.next()?;
                 if token != Token::RParen {
                     return Err(ParseError::UnexpectedToken(
-                        "expected ')'".to_owned(),
+                        "')'".to_owned(),
                         token,
                         rloc,
                     ));
@@ -344,54 +586,61 @@ impl<'src, 'ast> Parser<'src, 'ast> {
             token => {
                 let op = (&token).try_into().map_err(|_| {
                     ParseError::UnexpectedToken(
-                        "expected unary expression".to_owned(),
+                        "unary expression".to_owned(),
                         token,
                         span_from,
                     )
                 })?;
-                self.parse_unary(op)?
+                self.parse_unary(op, span_from)?
             }
         };
 
         // Handle postfix operators (selection, function calls, etc.)
-        left = self.parse_postfix(left)?;
+        left = self.parse_postfix(left, span_from)?;
 
         while let Some((op, _loc)) = self.parse_binary_operator(&prev_op)? {
             let (right, _) = self.parse_expr(Some(op))?;
             left = self.alloc_expr(Expr::Call(Call {
                 id: op.symbol().to_owned(),
                 args: vec![left, right],
+                span: self.span_from(span_from),
             }));
 
-            left = self.parse_postfix(left)?;
+            left = self.parse_postfix(left, span_from)?;
         }
 
-        Ok((left, span_from))
+        Ok((left, self.span_from(span_from)))
     }
 
-    fn parse_postfix(&mut self, operand: &'ast Expr<'ast, ParsedAst>) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+    fn parse_postfix(&mut self, operand: &'ast Expr<'ast, ParsedAst>, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
         let mut expr = operand;
 
-        while let Some((Token::LSquare, _)) = self.lexer.peek() {
-            expr = self.parse_sel(expr)?;
+        loop {
+            match self.lexer.peek() {
+                Some((Token::LSquare, _)) => expr = self.parse_sel(expr, span_from)?,
+                Some((Token::Dot, _)) => expr = self.parse_field(expr, span_from)?,
+                _ => break,
+            }
         }
 
         Ok(expr)
     }
 
-    fn parse_unary(&mut self, op: Uop) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
-        let (r, _) = self.parse_expr(Some(op))?;
+    fn parse_unary(&mut self, op: Uop, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+        let (r, r_span) = self.parse_expr(Some(op))?;
         Ok(self.alloc_expr(Expr::Call(Call {
             id: op.symbol().to_owned(),
             args: vec![r],
+            span: span_from.to(&r_span),
         })))
     }
 
-    fn parse_call(&mut self, id: String) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
-        let (args, _) = self.parse_items_enclosed(
+    fn parse_call(&mut self, id: String, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+        let (args, args_span) = self.parse_items_enclosed(
             Token::LParen, Token::RParen, Token::Comma,
             |p| p.parse_expr(None::<Bop>))?;
-        Ok(self.alloc_expr(Expr::Call(Call { id, args })))
+        let span = span_from.to(&args_span);
+        Ok(self.alloc_expr(Expr::Call(Call { id, args, span })))
     }
 
     fn parse_prf_call(&mut self, id: String, span: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
@@ -427,7 +676,9 @@ impl<'src, 'ast> Parser<'src, 'ast> {
     fn fold_dispatch_from_token(&self, token: Token, span: Span) -> ParseResult<String> {
         let id = match token {
             Token::Identifier(name) => name,
-            Token::Prf(_) => panic!("folding with primitive functions not yet supported"),
+            token @ Token::Prf(_) => {
+                return Err(ParseError::UnexpectedToken("fold function".to_owned(), token, span));
+            }
             token => {
                 let op: Bop = (&token).try_into().map_err(|_| {
                     ParseError::UnexpectedToken(
@@ -455,7 +706,7 @@ impl<'src, 'ast> Parser<'src, 'ast> {
         let (token, span) = self.next()?;
         let id = self.fold_dispatch_from_token(token, span)?;
 
-        if self.matches(&Token::LParen).is_none() {
+        if self.peek()?.0 != Token::LParen {
             return Ok(FoldFun::Name(id));
         }
 
@@ -466,7 +717,7 @@ impl<'src, 'ast> Parser<'src, 'ast> {
         Ok(FoldFun::Apply { id, args })
     }
 
-    fn parse_fold(&mut self) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+    fn parse_fold(&mut self, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
         self.expect(Token::LParen)?;
 
         let (neutral, _) = self.parse_expr(None::<Bop>)?;
@@ -475,18 +726,23 @@ impl<'src, 'ast> Parser<'src, 'ast> {
         let foldfun = self.parse_fold_fun()?;
         self.expect(Token::Comma)?;
 
-        let (selection_expr, _) = self.parse_expr(None::<Bop>)?;
+        let (selection_expr, selection_span) = self.parse_expr(None::<Bop>)?;
         let selection = match selection_expr {
-            Expr::Tensor(tensor) => tensor.clone(),
-            _ => return Err(ParseError::FoldSelectionMustBeTensor),
+            Expr::Tensor(Tensor { target: Some(_), .. }) => return Err(ParseError::FoldSelectionPermuted(selection_span)),
+            Expr::Tensor(tensor) => match tensor.single() {
+                Some(partition) => partition.clone(),
+                None => return Err(ParseError::FoldSelectionPartitioned(selection_span)),
+            },
+            _ => return Err(ParseError::FoldSelectionMustBeTensor(selection_span)),
         };
 
-        self.expect(Token::RParen)?;
+        let span_to = self.expect(Token::RParen)?;
 
         Ok(self.alloc_expr(Expr::Fold(Fold {
             neutral,
             foldfun,
             selection,
+            span: span_from.to(&span_to),
         })))
     }
 
@@ -495,27 +751,76 @@ impl<'src, 'ast> Parser<'src, 'ast> {
             Token::LSquare, Token::RSquare, Token::Comma,
             |p| p.parse_expr(None::<Bop>))?;
 
-        let expr = self.alloc_expr(Expr::Array(Array { elems }));
+        let expr = self.alloc_expr(Expr::Array(Array { elems, span }));
         Ok((expr, span))
     }
 
-    fn parse_sel(&mut self, arr: &'ast Expr<'ast, ParsedAst>) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+    fn parse_sel(&mut self, arr: &'ast Expr<'ast, ParsedAst>, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
         self.expect(Token::LSquare)?;
         let (idx, _) = self.parse_expr(None::<Bop>)?;
-        self.expect(Token::RSquare)?;
+        let span_to = self.expect(Token::RSquare)?;
 
         Ok(self.alloc_expr(Expr::Call(Call {
             id: "sel".to_owned(),
             args: vec![idx, arr],
+            span: span_from.to(&span_to),
+        })))
+    }
+
+    /// Whether the next tokens start the target of a tensor, i.e. `<id> [ <id> ] ->`.
+    ///
+    /// This distinguishes `{ q[iv] -> a[iv] | iv < [n] }` from a body that starts with a selection.
+    fn at_target(&mut self) -> bool {
+        let mut lookahead = self.lexer.clone();
+        matches!(lookahead.next(), Some((Token::Identifier(_), _)))
+            && matches!(lookahead.next(), Some((Token::LSquare, _)))
+            && matches!(lookahead.next(), Some((Token::Identifier(_), _)))
+            && matches!(lookahead.next(), Some((Token::RSquare, _)))
+            && matches!(lookahead.next(), Some((Token::Arrow, _)))
+    }
+
+    /// Whether the next tokens start the fields of a record construction, i.e. `{` followed by `.`.
+    ///
+    /// This distinguishes `complex32 { .real = x, ... }` from an identifier followed by a block.
+    fn at_record(&mut self) -> bool {
+        let mut lookahead = self.lexer.clone();
+        matches!(lookahead.next(), Some((Token::LBrace, _)))
+            && matches!(lookahead.next(), Some((Token::Dot, _)))
+    }
+
+    /// ```bnf
+    /// <record> = <id> "{" "." <id> "=" <expr> ("," "." <id> "=" <expr>)* "}"
+    /// ```
+    fn parse_record(&mut self, name: String, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+        let (fields, fields_span) = self.parse_items_enclosed(
+            Token::LBrace, Token::RBrace, Token::Comma,
+            |p| {
+                let span_from = p.expect(Token::Dot)?;
+                let (field, _) = p.parse_id()?;
+                p.expect(Token::Assign)?;
+                let (value, span_to) = p.parse_expr(None::<Bop>)?;
+                Ok(((field, value), span_from.to(&span_to)))
+            })?;
+        let span = span_from.to(&fields_span);
+        Ok(self.alloc_expr(Expr::Record(Record { name, fields, span })))
+    }
+
+    fn parse_field(&mut self, record: &'ast Expr<'ast, ParsedAst>, span_from: Span) -> ParseResult<&'ast Expr<'ast, ParsedAst>> {
+        self.expect(Token::Dot)?;
+        let (name, span_to) = self.parse_id()?;
+        Ok(self.alloc_expr(Expr::Field(Field {
+            record,
+            name,
+            span: span_from.to(&span_to),
         })))
     }
 
     fn parse_binary_operator(&mut self, previous: &Option<impl Operator>) -> ParseResult<Option<(Bop, Span)>> {
-        if let Some((token, _)) = self.lexer.peek()
+        if let Some((token, span)) = self.lexer.peek()
             && let Ok(op) = token.try_into()
-            && precedes(previous, &op)?
+            && precedes(previous, &op, *span)?
         {
-            let (_, span) = self.lexer.next().unwrap();
+            let (_, span) = self.next()?;
             Ok(Some((op, span)))
         } else {
             Ok(None)
@@ -549,6 +854,7 @@ impl<'src, 'ast> Parser<'src, 'ast> {
             Token::UsizeType => BaseType::Usize,
             Token::F32Type   => BaseType::F32,
             Token::F64Type   => BaseType::F64,
+            Token::Identifier(param) if self.type_params.contains(&param) => BaseType::Generic(param),
             Token::Identifier(udf) => BaseType::Udf(udf),
             _ => return Err(ParseError::UnexpectedToken("base type".to_owned(), token, span)),
         };
@@ -563,9 +869,10 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                 AxisPattern::Dim(DimPattern::Known(n as usize))
             }
             Token::Identifier(name) => {
-                if self.matches(&Token::Gt).is_some() || self.matches(&Token::Ge).is_some() {
-                    match self.next()? {
-                        (Token::NatValue(_), _) => {}
+                let strict = self.matches(&Token::Gt).is_some();
+                if strict || self.matches(&Token::Ge).is_some() {
+                    let bound = match self.next()? {
+                        (Token::NatValue(n), _) => n,
                         (token, span) => {
                             return Err(ParseError::UnexpectedToken(
                                 "natural number bound".to_owned(),
@@ -573,18 +880,20 @@ impl<'src, 'ast> Parser<'src, 'ast> {
                                 span,
                             ))
                         }
-                    }
+                    };
                     self.expect(Token::Colon)?;
                     let (shp_name, _) = self.parse_id()?;
                     AxisPattern::Rank(RankCapture {
                         dim_name: name,
                         shp_name,
+                        min_rank: if strict { bound + 1 } else { bound },
                     })
                 } else if self.matches(&Token::Colon).is_some() {
                     let (shp_name, _) = self.parse_id()?;
                     AxisPattern::Rank(RankCapture {
                         dim_name: name,
                         shp_name,
+                        min_rank: 0,
                     })
                 } else {
                     AxisPattern::Dim(DimPattern::Var(name))
@@ -39,13 +39,37 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
 
     const EXPR_DEFAULT: Self::ExprOut = ();
 
+    fn trav_program(&mut self, program: &mut Program<'ast, Self::Ast>) {
+        for structdef in &mut program.structs {
+            self.write(&format!("struct {} {{\n", structdef.name));
+            for field in &mut structdef.fields {
+                self.write("    ");
+                self.trav_type(&mut field.ty);
+                self.write(&format!(" {},\n", field.id));
+            }
+            self.write("}\n");
+        }
+
+        for fundef in program.fundefs.iter_mut() {
+            self.trav_fundef(fundef);
+        }
+    }
+
     fn trav_fundef(&mut self, fundef: &mut Fundef<'ast, Self::Ast>) {
         self.args = fundef.args.clone();
 
-        self.write(&format!("fn {}(", fundef.name));
+        self.write(&format!("fn {}", fundef.name));
+        if fundef.is_generic() {
+            self.write(&format!("<{}>", fundef.type_params.join(", ")));
+        }
+        self.write("(");
         self.trav_fargs(&mut fundef.args);
         self.write(") -> ");
         self.trav_type(&mut fundef.ret_type);
+        if !fundef.constraints.is_empty() {
+            let constraints: Vec<String> = fundef.constraints.iter().map(|c| c.to_string()).collect();
+            self.write(&format!(" where {}", constraints.join(", ")));
+        }
         self.write(" {\n");
 
         self.depth += 1;
@@ -107,6 +131,13 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
         self.write(";\n");
     }
 
+    fn trav_free(&mut self, free: &mut Free<'ast, Self::Ast>) {
+        self.indent();
+        self.write("free(");
+        self.trav_id(&mut free.id);
+        self.write(");\n");
+    }
+
     fn trav_cond(&mut self, cond: &mut Cond<'ast, Self::Ast>) {
         self.write("if ");
         Self::Ast::trav_operand(self, &mut cond.cond);
@@ -165,25 +196,50 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
 
         self.write(", ");
 
-        self.trav_tensor(&mut fold.selection);
-
-        self.write(")");
+        self.write("{\n");
+        self.trav_partition(&mut fold.selection);
+        self.write(" })");
     }
 
     fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, Self::Ast>) {
         self.write("{\n");
-        self.trav_body(&mut tensor.body);
+        if let Some(target) = &mut tensor.target {
+            self.depth += 1;
+            self.indent();
+            Ast::trav_operand(self, target);
+            self.write(&format!("[{}] ->\n", tensor.partitions[0].iv.name));
+            self.depth -= 1;
+        }
+        for (i, partition) in tensor.partitions.iter_mut().enumerate() {
+            if i > 0 {
+                self.write(";\n");
+            }
+            self.trav_partition(partition);
+        }
+        self.write(" }");
+    }
+
+    fn trav_partition(&mut self, partition: &mut Partition<'ast, Self::Ast>) {
+        self.trav_body(&mut partition.body);
         self.write(" | ");
 
-        if let Some(lb) = &mut tensor.lb {
+        if let Some(lb) = &mut partition.lb {
             Ast::trav_operand(self, lb);
             self.write(" <= ");
         }
 
-        self.write(&tensor.iv.name);
+        self.write(&partition.iv.name);
         self.write(" < ");
-        Ast::trav_operand(self, &mut tensor.ub);
-        self.write(" }");
+        Ast::trav_operand(self, &mut partition.ub);
+
+        if let Some(step) = &mut partition.step {
+            self.write(" step ");
+            Ast::trav_operand(self, step);
+        }
+        if let Some(width) = &mut partition.width {
+            self.write(" width ");
+            Ast::trav_operand(self, width);
+        }
     }
 
     fn trav_array(&mut self, array: &mut Array<'ast, Self::Ast>) {
@@ -195,6 +251,21 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
         self.write("]");
     }
 
+    fn trav_record(&mut self, record: &mut Record<'ast, Self::Ast>) {
+        self.write(&format!("{} {{ ", record.name));
+        for (name, value) in &mut record.fields {
+            self.write(&format!(".{} = ", name));
+            Ast::trav_operand(self, value);
+            self.write(", ");
+        }
+        self.write("}");
+    }
+
+    fn trav_field(&mut self, field: &mut Field<'ast, Self::Ast>) {
+        Ast::trav_operand(self, &mut field.record);
+        self.write(&format!(".{}", field.name));
+    }
+
     fn trav_id(&mut self, id: &mut Id<'ast, Self::Ast>) {
         match id {
             Id::Arg(i) => self.write(&self.args[*i].id.clone()),
@@ -228,6 +299,7 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
             F32 => "f32",
             F64 => "f64",
             Udf(udf) => udf,
+            Generic(name) => name,
         };
         self.write(ty_str);
 
@@ -241,6 +313,7 @@ impl<'ast, Ast: AstConfig + 'ast> Traverse<'ast> for Show<'ast, Ast> {
                         AxisPattern::Dim(DimPattern::Var(var)) => self.write(&var),
                         AxisPattern::Rank(capture) => {
                             self.write(&capture.dim_name);
+                            self.write(&capture.bound());
                             self.write(":");
                             self.write(&capture.shp_name);
                         }
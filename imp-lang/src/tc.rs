@@ -1,6 +1,12 @@
 //! # Type checking (`tc`)
+mod check_overloads;
+mod monomorphise;
 mod resolve_dispatch;
+mod shape_solver;
+mod specialise;
 mod type_infer;
 
-pub use resolve_dispatch::resolve_dispatch;
-pub use type_infer::type_infer;
+pub use check_overloads::{check_overloads, OverloadError};
+pub use resolve_dispatch::{resolve_dispatch, DispatchError};
+pub use specialise::specialise;
+pub use type_infer::{type_infer, InferenceError};
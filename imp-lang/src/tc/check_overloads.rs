@@ -0,0 +1,491 @@
+use std::{collections::HashMap, fmt};
+
+use crate::ast::*;
+
+/// Check that the overloads of every function are disjoint, as required by `doc/overloading.md`.
+///
+/// Every pair of overloads with the same base signature is compared, by searching for argument shapes
+/// that match the type patterns and [`ShapeFacts`] of both. An overlap is only reported if such
+/// a witness is found, so the check never rejects overloads that are in fact disjoint.
+///
+/// A generic function is compared with the other generic functions and with every base signature it can be
+/// instantiated for, except overloads with exactly its instantiated argument types, which take precedence.
+/// Its `where` clause is assumed to hold if an overload with the required base types exists.
+pub fn check_overloads<'ast, Ast: AstConfig>(program: &Program<'ast, Ast>) -> Result<(), Vec<OverloadError>> {
+    let mut errors = Vec::new();
+    let mut report = |a: &Fundef<'ast, Ast>, b: &Fundef<'ast, Ast>, witness: String| {
+        let (earlier, later) = if a.span <= b.span { (a, b) } else { (b, a) };
+        errors.push(OverloadError::Overlapping {
+            name: later.name.clone(),
+            other: signature(earlier),
+            witness,
+            span: later.span,
+        });
+    };
+
+    for groups in program.overloads.values() {
+        for (sig, fundefs) in groups {
+            if fundefs.iter().any(|fundef| fundef.is_generic()) {
+                continue;
+            }
+            for (j, later) in fundefs.iter().enumerate() {
+                for earlier in &fundefs[..j] {
+                    if let Some(witness) = witness(earlier, later, &sig.base_types) {
+                        report(earlier, later, witness);
+                    }
+                }
+            }
+        }
+
+        let mut generics: Vec<&Fundef<'ast, Ast>> = groups.values().flatten().copied().filter(|fundef| fundef.is_generic()).collect();
+        generics.sort_by_key(|fundef| fundef.span);
+        for (j, generic) in generics.iter().enumerate() {
+            for other in &generics[..j] {
+                let Some(bases) = common_bases(program, other, generic) else {
+                    continue;
+                };
+                if let Some(witness) = witness(other, generic, &bases) {
+                    report(other, generic, witness);
+                }
+            }
+
+            for (sig, fundefs) in groups {
+                if sig.base_types.iter().any(|base| matches!(base, BaseType::Generic(_))) {
+                    continue;
+                }
+                let Some(subst) = instantiable(program, generic, &sig.base_types) else {
+                    continue;
+                };
+                for fundef in fundefs {
+                    let shadowed = fundef.args.iter().zip(&generic.args)
+                        .all(|(arg, generic_arg)| arg.ty.to_string() == substitute(&generic_arg.ty, &subst).to_string());
+                    if shadowed {
+                        continue;
+                    }
+                    if let Some(witness) = witness(fundef, generic, &sig.base_types) {
+                        report(fundef, generic, witness);
+                    }
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug)]
+pub enum OverloadError {
+    /// Some arguments match both this overload and `other`.
+    Overlapping { name: String, other: String, witness: String, span: Span },
+}
+
+impl OverloadError {
+    pub fn span(&self) -> Span {
+        use OverloadError::*;
+        match self {
+            Overlapping { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for OverloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use OverloadError::*;
+        match self {
+            Overlapping { name, other, witness, .. } => write!(f,
+                "overload of `{}` is not disjoint from `{}`; both match arguments {}",
+                name, other, witness
+            ),
+        }
+    }
+}
+
+/// Give up on a pair of overloads after trying this many layouts, without reporting an overlap.
+const MAX_LAYOUTS: usize = 4096;
+
+/// Extent given to the dimensions of a witness that neither overload constrains.
+const FREE_EXTENT: usize = 1;
+
+/// The `name(types)` form of an overload, as shown in error messages.
+fn signature<Ast: AstConfig>(fundef: &Fundef<'_, Ast>) -> String {
+    let args: Vec<String> = fundef.args.iter().map(|arg| arg.ty.to_string()).collect();
+    format!("{}({})", fundef.name, args.join(", "))
+}
+
+/// Base types that a type parameter without further requirements is given in a witness, in order of preference.
+const WITNESS_BASES: [BaseType; 8] = [
+    BaseType::Usize, BaseType::I32, BaseType::I64, BaseType::U32,
+    BaseType::U64, BaseType::F32, BaseType::F64, BaseType::Bool,
+];
+
+/// The base types of the type parameters of `generic` for which its arguments have the base types `bases`,
+/// if its constraints can be met.
+fn instantiable<Ast: AstConfig>(program: &Program<'_, Ast>, generic: &Fundef<'_, Ast>, bases: &[BaseType]) -> Option<HashMap<String, BaseType>> {
+    let subst = unify_bases(&generic.args, bases)?;
+    provides_constraints(program, generic, &subst).then_some(subst)
+}
+
+/// Base types of arguments that two generic functions both accept, if any.
+///
+/// Positions where both have a type parameter are given the first of [`WITNESS_BASES`] that
+/// meets the constraints of both, tried for all such positions at once.
+fn common_bases<'ast, Ast: AstConfig>(program: &Program<'ast, Ast>, a: &Fundef<'ast, Ast>, b: &Fundef<'ast, Ast>) -> Option<Vec<BaseType>> {
+    if a.args.len() != b.args.len() {
+        return None;
+    }
+
+    WITNESS_BASES.iter().find_map(|free| {
+        // Each position takes the concrete type of either function, or `free` if both are generic
+        let mut bases: Vec<Option<BaseType>> = a.args.iter().zip(&b.args)
+            .map(|(a_arg, b_arg)| match (&a_arg.ty.ty, &b_arg.ty.ty) {
+                (BaseType::Generic(_), BaseType::Generic(_)) => None,
+                (BaseType::Generic(_), base) | (base, BaseType::Generic(_)) => Some(base.clone()),
+                (a_base, b_base) => (a_base == b_base).then(|| a_base.clone()),
+            })
+            .collect();
+        // A type parameter bound by a concrete position takes its type at every other position
+        for fundef in [a, b] {
+            for (i, arg) in fundef.args.iter().enumerate() {
+                let BaseType::Generic(param) = &arg.ty.ty else {
+                    continue;
+                };
+                let bound = fundef.args.iter().zip(&bases)
+                    .find_map(|(other, base)| match (&other.ty.ty, base) {
+                        (BaseType::Generic(other), Some(base)) if other == param => Some(base.clone()),
+                        _ => None,
+                    });
+                if bases[i].is_none() {
+                    bases[i] = bound;
+                }
+            }
+        }
+        let bases: Vec<BaseType> = bases.into_iter().map(|base| base.unwrap_or_else(|| free.clone())).collect();
+
+        [a, b].iter().all(|fundef| instantiable(program, fundef, &bases).is_some()).then_some(bases)
+    })
+}
+
+/// The type parameters for which `args` have the base types `bases`, if they are consistent.
+fn unify_bases(args: &[Farg], bases: &[BaseType]) -> Option<HashMap<String, BaseType>> {
+    if args.len() != bases.len() {
+        return None;
+    }
+
+    let mut subst: HashMap<String, BaseType> = HashMap::new();
+    for (arg, base) in args.iter().zip(bases) {
+        let consistent = match &arg.ty.ty {
+            BaseType::Generic(param) => subst.entry(param.clone()).or_insert_with(|| base.clone()) == base,
+            ty => ty == base,
+        };
+        if !consistent {
+            return None;
+        }
+    }
+    Some(subst)
+}
+
+fn substitute(ty: &Type, subst: &HashMap<String, BaseType>) -> Type {
+    match &ty.ty {
+        BaseType::Generic(param) => Type { ty: subst[param].clone(), shape: ty.shape.clone() },
+        _ => ty.clone(),
+    }
+}
+
+/// Whether an overload with the required base types exists for every constraint of `generic`.
+fn provides_constraints<Ast: AstConfig>(program: &Program<'_, Ast>, generic: &Fundef<'_, Ast>, subst: &HashMap<String, BaseType>) -> bool {
+    generic.constraints.iter().all(|constraint| {
+        let bases: Vec<BaseType> = constraint.args.iter().map(|ty| substitute(ty, subst).ty).collect();
+        let ret = substitute(&constraint.ret_type, subst).ty;
+        program.overloads.get(&constraint.name).into_iter()
+            .flat_map(|groups| groups.values().flatten())
+            .any(|fundef| match unify_bases(&fundef.args, &bases) {
+                Some(inner) => match &fundef.ret_type.ty {
+                    BaseType::Generic(param) => inner.get(param).is_none_or(|base| *base == ret),
+                    base => *base == ret,
+                },
+                None => false,
+            })
+    })
+}
+
+/// Argument types with concrete shapes that both overloads accept, if any, given the base types of the arguments.
+fn witness<'ast, Ast: AstConfig>(a: &Fundef<'ast, Ast>, b: &Fundef<'ast, Ast>, bases: &[BaseType]) -> Option<String> {
+    if a.args.len() != b.args.len() {
+        return None;
+    }
+
+    let overloads = [a, b];
+    // Ranks beyond the largest extent and the number of axes cannot help satisfying the patterns
+    let slack = overloads.iter()
+        .flat_map(|fundef| &fundef.args)
+        .map(|arg| match &arg.ty.shape {
+            TypePattern::Scalar => 0,
+            TypePattern::Axes(axes) => axes.len() + axes.iter().map(|axis| match axis {
+                AxisPattern::Dim(DimPattern::Known(n)) => *n,
+                _ => 0,
+            }).max().unwrap_or(0),
+        })
+        .max()
+        .unwrap_or(0);
+
+    let choices: Vec<Vec<Layout>> = a.args.iter().zip(&b.args)
+        .map(|(a_arg, b_arg)| Layout::all(&a_arg.ty.shape, &b_arg.ty.shape, slack))
+        .collect();
+    if choices.iter().any(Vec::is_empty) {
+        return None;
+    }
+
+    let mut current = vec![0; choices.len()];
+    for _ in 0..MAX_LAYOUTS {
+        let layouts: Vec<&Layout> = current.iter().zip(&choices).map(|(&i, layouts)| &layouts[i]).collect();
+        if let Some(extents) = Unifier::solve(&overloads, &layouts) {
+            let args: Vec<String> = bases.iter().zip(extents)
+                .map(|(base, shp)| if shp.is_empty() {
+                    base.to_string()
+                } else {
+                    let shp: Vec<String> = shp.iter().map(usize::to_string).collect();
+                    format!("{}[{}]", base, shp.join(", "))
+                })
+                .collect();
+            return Some(format!("({})", args.join(", ")));
+        }
+
+        // Advance to the next combination of layouts, trying smaller ranks first
+        let i = (0..current.len()).rev().find(|&i| current[i] + 1 < choices[i].len())?;
+        current[i] += 1;
+        for later in &mut current[i + 1..] {
+            *later = 0;
+        }
+    }
+
+    None
+}
+
+/// A concrete rank of one argument, together with the number of axes
+/// covered by each rank capture in the patterns of both overloads.
+struct Layout {
+    rank: usize,
+    widths: [Vec<usize>; 2],
+}
+
+impl Layout {
+    /// All layouts that both patterns admit, with ranks up to `slack` above their lower bound.
+    fn all(a: &TypePattern, b: &TypePattern, slack: usize) -> Vec<Self> {
+        let ranks = match (a.has_rank_capture(), b.has_rank_capture()) {
+            (false, _) => a.min_rank()..=a.min_rank(),
+            (true, false) => b.min_rank()..=b.min_rank(),
+            (true, true) => {
+                let lo = a.min_rank().max(b.min_rank());
+                lo..=lo + slack
+            }
+        };
+
+        let mut layouts = Vec::new();
+        for rank in ranks {
+            for a_widths in widths(a, rank) {
+                for b_widths in widths(b, rank) {
+                    layouts.push(Layout { rank, widths: [a_widths.clone(), b_widths] });
+                }
+            }
+        }
+        layouts
+    }
+
+    /// The axes of the argument matched by each entry of `axes`, given the widths of its rank captures.
+    fn positions(&self, axes: &[AxisPattern], widths: &[usize]) -> Vec<std::ops::Range<usize>> {
+        let mut widths = widths.iter();
+        let mut cursor = 0;
+        axes.iter()
+            .map(|axis| {
+                let width = match axis {
+                    AxisPattern::Dim(_) => 1,
+                    AxisPattern::Rank(_) => *widths.next().unwrap(),
+                };
+                cursor += width;
+                cursor - width..cursor
+            })
+            .collect()
+    }
+}
+
+/// Every way to divide the axes of an array of rank `rank` over the rank captures of `pattern`.
+fn widths(pattern: &TypePattern, rank: usize) -> Vec<Vec<usize>> {
+    let TypePattern::Axes(axes) = pattern else {
+        return if rank == 0 { vec![Vec::new()] } else { Vec::new() };
+    };
+
+    let dims = axes.iter().filter(|axis| matches!(axis, AxisPattern::Dim(_))).count();
+    let mins: Vec<usize> = axes.iter()
+        .filter_map(|axis| match axis {
+            AxisPattern::Rank(capture) => Some(capture.min_rank),
+            AxisPattern::Dim(_) => None,
+        })
+        .collect();
+
+    if mins.is_empty() {
+        return if rank == dims { vec![Vec::new()] } else { Vec::new() };
+    }
+    if rank < dims {
+        return Vec::new();
+    }
+
+    fn split(remaining: usize, mins: &[usize]) -> Vec<Vec<usize>> {
+        match mins {
+            [] => Vec::new(),
+            [min] if remaining >= *min => vec![vec![remaining]],
+            [_] => Vec::new(),
+            [min, rest @ ..] => (*min..=remaining)
+                .flat_map(|width| split(remaining - width, rest).into_iter().map(move |mut tail| {
+                    tail.insert(0, width);
+                    tail
+                }))
+                .collect(),
+        }
+    }
+
+    split(rank - dims, &mins)
+}
+
+/// A shape quantity, identified by the argument axis it describes or the symbol it is bound to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Node {
+    Extent { arg_index: usize, axis: usize },
+    /// A symbol without a binding in the facts, such as a scalar argument, of either overload.
+    Symbol { overload: usize, name: String },
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Node(Node),
+    Known(usize),
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Scalar(Operand),
+    Vector(Vec<Node>),
+}
+
+/// Union-find over the shape quantities of both overloads for one combination of layouts.
+struct Unifier {
+    parents: HashMap<Node, Node>,
+    values: HashMap<Node, usize>,
+}
+
+impl Unifier {
+    /// The argument shapes accepted by both overloads under `layouts`, if they agree.
+    fn solve<Ast: AstConfig>(overloads: &[&Fundef<'_, Ast>; 2], layouts: &[&Layout]) -> Option<Vec<Vec<usize>>> {
+        let mut unifier = Unifier { parents: HashMap::new(), values: HashMap::new() };
+
+        for (overload, fundef) in overloads.iter().enumerate() {
+            for (arg_index, (arg, layout)) in fundef.args.iter().zip(layouts).enumerate() {
+                let TypePattern::Axes(axes) = &arg.ty.shape else {
+                    continue;
+                };
+
+                for (axis, range) in axes.iter().zip(layout.positions(axes, &layout.widths[overload])) {
+                    if let AxisPattern::Dim(DimPattern::Known(n)) = axis {
+                        let node = Node::Extent { arg_index, axis: range.start };
+                        unifier.unify(&Operand::Node(node), &Operand::Known(*n))?;
+                    }
+                }
+            }
+
+            for equality in &fundef.shape_facts.equalities {
+                let left = unifier.value(overload, fundef, layouts, &equality.left);
+                let right = unifier.value(overload, fundef, layouts, &equality.right);
+                match (left, right) {
+                    (Some(Value::Scalar(left)), Some(Value::Scalar(right))) => unifier.unify(&left, &right)?,
+                    (Some(Value::Vector(left)), Some(Value::Vector(right))) => {
+                        if left.len() != right.len() {
+                            return None;
+                        }
+                        for (left, right) in left.into_iter().zip(right) {
+                            unifier.unify(&Operand::Node(left), &Operand::Node(right))?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let shapes = layouts.iter().enumerate()
+            .map(|(arg_index, layout)| (0..layout.rank)
+                .map(|axis| {
+                    let root = unifier.find(&Node::Extent { arg_index, axis });
+                    unifier.values.get(&root).copied().unwrap_or(FREE_EXTENT)
+                })
+                .collect())
+            .collect();
+        Some(shapes)
+    }
+
+    /// The quantity described by `term` in the facts of one overload.
+    fn value<Ast: AstConfig>(&self, overload: usize, fundef: &Fundef<'_, Ast>, layouts: &[&Layout], term: &ShapeTerm) -> Option<Value> {
+        let capture = |arg_index: usize, axis_index: usize| {
+            let TypePattern::Axes(axes) = &fundef.args[arg_index].ty.shape else {
+                unreachable!("shape facts only refer to axes of array arguments");
+            };
+            let layout = layouts[arg_index];
+            layout.positions(axes, &layout.widths[overload]).swap_remove(axis_index)
+        };
+
+        let value = match term {
+            ShapeTerm::Known(n) => Value::Scalar(Operand::Known(*n)),
+            ShapeTerm::Symbol(name) => match fundef.shape_facts.bindings.iter().find(|binding| &binding.symbol == name) {
+                Some(binding) => return self.value(overload, fundef, layouts, &binding.term),
+                None => Value::Scalar(Operand::Node(Node::Symbol { overload, name: name.clone() })),
+            },
+            ShapeTerm::ArgDim { arg_index, axis_index } => {
+                let axis = capture(*arg_index, *axis_index).start;
+                Value::Scalar(Operand::Node(Node::Extent { arg_index: *arg_index, axis }))
+            }
+            ShapeTerm::ArgRank { arg_index, axis_index } => Value::Scalar(Operand::Known(capture(*arg_index, *axis_index).len())),
+            ShapeTerm::TailShape { arg_index, start_axis } => Value::Vector(capture(*arg_index, *start_axis)
+                .map(|axis| Node::Extent { arg_index: *arg_index, axis })
+                .collect()),
+            ShapeTerm::RetDim { .. } | ShapeTerm::RetRank { .. } => return None,
+        };
+        Some(value)
+    }
+
+    fn find(&self, node: &Node) -> Node {
+        let mut node = node.clone();
+        while let Some(parent) = self.parents.get(&node) {
+            node = parent.clone();
+        }
+        node
+    }
+
+    /// Merge two quantities, or `None` if they are known to differ.
+    fn unify(&mut self, a: &Operand, b: &Operand) -> Option<()> {
+        match (a, b) {
+            (Operand::Known(x), Operand::Known(y)) => (x == y).then_some(()),
+            (Operand::Node(node), Operand::Known(n)) | (Operand::Known(n), Operand::Node(node)) => {
+                let root = self.find(node);
+                match self.values.get(&root) {
+                    Some(value) => (value == n).then_some(()),
+                    None => {
+                        self.values.insert(root, *n);
+                        Some(())
+                    }
+                }
+            }
+            (Operand::Node(a), Operand::Node(b)) => {
+                let (a, b) = (self.find(a), self.find(b));
+                if a == b {
+                    return Some(());
+                }
+                if let Some(value) = self.values.remove(&a) {
+                    self.unify(&Operand::Node(b.clone()), &Operand::Known(value))?;
+                }
+                self.parents.insert(a, b);
+                Some(())
+            }
+        }
+    }
+}
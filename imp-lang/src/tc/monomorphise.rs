@@ -0,0 +1,192 @@
+use std::{collections::HashMap, mem};
+
+use typed_arena::Arena;
+
+use crate::ast::*;
+
+/// Copy a generic function with `types` substituted for its type parameters.
+///
+/// The copy is an ordinary overload of the generic function, which is type checked and
+/// dispatched to like any other. Its variables are fresh, as type inference assigns their types in place.
+pub fn monomorphise<'ast>(generic: &Fundef<'ast, UntypedAst>, types: &[BaseType]) -> Fundef<'ast, UntypedAst> {
+    let decs = Arena::new();
+    let exprs = Arena::new();
+
+    let mut mono = Monomorphise {
+        type_params: &generic.type_params,
+        types,
+        // SAFETY: the arenas are moved into the returned Fundef, their contents are not.
+        decs: unsafe { mem::transmute::<&Arena<VarInfo<'ast, UntypedAst>>, &'ast Arena<VarInfo<'ast, UntypedAst>>>(&decs) },
+        exprs: unsafe { mem::transmute::<&Arena<Expr<'ast, UntypedAst>>, &'ast Arena<Expr<'ast, UntypedAst>>>(&exprs) },
+        copies: HashMap::new(),
+    };
+
+    let args = generic.args.iter()
+        .map(|arg| Farg { ty: mono.substitute(&arg.ty), ..arg.clone() })
+        .collect();
+    let ret_type = mono.substitute(&generic.ret_type);
+    let shape_prelude = generic.shape_prelude.iter().map(|assign| mono.copy_assign(assign)).collect();
+    let body = mono.copy_body(&generic.body);
+
+    Fundef {
+        name: generic.name.clone(),
+        type_params: Vec::new(),
+        constraints: Vec::new(),
+        ret_type,
+        args,
+        shape_prelude,
+        shape_facts: generic.shape_facts.clone(),
+        decs,
+        exprs,
+        body,
+        span: generic.span,
+        // Instances are generated by the module that needs them, even if the generic function is imported
+        import: None,
+        internal: generic.import.is_some(),
+    }
+}
+
+/// Substitute `types` for the type parameters `type_params` in `ty`.
+pub fn substitute(ty: &Type, type_params: &[String], types: &[BaseType]) -> Type {
+    match &ty.ty {
+        BaseType::Generic(param) => {
+            let i = type_params.iter().position(|p| p == param)
+                .expect("type parameter is declared by its function");
+            Type { ty: types[i].clone(), shape: ty.shape.clone() }
+        }
+        _ => ty.clone(),
+    }
+}
+
+struct Monomorphise<'a, 'ast> {
+    type_params: &'a [String],
+    types: &'a [BaseType],
+    decs: &'ast Arena<VarInfo<'ast, UntypedAst>>,
+    exprs: &'ast Arena<Expr<'ast, UntypedAst>>,
+    /// Copies of the variables of the generic function.
+    copies: HashMap<*const VarInfo<'ast, UntypedAst>, &'ast VarInfo<'ast, UntypedAst>>,
+}
+
+impl<'ast> Monomorphise<'_, 'ast> {
+    fn substitute(&self, ty: &Type) -> Type {
+        substitute(ty, self.type_params, self.types)
+    }
+
+    fn copy_lvis(&mut self, lvis: &'ast VarInfo<'ast, UntypedAst>, ssa: Option<&'ast Expr<'ast, UntypedAst>>) -> &'ast VarInfo<'ast, UntypedAst> {
+        let copy = self.decs.alloc(VarInfo {
+            name: lvis.name.clone(),
+            ty: lvis.ty.as_ref().map(|ty| self.substitute(ty)),
+            ssa,
+        });
+        self.copies.insert(lvis as *const _, copy);
+        copy
+    }
+
+    fn copy_id(&self, id: &Id<'ast, UntypedAst>) -> Id<'ast, UntypedAst> {
+        match id {
+            Id::Var(v) => Id::Var(self.copies[&(*v as *const _)]),
+            Id::Arg(i) => Id::Arg(*i),
+        }
+    }
+
+    fn copy_assign(&mut self, assign: &Assign<'ast, UntypedAst>) -> Assign<'ast, UntypedAst> {
+        let expr = self.copy_expr(assign.expr);
+        let expr = &*self.exprs.alloc(expr);
+        let lhs = self.copy_lvis(assign.lhs, assign.lhs.ssa.map(|_| expr));
+        Assign { lhs, expr, span: assign.span }
+    }
+
+    fn copy_body(&mut self, body: &Body<'ast, UntypedAst>) -> Body<'ast, UntypedAst> {
+        let stmts = body.stmts.iter().map(|stmt| match stmt {
+            Stmt::Assign(assign) => Stmt::Assign(self.copy_assign(assign)),
+            Stmt::Printf(printf) => Stmt::Printf(Printf { id: self.copy_id(&printf.id) }),
+            Stmt::Free(free) => Stmt::Free(Free { id: self.copy_id(&free.id) }),
+        }).collect();
+
+        Body { stmts, ret: self.copy_id(&body.ret) }
+    }
+
+    fn copy_tensor(&mut self, tensor: &Tensor<'ast, UntypedAst>) -> Tensor<'ast, UntypedAst> {
+        Tensor {
+            target: tensor.target.map(|target| self.copy_id(&target)),
+            partitions: tensor.partitions.iter().map(|partition| self.copy_partition(partition)).collect(),
+            span: tensor.span,
+        }
+    }
+
+    fn copy_partition(&mut self, partition: &Partition<'ast, UntypedAst>) -> Partition<'ast, UntypedAst> {
+        let lb = partition.lb.map(|lb| self.copy_id(&lb));
+        let ub = self.copy_id(&partition.ub);
+        let step = partition.step.map(|step| self.copy_id(&step));
+        let width = partition.width.map(|width| self.copy_id(&width));
+        let iv = self.copy_lvis(partition.iv, None);
+        Partition {
+            body: self.copy_body(&partition.body),
+            iv,
+            lb,
+            ub,
+            step,
+            width,
+            span: partition.span,
+        }
+    }
+
+    fn copy_expr(&mut self, expr: &Expr<'ast, UntypedAst>) -> Expr<'ast, UntypedAst> {
+        match expr {
+            Expr::Cond(cond) => Expr::Cond(Cond {
+                cond: self.copy_id(&cond.cond),
+                then_branch: self.copy_body(&cond.then_branch),
+                else_branch: self.copy_body(&cond.else_branch),
+                span: cond.span,
+            }),
+            Expr::Call(call) => Expr::Call(Call {
+                id: call.id.clone(),
+                args: call.args.iter().map(|arg| self.copy_id(arg)).collect(),
+                span: call.span,
+            }),
+            Expr::Prf(prf) => {
+                let mut prf = prf.clone();
+                for arg in prf.args_mut() {
+                    *arg = self.copy_id(arg);
+                }
+                Expr::Prf(prf)
+            }
+            Expr::Tensor(tensor) => Expr::Tensor(self.copy_tensor(tensor)),
+            Expr::Fold(fold) => {
+                let neutral = self.copy_id(&fold.neutral);
+                let foldfun = match &fold.foldfun {
+                    FoldFun::Name(id) => FoldFun::Name(id.clone()),
+                    FoldFun::Apply { id, args } => FoldFun::Apply {
+                        id: id.clone(),
+                        args: args.iter().map(|arg| match arg {
+                            FoldFunArg::Placeholder => FoldFunArg::Placeholder,
+                            FoldFunArg::Bound(bound) => FoldFunArg::Bound(self.copy_id(bound)),
+                        }).collect(),
+                    },
+                };
+                Expr::Fold(Fold {
+                    neutral,
+                    foldfun,
+                    selection: self.copy_partition(&fold.selection),
+                    span: fold.span,
+                })
+            }
+            Expr::Array(array) => Expr::Array(Array {
+                elems: array.elems.iter().map(|elem| self.copy_id(elem)).collect(),
+                span: array.span,
+            }),
+            Expr::Record(record) => Expr::Record(Record {
+                name: record.name.clone(),
+                fields: record.fields.iter().map(|(name, value)| (name.clone(), self.copy_id(value))).collect(),
+                span: record.span,
+            }),
+            Expr::Field(field) => Expr::Field(Field {
+                record: self.copy_id(&field.record),
+                name: field.name.clone(),
+                span: field.span,
+            }),
+            Expr::Id(id) => Expr::Id(self.copy_id(id)),
+            Expr::Const(c) => Expr::Const(*c),
+        }
+    }
+}
@@ -1,13 +1,14 @@
-use std::{collections::HashMap, mem};
+use std::{collections::HashMap, fmt, mem};
 
 use typed_arena::Arena;
 
 use crate::ast::*;
 
-pub fn resolve_dispatch<'ast>(program: Program<'ast, UntypedAst>) -> Result<Program<'ast, TypedAst>, DispatchError> {
+pub fn resolve_dispatch<'ast>(program: Program<'ast, UntypedAst>) -> Result<Program<'ast, TypedAst>, Vec<DispatchError>> {
     let mut out_program = Program {
         overloads: HashMap::new(),
         fundefs: Arena::new(),
+        structs: program.structs.clone(),
     };
 
     let mut overloads: HashMap<String, HashMap<BaseSignature, Vec<&'ast Fundef<'ast, TypedAst>>>> = HashMap::new();
@@ -15,11 +16,14 @@ pub fn resolve_dispatch<'ast>(program: Program<'ast, UntypedAst>) -> Result<Prog
 
     for (name, groups) in &program.overloads {
         let mut out_groups = HashMap::new();
-        for (sig, fundefs) in groups {
+        // Generic functions are only kept through the instances that type inference made of them
+        for (sig, fundefs) in groups.iter().filter(|(_, fundefs)| !fundefs[0].is_generic()) {
             let mut out_fundefs = Vec::new();
             for fundef in fundefs {
                 let stub = out_program.fundefs.alloc(Fundef {
                     name: fundef.name.clone(),
+                    type_params: Vec::new(),
+                    constraints: Vec::new(),
                     ret_type: fundef.ret_type.clone(),
                     args: fundef.args.clone(),
                     shape_prelude: Vec::new(),
@@ -30,6 +34,9 @@ pub fn resolve_dispatch<'ast>(program: Program<'ast, UntypedAst>) -> Result<Prog
                         stmts: Vec::new(),
                         ret: Id::Arg(usize::MAX),
                     },
+                    span: fundef.span,
+                    import: fundef.import.clone(),
+                    internal: fundef.internal,
                 });
                 let stub_ptr = stub as *mut Fundef<'ast, TypedAst>;
                 let stub_ref: &'ast Fundef<'ast, TypedAst> = unsafe { std::mem::transmute(stub) };
@@ -38,31 +45,69 @@ pub fn resolve_dispatch<'ast>(program: Program<'ast, UntypedAst>) -> Result<Prog
             }
             out_groups.insert(sig.clone(), out_fundefs);
         }
-        overloads.insert(name.clone(), out_groups);
+        if !out_groups.is_empty() {
+            overloads.insert(name.clone(), out_groups);
+        }
     }
 
+    let mut errors = Vec::new();
     for (slot_ptr, src_fundef) in work_items {
         let mut lower = DispatchResolver::new(overloads.clone());
         let lowered = lower.lower_fundef(src_fundef);
-        if let Some(err) = lower.errors.into_iter().next() {
-            return Err(err);
-        }
+        errors.extend(lower.errors);
         unsafe {
             std::ptr::replace(slot_ptr, lowered);
         }
     }
 
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
     out_program.overloads = overloads;
     Ok(out_program)
 }
 
-#[allow(unused)]
 #[derive(Debug)]
 pub enum DispatchError {
-    MissingTypeAnnotation { name: String },
-    UndefinedFunction { name: String },
-    NoMatchingOverload { name: String, arg_bases: BaseSignature },
-    AmbiguousOverload { name: String, arg_bases: BaseSignature },
+    MissingTypeAnnotation { name: String, span: Span },
+    UndefinedFunction { name: String, span: Span },
+    NoMatchingOverload { name: String, arg_bases: BaseSignature, span: Span },
+    AmbiguousOverload { name: String, arg_bases: BaseSignature, span: Span },
+}
+
+impl DispatchError {
+    pub fn span(&self) -> Span {
+        use DispatchError::*;
+        match self {
+            MissingTypeAnnotation { span, .. }
+            | UndefinedFunction { span, .. }
+            | NoMatchingOverload { span, .. }
+            | AmbiguousOverload { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DispatchError::*;
+        match self {
+            MissingTypeAnnotation { name, .. } =>
+                write!(f, "no type was inferred for `{}`", name),
+            UndefinedFunction { name, .. } =>
+                write!(f, "cannot find function `{}`", name),
+            NoMatchingOverload { name, arg_bases, .. } =>
+                write!(f, "no overload of `{}` matches argument types {}", name, arg_bases),
+            AmbiguousOverload { name, arg_bases, .. } =>
+                write!(f, "call to `{}` with argument types {} is ambiguous", name, arg_bases),
+        }
+    }
+}
+
+/// Stands in for a call that could not be resolved. The error has been
+/// recorded, so the lowered program is discarded anyway.
+fn unresolved<'ast>() -> Expr<'ast, TypedAst> {
+    Expr::Id(Id::Arg(usize::MAX))
 }
 
 struct DispatchResolver<'ast> {
@@ -72,6 +117,8 @@ struct DispatchResolver<'ast> {
     expr_arena: Arena<Expr<'ast, TypedAst>>,
     errors: Vec<DispatchError>,
     overloads: HashMap<String, HashMap<BaseSignature, Vec<&'ast Fundef<'ast, TypedAst>>>>,
+    /// Span of the assignment currently being lowered, for error reporting.
+    span: Span,
 }
 
 impl<'ast> DispatchResolver<'ast> {
@@ -83,6 +130,7 @@ impl<'ast> DispatchResolver<'ast> {
             expr_arena: Arena::new(),
             errors: Vec::new(),
             overloads,
+            span: Span::default(),
         }
     }
 
@@ -100,6 +148,7 @@ impl<'ast> DispatchResolver<'ast> {
             None => {
                 self.errors.push(DispatchError::MissingTypeAnnotation {
                     name: name.to_owned(),
+                    span: self.span,
                 });
                 Type::scalar(BaseType::I32)
             }
@@ -113,12 +162,13 @@ impl<'ast> DispatchResolver<'ast> {
         }
     }
 
-    fn resolve_target(&mut self, func_name: &str, arg_types: &[Type]) -> &'ast Fundef<'ast, TypedAst> {
+    fn resolve_target(&mut self, func_name: &str, arg_types: &[Type], span: Span) -> Option<&'ast Fundef<'ast, TypedAst>> {
         let Some(group) = self.overloads.get(func_name) else {
             self.errors.push(DispatchError::UndefinedFunction {
                 name: func_name.to_owned(),
+                span,
             });
-            panic!("undefined function during dispatch resolution: {}", func_name);
+            return None;
         };
 
         let key = BaseSignature {
@@ -129,8 +179,9 @@ impl<'ast> DispatchResolver<'ast> {
             self.errors.push(DispatchError::NoMatchingOverload {
                 name: func_name.to_owned(),
                 arg_bases: key.clone(),
+                span,
             });
-            panic!("no matching overload during dispatch resolution: {}", func_name);
+            return None;
         };
 
         let mut matches = Vec::new();
@@ -151,8 +202,9 @@ impl<'ast> DispatchResolver<'ast> {
             self.errors.push(DispatchError::NoMatchingOverload {
                 name: func_name.to_owned(),
                 arg_bases: key.clone(),
+                span,
             });
-            panic!("no compatible overload during dispatch resolution: {}", func_name);
+            return None;
         }
 
         let best = maximal_candidates(&matches);
@@ -160,10 +212,11 @@ impl<'ast> DispatchResolver<'ast> {
             self.errors.push(DispatchError::AmbiguousOverload {
                 name: func_name.to_owned(),
                 arg_bases: key,
+                span,
             });
         }
 
-        best[0]
+        Some(best[0])
     }
 
     fn lower_fundef(&mut self, fundef: &Fundef<'ast, UntypedAst>) -> Fundef<'ast, TypedAst> {
@@ -171,6 +224,7 @@ impl<'ast> DispatchResolver<'ast> {
         self.idmap.clear();
         self.decs_arena = Arena::new();
         self.expr_arena = Arena::new();
+        self.span = fundef.span;
 
         let mut shape_prelude = Vec::new();
         for assign in &fundef.shape_prelude {
@@ -184,6 +238,8 @@ impl<'ast> DispatchResolver<'ast> {
 
         Fundef {
             name: fundef.name.clone(),
+            type_params: Vec::new(),
+            constraints: Vec::new(),
             ret_type: fundef.ret_type.clone(),
             args: fundef.args.clone(),
             shape_prelude,
@@ -191,6 +247,9 @@ impl<'ast> DispatchResolver<'ast> {
             decs,
             exprs,
             body,
+            span: fundef.span,
+            import: fundef.import.clone(),
+            internal: fundef.internal,
         }
     }
 
@@ -207,16 +266,20 @@ impl<'ast> DispatchResolver<'ast> {
         match stmt {
             Stmt::Assign(a) => Stmt::Assign(self.lower_assign(a)),
             Stmt::Printf(p) => Stmt::Printf(self.lower_printf(p)),
+            Stmt::Free(f) => Stmt::Free(self.lower_free(f)),
         }
     }
 
     fn lower_assign(&mut self, assign: Assign<'ast, UntypedAst>) -> Assign<'ast, TypedAst> {
+        let old_span = self.span;
+        self.span = assign.span;
         let expr = self.lower_expr((*assign.expr).clone());
         let expr_ref = self.alloc_expr(expr);
         let lhs_ty = self.require_ty(&assign.lhs.name, &assign.lhs.ty);
         let lhs = self.alloc_lvis(assign.lhs.name.clone(), lhs_ty, Some(expr_ref));
         self.idmap.insert(assign.lhs as *const _, lhs);
-        Assign { lhs, expr: expr_ref }
+        self.span = old_span;
+        Assign { lhs, expr: expr_ref, span: assign.span }
     }
 
     fn lower_printf(&mut self, printf: Printf<'ast, UntypedAst>) -> Printf<'ast, TypedAst> {
@@ -225,14 +288,22 @@ impl<'ast> DispatchResolver<'ast> {
         }
     }
 
+    fn lower_free(&mut self, free: Free<'ast, UntypedAst>) -> Free<'ast, TypedAst> {
+        Free {
+            id: self.lower_id(free.id),
+        }
+    }
+
     fn lower_expr(&mut self, expr: Expr<'ast, UntypedAst>) -> Expr<'ast, TypedAst> {
         match expr {
             Expr::Cond(n) => Expr::Cond(self.lower_cond(n)),
-            Expr::Call(n) => Expr::Call(self.lower_call(n)),
+            Expr::Call(n) => self.lower_call(n).map_or_else(unresolved, Expr::Call),
             Expr::Prf(n) => Expr::Prf(self.lower_prf(n)),
-            Expr::Fold(n) => Expr::Fold(self.lower_fold(n)),
+            Expr::Fold(n) => self.lower_fold(n).map_or_else(unresolved, Expr::Fold),
             Expr::Tensor(n) => Expr::Tensor(self.lower_tensor(n)),
             Expr::Array(n) => Expr::Array(self.lower_array(n)),
+            Expr::Record(n) => Expr::Record(self.lower_record(n)),
+            Expr::Field(n) => Expr::Field(self.lower_field(n)),
             Expr::Id(n) => Expr::Id(self.lower_id(n)),
             Expr::Const(n) => Expr::Const(n),
         }
@@ -243,20 +314,22 @@ impl<'ast> DispatchResolver<'ast> {
             cond: self.lower_id(cond.cond),
             then_branch: self.lower_body(cond.then_branch),
             else_branch: self.lower_body(cond.else_branch),
+            span: cond.span,
         }
     }
 
-    fn lower_call(&mut self, call: Call<'ast, UntypedAst>) -> Call<'ast, TypedAst> {
+    fn lower_call(&mut self, call: Call<'ast, UntypedAst>) -> Option<Call<'ast, TypedAst>> {
         let mut args = Vec::with_capacity(call.args.len());
         for arg in call.args {
             args.push(self.lower_id(arg));
         }
         let arg_types = args.iter().map(|arg| self.id_type(arg)).collect::<Vec<_>>();
-        let target = self.resolve_target(&call.id, &arg_types);
-        Call {
+        let target = self.resolve_target(&call.id, &arg_types, call.span)?;
+        Some(Call {
             id: CallTarget::Function(target),
             args,
-        }
+            span: call.span,
+        })
     }
 
     fn lower_prf(&mut self, prf: Prf<'ast, UntypedAst>) -> Prf<'ast, TypedAst> {
@@ -265,6 +338,8 @@ impl<'ast> DispatchResolver<'ast> {
             ShapeA(a) => ShapeA(self.lower_id(a)),
             DimA(a) => DimA(self.lower_id(a)),
             SelVxA(i, a) => SelVxA(self.lower_id(i), self.lower_id(a)),
+            TakeSxV(n, v) => TakeSxV(self.lower_id(n), self.lower_id(v)),
+            DropSxV(n, v) => DropSxV(self.lower_id(n), self.lower_id(v)),
             AddSxS(l, r) => AddSxS(self.lower_id(l), self.lower_id(r)),
             SubSxS(l, r) => SubSxS(self.lower_id(l), self.lower_id(r)),
             MulSxS(l, r) => MulSxS(self.lower_id(l), self.lower_id(r)),
@@ -280,44 +355,88 @@ impl<'ast> DispatchResolver<'ast> {
         }
     }
 
-    fn lower_fold(&mut self, fold: Fold<'ast, UntypedAst>) -> Fold<'ast, TypedAst> {
+    fn lower_fold(&mut self, fold: Fold<'ast, UntypedAst>) -> Option<Fold<'ast, TypedAst>> {
         let neutral = self.lower_id(fold.neutral);
-        let selection = self.lower_tensor(fold.selection);
+        let selection = self.lower_partition(fold.selection);
 
         let foldfun = match fold.foldfun {
             FoldFun::Name(name) => {
                 let arg_types = vec![self.id_type(&neutral), self.id_type(&selection.body.ret)];
-                let target = self.resolve_target(&name, &arg_types);
+                let target = self.resolve_target(&name, &arg_types, fold.span)?;
                 FoldFun::Name(CallTarget::Function(target))
             }
-            FoldFun::Apply { .. } => {
-                unimplemented!("dispatch resolution for partial-application fold is not implemented")
+            FoldFun::Apply { id, args } => {
+                // The first placeholder receives the accumulator, the second the element
+                let mut holes = [self.id_type(&neutral), self.id_type(&selection.body.ret)].into_iter();
+                let args: Vec<FoldFunArg<'ast, TypedAst>> = args.into_iter()
+                    .map(|arg| match arg {
+                        FoldFunArg::Placeholder => FoldFunArg::Placeholder,
+                        FoldFunArg::Bound(bound) => FoldFunArg::Bound(self.lower_id(bound)),
+                    })
+                    .collect();
+                let arg_types: Vec<Type> = args.iter()
+                    .map(|arg| match arg {
+                        FoldFunArg::Placeholder => holes.next().expect("type inference checked the placeholder count"),
+                        FoldFunArg::Bound(bound) => self.id_type(bound),
+                    })
+                    .collect();
+                let target = self.resolve_target(&id, &arg_types, fold.span)?;
+                FoldFun::Apply { id: CallTarget::Function(target), args }
             }
         };
 
-        Fold {
+        Some(Fold {
             neutral,
             foldfun,
             selection,
-        }
+            span: fold.span,
+        })
     }
 
     fn lower_tensor(&mut self, tensor: Tensor<'ast, UntypedAst>) -> Tensor<'ast, TypedAst> {
-        let iv_ty = self.require_ty(&tensor.iv.name, &tensor.iv.ty);
-        let iv = self.alloc_lvis(tensor.iv.name.clone(), iv_ty, None);
-        self.idmap.insert(tensor.iv as *const _, iv);
-
         Tensor {
+            target: tensor.target.map(|target| self.lower_id(target)),
+            partitions: tensor.partitions.into_iter().map(|partition| self.lower_partition(partition)).collect(),
+            span: tensor.span,
+        }
+    }
+
+    fn lower_partition(&mut self, partition: Partition<'ast, UntypedAst>) -> Partition<'ast, TypedAst> {
+        let iv_ty = self.require_ty(&partition.iv.name, &partition.iv.ty);
+        let iv = self.alloc_lvis(partition.iv.name.clone(), iv_ty, None);
+        self.idmap.insert(partition.iv as *const _, iv);
+
+        Partition {
             iv,
-            lb: tensor.lb.map(|lb| self.lower_id(lb)),
-            ub: self.lower_id(tensor.ub),
-            body: self.lower_body(tensor.body),
+            lb: partition.lb.map(|lb| self.lower_id(lb)),
+            ub: self.lower_id(partition.ub),
+            step: partition.step.map(|step| self.lower_id(step)),
+            width: partition.width.map(|width| self.lower_id(width)),
+            body: self.lower_body(partition.body),
+            span: partition.span,
         }
     }
 
     fn lower_array(&mut self, array: Array<'ast, UntypedAst>) -> Array<'ast, TypedAst> {
         Array {
             elems: array.elems.into_iter().map(|id| self.lower_id(id)).collect(),
+            span: array.span,
+        }
+    }
+
+    fn lower_record(&mut self, record: Record<'ast, UntypedAst>) -> Record<'ast, TypedAst> {
+        Record {
+            name: record.name,
+            fields: record.fields.into_iter().map(|(name, id)| (name, self.lower_id(id))).collect(),
+            span: record.span,
+        }
+    }
+
+    fn lower_field(&mut self, field: Field<'ast, UntypedAst>) -> Field<'ast, TypedAst> {
+        Field {
+            record: self.lower_id(field.record),
+            name: field.name,
+            span: field.span,
         }
     }
 
@@ -335,7 +454,7 @@ impl<'ast> DispatchResolver<'ast> {
     }
 }
 
-fn maximal_candidates<'ast>(candidates: &[&'ast Fundef<'ast, TypedAst>]) -> Vec<&'ast Fundef<'ast, TypedAst>> {
+pub(super) fn maximal_candidates<'ast>(candidates: &[&'ast Fundef<'ast, TypedAst>]) -> Vec<&'ast Fundef<'ast, TypedAst>> {
     let mut maximal: Vec<&Fundef<'_, TypedAst>> = Vec::new();
 
     'outer: for a in candidates {
@@ -396,23 +515,22 @@ fn shape_relation(a: &TypePattern, b: &TypePattern) -> ShapeRel {
 fn shape_more_or_equal(a: &TypePattern, b: &TypePattern) -> bool {
     match (a, b) {
         (TypePattern::Scalar, TypePattern::Scalar) => true,
-        (TypePattern::Scalar, TypePattern::Axes(axes)) => axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))),
-        (TypePattern::Axes(a_axes), TypePattern::Scalar) => a_axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))),
-        (TypePattern::Axes(a_axes), TypePattern::Axes(b_axes)) => axes_more_or_equal(a_axes, b_axes),
+        // A rank capture only overlaps with scalars if it has no lower bound
+        (TypePattern::Scalar, TypePattern::Axes(_)) => b.has_rank_capture() && b.min_rank() == 0,
+        (TypePattern::Axes(_), TypePattern::Scalar) => a.has_rank_capture() && a.min_rank() == 0,
+        (TypePattern::Axes(a_axes), TypePattern::Axes(b_axes)) => {
+            if b.has_rank_capture() {
+                return a.min_rank() >= b.min_rank();
+            }
+            if a.has_rank_capture() {
+                return false;
+            }
+            axes_more_or_equal(a_axes, b_axes)
+        }
     }
 }
 
 fn axes_more_or_equal(a: &[AxisPattern], b: &[AxisPattern]) -> bool {
-    let a_has_rank = a.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
-    let b_has_rank = b.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
-
-    if b_has_rank {
-        return true;
-    }
-    if a_has_rank {
-        return false;
-    }
-
     if a.len() != b.len() {
         return false;
     }
@@ -422,7 +540,7 @@ fn axes_more_or_equal(a: &[AxisPattern], b: &[AxisPattern]) -> bool {
 
 fn axis_more_or_equal(a: &AxisPattern, b: &AxisPattern) -> bool {
     match (a, b) {
-        (AxisPattern::Rank(_), AxisPattern::Rank(_)) => true,
+        (AxisPattern::Rank(a), AxisPattern::Rank(b)) => a.min_rank >= b.min_rank,
         (AxisPattern::Rank(_), _) => false,
         (_, AxisPattern::Rank(_)) => true,
         (AxisPattern::Dim(ad), AxisPattern::Dim(bd)) => dim_more_or_equal(ad, bd),
@@ -438,17 +556,20 @@ fn dim_more_or_equal(a: &DimPattern, b: &DimPattern) -> bool {
     }
 }
 
-fn types_compatible(expected: &Type, provided: &Type) -> bool {
+pub(super) fn types_compatible(expected: &Type, provided: &Type) -> bool {
     expected.ty == provided.ty && shapes_compatible(&expected.shape, &provided.shape)
 }
 
 fn shapes_compatible(expected: &TypePattern, provided: &TypePattern) -> bool {
-    let has_rank = |axes: &[AxisPattern]| axes.iter().any(|a| matches!(a, AxisPattern::Rank(_)));
     match (expected, provided) {
         (TypePattern::Scalar, TypePattern::Scalar) => true,
         (TypePattern::Axes(exp_axes), TypePattern::Axes(prov_axes)) => {
-            if has_rank(exp_axes) || has_rank(prov_axes) {
-                return true;
+            // A rank capture matches any rank from its lower bound upwards
+            match (expected.has_rank_capture(), provided.has_rank_capture()) {
+                (true, true) => return true,
+                (true, false) => return prov_axes.len() >= expected.min_rank(),
+                (false, true) => return exp_axes.len() >= provided.min_rank(),
+                (false, false) => {}
             }
             if exp_axes.len() != prov_axes.len() {
                 return false;
@@ -0,0 +1,208 @@
+use std::{collections::HashMap, fmt};
+
+use crate::ast::*;
+
+/// Unify the type patterns of a function with the argument types of a call to it.
+///
+/// The [`ShapeFacts`] of the function bind each of its shape symbols to a [`ShapeTerm`], which is
+/// evaluated against the argument types. A symbol that occurs more than once must have the same
+/// value everywhere; the call is rejected if two occurrences are provably different.
+///
+/// `scalars` gives the value of each scalar `usize` argument, if known, such that
+/// the return type of `fn zeros(usize n) -> usize[n]` can refer to it.
+pub fn solve_shapes(args: &[Farg], facts: &ShapeFacts, actuals: &[Type], scalars: &[Option<DimPattern>]) -> Result<ShapeSolution, ShapeConflict> {
+    let mut solver = Solver { args, actuals, symbols: HashMap::new() };
+
+    for (arg, value) in args.iter().zip(scalars) {
+        if let Some(value) = value {
+            solver.symbols.insert(arg.id.clone(), ShapeValue::Dim(value.clone()));
+        }
+    }
+
+    for binding in &facts.bindings {
+        if let Some(value) = solver.eval(&binding.term) {
+            solver.bind(&binding.symbol, value)?;
+        }
+    }
+
+    // Further occurrences of a symbol are recorded as equalities with that symbol
+    for equality in &facts.equalities {
+        if let ShapeTerm::Symbol(symbol) = &equality.left
+            && let Some(value) = solver.eval(&equality.right)
+        {
+            solver.bind(symbol, value)?;
+        }
+    }
+
+    Ok(ShapeSolution { symbols: solver.symbols })
+}
+
+/// Statically known value of a shape symbol, in terms of the shapes of the caller.
+#[derive(Clone, Debug)]
+pub enum ShapeValue {
+    /// An extent or a rank
+    Dim(DimPattern),
+    /// A shape vector, e.g. the `shp` of a `d:shp` capture
+    Shape(Vec<AxisPattern>),
+}
+
+impl ShapeValue {
+    /// Whether both values may be equal, i.e. they do not differ in a known rank or extent.
+    fn agrees(&self, other: &ShapeValue) -> bool {
+        let dims_agree = |a: &DimPattern, b: &DimPattern| match (a, b) {
+            (DimPattern::Known(a), DimPattern::Known(b)) => a == b,
+            _ => true,
+        };
+
+        match (self, other) {
+            (ShapeValue::Dim(a), ShapeValue::Dim(b)) => dims_agree(a, b),
+            (ShapeValue::Shape(a), ShapeValue::Shape(b)) => {
+                let has_rank = |axes: &[AxisPattern]| axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
+                if has_rank(a) || has_rank(b) {
+                    return true;
+                }
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| match (a, b) {
+                    (AxisPattern::Dim(a), AxisPattern::Dim(b)) => dims_agree(a, b),
+                    _ => true,
+                })
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ShapeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeValue::Dim(DimPattern::Known(n)) => write!(f, "{}", n),
+            ShapeValue::Dim(DimPattern::Var(var)) => write!(f, "{}", if var.is_empty() { "_" } else { var }),
+            ShapeValue::Shape(axes) => write!(f, "{}", TypePattern::Axes(axes.clone())),
+        }
+    }
+}
+
+/// Two occurrences of a shape symbol that are provably different.
+#[derive(Clone, Debug)]
+pub struct ShapeConflict {
+    pub symbol: String,
+    pub first: ShapeValue,
+    pub second: ShapeValue,
+}
+
+/// The values of the shape symbols of a function for one call to it.
+#[derive(Clone, Debug)]
+pub struct ShapeSolution {
+    symbols: HashMap<String, ShapeValue>,
+}
+
+impl ShapeSolution {
+    /// Substitute the known symbols in a type of the function, such as its return type.
+    ///
+    /// Symbols without a known value are kept, a rank capture of which only the rank is known becomes
+    /// that many unknown extents.
+    pub fn instantiate(&self, ty: &Type) -> Type {
+        let TypePattern::Axes(axes) = &ty.shape else {
+            return ty.clone();
+        };
+
+        let mut out = Vec::with_capacity(axes.len());
+        for axis in axes {
+            match axis {
+                AxisPattern::Dim(DimPattern::Var(var)) => match self.symbols.get(var) {
+                    Some(ShapeValue::Dim(dim)) => out.push(AxisPattern::Dim(dim.clone())),
+                    _ => out.push(axis.clone()),
+                },
+                AxisPattern::Dim(DimPattern::Known(_)) => out.push(axis.clone()),
+                AxisPattern::Rank(capture) => match (self.symbols.get(&capture.shp_name), self.symbols.get(&capture.dim_name)) {
+                    (Some(ShapeValue::Shape(shp)), _) => out.extend(shp.iter().cloned()),
+                    (_, Some(ShapeValue::Dim(DimPattern::Known(rank)))) => {
+                        out.extend((0..*rank).map(|_| AxisPattern::Dim(DimPattern::any())));
+                    }
+                    _ => out.push(axis.clone()),
+                },
+            }
+        }
+
+        let shape = if out.is_empty() { TypePattern::Scalar } else { TypePattern::Axes(out) };
+        Type { ty: ty.ty.clone(), shape }
+    }
+}
+
+struct Solver<'a> {
+    args: &'a [Farg],
+    actuals: &'a [Type],
+    symbols: HashMap<String, ShapeValue>,
+}
+
+impl Solver<'_> {
+    fn bind(&mut self, symbol: &str, value: ShapeValue) -> Result<(), ShapeConflict> {
+        match self.symbols.get(symbol) {
+            Some(existing) if !existing.agrees(&value) => Err(ShapeConflict {
+                symbol: symbol.to_owned(),
+                first: existing.clone(),
+                second: value,
+            }),
+            Some(_) => Ok(()),
+            None => {
+                self.symbols.insert(symbol.to_owned(), value);
+                Ok(())
+            }
+        }
+    }
+
+    fn eval(&self, term: &ShapeTerm) -> Option<ShapeValue> {
+        match term {
+            ShapeTerm::Known(n) => Some(ShapeValue::Dim(DimPattern::Known(*n))),
+            ShapeTerm::Symbol(symbol) => self.symbols.get(symbol).cloned(),
+            ShapeTerm::ArgDim { arg_index, axis_index } => match self.covered(*arg_index, *axis_index)? {
+                [AxisPattern::Dim(dim)] => Some(ShapeValue::Dim(dim.clone())),
+                _ => None,
+            },
+            ShapeTerm::ArgRank { arg_index, axis_index } => {
+                let axes = self.covered(*arg_index, *axis_index)?;
+                (!axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))))
+                    .then_some(ShapeValue::Dim(DimPattern::Known(axes.len())))
+            }
+            ShapeTerm::TailShape { arg_index, start_axis } => {
+                Some(ShapeValue::Shape(self.covered(*arg_index, *start_axis)?.to_vec()))
+            }
+            ShapeTerm::RetDim { .. } | ShapeTerm::RetRank { .. } => None,
+        }
+    }
+
+    /// The axes of the argument type that are matched by one axis of the parameter's pattern, if known.
+    fn covered(&self, arg_index: usize, axis_index: usize) -> Option<&[AxisPattern]> {
+        let TypePattern::Axes(pattern) = &self.args[arg_index].ty.shape else {
+            return None;
+        };
+        let actual: &[AxisPattern] = match &self.actuals[arg_index].shape {
+            TypePattern::Scalar => &[],
+            TypePattern::Axes(axes) => axes,
+        };
+
+        let is_rank = |axis: &AxisPattern| matches!(axis, AxisPattern::Rank(_));
+        let first = pattern.iter().position(is_rank).unwrap_or(pattern.len());
+        let last = pattern.iter().rposition(is_rank);
+        let fixed = |axes: &[AxisPattern]| !axes.iter().any(is_rank);
+
+        // Axes before the first capture are matched from the front, those after the last one from the back
+        if axis_index < first {
+            return (axis_index < actual.len() && fixed(&actual[..=axis_index]))
+                .then(|| &actual[axis_index..=axis_index]);
+        }
+        let last = last.unwrap();
+        let from_back = pattern.len() - axis_index;
+        if axis_index > last {
+            let pos = actual.len().checked_sub(from_back)?;
+            return fixed(&actual[pos..]).then(|| &actual[pos..=pos]);
+        }
+
+        // A single capture matches everything in between
+        if first != last {
+            return None;
+        }
+        let end = actual.len().checked_sub(from_back - 1)?;
+        (first <= end && fixed(&actual[..first]) && fixed(&actual[end..]))
+            .then(|| &actual[first..end])
+    }
+}
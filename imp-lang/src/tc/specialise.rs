@@ -0,0 +1,454 @@
+use std::{collections::HashMap, mem, ptr};
+
+use typed_arena::Arena;
+
+use crate::ast::*;
+
+use super::{resolve_dispatch::{maximal_candidates, types_compatible}, shape_solver::{solve_shapes, ShapeSolution}};
+
+/// Upper bound on the number of clones of a single function, such that a recursive function
+/// whose argument shapes change with every call is not specialised indefinitely.
+const MAX_SPECIALISATIONS: usize = 20;
+
+/// Clone functions for the argument shapes they are called with.
+///
+/// Following the compilation scheme for a hierarchy of array types (see `resources/`), the shape of an array
+/// is either unknown, with only its base type known (AUD, `i32[d:shp]`), of known rank (AKD, `i32[n,m]`),
+/// or known entirely (AKS, `i32[2,3]`). If the arguments of a call are more specific in this hierarchy than
+/// the parameters of its target, the call is redirected to a clone of the target with its parameters
+/// specialised to the arguments. The types in the clone are refined accordingly, such that e.g.
+/// a tensor over `shp` gets an index vector of known length. Calls in a clone are dispatched again
+/// on their more specific argument types, and specialised in turn.
+///
+/// Clones are only reachable through the calls that were redirected to them, so they are not added to the overloads.
+/// They keep the name and base types of the function they were made of, by which later passes find their family.
+/// Imported functions are not specialised, as their code is generated by their own module.
+pub fn specialise<'ast>(program: &mut Program<'ast, TypedAst>) {
+    let mut worklist: Vec<*mut Fundef<'ast, TypedAst>> = program.fundefs.iter_mut()
+        .filter(|fundef| fundef.import.is_none())
+        .map(|fundef| fundef as *mut _)
+        .collect();
+
+    let mut spec = Specialise {
+        fundefs: &program.fundefs,
+        overloads: &program.overloads,
+        clones: HashMap::new(),
+        origins: HashMap::new(),
+        counts: HashMap::new(),
+        args: Vec::new(),
+        new: Vec::new(),
+    };
+
+    while let Some(fundef) = worklist.pop() {
+        // SAFETY: the arena never moves its fundefs, and each one is traversed exactly once.
+        spec.trav_fundef(unsafe { &mut *fundef });
+        worklist.append(&mut spec.new);
+    }
+}
+
+struct Specialise<'a, 'ast> {
+    fundefs: &'a Arena<Fundef<'ast, TypedAst>>,
+    overloads: &'a HashMap<String, HashMap<BaseSignature, Vec<&'ast Fundef<'ast, TypedAst>>>>,
+    /// Clones by the function they were made of and their argument types.
+    clones: HashMap<(*const Fundef<'ast, TypedAst>, String), &'ast Fundef<'ast, TypedAst>>,
+    /// The function each clone was made of.
+    origins: HashMap<*const Fundef<'ast, TypedAst>, &'ast Fundef<'ast, TypedAst>>,
+    /// The number of clones made of each function.
+    counts: HashMap<*const Fundef<'ast, TypedAst>, usize>,
+    /// Arguments of the function being traversed.
+    args: Vec<Farg>,
+    /// Clones made while traversing the current function, which still need to be traversed themselves.
+    new: Vec<*mut Fundef<'ast, TypedAst>>,
+}
+
+impl<'ast> Specialise<'_, 'ast> {
+    fn id_type(&self, id: &Id<'ast, TypedAst>) -> Type {
+        match id {
+            Id::Arg(i) => self.args[*i].ty.clone(),
+            Id::Var(v) => v.ty.clone(),
+        }
+    }
+
+    fn origin(&self, fundef: &'ast Fundef<'ast, TypedAst>) -> &'ast Fundef<'ast, TypedAst> {
+        self.origins.get(&(fundef as *const _)).copied().unwrap_or(fundef)
+    }
+
+    /// Dispatch again on the argument types of a call, which may be more specific in a clone
+    /// than they were in the function it was made of.
+    fn redispatch(&self, target: &'ast Fundef<'ast, TypedAst>, arg_types: &[Type]) -> &'ast Fundef<'ast, TypedAst> {
+        let origin = self.origin(target);
+        let key = BaseSignature {
+            base_types: arg_types.iter().map(|ty| ty.ty.clone()).collect(),
+        };
+        let Some(candidates) = self.overloads.get(&origin.name).and_then(|groups| groups.get(&key)) else {
+            return target;
+        };
+
+        let matches: Vec<&'ast Fundef<'ast, TypedAst>> = candidates.iter()
+            .copied()
+            .filter(|candidate| candidate.args.iter().zip(arg_types).all(|(arg, ty)| types_compatible(&arg.ty, ty)))
+            .collect();
+        match maximal_candidates(&matches)[..] {
+            [best] if !ptr::eq(best, origin) => best,
+            _ => target,
+        }
+    }
+
+    /// The clone of `target` for arguments of types `arg_types`, or `target` itself if
+    /// the arguments are not more specific than its parameters.
+    fn specialise(&mut self, target: &'ast Fundef<'ast, TypedAst>, arg_types: &[Type]) -> &'ast Fundef<'ast, TypedAst> {
+        let origin = self.origin(target);
+        if origin.import.is_some() {
+            return target;
+        }
+
+        let args: Vec<Farg> = origin.args.iter()
+            .zip(arg_types)
+            .map(|(arg, actual)| Farg {
+                ty: Type { ty: arg.ty.ty.clone(), shape: refine(&arg.ty.shape, &actual.shape) },
+                ..arg.clone()
+            })
+            .collect();
+        let signature = args.iter().map(|arg| arg.ty.to_string()).collect::<Vec<_>>().join(", ");
+        if signature == origin.args.iter().map(|arg| arg.ty.to_string()).collect::<Vec<_>>().join(", ") {
+            return target;
+        }
+
+        let key = (origin as *const _, signature);
+        if let Some(clone) = self.clones.get(&key) {
+            return clone;
+        }
+
+        let count = self.counts.entry(origin as *const _).or_default();
+        if *count == MAX_SPECIALISATIONS {
+            return target;
+        }
+
+        let actuals: Vec<Type> = args.iter().map(|arg| arg.ty.clone()).collect();
+        let Ok(solution) = solve_shapes(&origin.args, &origin.shape_facts, &actuals, &vec![None; args.len()]) else {
+            return target;
+        };
+        *count += 1;
+
+        let clone = self.fundefs.alloc(instantiate(origin, args, &solution));
+        self.new.push(clone as *mut _);
+        // SAFETY: the arena is owned by the program, which outlives the calls referring to the clone.
+        let clone: &'ast Fundef<'ast, TypedAst> = unsafe { mem::transmute(clone) };
+        self.origins.insert(clone as *const _, origin);
+        self.clones.insert(key, clone);
+        clone
+    }
+}
+
+impl<'ast> Traverse<'ast> for Specialise<'_, 'ast> {
+    type Ast = TypedAst;
+
+    type ExprOut = ();
+
+    const EXPR_DEFAULT: Self::ExprOut = ();
+
+    fn trav_fundef(&mut self, fundef: &mut Fundef<'ast, TypedAst>) {
+        self.args = fundef.args.clone();
+        self.trav_body(&mut fundef.body);
+    }
+
+    fn trav_call(&mut self, call: &mut Call<'ast, TypedAst>) {
+        let arg_types: Vec<Type> = call.args.iter().map(|arg| self.id_type(arg)).collect();
+        let CallTarget::Function(target) = call.id.clone();
+        let target = self.redispatch(target, &arg_types);
+        call.id = CallTarget::Function(self.specialise(target, &arg_types));
+    }
+}
+
+/// The parameter pattern `pattern`, specialised to an argument of shape `actual`.
+///
+/// A rank capture is replaced by the axes it matches if the rank of the argument is known,
+/// and a symbolic extent by the extent of the argument if that is known.
+/// Extents of the argument that are only known by name are not taken over, as those names belong to the caller.
+fn refine(pattern: &TypePattern, actual: &TypePattern) -> TypePattern {
+    let (TypePattern::Axes(axes), TypePattern::Axes(actual)) = (pattern, actual) else {
+        return pattern.clone();
+    };
+
+    let is_rank = |axis: &AxisPattern| matches!(axis, AxisPattern::Rank(_));
+    if actual.iter().any(is_rank) {
+        return pattern.clone();
+    }
+    let width = match axes.iter().filter(|axis| is_rank(axis)).count() {
+        0 if axes.len() == actual.len() => 1,
+        1 => match actual.len().checked_sub(axes.len() - 1) {
+            // Parameters are kept arrays, the code of the function may not work on scalars
+            Some(0) if axes.len() == 1 => return pattern.clone(),
+            Some(width) => width,
+            None => return pattern.clone(),
+        },
+        _ => return pattern.clone(),
+    };
+
+    let known = |axis: &AxisPattern| match axis {
+        AxisPattern::Dim(DimPattern::Known(n)) => Some(AxisPattern::Dim(DimPattern::Known(*n))),
+        _ => None,
+    };
+
+    let mut refined = Vec::with_capacity(actual.len());
+    let mut pos = 0;
+    for axis in axes {
+        match axis {
+            AxisPattern::Dim(_) => {
+                refined.push(known(&actual[pos]).unwrap_or_else(|| axis.clone()));
+                pos += 1;
+            }
+            AxisPattern::Rank(_) => {
+                refined.extend(actual[pos..pos + width].iter()
+                    .map(|axis| known(axis).unwrap_or(AxisPattern::Dim(DimPattern::any()))));
+                pos += width;
+            }
+        }
+    }
+
+    TypePattern::Axes(refined)
+}
+
+/// Copy `fundef` with parameters `args`, substituting the shape symbols solved for those
+/// parameters in the types of its variables.
+fn instantiate<'ast>(fundef: &Fundef<'ast, TypedAst>, args: Vec<Farg>, solution: &ShapeSolution) -> Fundef<'ast, TypedAst> {
+    let decs = Arena::new();
+    let exprs = Arena::new();
+
+    let mut inst = Instantiate {
+        solution,
+        // SAFETY: the arenas are moved into the returned Fundef, their contents are not.
+        decs: unsafe { mem::transmute::<&Arena<VarInfo<'ast, TypedAst>>, &'ast Arena<VarInfo<'ast, TypedAst>>>(&decs) },
+        exprs: unsafe { mem::transmute::<&Arena<Expr<'ast, TypedAst>>, &'ast Arena<Expr<'ast, TypedAst>>>(&exprs) },
+        copies: HashMap::new(),
+    };
+
+    // A rank capture that was replaced by the axes it matches moves the axes after it
+    let layouts: Vec<Option<(usize, usize)>> = fundef.args.iter()
+        .zip(&args)
+        .map(|(param, arg)| match (&param.ty.shape, &arg.ty.shape) {
+            (TypePattern::Axes(before), TypePattern::Axes(after)) if !arg.ty.shape.has_rank_capture() => {
+                let capture = before.iter().position(|axis| matches!(axis, AxisPattern::Rank(_)))?;
+                Some((capture, after.len() + 1 - before.len()))
+            }
+            _ => None,
+        })
+        .collect();
+    let shape_facts = ShapeFacts {
+        bindings: fundef.shape_facts.bindings.iter()
+            .filter_map(|binding| Some(ShapeBinding {
+                symbol: binding.symbol.clone(),
+                term: remap(&binding.term, &layouts)?,
+            }))
+            .collect(),
+        equalities: fundef.shape_facts.equalities.iter()
+            .filter_map(|equality| Some(ShapeEquality {
+                left: remap(&equality.left, &layouts)?,
+                right: remap(&equality.right, &layouts)?,
+            }))
+            .collect(),
+        ..fundef.shape_facts.clone()
+    };
+
+    let shape_prelude = fundef.shape_prelude.iter().map(|assign| inst.copy_assign(assign)).collect();
+    let body = inst.copy_body(&fundef.body);
+
+    Fundef {
+        name: fundef.name.clone(),
+        type_params: Vec::new(),
+        constraints: Vec::new(),
+        ret_type: solution.instantiate(&fundef.ret_type),
+        args,
+        shape_prelude,
+        shape_facts,
+        decs,
+        exprs,
+        body,
+        span: fundef.span,
+        import: None,
+        internal: fundef.internal,
+    }
+}
+
+/// A shape term of the original function in terms of the parameters of its clone, if it can be expressed.
+fn remap(term: &ShapeTerm, layouts: &[Option<(usize, usize)>]) -> Option<ShapeTerm> {
+    match *term {
+        ShapeTerm::ArgDim { arg_index, axis_index } => match layouts[arg_index] {
+            Some((capture, width)) if axis_index > capture => Some(ShapeTerm::ArgDim {
+                arg_index,
+                axis_index: axis_index + width - 1,
+            }),
+            _ => Some(term.clone()),
+        },
+        ShapeTerm::ArgRank { arg_index, .. } => match layouts[arg_index] {
+            Some((_, width)) => Some(ShapeTerm::Known(width)),
+            None => Some(term.clone()),
+        },
+        ShapeTerm::TailShape { arg_index, .. } => layouts[arg_index].is_none().then(|| term.clone()),
+        _ => Some(term.clone()),
+    }
+}
+
+struct Instantiate<'a, 'ast> {
+    solution: &'a ShapeSolution,
+    decs: &'ast Arena<VarInfo<'ast, TypedAst>>,
+    exprs: &'ast Arena<Expr<'ast, TypedAst>>,
+    /// Copies of the variables of the original function.
+    copies: HashMap<*const VarInfo<'ast, TypedAst>, &'ast VarInfo<'ast, TypedAst>>,
+}
+
+impl<'ast> Instantiate<'_, 'ast> {
+    fn copy_lvis(&mut self, lvis: &'ast VarInfo<'ast, TypedAst>, ty: Type, ssa: Option<&'ast Expr<'ast, TypedAst>>) -> &'ast VarInfo<'ast, TypedAst> {
+        let copy = self.decs.alloc(VarInfo {
+            name: lvis.name.clone(),
+            ty,
+            ssa,
+        });
+        self.copies.insert(lvis as *const _, copy);
+        copy
+    }
+
+    fn copy_id(&self, id: &Id<'ast, TypedAst>) -> Id<'ast, TypedAst> {
+        match id {
+            Id::Var(v) => Id::Var(self.copies[&(*v as *const _)]),
+            Id::Arg(i) => Id::Arg(*i),
+        }
+    }
+
+    fn copy_assign(&mut self, assign: &Assign<'ast, TypedAst>) -> Assign<'ast, TypedAst> {
+        let expr = self.copy_expr(assign.expr);
+        let expr = &*self.exprs.alloc(expr);
+
+        // The rank of a tensor may only have become known in the clone
+        let mut ty = self.solution.instantiate(&assign.lhs.ty);
+        if let Expr::Tensor(tensor) = expr
+            && ty.shape.has_rank_capture()
+            && let Some(shape) = tensor_shape(tensor)
+        {
+            ty.shape = shape;
+        }
+
+        let lhs = self.copy_lvis(assign.lhs, ty, assign.lhs.ssa.map(|_| expr));
+        Assign { lhs, expr, span: assign.span }
+    }
+
+    fn copy_body(&mut self, body: &Body<'ast, TypedAst>) -> Body<'ast, TypedAst> {
+        let stmts = body.stmts.iter().map(|stmt| match stmt {
+            Stmt::Assign(assign) => Stmt::Assign(self.copy_assign(assign)),
+            Stmt::Printf(printf) => Stmt::Printf(Printf { id: self.copy_id(&printf.id) }),
+            Stmt::Free(free) => Stmt::Free(Free { id: self.copy_id(&free.id) }),
+        }).collect();
+
+        Body { stmts, ret: self.copy_id(&body.ret) }
+    }
+
+    fn copy_tensor(&mut self, tensor: &Tensor<'ast, TypedAst>) -> Tensor<'ast, TypedAst> {
+        Tensor {
+            target: tensor.target.map(|target| self.copy_id(&target)),
+            partitions: tensor.partitions.iter().map(|partition| self.copy_partition(partition)).collect(),
+            span: tensor.span,
+        }
+    }
+
+    fn copy_partition(&mut self, partition: &Partition<'ast, TypedAst>) -> Partition<'ast, TypedAst> {
+        let lb = partition.lb.map(|lb| self.copy_id(&lb));
+        let ub = self.copy_id(&partition.ub);
+        let step = partition.step.map(|step| self.copy_id(&step));
+        let width = partition.width.map(|width| self.copy_id(&width));
+        let iv = self.copy_lvis(partition.iv, self.solution.instantiate(&partition.iv.ty), None);
+        Partition {
+            body: self.copy_body(&partition.body),
+            iv,
+            lb,
+            ub,
+            step,
+            width,
+            span: partition.span,
+        }
+    }
+
+    fn copy_expr(&mut self, expr: &Expr<'ast, TypedAst>) -> Expr<'ast, TypedAst> {
+        match expr {
+            Expr::Cond(cond) => Expr::Cond(Cond {
+                cond: self.copy_id(&cond.cond),
+                then_branch: self.copy_body(&cond.then_branch),
+                else_branch: self.copy_body(&cond.else_branch),
+                span: cond.span,
+            }),
+            Expr::Call(call) => Expr::Call(Call {
+                id: call.id.clone(),
+                args: call.args.iter().map(|arg| self.copy_id(arg)).collect(),
+                span: call.span,
+            }),
+            Expr::Prf(prf) => {
+                let mut prf = prf.clone();
+                for arg in prf.args_mut() {
+                    *arg = self.copy_id(arg);
+                }
+                Expr::Prf(prf)
+            }
+            Expr::Tensor(tensor) => Expr::Tensor(self.copy_tensor(tensor)),
+            Expr::Fold(fold) => {
+                let neutral = self.copy_id(&fold.neutral);
+                let foldfun = match &fold.foldfun {
+                    FoldFun::Name(target) => FoldFun::Name(target.clone()),
+                    FoldFun::Apply { id, args } => FoldFun::Apply {
+                        id: id.clone(),
+                        args: args.iter().map(|arg| match arg {
+                            FoldFunArg::Placeholder => FoldFunArg::Placeholder,
+                            FoldFunArg::Bound(bound) => FoldFunArg::Bound(self.copy_id(bound)),
+                        }).collect(),
+                    },
+                };
+                Expr::Fold(Fold {
+                    neutral,
+                    foldfun,
+                    selection: self.copy_partition(&fold.selection),
+                    span: fold.span,
+                })
+            }
+            Expr::Array(array) => Expr::Array(Array {
+                elems: array.elems.iter().map(|elem| self.copy_id(elem)).collect(),
+                span: array.span,
+            }),
+            Expr::Record(record) => Expr::Record(Record {
+                name: record.name.clone(),
+                fields: record.fields.iter().map(|(name, value)| (name.clone(), self.copy_id(value))).collect(),
+                span: record.span,
+            }),
+            Expr::Field(field) => Expr::Field(Field {
+                record: self.copy_id(&field.record),
+                name: field.name.clone(),
+                span: field.span,
+            }),
+            Expr::Id(id) => Expr::Id(self.copy_id(id)),
+            Expr::Const(c) => Expr::Const(*c),
+        }
+    }
+}
+
+/// The shape of the result of a tensor, if its rank is known.
+///
+/// That is, if the length of its index vector is known and its elements are not of unknown rank.
+/// All partitions agree in both, so the first one suffices.
+fn tensor_shape(tensor: &Tensor<'_, TypedAst>) -> Option<TypePattern> {
+    let partition = &tensor.partitions[0];
+    let TypePattern::Axes(iv) = &partition.iv.ty.shape else {
+        return None;
+    };
+    let [AxisPattern::Dim(DimPattern::Known(len))] = iv[..] else {
+        return None;
+    };
+    let Id::Var(elem) = partition.body.ret else {
+        return None;
+    };
+    if elem.ty.shape.has_rank_capture() {
+        return None;
+    }
+
+    let mut axes = vec![AxisPattern::Dim(DimPattern::any()); len];
+    if let TypePattern::Axes(elem) = &elem.ty.shape {
+        axes.extend(elem.iter().cloned());
+    }
+    Some(if axes.is_empty() { TypePattern::Scalar } else { TypePattern::Axes(axes) })
+}
@@ -1,108 +1,495 @@
-use std::{collections::HashMap, mem};
+use std::{collections::{HashMap, HashSet}, fmt, mem};
 
 use typed_arena::Arena;
 
 use crate::ast::*;
 
-pub fn type_infer<'ast>(program: &mut Program<'ast, UntypedAst>) -> Result<(), InferenceError> {
-    validate_overload_families(&program.overloads)?;
+use super::{monomorphise::{monomorphise, substitute}, shape_solver::{solve_shapes, ShapeConflict}};
+
+/// Infer the types of all variables, reporting the errors of every function at once.
+///
+/// Generic functions are not inferred themselves. A call that needs a generic function for some
+/// base types instead instantiates it, adding a copy with those base types to the overloads.
+/// These instances are then inferred in turn, until no new instances are needed.
+pub fn type_infer<'ast>(program: &mut Program<'ast, UntypedAst>) -> Result<(), Vec<InferenceError>> {
+    let mut errors = validate_overload_families(&program.overloads);
+    errors.extend(validate_structs(&program.structs));
+    let structs = program.structs.clone();
 
     let mut stubs: HashMap<String, HashMap<BaseSignature, Vec<DispatchStub>>> = HashMap::new();
+    let mut generics: HashMap<String, Vec<GenericStub>> = HashMap::new();
+    let mut templates: Vec<&'ast Fundef<'ast, UntypedAst>> = Vec::new();
 
     for (name, overloads) in &program.overloads {
         let mut stub_groups = HashMap::new();
         for (sig, fundefs) in overloads {
             let mut stub_fundefs = Vec::new();
             for fundef in fundefs {
-                stub_fundefs.push(DispatchStub {
-                    args: fundef.args.clone(),
-                    ret_type: fundef.ret_type.clone(),
-                });
+                errors.extend(undefined_types(fundef, &structs));
+                if fundef.is_generic() {
+                    let unbound = unbound_type_params(fundef);
+                    if unbound.is_empty() {
+                        generics.entry(name.clone()).or_default().push(GenericStub::new(fundef, templates.len()));
+                    }
+                    errors.extend(unbound);
+                    templates.push(fundef);
+                } else {
+                    stub_fundefs.push(DispatchStub {
+                        args: fundef.args.clone(),
+                        ret_type: fundef.ret_type.clone(),
+                        shape_facts: fundef.shape_facts.clone(),
+                    });
+                }
+            }
+            if !stub_fundefs.is_empty() {
+                stub_groups.insert(sig.clone(), stub_fundefs);
             }
-            stub_groups.insert(sig.clone(), stub_fundefs);
         }
         stubs.insert(name.clone(), stub_groups);
     }
 
-    for fundef in program.fundefs.iter_mut() {
-        let mut tc = TypeInfer::new(stubs.clone());
+    let mut pending = Vec::new();
+    for fundef in program.fundefs.iter_mut().filter(|fundef| !fundef.is_generic()) {
+        let mut tc = TypeInfer::new(stubs.clone(), generics.clone(), structs.clone());
         tc.trav_fundef(fundef);
+        errors.extend(tc.errors);
+        pending.extend(tc.instances);
+    }
+
+    let mut instantiated = HashSet::new();
+    while !pending.is_empty() {
+        let mut instances = Vec::new();
+        for (template, types) in mem::take(&mut pending) {
+            if !instantiated.insert((template, types.clone())) {
+                continue;
+            }
 
-        if let Some(err) = tc.errors.into_iter().next() {
-            return Err(err);
+            let instance = program.fundefs.alloc(monomorphise(templates[template], &types));
+            // SAFETY: the arena is owned by the program, which outlives its overloads.
+            let instance: &'ast mut Fundef<'ast, UntypedAst> = unsafe { mem::transmute(instance) };
+            let instance = instance as *mut Fundef<'ast, UntypedAst>;
+            let instance_ref: &'ast Fundef<'ast, UntypedAst> = unsafe { &*instance };
+
+            stubs.entry(instance_ref.name.clone())
+                .or_default()
+                .entry(instance_ref.signature())
+                .or_default()
+                .push(DispatchStub {
+                    args: instance_ref.args.clone(),
+                    ret_type: instance_ref.ret_type.clone(),
+                    shape_facts: instance_ref.shape_facts.clone(),
+                });
+            program.overloads.entry(instance_ref.name.clone())
+                .or_default()
+                .entry(instance_ref.signature())
+                .or_default()
+                .push(instance_ref);
+            instances.push(instance);
+        }
+
+        for instance in instances {
+            let mut tc = TypeInfer::new(stubs.clone(), generics.clone(), structs.clone());
+            tc.trav_fundef(unsafe { &mut *instance });
+            errors.extend(tc.errors);
+            pending.extend(tc.instances);
         }
     }
 
-    Ok(())
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
 #[derive(Clone, Debug)]
 struct DispatchStub {
     args: Vec<Farg>,
     ret_type: Type,
+    shape_facts: ShapeFacts,
+}
+
+/// The signature of a generic function, which is instantiated for each call that needs it.
+#[derive(Clone, Debug)]
+struct GenericStub {
+    /// Index of the generic function in the templates collected by `type_infer`.
+    template: usize,
+    type_params: Vec<String>,
+    args: Vec<Farg>,
+    ret_type: Type,
+    shape_facts: ShapeFacts,
+    constraints: Vec<Constraint>,
+}
+
+impl GenericStub {
+    fn new(fundef: &Fundef<'_, UntypedAst>, template: usize) -> Self {
+        Self {
+            template,
+            type_params: fundef.type_params.clone(),
+            args: fundef.args.clone(),
+            ret_type: fundef.ret_type.clone(),
+            shape_facts: fundef.shape_facts.clone(),
+            constraints: fundef.constraints.clone(),
+        }
+    }
+
+    /// The base types to substitute for the type parameters, such that the arguments have the base types of `key`.
+    fn unify(&self, key: &BaseSignature) -> Option<Vec<BaseType>> {
+        if key.base_types.len() != self.args.len() {
+            return None;
+        }
+
+        let mut subst: HashMap<&str, &BaseType> = HashMap::new();
+        for (arg, base) in self.args.iter().zip(&key.base_types) {
+            let consistent = match &arg.ty.ty {
+                BaseType::Generic(param) => *subst.entry(param).or_insert(base) == base,
+                ty => ty == base,
+            };
+            if !consistent {
+                return None;
+            }
+        }
+
+        Some(self.type_params.iter().map(|param| subst[param.as_str()].clone()).collect())
+    }
+
+    fn instantiate(&self, types: &[BaseType]) -> DispatchStub {
+        DispatchStub {
+            args: self.args.iter()
+                .map(|arg| Farg { ty: substitute(&arg.ty, &self.type_params, types), ..arg.clone() })
+                .collect(),
+            ret_type: substitute(&self.ret_type, &self.type_params, types),
+            shape_facts: self.shape_facts.clone(),
+        }
+    }
+
+    fn instantiate_constraint(&self, constraint: &Constraint, types: &[BaseType]) -> Constraint {
+        Constraint {
+            name: constraint.name.clone(),
+            args: constraint.args.iter().map(|ty| substitute(ty, &self.type_params, types)).collect(),
+            ret_type: substitute(&constraint.ret_type, &self.type_params, types),
+            span: constraint.span,
+        }
+    }
+}
+
+/// Type parameters are inferred from the arguments only, so each must occur in an argument type.
+fn unbound_type_params(fundef: &Fundef<'_, UntypedAst>) -> Vec<InferenceError> {
+    fundef.type_params.iter()
+        .filter(|param| !fundef.args.iter().any(|arg| matches!(&arg.ty.ty, BaseType::Generic(p) if p == *param)))
+        .map(|param| InferenceError::UnboundTypeParameter {
+            name: fundef.name.clone(),
+            param: param.clone(),
+            span: fundef.span,
+        })
+        .collect()
+}
+
+/// Struct names are unique, fields are scalars of declared types, and no struct contains itself.
+fn validate_structs(structs: &[Structdef]) -> Vec<InferenceError> {
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    for structdef in structs {
+        if !names.insert(&structdef.name) {
+            errors.push(InferenceError::RedefinedStruct { name: structdef.name.clone(), span: structdef.span });
+        }
+
+        let mut fields = HashSet::new();
+        for field in &structdef.fields {
+            if !fields.insert(&field.id) {
+                errors.push(InferenceError::DuplicateField {
+                    record: structdef.name.clone(),
+                    field: field.id.clone(),
+                    span: field.span,
+                });
+            }
+            if !field.ty.is_scalar() {
+                errors.push(InferenceError::NonScalarField {
+                    record: structdef.name.clone(),
+                    field: field.id.clone(),
+                    ty: field.ty.clone(),
+                    span: field.span,
+                });
+            }
+            if let BaseType::Udf(name) = &field.ty.ty
+                && !structs.iter().any(|s| &s.name == name)
+            {
+                errors.push(InferenceError::UndefinedType { name: name.clone(), span: field.span });
+            }
+        }
+
+        if contains(structs, structdef, &structdef.name, &mut HashSet::new()) {
+            errors.push(InferenceError::RecursiveStruct { name: structdef.name.clone(), span: structdef.span });
+        }
+    }
+    errors
+}
+
+/// Whether `structdef` has a field of struct type `name`, directly or through the fields of its fields.
+fn contains<'a>(structs: &'a [Structdef], structdef: &'a Structdef, name: &str, visited: &mut HashSet<&'a str>) -> bool {
+    structdef.fields.iter().any(|field| match &field.ty.ty {
+        BaseType::Udf(udf) if udf == name => true,
+        BaseType::Udf(udf) if visited.insert(udf) => structs.iter()
+            .find(|s| &s.name == udf)
+            .is_some_and(|inner| contains(structs, inner, name, visited)),
+        _ => false,
+    })
+}
+
+/// The record types in the signature of `fundef` that are not declared.
+fn undefined_types(fundef: &Fundef<'_, UntypedAst>, structs: &[Structdef]) -> Vec<InferenceError> {
+    let types = fundef.args.iter().map(|arg| &arg.ty)
+        .chain([&fundef.ret_type])
+        .chain(fundef.constraints.iter().flat_map(|c| c.args.iter().chain([&c.ret_type])));
+
+    let mut undefined: Vec<&String> = Vec::new();
+    for ty in types {
+        if let BaseType::Udf(name) = &ty.ty
+            && !structs.iter().any(|s| &s.name == name)
+            && !undefined.contains(&name)
+        {
+            undefined.push(name);
+        }
+    }
+
+    undefined.into_iter()
+        .map(|name| InferenceError::UndefinedType { name: name.clone(), span: fundef.span })
+        .collect()
 }
 
-fn validate_overload_families(overloads: &HashMap<String, HashMap<BaseSignature, Vec<&Fundef<'_, UntypedAst>>>>) -> Result<(), InferenceError> {
+/// An overload that accepts the arguments of a call.
+struct Candidate {
+    stub: DispatchStub,
+    /// The generic function and base types to instantiate, if this is an instance that does not exist yet.
+    instance: Option<(usize, Vec<BaseType>)>,
+}
+
+fn validate_overload_families(overloads: &HashMap<String, HashMap<BaseSignature, Vec<&Fundef<'_, UntypedAst>>>>) -> Vec<InferenceError> {
+    let mut errors = Vec::new();
     for (name, group) in overloads {
         for (sig, fundefs) in group {
             let (first, rest) = fundefs.split_first().unwrap();
             let expected_ret_ty = &first.ret_type.ty;
             for fundef in rest {
                 if &fundef.ret_type.ty != expected_ret_ty {
-                    return Err(InferenceError::InconsistentOverloadReturnBase {
+                    errors.push(InferenceError::InconsistentOverloadReturnBase {
                         name: name.clone(),
                         arg_bases: sig.clone(),
                         expected: expected_ret_ty.clone(),
                         found: fundef.ret_type.ty.clone(),
+                        span: fundef.span,
                     });
                 }
             }
         }
     }
-    Ok(())
+    errors
 }
 
 pub struct TypeInfer<'ast> {
     args: Vec<Farg>,
+    /// Shape facts of the current function, to name its shape symbols in diagnostics.
+    shape_facts: ShapeFacts,
+    /// Variables of the current function by their SSA name, to render their source in diagnostics.
+    vars: HashMap<String, &'ast VarInfo<'ast, UntypedAst>>,
+    structs: Vec<Structdef>,
     decs: Arena<VarInfo<'ast, UntypedAst>>,
     exprs: Arena<Expr<'ast, UntypedAst>>,
     typed: HashMap<*const VarInfo<'ast, UntypedAst>, Type>,
     stubs: HashMap<String, HashMap<BaseSignature, Vec<DispatchStub>>>,
+    generics: HashMap<String, Vec<GenericStub>>,
+    /// Instances of generic functions needed by the calls in this function.
+    instances: Vec<(usize, Vec<BaseType>)>,
+    /// Calls whose candidates are being collected, to not instantiate
+    /// a generic function for the constraints of that same instance.
+    checking: HashSet<(String, BaseSignature)>,
     errors: Vec<InferenceError>,
+    /// Variables whose definition contains a type error. Checks involving these
+    /// are skipped, so that a single mistake is not reported again at every use.
+    poisoned: HashSet<*const VarInfo<'ast, UntypedAst>>,
+    /// Span of the assignment currently being inferred, for error reporting.
+    span: Span,
 }
 
 #[allow(unused)]
 #[derive(Debug)]
 pub enum InferenceError {
-    SelectionIndexNotVector { ty: Type },
-    SelectionIndexNotInteger { ty: Type },
-    SelectionRankTooSmall { needed: usize, known_min_rank: Option<usize>, shape: TypePattern },
-    InhomogeneousArray { element: usize, expected: Type, found: Type },
-    UndefinedFunction { name: String },
-    NoMatchingOverload { name: String, arg_bases: BaseSignature },
-    CallArgumentTypeMismatch { func_name: String, arg_index: usize, expected: Type, provided: Type },
-    AmbiguousOverload { name: String, arg_bases: BaseSignature },
-    PrimitiveArgumentKindMismatch { primitive: String, arg_index: usize, expected: &'static str, provided: Type },
-    InconsistentOverloadReturnBase { name: String, arg_bases: BaseSignature, expected: BaseType, found: BaseType },
-    FoldSelectionTypeMismatch { expected: Type, found: Type },
-    FoldFunPlaceholderCountMismatch { found: usize },
-    FoldFunctionTypeMismatch { expected: Type, found: Type },
-    MissingTypeAnnotation { name: String },
+    SelectionIndexNotVector { ty: Type, span: Span },
+    SelectionIndexNotInteger { ty: Type, span: Span },
+    SelectionRankTooSmall { needed: usize, known_min_rank: Option<usize>, shape: TypePattern, span: Span },
+    InhomogeneousArray { element: usize, expected: Type, found: Type, span: Span },
+    UndefinedFunction { name: String, span: Span },
+    UndefinedVariable { name: String, span: Span },
+    NoMatchingOverload { name: String, arg_bases: BaseSignature, span: Span },
+    CallArgumentTypeMismatch { func_name: String, arg_index: usize, expected: Type, provided: Type, span: Span },
+    AmbiguousOverload { name: String, arg_bases: BaseSignature, span: Span },
+    InconsistentShapes { name: String, conflict: ShapeConflict, span: Span },
+    PrimitiveArgumentKindMismatch { primitive: String, arg_index: usize, expected: &'static str, provided: Type, span: Span },
+    InconsistentOverloadReturnBase { name: String, arg_bases: BaseSignature, expected: BaseType, found: BaseType, span: Span },
+    FoldSelectionTypeMismatch { expected: Type, found: Type, span: Span },
+    FoldFunPlaceholderCountMismatch { found: usize, span: Span },
+    FoldFunctionTypeMismatch { expected: Type, found: Type, span: Span },
+    ReturnTypeMismatch { name: String, expected: Type, found: Type, span: Span },
+    MissingTypeAnnotation { name: String, span: Span },
+    UnsatisfiedConstraint { name: String, arg_bases: BaseSignature, constraint: Constraint, span: Span },
+    UnboundTypeParameter { name: String, param: String, span: Span },
+    UndefinedType { name: String, span: Span },
+    RedefinedStruct { name: String, span: Span },
+    RecursiveStruct { name: String, span: Span },
+    NonScalarField { record: String, field: String, ty: Type, span: Span },
+    DuplicateField { record: String, field: String, span: Span },
+    UnknownField { record: String, field: String, span: Span },
+    MissingField { record: String, field: String, span: Span },
+    FieldTypeMismatch { record: String, field: String, expected: Type, found: Type, span: Span },
+    FieldOfNonRecord { field: String, ty: Type, span: Span },
+    PartitionRankMismatch { partition: usize, expected: usize, found: usize, span: Span },
+    PartitionTypeMismatch { partition: usize, expected: Type, found: Type, span: Span },
+    PartitionCoverage { coverage: Coverage, span: Span },
+    /// The generator of a tensor with a target does not select every index below its upper bound.
+    TargetDomain { span: Span },
+    /// The target of a tensor cannot be a permutation of the indices below its upper bound.
+    TargetTypeMismatch { expected: Type, found: Type, span: Span },
+}
+
+impl InferenceError {
+    pub fn span(&self) -> Span {
+        use InferenceError::*;
+        match self {
+            SelectionIndexNotVector { span, .. }
+            | SelectionIndexNotInteger { span, .. }
+            | SelectionRankTooSmall { span, .. }
+            | InhomogeneousArray { span, .. }
+            | UndefinedFunction { span, .. }
+            | UndefinedVariable { span, .. }
+            | NoMatchingOverload { span, .. }
+            | CallArgumentTypeMismatch { span, .. }
+            | AmbiguousOverload { span, .. }
+            | InconsistentShapes { span, .. }
+            | PrimitiveArgumentKindMismatch { span, .. }
+            | InconsistentOverloadReturnBase { span, .. }
+            | FoldSelectionTypeMismatch { span, .. }
+            | FoldFunPlaceholderCountMismatch { span, .. }
+            | FoldFunctionTypeMismatch { span, .. }
+            | ReturnTypeMismatch { span, .. }
+            | MissingTypeAnnotation { span, .. }
+            | UnsatisfiedConstraint { span, .. }
+            | UnboundTypeParameter { span, .. }
+            | UndefinedType { span, .. }
+            | RedefinedStruct { span, .. }
+            | RecursiveStruct { span, .. }
+            | NonScalarField { span, .. }
+            | DuplicateField { span, .. }
+            | UnknownField { span, .. }
+            | MissingField { span, .. }
+            | FieldTypeMismatch { span, .. }
+            | FieldOfNonRecord { span, .. }
+            | PartitionRankMismatch { span, .. }
+            | PartitionTypeMismatch { span, .. }
+            | PartitionCoverage { span, .. }
+            | TargetDomain { span }
+            | TargetTypeMismatch { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use InferenceError::*;
+        match self {
+            SelectionIndexNotVector { ty, .. } =>
+                write!(f, "selection index must be a vector, found `{}`", ty),
+            SelectionIndexNotInteger { ty, .. } =>
+                write!(f, "selection index must be an integer vector, found `{}`", ty),
+            SelectionRankTooSmall { needed, shape, .. } =>
+                write!(f, "selection needs an array of at least rank {}, found shape `{}`", needed, shape),
+            InhomogeneousArray { element, expected, found, .. } =>
+                write!(f, "array element {} has type `{}`, expected `{}`", element, found, expected),
+            UndefinedFunction { name, .. } =>
+                write!(f, "cannot find function `{}`", name),
+            UndefinedVariable { name, .. } =>
+                write!(f, "cannot find variable `{}`", name),
+            NoMatchingOverload { name, arg_bases, .. } =>
+                write!(f, "no overload of `{}` matches argument types {}", name, arg_bases),
+            CallArgumentTypeMismatch { func_name, arg_index, expected, provided, .. } =>
+                write!(f, "argument {} of `{}` expects `{}`, found `{}`", arg_index, func_name, expected, provided),
+            AmbiguousOverload { name, arg_bases, .. } =>
+                write!(f, "call to `{}` with argument types {} is ambiguous", name, arg_bases),
+            InconsistentShapes { name, conflict, .. } =>
+                write!(f, "arguments of `{}` have inconsistent shapes: `{}` is both `{}` and `{}`",
+                    name, conflict.symbol, conflict.first, conflict.second),
+            PrimitiveArgumentKindMismatch { primitive, arg_index, expected, provided, .. } =>
+                write!(f, "argument {} of `{}` expects {}, found `{}`", arg_index, primitive, expected, provided),
+            InconsistentOverloadReturnBase { name, arg_bases, expected, found, .. } =>
+                write!(f, "overloads of `{}` for {} must return the same base type, found `{}` and `{}`", name, arg_bases, expected, found),
+            FoldSelectionTypeMismatch { expected, found, .. } =>
+                write!(f, "fold selection has type `{}`, expected `{}`", found, expected),
+            FoldFunPlaceholderCountMismatch { found, .. } =>
+                write!(f, "fold function must have exactly two placeholders, found {}", found),
+            FoldFunctionTypeMismatch { expected, found, .. } =>
+                write!(f, "fold function returns `{}`, expected `{}`", found, expected),
+            ReturnTypeMismatch { name, expected, found, .. } =>
+                write!(f, "`{}` returns a value of type `{}`, but declares return type `{}`", name, found, expected),
+            MissingTypeAnnotation { name, .. } =>
+                write!(f, "cannot infer the type of `{}`", name),
+            UnsatisfiedConstraint { name, arg_bases, constraint, .. } =>
+                write!(f, "`{}` for argument types {} requires `{}`, which no overload provides", name, arg_bases, constraint),
+            UnboundTypeParameter { name, param, .. } =>
+                write!(f, "type parameter `{}` of `{}` does not occur in its argument types", param, name),
+            UndefinedType { name, .. } =>
+                write!(f, "cannot find type `{}`", name),
+            RedefinedStruct { name, .. } =>
+                write!(f, "struct `{}` is defined more than once", name),
+            RecursiveStruct { name, .. } =>
+                write!(f, "struct `{}` contains itself", name),
+            NonScalarField { record, field, ty, .. } =>
+                write!(f, "field `{}` of `{}` has type `{}`, but fields must be scalars", field, record, ty),
+            DuplicateField { record, field, .. } =>
+                write!(f, "field `{}` of `{}` is given more than once", field, record),
+            UnknownField { record, field, .. } =>
+                write!(f, "struct `{}` has no field `{}`", record, field),
+            MissingField { record, field, .. } =>
+                write!(f, "missing field `{}` of `{}`", field, record),
+            FieldTypeMismatch { record, field, expected, found, .. } =>
+                write!(f, "field `{}` of `{}` expects `{}`, found `{}`", field, record, expected, found),
+            FieldOfNonRecord { field, ty, .. } =>
+                write!(f, "cannot access field `{}` of `{}`, which is not a record", field, ty),
+            PartitionRankMismatch { partition, expected, found, .. } =>
+                write!(f, "partition {} of tensor has an index vector of length {}, expected {}", partition, found, expected),
+            PartitionTypeMismatch { partition, expected, found, .. } =>
+                write!(f, "partition {} of tensor has elements of type `{}`, expected `{}`", partition, found, expected),
+            PartitionCoverage { coverage: Coverage::Gap(index), .. } =>
+                write!(f, "no partition of tensor selects index {:?}", index),
+            PartitionCoverage { coverage: Coverage::Overlap(index), .. } =>
+                write!(f, "partitions of tensor overlap at index {:?}", index),
+            TargetDomain { .. } =>
+                write!(f, "the generator of a tensor with a target must select every index below its upper bound"),
+            TargetTypeMismatch { expected, found, .. } =>
+                write!(f, "the target of a tensor must be a permutation of type `{}`, found `{}`", expected, found),
+        }
+    }
 }
 
 impl<'ast> TypeInfer<'ast> {
-    fn new(overloads: HashMap<String, HashMap<BaseSignature, Vec<DispatchStub>>>) -> Self {
+    fn new(overloads: HashMap<String, HashMap<BaseSignature, Vec<DispatchStub>>>, generics: HashMap<String, Vec<GenericStub>>, structs: Vec<Structdef>) -> Self {
         Self {
             args: Vec::new(),
+            shape_facts: ShapeFacts::default(),
+            vars: HashMap::new(),
+            structs,
             decs: Arena::new(),
             exprs: Arena::new(),
             typed: HashMap::new(),
             stubs: overloads,
+            generics,
+            instances: Vec::new(),
+            checking: HashSet::new(),
             errors: Vec::new(),
+            poisoned: HashSet::new(),
+            span: Span::default(),
         }
     }
 
-    fn array_literal_type(&mut self, elem_types: Vec<Type>) -> Type {
+    fn array_literal_type(&mut self, elem_types: Vec<Type>, span: Span) -> Type {
         let count = elem_types.len();
         let Some(first) = elem_types.first() else {
             return Type::vector_dim(BaseType::I32, DimPattern::Known(0));
@@ -118,6 +505,7 @@ impl<'ast> TypeInfer<'ast> {
                     element: i,
                     expected: first.clone(),
                     found: ty.clone(),
+                    span,
                 });
             }
         }
@@ -144,7 +532,8 @@ impl<'ast> TypeInfer<'ast> {
             TypePattern::Axes(axes) if axes.len() == 1 && matches!(axes[0], AxisPattern::Dim(_)) => {
                 match &axes[0] {
                     AxisPattern::Dim(DimPattern::Known(k)) => (Type::vector_dim(ub_ty.ty.clone(), DimPattern::Known(*k)), Some(*k)),
-                    _ => unreachable!(),
+                    // E.g. the shape of a `d:shp` capture, of which the length is only known at runtime
+                    dim => (Type { ty: ub_ty.ty.clone(), shape: TypePattern::Axes(vec![dim.clone()]) }, None),
                 }
             }
             _ => (Type { ty: ub_ty.ty.clone(), shape: TypePattern::any() }, None),
@@ -168,69 +557,324 @@ impl<'ast> TypeInfer<'ast> {
         Type { ty: elem_ty.ty, shape: result_shape }
     }
 
+    /// Infer the type of the index vector and the body of a partition,
+    /// returning the axes of its upper bound if known and the type of its elements.
+    fn infer_partition(&mut self, partition: &mut Partition<'ast, UntypedAst>) -> (Option<Vec<AxisPattern>>, Type) {
+        let ub_named_axes = self.extract_ub_axes(&partition.ub);
+
+        if let Some(lb) = &mut partition.lb {
+            self.trav_id(lb);
+        }
+
+        let ub_ty = self.trav_id(&mut partition.ub);
+
+        for id in partition.step.iter_mut().chain(&mut partition.width) {
+            self.trav_id(id);
+        }
+
+        let (iv_ty, leading_k) = Self::tensor_iv_and_dims(&ub_ty);
+
+        let leading_axes: Option<Vec<AxisPattern>> = ub_named_axes.or_else(|| {
+            leading_k.map(|k| (0..k).map(|_| AxisPattern::Dim(DimPattern::any())).collect())
+        });
+
+        self.typed.insert(partition.iv as *const _, iv_ty.clone());
+
+        unsafe {
+            let ptr = partition.iv as *const VarInfo<'ast, UntypedAst> as *mut VarInfo<'ast, UntypedAst>;
+            (*ptr).ty = Some(iv_ty);
+        }
+
+        let elem_ty = self.trav_body(&mut partition.body);
+        (leading_axes, elem_ty)
+    }
+
+    /// Reject a partitioned tensor whose constant bounds select an index not exactly once.
+    ///
+    /// Tensors with bounds that are not constant, or with more than [`MAX_COVERAGE_CHECK`]
+    /// elements, are only checked at runtime.
+    fn check_coverage(&mut self, tensor: &Tensor<'ast, UntypedAst>) {
+        let bounds: Option<Vec<Bounds>> = tensor.partitions.iter()
+            .map(|partition| {
+                let ub = self.const_vector(&partition.ub)?;
+                let or_fill = |id: &Option<Id<'ast, UntypedAst>>, fill: usize| match id {
+                    Some(id) => self.const_vector(id),
+                    None => Some(vec![fill; ub.len()]),
+                };
+                let bounds = Bounds { lb: or_fill(&partition.lb, 0)?, step: or_fill(&partition.step, 1)?, width: or_fill(&partition.width, 1)?, ub };
+                let lengths_agree = [&bounds.lb, &bounds.step, &bounds.width].iter().all(|v| v.len() == bounds.ub.len());
+                (lengths_agree && !bounds.step.contains(&0)).then_some(bounds)
+            })
+            .collect();
+        let Some(bounds) = bounds else {
+            return;
+        };
+        if bounds.iter().any(|b| b.ub.len() != bounds[0].ub.len())
+            || Bounds::upper_bound(&bounds).iter().product::<usize>() > MAX_COVERAGE_CHECK
+        {
+            return;
+        }
+
+        if let Some(coverage) = Bounds::coverage(&bounds) {
+            self.errors.push(InferenceError::PartitionCoverage { coverage, span: tensor.span });
+        }
+    }
+
+    /// Reject a target that cannot map the index vectors of a tensor onto themselves.
+    ///
+    /// The domain and the range of the target must be the same, so the generator must select every
+    /// index below `ub` and the target must have type `usize[ub, len(iv)]`. Whether it is
+    /// a permutation is only checked at runtime.
+    fn check_target(&mut self, tensor: &mut Tensor<'ast, UntypedAst>, leading_axes: Option<Vec<AxisPattern>>) {
+        let Some(target) = &mut tensor.target else {
+            return;
+        };
+        let partition = &tensor.partitions[0];
+        if partition.has_default() {
+            self.errors.push(InferenceError::TargetDomain { span: partition.span });
+        }
+
+        let found = self.trav_id(target);
+        if self.is_poisoned(target) || self.is_poisoned(&partition.ub) {
+            return;
+        }
+
+        let iv_ty = self.type_of(&Id::Var(partition.iv));
+        let shape = match (leading_axes, iv_length(partition.iv)) {
+            (Some(mut axes), rank) => {
+                axes.push(AxisPattern::Dim(rank.map_or_else(DimPattern::any, DimPattern::Known)));
+                TypePattern::Axes(axes)
+            }
+            (None, _) => TypePattern::any(),
+        };
+        let expected = Type { ty: iv_ty.ty, shape };
+        if return_type_disagrees(&expected, &found) {
+            let (expected, found) = (self.source_type(&expected), self.source_type(&found));
+            self.errors.push(InferenceError::TargetTypeMismatch { expected, found, span: tensor.span });
+        }
+    }
+
+    /// The elements of a vector of constants, such as the bounds of a tensor.
+    fn const_vector(&self, id: &Id<'ast, UntypedAst>) -> Option<Vec<usize>> {
+        let Id::Var(VarInfo { ssa: Some(Expr::Array(array)), .. }) = id else {
+            return None;
+        };
+        array.elems.iter()
+            .map(|elem| match self.id_dim(elem) {
+                DimPattern::Known(n) => Some(n),
+                DimPattern::Var(_) => None,
+            })
+            .collect()
+    }
+
+    /// The axes of a tensor with upper bound `ub`, if its elements or the array it is the shape of are known.
     fn extract_ub_axes(&self, ub: &Id<'ast, UntypedAst>) -> Option<Vec<AxisPattern>> {
         let lvis = match ub {
             Id::Var(v) => v,
             Id::Arg(_) => return None,
         };
 
-        let arr = match lvis.ssa? {
-            Expr::Array(arr) => arr,
-            _ => return None,
+        match lvis.ssa? {
+            Expr::Array(arr) => Some(arr.elems.iter().map(|elem| AxisPattern::Dim(self.id_dim(elem))).collect()),
+            Expr::Prf(Prf::ShapeA(arr)) => match self.type_of(arr).shape {
+                TypePattern::Axes(axes) => Some(axes),
+                TypePattern::Scalar => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// A type with the extents that are named by SSA variables written as the source
+    /// expressions that define them, for diagnostics.
+    fn source_type(&self, ty: &Type) -> Type {
+        let TypePattern::Axes(axes) = &ty.shape else {
+            return ty.clone();
         };
+        let axes = axes.iter().map(|axis| match axis {
+            AxisPattern::Dim(DimPattern::Var(var)) if self.vars.contains_key(var) => {
+                AxisPattern::Dim(DimPattern::Var(self.source_expr(&Id::Var(self.vars[var]))))
+            }
+            _ => axis.clone(),
+        }).collect();
+        Type { ty: ty.ty.clone(), shape: TypePattern::Axes(axes) }
+    }
 
-        let mut axes = Vec::with_capacity(arr.elems.len());
-        for elem in &arr.elems {
-            let dp = match elem {
-                Id::Arg(i) => DimPattern::Var(self.args[*i].id.clone()),
-                Id::Var(v) => match v.ssa {
-                    Some(Expr::Const(Const::Usize(val))) => DimPattern::Known(*val),
-                    _ => DimPattern::Var(v.name.clone()),
-                },
-            };
-            axes.push(AxisPattern::Dim(dp));
+    /// The source expression of a scalar, as far as it is built from arguments, shape symbols,
+    /// `usize` constants and calls. Anything else is written by its SSA name.
+    fn source_expr(&self, id: &Id<'ast, UntypedAst>) -> String {
+        let v = match id {
+            Id::Arg(i) => return self.args[*i].id.clone(),
+            Id::Var(v) => v,
+        };
+        match v.ssa {
+            Some(Expr::Id(id)) => self.source_expr(id),
+            Some(Expr::Const(Const::Usize(val))) => val.to_string(),
+            Some(Expr::Call(call)) => match (infix_operator(&call.id), call.args.as_slice()) {
+                (Some(op), [l, r]) => format!("{} {} {}", self.source_operand(l), op, self.source_operand(r)),
+                _ => {
+                    let args: Vec<String> = call.args.iter().map(|arg| self.source_expr(arg)).collect();
+                    format!("{}({})", call.id, args.join(", "))
+                }
+            },
+            Some(Expr::Prf(prf)) => match (prf_operator(prf), self.shape_symbol(prf)) {
+                (Some((op, l, r)), _) => format!("{} {} {}", self.source_operand(l), op, self.source_operand(r)),
+                (None, Some(symbol)) => symbol,
+                (None, None) => v.name.clone(),
+            },
+            _ => v.name.clone(),
+        }
+    }
+
+    /// The source expression of an operand of an infix operator, parenthesised if it is one itself.
+    fn source_operand(&self, id: &Id<'ast, UntypedAst>) -> String {
+        match id {
+            Id::Var(VarInfo { ssa: Some(Expr::Call(call)), .. }) if call.args.len() == 2 && infix_operator(&call.id).is_some() => {
+                format!("({})", self.source_expr(id))
+            }
+            Id::Var(VarInfo { ssa: Some(Expr::Prf(prf)), .. }) if prf_operator(prf).is_some() => {
+                format!("({})", self.source_expr(id))
+            }
+            _ => self.source_expr(id),
         }
-        Some(axes)
     }
 
-    fn resolve_overload(&mut self, func_name: &str, arg_types: &[Type]) -> (&DispatchStub, bool) {
-        let Some(group) = self.stubs.get(func_name) else {
-            self.errors.push(InferenceError::UndefinedFunction { name: func_name.to_owned() });
-            panic!("undefined function: {}", func_name);
+    /// The shape symbol bound by the shape prelude to the extent or rank of an argument.
+    fn shape_symbol(&self, prf: &Prf<'ast, UntypedAst>) -> Option<String> {
+        let term = match prf {
+            Prf::SelVxA(Id::Var(idx), Id::Var(shp)) => {
+                let (Some(Expr::Array(idx)), Some(Expr::Prf(Prf::ShapeA(Id::Arg(arg_index))))) = (idx.ssa, shp.ssa) else {
+                    return None;
+                };
+                let [Id::Var(VarInfo { ssa: Some(Expr::Const(Const::Usize(axis_index))), .. })] = idx.elems.as_slice() else {
+                    return None;
+                };
+                ShapeTerm::ArgDim { arg_index: *arg_index, axis_index: *axis_index }
+            }
+            Prf::DimA(Id::Arg(arg_index)) => {
+                return self.shape_facts.bindings.iter()
+                    .find(|b| matches!(b.term, ShapeTerm::ArgRank { arg_index: i, .. } if i == *arg_index))
+                    .map(|b| b.symbol.clone());
+            }
+            _ => return None,
         };
+        self.shape_facts.bindings.iter()
+            .find(|b| b.term == term)
+            .map(|b| b.symbol.clone())
+    }
+
+    /// The extent denoted by a `usize` scalar, as a constant or by the name of its variable.
+    fn id_dim(&self, id: &Id<'ast, UntypedAst>) -> DimPattern {
+        match id {
+            Id::Arg(i) => DimPattern::Var(self.args[*i].id.clone()),
+            Id::Var(v) => match v.ssa {
+                Some(Expr::Const(Const::Usize(val))) => DimPattern::Known(*val),
+                _ => DimPattern::Var(v.name.clone()),
+            },
+        }
+    }
+
+    fn type_of(&self, id: &Id<'ast, UntypedAst>) -> Type {
+        match id {
+            Id::Arg(i) => self.args[*i].ty.clone(),
+            Id::Var(v) => self.typed[&(*v as *const _)].clone(),
+        }
+    }
+
+    /// The extents denoted by the `usize` scalars among `ids`, for solving the shapes of a call.
+    fn scalar_dims(&self, ids: &[Id<'ast, UntypedAst>], types: &[Type]) -> Vec<Option<DimPattern>> {
+        ids.iter().zip(types)
+            .map(|(id, ty)| (ty.is_scalar() && ty.ty == BaseType::Usize).then(|| self.id_dim(id)))
+            .collect()
+    }
+
+    fn is_poisoned(&self, id: &Id<'ast, UntypedAst>) -> bool {
+        match id {
+            Id::Arg(_) => false,
+            Id::Var(v) => self.poisoned.contains(&(*v as *const _)),
+        }
+    }
+
+    /// Whether any operand of `expr` itself is poisoned. Nested bodies are not inspected,
+    /// their assignments are poisoned individually.
+    fn uses_poisoned(&self, expr: &Expr<'ast, UntypedAst>) -> bool {
+        match expr {
+            Expr::Cond(n) => self.is_poisoned(&n.cond),
+            Expr::Call(n) => n.args.iter().any(|arg| self.is_poisoned(arg)),
+            Expr::Prf(n) => n.args().into_iter().any(|arg| self.is_poisoned(arg)),
+            Expr::Tensor(n) => n.target.iter()
+                .chain(n.partitions.iter().flat_map(|p| p.lb.iter().chain([&p.ub]).chain(&p.step).chain(&p.width)))
+                .any(|id| self.is_poisoned(id)),
+            Expr::Fold(n) => self.is_poisoned(&n.neutral) || match &n.foldfun {
+                FoldFun::Name(_) => false,
+                FoldFun::Apply { args, .. } => args.iter()
+                    .any(|arg| matches!(arg, FoldFunArg::Bound(bound) if self.is_poisoned(bound))),
+            },
+            Expr::Array(n) => n.elems.iter().any(|elem| self.is_poisoned(elem)),
+            Expr::Record(n) => n.fields.iter().any(|(_, value)| self.is_poisoned(value)),
+            Expr::Field(n) => self.is_poisoned(&n.record),
+            Expr::Id(id) => self.is_poisoned(id),
+            Expr::Const(_) => false,
+        }
+    }
+
+    /// The type given to the result of an expression that failed to type check.
+    /// Its variable is poisoned, so this type is never inspected.
+    fn error_type() -> Type {
+        Type { ty: BaseType::I32, shape: TypePattern::any() }
+    }
+
+    /// Find the return type of the most specific overload of `func_name` that accepts `arg_types`.
+    ///
+    /// Overloads whose shape symbols would take provably different values are not considered, and the
+    /// symbols of the chosen overload are substituted in its return type. `scalars` are the extents
+    /// denoted by scalar `usize` arguments, see [`solve_shapes`].
+    fn resolve_overload(&mut self, func_name: &str, arg_types: &[Type], scalars: &[Option<DimPattern>], span: Span) -> Option<Type> {
+        if !self.stubs.contains_key(func_name) {
+            self.errors.push(InferenceError::UndefinedFunction { name: func_name.to_owned(), span });
+            return None;
+        }
 
         let key = BaseSignature {
             base_types: arg_types.iter().map(|t| t.ty.clone()).collect(),
         };
 
-        let Some(candidates) = group.get(&key) else {
-            self.errors.push(InferenceError::NoMatchingOverload {
-                name: func_name.to_owned(),
-                arg_bases: key.clone(),
-            });
-            panic!("no matching overload for function: {}", func_name);
-        };
+        let mut unsatisfied = Vec::new();
+        let mut matches = self.candidates(func_name, arg_types, &mut unsatisfied);
 
-        let mut matches = Vec::new();
-        for target in candidates {
-            let mut is_match = true;
-            for (expected, provided) in target.args.iter().zip(arg_types.iter()) {
-                if !types_compatible(&expected.ty, provided) {
-                    is_match = false;
-                    break;
-                }
+        let mut conflict = None;
+        let mut solutions = Vec::new();
+        matches.retain(|candidate| match solve_shapes(&candidate.stub.args, &candidate.stub.shape_facts, arg_types, scalars) {
+            Ok(solution) => {
+                solutions.push(solution);
+                true
             }
-            if is_match {
-                matches.push(target);
+            Err(error) => {
+                conflict.get_or_insert(error);
+                false
             }
+        });
+
+        if let (true, Some(conflict)) = (matches.is_empty(), conflict) {
+            self.errors.push(InferenceError::InconsistentShapes { name: func_name.to_owned(), conflict, span });
+            return None;
         }
 
         if matches.is_empty() {
-            self.errors.push(InferenceError::NoMatchingOverload {
-                name: func_name.to_owned(),
-                arg_bases: key.clone(),
-            });
-            panic!("no matching overload for function: {}", func_name);
+            let error = match unsatisfied.into_iter().next() {
+                Some(constraint) => InferenceError::UnsatisfiedConstraint {
+                    name: func_name.to_owned(),
+                    arg_bases: key,
+                    constraint,
+                    span,
+                },
+                None => InferenceError::NoMatchingOverload {
+                    name: func_name.to_owned(),
+                    arg_bases: key,
+                    span,
+                },
+            };
+            self.errors.push(error);
+            return None;
         }
 
         let best_matches = maximal_candidates(&matches);
@@ -241,10 +885,79 @@ impl<'ast> TypeInfer<'ast> {
             self.errors.push(InferenceError::AmbiguousOverload {
                 name: func_name.to_owned(),
                 arg_bases: key,
+                span,
             });
         }
 
-        (best_matches[0], needs_runtime_dispatch)
+        self.instances.extend(best_matches.iter().filter_map(|candidate| candidate.instance.clone()));
+
+        let ret_type = &best_matches[0].stub.ret_type;
+        if needs_runtime_dispatch {
+            Some(Type { ty: ret_type.ty.clone(), shape: TypePattern::any() })
+        } else {
+            let best = matches.iter().position(|candidate| std::ptr::eq(candidate, best_matches[0])).unwrap();
+            Some(solutions[best].instantiate(ret_type))
+        }
+    }
+
+    /// The overloads of `func_name` that accept `arg_types`, including instances of generic functions.
+    ///
+    /// An overload with the same argument types takes precedence over an instance, and an instance
+    /// is only a candidate if its constraints are satisfied. The first constraint that is not
+    /// satisfied of each rejected instance is added to `unsatisfied`.
+    fn candidates(&mut self, func_name: &str, arg_types: &[Type], unsatisfied: &mut Vec<Constraint>) -> Vec<Candidate> {
+        let key = BaseSignature {
+            base_types: arg_types.iter().map(|t| t.ty.clone()).collect(),
+        };
+
+        let mut matches: Vec<Candidate> = self.stubs.get(func_name)
+            .and_then(|group| group.get(&key))
+            .into_iter()
+            .flatten()
+            .filter(|stub| args_compatible(&stub.args, arg_types))
+            .map(|stub| Candidate { stub: stub.clone(), instance: None })
+            .collect();
+
+        // A generic function cannot satisfy its own constraints
+        let check = (func_name.to_owned(), key);
+        if self.checking.contains(&check) {
+            return matches;
+        }
+
+        let generics = self.generics.get(func_name).cloned().unwrap_or_default();
+        self.checking.insert(check.clone());
+
+        for generic in generics {
+            let Some(types) = generic.unify(&check.1) else {
+                continue;
+            };
+
+            let stub = generic.instantiate(&types);
+            if !args_compatible(&stub.args, arg_types)
+                || matches.iter().any(|candidate| same_args(&candidate.stub.args, &stub.args))
+            {
+                continue;
+            }
+
+            let failed = generic.constraints.iter()
+                .map(|constraint| generic.instantiate_constraint(constraint, &types))
+                .find(|constraint| !self.satisfies(constraint));
+            match failed {
+                Some(constraint) => unsatisfied.push(constraint),
+                None => matches.push(Candidate { stub, instance: Some((generic.template, types)) }),
+            }
+        }
+
+        self.checking.remove(&check);
+        matches
+    }
+
+    /// Whether the most specific overloads for the arguments of `constraint` return its return type.
+    fn satisfies(&mut self, constraint: &Constraint) -> bool {
+        let matches = self.candidates(&constraint.name, &constraint.args, &mut Vec::new());
+        let best_matches = maximal_candidates(&matches);
+        !best_matches.is_empty()
+            && best_matches.iter().all(|candidate| types_compatible(&constraint.ret_type, &candidate.stub.ret_type))
     }
 }
 
@@ -262,24 +975,44 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
         debug_assert!(self.exprs.len() == 0);
 
         self.args = fundef.args.clone();
+        self.shape_facts = fundef.shape_facts.clone();
         self.decs = mem::take(&mut fundef.decs);
         self.exprs = mem::take(&mut fundef.exprs);
+        self.span = fundef.span;
 
         for assign in &mut fundef.shape_prelude {
             self.trav_assign(assign);
         }
 
-        let _ret_ty = self.trav_body(&mut fundef.body);
+        let ret_ty = self.trav_body(&mut fundef.body);
+        // What cannot be proven here is asserted at runtime by the generated code
+        if !self.is_poisoned(&fundef.body.ret) && return_type_disagrees(&fundef.ret_type, &ret_ty) {
+            self.errors.push(InferenceError::ReturnTypeMismatch {
+                name: fundef.name.clone(),
+                expected: fundef.ret_type.clone(),
+                found: ret_ty,
+                span: fundef.span,
+            });
+        }
 
         fundef.decs = mem::take(&mut self.decs);
         fundef.exprs = mem::take(&mut self.exprs);
         self.typed.clear();
+        self.vars.clear();
         self.args.clear();
     }
 
     fn trav_assign(&mut self, assign: &mut Assign<'ast, UntypedAst>) {
+        let old_span = self.span;
+        self.span = assign.span;
+        let n_errors = self.errors.len();
         let ty = self.trav_expr(&mut assign.expr);
+        if self.errors.len() > n_errors || self.uses_poisoned(assign.expr) {
+            self.poisoned.insert(assign.lhs as *const _);
+        }
+        self.span = old_span;
         self.typed.insert(assign.lhs as *const _, ty.clone());
+        self.vars.insert(assign.lhs.name.clone(), assign.lhs);
 
         unsafe {
             let ptr = assign.lhs as *const VarInfo<'ast, UntypedAst> as *mut VarInfo<'ast, UntypedAst>;
@@ -290,24 +1023,28 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
     fn trav_cond(&mut self, cond: &mut Cond<'ast, UntypedAst>) -> Self::ExprOut {
         let cond_ty = self.trav_id(&mut cond.cond);
 
-        if !(cond_ty.is_scalar() && cond_ty.ty == BaseType::Bool) {
+        let is_bool = cond_ty.is_scalar() && cond_ty.ty == BaseType::Bool;
+        if !is_bool && !self.is_poisoned(&cond.cond) {
             self.errors.push(InferenceError::PrimitiveArgumentKindMismatch {
                 primitive: "cond".to_owned(),
                 arg_index: 0,
                 expected: "bool scalar",
                 provided: cond_ty,
+                span: cond.span,
             });
         }
 
         let then_ty = self.trav_body(&mut cond.then_branch);
         let else_ty = self.trav_body(&mut cond.else_branch);
 
-        if !types_compatible(&then_ty, &else_ty) || !types_compatible(&else_ty, &then_ty) {
+        let branch_poisoned = self.is_poisoned(&cond.then_branch.ret) || self.is_poisoned(&cond.else_branch.ret);
+        if !branch_poisoned && (!types_compatible(&then_ty, &else_ty) || !types_compatible(&else_ty, &then_ty)) {
             self.errors.push(InferenceError::PrimitiveArgumentKindMismatch {
                 primitive: "cond".to_owned(),
                 arg_index: 2,
                 expected: "same type as true-branch",
-                provided: then_ty.clone(),
+                provided: else_ty,
+                span: cond.span,
             });
         }
 
@@ -321,14 +1058,13 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
             arg_types.push(ty);
         }
 
-        let (target, runtime_dispatch) = self.resolve_overload(&call.id, &arg_types);
-        let out_ty = if runtime_dispatch {
-            Type { ty: target.ret_type.ty.clone(), shape: TypePattern::any() }
-        } else {
-            target.ret_type.clone()
-        };
+        if call.args.iter().any(|arg| self.is_poisoned(arg)) {
+            return Self::error_type();
+        }
 
-        out_ty
+        let scalars = self.scalar_dims(&call.args, &arg_types);
+        self.resolve_overload(&call.id, &arg_types, &scalars, call.span)
+            .unwrap_or_else(Self::error_type)
     }
 
     fn trav_prf(&mut self, prf: &mut Prf<'ast, UntypedAst>) -> Self::ExprOut {
@@ -336,24 +1072,34 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
         match prf {
             ShapeA(arr) => {
                 let arr_ty = self.trav_id(arr);
-                if !arr_ty.is_array() {
+                if !self.is_poisoned(arr) && !arr_ty.is_array() {
                     self.errors.push(InferenceError::PrimitiveArgumentKindMismatch {
                         primitive: "shape".to_owned(),
                         arg_index: 0,
                         expected: "array",
-                        provided: arr_ty,
+                        provided: arr_ty.clone(),
+                        span: self.span,
                     });
                 }
-                Type::vector_dim(BaseType::Usize, DimPattern::any())
+                // The length of the shape is the rank of the array, if known
+                let rank = match &arr_ty.shape {
+                    TypePattern::Axes(axes) => match axes.as_slice() {
+                        [AxisPattern::Rank(capture)] => DimPattern::Var(capture.dim_name.clone()),
+                        _ => arr_ty.rank().map_or_else(DimPattern::any, |rank| DimPattern::Known(rank as usize)),
+                    },
+                    TypePattern::Scalar => DimPattern::Known(0),
+                };
+                Type::vector_dim(BaseType::Usize, rank)
             }
             DimA(arr) => {
                 let arr_ty = self.trav_id(arr);
-                if !arr_ty.is_array() {
+                if !self.is_poisoned(arr) && !arr_ty.is_array() {
                     self.errors.push(InferenceError::PrimitiveArgumentKindMismatch {
                         primitive: "dim".to_owned(),
                         arg_index: 0,
                         expected: "array",
                         provided: arr_ty,
+                        span: self.span,
                     });
                 }
                 Type::scalar(BaseType::Usize)
@@ -363,6 +1109,11 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
                 let arr_ty = self.trav_id(arr);
                 Type::scalar(arr_ty.ty)
             }
+            TakeSxV(n, v) | DropSxV(n, v) => {
+                let _n_ty = self.trav_id(n);
+                let v_ty = self.trav_id(v);
+                Type::vector_dim(v_ty.ty, DimPattern::any())
+            }
             AddSxS(l, r) => {
                 let l_ty = self.trav_id(l);
                 let _r_ty = self.trav_id(r);
@@ -400,76 +1151,118 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
     }
 
     fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, UntypedAst>) -> Self::ExprOut {
-        let ub_named_axes = self.extract_ub_axes(&tensor.ub);
-
-        if let Some(lb) = &mut tensor.lb {
-            self.trav_id(lb);
-        }
-
-        let ub_ty = self.trav_id(&mut tensor.ub);
-
-        let (iv_ty, leading_k) = Self::tensor_iv_and_dims(&ub_ty);
-
-        let leading_axes: Option<Vec<AxisPattern>> = ub_named_axes.or_else(|| {
-            leading_k.map(|k| (0..k).map(|_| AxisPattern::Dim(DimPattern::any())).collect())
-        });
+        let mut partitions = tensor.partitions.iter_mut();
+        let first = partitions.next().expect("a tensor has at least one partition");
+        let (mut leading_axes, mut elem_ty) = self.infer_partition(first);
+        let rank = iv_length(first.iv);
+
+        for (i, partition) in partitions.enumerate() {
+            let (axes, ty) = self.infer_partition(partition);
+
+            if let (Some(expected), Some(found)) = (rank, iv_length(partition.iv))
+                && expected != found
+            {
+                self.errors.push(InferenceError::PartitionRankMismatch { partition: i + 1, expected, found, span: partition.span });
+            }
 
-        self.typed.insert(tensor.iv as *const _, iv_ty.clone());
+            if !self.is_poisoned(&partition.body.ret) {
+                match unify_types(&elem_ty, &ty) {
+                    Some(unified) => elem_ty = unified,
+                    None => self.errors.push(InferenceError::PartitionTypeMismatch {
+                        partition: i + 1,
+                        expected: elem_ty.clone(),
+                        found: ty,
+                        span: partition.span,
+                    }),
+                }
+            }
 
-        unsafe {
-            let ptr = tensor.iv as *const VarInfo<'ast, UntypedAst> as *mut VarInfo<'ast, UntypedAst>;
-            (*ptr).ty = Some(iv_ty);
+            leading_axes = match (leading_axes, axes) {
+                (Some(a), Some(b)) if a.len() == b.len() => Some(a.iter().zip(&b).map(|(a, b)| join_extents(a, b)).collect()),
+                _ => rank.map(|k| vec![AxisPattern::Dim(DimPattern::any()); k]),
+            };
         }
 
-        let ret_ty = self.trav_body(&mut tensor.body);
+        if tensor.partitions.len() > 1 {
+            self.check_coverage(tensor);
+        }
+        if tensor.target.is_some() {
+            self.check_target(tensor, leading_axes.clone());
+        }
 
-        let result_ty = match leading_axes {
-            Some(axes) => Self::tensor_result_type(ret_ty, axes),
+        match leading_axes {
+            Some(axes) => Self::tensor_result_type(elem_ty, axes),
             None => Type {
-                ty: ret_ty.ty,
+                ty: elem_ty.ty,
                 shape: TypePattern::any(),
             },
-        };
-
-        result_ty
+        }
     }
 
     fn trav_fold(&mut self, fold: &mut Fold<'ast, UntypedAst>) -> Self::ExprOut {
         let neutral_ty = self.trav_id(&mut fold.neutral);
 
-        let _selection_ty = self.trav_tensor(&mut fold.selection);
-
-        //if !types_compatible(&neutral_ty, &selection_ty) {
-        //    self.errors.push(InferenceError::FoldSelectionTypeMismatch {
-        //        expected: neutral_ty.clone(),
-        //        found: selection_ty.clone(),
-        //    });
-        //}
-
-        let ret_ty = match &mut fold.foldfun {
-            FoldFun::Name(id) => {
-                let arg_types = vec![neutral_ty.clone(), neutral_ty.clone()];
-                let (target, runtime_dispatch) = self.resolve_overload(&id, &arg_types);
-                let out_ty = if runtime_dispatch {
-                    Type { ty: target.ret_type.ty.clone(), shape: TypePattern::any() }
-                } else {
-                    target.ret_type.clone()
-                };
-                out_ty
-            }
-            FoldFun::Apply { .. } => {
-                unimplemented!("'partial application' fold not yet supported")
+        let (_, elem_ty) = self.infer_partition(&mut fold.selection);
+
+        // The fold function is applied to the accumulator and an element
+        let (id, arg_types) = match &mut fold.foldfun {
+            FoldFun::Name(id) => (id, vec![neutral_ty.clone(), elem_ty.clone()]),
+            FoldFun::Apply { id, args } => {
+                let mut holes = [neutral_ty.clone(), elem_ty.clone()].into_iter();
+                let mut arg_types = Vec::with_capacity(args.len());
+                let mut placeholders = 0;
+                for arg in args.iter_mut() {
+                    match arg {
+                        FoldFunArg::Placeholder => {
+                            placeholders += 1;
+                            arg_types.push(holes.next().unwrap_or_else(Self::error_type));
+                        }
+                        FoldFunArg::Bound(bound) => arg_types.push(self.trav_id(bound)),
+                    }
+                }
+
+                if placeholders != 2 {
+                    self.errors.push(InferenceError::FoldFunPlaceholderCountMismatch {
+                        found: placeholders,
+                        span: fold.span,
+                    });
+                    return Self::error_type();
+                }
+                if args.iter().any(|arg| matches!(arg, FoldFunArg::Bound(bound) if self.is_poisoned(bound))) {
+                    return Self::error_type();
+                }
+                (id, arg_types)
             }
         };
 
-        //if !types_compatible(&neutral_ty, &ret_ty) {
-        //    self.errors.push(InferenceError::FoldFunctionTypeMismatch {
-        //        expected: neutral_ty.clone(),
-        //        found: ret_ty,
-        //    });
-        //}
+        if self.is_poisoned(&fold.neutral) || self.is_poisoned(&fold.selection.body.ret) {
+            return Self::error_type();
+        }
+
+        let Some(acc_ty) = unify_types(&neutral_ty, &elem_ty) else {
+            self.errors.push(InferenceError::FoldSelectionTypeMismatch {
+                expected: neutral_ty,
+                found: elem_ty,
+                span: fold.span,
+            });
+            return Self::error_type();
+        };
+
+        let Some(ret_ty) = self.resolve_overload(id, &arg_types, &vec![None; arg_types.len()], fold.span) else {
+            return Self::error_type();
+        };
 
-        ret_ty
+        match unify_types(&acc_ty, &ret_ty) {
+            Some(ty) => ty,
+            None => {
+                self.errors.push(InferenceError::FoldFunctionTypeMismatch {
+                    expected: acc_ty,
+                    found: ret_ty,
+                    span: fold.span,
+                });
+                Self::error_type()
+            }
+        }
     }
 
     fn trav_array(&mut self, array: &mut Array<'ast, UntypedAst>) -> Self::ExprOut {
@@ -478,7 +1271,95 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
             let ty = self.trav_id(value);
             elem_types.push(ty);
         }
-        self.array_literal_type(elem_types)
+        if array.elems.iter().any(|elem| self.is_poisoned(elem)) {
+            return Self::error_type();
+        }
+        self.array_literal_type(elem_types, array.span)
+    }
+
+    fn trav_record(&mut self, record: &mut Record<'ast, UntypedAst>) -> Self::ExprOut {
+        let mut field_types = Vec::with_capacity(record.fields.len());
+        for (_, value) in &mut record.fields {
+            field_types.push(self.trav_id(value));
+        }
+
+        let ty = Type::scalar(BaseType::Udf(record.name.clone()));
+        let Some(structdef) = self.structs.iter().find(|structdef| structdef.name == record.name) else {
+            self.errors.push(InferenceError::UndefinedType { name: record.name.clone(), span: record.span });
+            return ty;
+        };
+
+        let mut errors = Vec::new();
+        let mut given = HashSet::new();
+        for ((name, value), found) in record.fields.iter().zip(field_types) {
+            if !given.insert(name) {
+                errors.push(InferenceError::DuplicateField {
+                    record: record.name.clone(),
+                    field: name.clone(),
+                    span: record.span,
+                });
+                continue;
+            }
+
+            match structdef.field(name) {
+                None => errors.push(InferenceError::UnknownField {
+                    record: record.name.clone(),
+                    field: name.clone(),
+                    span: record.span,
+                }),
+                Some(field) if !self.is_poisoned(value) && !types_compatible(&field.ty, &found) => {
+                    errors.push(InferenceError::FieldTypeMismatch {
+                        record: record.name.clone(),
+                        field: name.clone(),
+                        expected: field.ty.clone(),
+                        found,
+                        span: record.span,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+
+        for field in structdef.fields.iter().filter(|field| !given.contains(&field.id)) {
+            errors.push(InferenceError::MissingField {
+                record: record.name.clone(),
+                field: field.id.clone(),
+                span: record.span,
+            });
+        }
+
+        self.errors.extend(errors);
+        ty
+    }
+
+    fn trav_field(&mut self, field: &mut Field<'ast, UntypedAst>) -> Self::ExprOut {
+        let ty = self.trav_id(&mut field.record);
+        if self.is_poisoned(&field.record) {
+            return Self::error_type();
+        }
+
+        let structdef = match &ty {
+            Type { ty: BaseType::Udf(name), shape: TypePattern::Scalar } => {
+                self.structs.iter().find(|structdef| &structdef.name == name)
+            }
+            _ => None,
+        };
+        let Some(structdef) = structdef else {
+            self.errors.push(InferenceError::FieldOfNonRecord { field: field.name.clone(), ty, span: field.span });
+            return Self::error_type();
+        };
+
+        match structdef.field(&field.name) {
+            Some(farg) => farg.ty.clone(),
+            None => {
+                self.errors.push(InferenceError::UnknownField {
+                    record: structdef.name.clone(),
+                    field: field.name.clone(),
+                    span: field.span,
+                });
+                Self::error_type()
+            }
+        }
     }
 
     fn trav_id(&mut self, id: &mut Id<'ast, UntypedAst>) -> Self::ExprOut {
@@ -508,17 +1389,124 @@ impl<'ast> Traverse<'ast> for TypeInfer<'ast> {
     }
 }
 
+/// Partitioned tensors with more elements than this are only checked for coverage at runtime.
+const MAX_COVERAGE_CHECK: usize = 1 << 16;
+
+/// The length of an index vector, if known.
+fn iv_length(iv: &VarInfo<'_, UntypedAst>) -> Option<usize> {
+    match iv.ty.as_ref()?.shape {
+        TypePattern::Axes(ref axes) => match axes[..] {
+            [AxisPattern::Dim(DimPattern::Known(k))] => Some(k),
+            _ => None,
+        },
+        TypePattern::Scalar => None,
+    }
+}
+
+/// The extent of an axis of a partitioned tensor, which is the maximum of the extents of its partitions.
+fn join_extents(a: &AxisPattern, b: &AxisPattern) -> AxisPattern {
+    match (a, b) {
+        (AxisPattern::Dim(DimPattern::Known(a)), AxisPattern::Dim(DimPattern::Known(b))) => AxisPattern::Dim(DimPattern::Known(*a.max(b))),
+        (AxisPattern::Dim(DimPattern::Var(a)), AxisPattern::Dim(DimPattern::Var(b))) if a == b => AxisPattern::Dim(DimPattern::Var(a.clone())),
+        _ => AxisPattern::Dim(DimPattern::any()),
+    }
+}
+
 fn types_compatible(expected: &Type, provided: &Type) -> bool {
     expected.ty == provided.ty && shapes_compatible(&expected.shape, &provided.shape)
 }
 
-fn shapes_compatible(expected: &TypePattern, provided: &TypePattern) -> bool {
+/// The most specific type that is compatible with both `a` and `b`, if they are compatible.
+///
+/// Known dimensions take precedence over symbolic ones, and explicit axes over a rank capture.
+fn unify_types(a: &Type, b: &Type) -> Option<Type> {
+    if !types_compatible(a, b) {
+        return None;
+    }
+
     let has_rank = |axes: &[AxisPattern]| axes.iter().any(|a| matches!(a, AxisPattern::Rank(_)));
+    let shape = match (&a.shape, &b.shape) {
+        (TypePattern::Axes(a_axes), TypePattern::Axes(b_axes)) if !has_rank(a_axes) && !has_rank(b_axes) => {
+            let axes = a_axes.iter().zip(b_axes)
+                .map(|(a, b)| match (a, b) {
+                    (AxisPattern::Dim(DimPattern::Var(_)), AxisPattern::Dim(DimPattern::Known(_))) => b.clone(),
+                    _ => a.clone(),
+                })
+                .collect();
+            TypePattern::Axes(axes)
+        }
+        (TypePattern::Axes(a_axes), b_shape) if has_rank(a_axes) => b_shape.clone(),
+        (a_shape, _) => a_shape.clone(),
+    };
+
+    Some(Type { ty: a.ty.clone(), shape })
+}
+
+/// Whether a value of type `found` can never have the declared return type `declared`.
+///
+/// That is, the base types, the ranks, or known extents differ. A symbol that occurs more than once
+/// in the declared type must have the same extent everywhere.
+/// The operator that a call to `name` is written with in the source, if any.
+fn infix_operator(name: &str) -> Option<&'static str> {
+    match name {
+        "add" => Some("+"),
+        "sub" => Some("-"),
+        "mul" => Some("*"),
+        "div" => Some("/"),
+        _ => None,
+    }
+}
+
+/// The infix operator and operands of an arithmetic primitive.
+fn prf_operator<'a, 'ast>(prf: &'a Prf<'ast, UntypedAst>) -> Option<(&'static str, &'a Id<'ast, UntypedAst>, &'a Id<'ast, UntypedAst>)> {
+    match prf {
+        Prf::AddSxS(l, r) => Some(("+", l, r)),
+        Prf::SubSxS(l, r) => Some(("-", l, r)),
+        Prf::MulSxS(l, r) => Some(("*", l, r)),
+        Prf::DivSxS(l, r) => Some(("/", l, r)),
+        _ => None,
+    }
+}
+
+fn return_type_disagrees(declared: &Type, found: &Type) -> bool {
+    if declared.ty != found.ty {
+        return true;
+    }
+
+    let (declared_min, found_min) = (declared.shape.min_rank(), found.shape.min_rank());
+    let declared_fixed = !declared.shape.has_rank_capture();
+    let found_fixed = !found.shape.has_rank_capture();
+    if (declared_fixed && declared_min < found_min) || (found_fixed && found_min < declared_min) {
+        return true;
+    }
+
+    let (TypePattern::Axes(declared), TypePattern::Axes(found)) = (&declared.shape, &found.shape) else {
+        return false;
+    };
+    if !declared_fixed || !found_fixed {
+        return false;
+    }
+
+    let mut extents = HashMap::new();
+    declared.iter().zip(found).any(|(declared, found)| match (declared, found) {
+        (AxisPattern::Dim(DimPattern::Known(a)), AxisPattern::Dim(DimPattern::Known(b))) => a != b,
+        (AxisPattern::Dim(DimPattern::Var(var)), AxisPattern::Dim(DimPattern::Known(b))) if !var.is_empty() => {
+            *extents.entry(var).or_insert(*b) != *b
+        }
+        _ => false,
+    })
+}
+
+fn shapes_compatible(expected: &TypePattern, provided: &TypePattern) -> bool {
     match (expected, provided) {
         (TypePattern::Scalar, TypePattern::Scalar) => true,
         (TypePattern::Axes(exp_axes), TypePattern::Axes(prov_axes)) => {
-            if has_rank(exp_axes) || has_rank(prov_axes) {
-                return true;
+            // A rank capture matches any rank from its lower bound upwards
+            match (expected.has_rank_capture(), provided.has_rank_capture()) {
+                (true, true) => return true,
+                (true, false) => return prov_axes.len() >= expected.min_rank(),
+                (false, true) => return exp_axes.len() >= provided.min_rank(),
+                (false, false) => {}
             }
             if exp_axes.len() != prov_axes.len() {
                 return false;
@@ -546,19 +1534,30 @@ fn dims_compatible(expected: &DimPattern, provided: &DimPattern) -> bool {
     }
 }
 
-fn maximal_candidates<'a>(candidates: &[&'a DispatchStub]) -> Vec<&'a DispatchStub> {
+fn args_compatible(expected: &[Farg], provided: &[Type]) -> bool {
+    expected.len() == provided.len()
+        && expected.iter().zip(provided).all(|(expected, provided)| types_compatible(&expected.ty, provided))
+}
+
+/// Whether two overloads accept exactly the same arguments.
+fn same_args(a: &[Farg], b: &[Farg]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| a.ty.ty == b.ty.ty && shape_relation(&a.ty.shape, &b.ty.shape) == ShapeRel::Equal)
+}
+
+fn maximal_candidates(candidates: &[Candidate]) -> Vec<&Candidate> {
     let mut maximal = Vec::new();
 
     'outer: for a in candidates {
         for b in candidates {
-            if std::ptr::eq(*a, *b) {
+            if std::ptr::eq(a, b) {
                 continue;
             }
-            if overload_more_specific(&b.args, &a.args) {
+            if overload_more_specific(&b.stub.args, &a.stub.args) {
                 continue 'outer;
             }
         }
-        maximal.push(*a);
+        maximal.push(a);
     }
 
     maximal
@@ -607,23 +1606,22 @@ fn shape_relation(a: &TypePattern, b: &TypePattern) -> ShapeRel {
 fn shape_more_or_equal(a: &TypePattern, b: &TypePattern) -> bool {
     match (a, b) {
         (TypePattern::Scalar, TypePattern::Scalar) => true,
-        (TypePattern::Scalar, TypePattern::Axes(axes)) => axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))),
-        (TypePattern::Axes(a_axes), TypePattern::Scalar) => a_axes.iter().any(|axis| matches!(axis, AxisPattern::Rank(_))),
-        (TypePattern::Axes(a_axes), TypePattern::Axes(b_axes)) => axes_more_or_equal(a_axes, b_axes),
+        // A rank capture only overlaps with scalars if it has no lower bound
+        (TypePattern::Scalar, TypePattern::Axes(_)) => b.has_rank_capture() && b.min_rank() == 0,
+        (TypePattern::Axes(_), TypePattern::Scalar) => a.has_rank_capture() && a.min_rank() == 0,
+        (TypePattern::Axes(a_axes), TypePattern::Axes(b_axes)) => {
+            if b.has_rank_capture() {
+                return a.min_rank() >= b.min_rank();
+            }
+            if a.has_rank_capture() {
+                return false;
+            }
+            axes_more_or_equal(a_axes, b_axes)
+        }
     }
 }
 
 fn axes_more_or_equal(a: &[AxisPattern], b: &[AxisPattern]) -> bool {
-    let a_has_rank = a.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
-    let b_has_rank = b.iter().any(|axis| matches!(axis, AxisPattern::Rank(_)));
-
-    if b_has_rank {
-        return true;
-    }
-    if a_has_rank {
-        return false;
-    }
-
     if a.len() != b.len() {
         return false;
     }
@@ -633,7 +1631,7 @@ fn axes_more_or_equal(a: &[AxisPattern], b: &[AxisPattern]) -> bool {
 
 fn axis_more_or_equal(a: &AxisPattern, b: &AxisPattern) -> bool {
     match (a, b) {
-        (AxisPattern::Rank(_), AxisPattern::Rank(_)) => true,
+        (AxisPattern::Rank(a), AxisPattern::Rank(b)) => a.min_rank >= b.min_rank,
         (AxisPattern::Rank(_), _) => false,
         (_, AxisPattern::Rank(_)) => true,
         (AxisPattern::Dim(ad), AxisPattern::Dim(bd)) => dim_more_or_equal(ad, bd),
@@ -5,4 +5,4 @@ mod analyse_tp;
 mod check_tp;
 
 pub use analyse_tp::analyse_tp;
-pub use check_tp::check_tp;
+pub use check_tp::{check_tp, TypePatternError};
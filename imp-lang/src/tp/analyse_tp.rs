@@ -45,7 +45,8 @@ impl<'ast> AnalyseTp {
 
     fn dim_at_expr(&self, fundef: &Fundef<'ast, ParsedAst>, arg_index: usize, axis_index: usize) -> Expr<'ast, ParsedAst> {
         let idx = self.alloc_expr(fundef, Expr::Const(Const::Usize(axis_index)));
-        let idx_vec = self.alloc_expr(fundef, Expr::Array(Array { elems: vec![idx] }));
+        let span = fundef.args[arg_index].span;
+        let idx_vec = self.alloc_expr(fundef, Expr::Array(Array { elems: vec![idx], span }));
         let shp = self.alloc_expr(fundef, self.shape_of_arg_expr(fundef, arg_index));
         Expr::Prf(Prf::SelVxA(idx_vec, shp))
     }
@@ -57,13 +58,14 @@ impl<'ast> AnalyseTp {
         term: ShapeTerm,
         expr: Expr<'ast, ParsedAst>,
         ty: Type,
+        span: Span,
     ) {
         if self.defined.insert(symbol.to_owned()) {
             self.symbol_terms.insert(symbol.to_owned(), term.clone());
 
             let lhs = self.alloc_lvis(fundef, symbol.to_owned(), Some(ty));
             let expr = self.alloc_expr(fundef, expr);
-            fundef.shape_prelude.push(Assign { lhs, expr });
+            fundef.shape_prelude.push(Assign { lhs, expr, span });
             fundef.shape_facts.bindings.push(ShapeBinding {
                 symbol: symbol.to_owned(),
                 term,
@@ -77,7 +79,7 @@ impl<'ast> AnalyseTp {
     }
 
     fn analyse_arg_patterns(&mut self, fundef: &mut Fundef<'ast, ParsedAst>) {
-        let mut pending: Vec<(String, ShapeTerm, Expr<'ast, ParsedAst>, Type)> = Vec::new();
+        let mut pending: Vec<(String, ShapeTerm, Expr<'ast, ParsedAst>, Type, Span)> = Vec::new();
 
         for (arg_index, arg) in fundef.args.iter().enumerate() {
             let TypePattern::Axes(axes) = &arg.ty.shape else {
@@ -89,7 +91,7 @@ impl<'ast> AnalyseTp {
                     AxisPattern::Dim(DimPattern::Var(var)) => {
                         let term = ShapeTerm::ArgDim { arg_index, axis_index };
                         let expr = self.dim_at_expr(fundef, arg_index, axis_index);
-                        pending.push((var.clone(), term, expr, Type::scalar(BaseType::Usize)));
+                        pending.push((var.clone(), term, expr, Type::scalar(BaseType::Usize), arg.span));
                     }
                     AxisPattern::Rank(capture) => {
                         let dim_term = ShapeTerm::ArgRank {
@@ -102,6 +104,7 @@ impl<'ast> AnalyseTp {
                             dim_term,
                             dim_expr,
                             Type::scalar(BaseType::Usize),
+                            arg.span,
                         ));
 
                         let shp_term = ShapeTerm::TailShape {
@@ -117,6 +120,7 @@ impl<'ast> AnalyseTp {
                                 ty: BaseType::Usize,
                                 shape: TypePattern::any(),
                             },
+                            arg.span,
                         ));
                     }
                     AxisPattern::Dim(DimPattern::Known(_)) => {}
@@ -124,8 +128,8 @@ impl<'ast> AnalyseTp {
             }
         }
 
-        for (symbol, term, expr, ty) in pending {
-            self.bind_symbol(fundef, &symbol, term, expr, ty);
+        for (symbol, term, expr, ty, span) in pending {
+            self.bind_symbol(fundef, &symbol, term, expr, ty, span);
         }
     }
 
@@ -1,15 +1,49 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt};
 
 use crate::ast::*;
 
 /// Not all patterns that can be constructed from the grammar are actually resolvable.
 /// This pass rejects unresolved variable-rank patterns (`d:shp`) at compile time.
-pub fn check_tp(program: Program<'static, ParsedAst>) -> Result<Program<'static, ParsedAst>, String> {
+pub fn check_tp(program: Program<'static, ParsedAst>) -> Result<Program<'static, ParsedAst>, Vec<TypePatternError>> {
 	CheckTypePatterns::new().run(program)
 }
 
+#[derive(Debug)]
+pub enum TypePatternError {
+	/// More than one rank capture in the arguments is not constrained by an earlier symbol.
+	UnconstrainedArgRanks { fundef: String, count: usize, span: Span },
+	/// A rank capture in the return type is not bound by any argument.
+	UnconstrainedRetRank { fundef: String, dim_name: String, span: Span },
+}
+
+impl TypePatternError {
+	pub fn span(&self) -> Span {
+		use TypePatternError::*;
+		match self {
+			UnconstrainedArgRanks { span, .. }
+			| UnconstrainedRetRank { span, .. } => *span,
+		}
+	}
+}
+
+impl fmt::Display for TypePatternError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		use TypePatternError::*;
+		match self {
+			UnconstrainedArgRanks { fundef, count, .. } => write!(f,
+				"function `{}` has {} unconstrained rank captures in argument type patterns; at most one is allowed",
+				fundef, count
+			),
+			UnconstrainedRetRank { fundef, dim_name, .. } => write!(f,
+				"function `{}` return type contains unconstrained rank capture `{}`; return rank captures must be constrained by argument symbols",
+				fundef, dim_name
+			),
+		}
+	}
+}
+
 struct CheckTypePatterns {
-	errors: Vec<String>,
+	errors: Vec<TypePatternError>,
 }
 
 impl CheckTypePatterns {
@@ -17,7 +51,7 @@ impl CheckTypePatterns {
 		Self { errors: Vec::new() }
 	}
 
-	fn run(mut self, program: Program<'static, ParsedAst>) -> Result<Program<'static, ParsedAst>, String> {
+	fn run(mut self, program: Program<'static, ParsedAst>) -> Result<Program<'static, ParsedAst>, Vec<TypePatternError>> {
         for (_, groups) in &program.overloads {
             for (_, fundefs) in groups {
                 for fundef in fundefs {
@@ -29,7 +63,7 @@ impl CheckTypePatterns {
 		if self.errors.is_empty() {
 			Ok(program)
 		} else {
-			Err(self.errors.join("\n"))
+			Err(self.errors)
 		}
 	}
 
@@ -48,13 +82,14 @@ impl CheckTypePatterns {
 		}
 
 		if unconstrained_rank_captures > 1 {
-			self.errors.push(format!(
-				"function `{}` has {} unconstrained rank captures in argument type patterns; at most one is allowed",
-				fundef.name, unconstrained_rank_captures
-			));
+			self.errors.push(TypePatternError::UnconstrainedArgRanks {
+				fundef: fundef.name.clone(),
+				count: unconstrained_rank_captures,
+				span: fundef.span,
+			});
 		}
 
-		self.check_return_pattern(&fundef.name, &fundef.ret_type.shape, &defined_symbols);
+		self.check_return_pattern(fundef, &defined_symbols);
 	}
 
 	fn collect_arg_symbols(
@@ -85,18 +120,19 @@ impl CheckTypePatterns {
 		}
 	}
 
-	fn check_return_pattern(&mut self, fundef_name: &str, ret_shape: &TypePattern, defined_symbols: &HashSet<String>) {
-		let TypePattern::Axes(axes) = ret_shape else {
+	fn check_return_pattern(&mut self, fundef: &Fundef<'static, ParsedAst>, defined_symbols: &HashSet<String>) {
+		let TypePattern::Axes(axes) = &fundef.ret_type.shape else {
 			return;
 		};
 
 		for axis in axes {
 			if let AxisPattern::Rank(capture) = axis
 				&& !defined_symbols.contains(&capture.dim_name) {
-				self.errors.push(format!(
-					"function `{}` return type contains unconstrained rank capture `{}`; return rank captures must be constrained by argument symbols",
-					fundef_name, capture.dim_name
-				));
+				self.errors.push(TypePatternError::UnconstrainedRetRank {
+					fundef: fundef.name.clone(),
+					dim_name: capture.dim_name.clone(),
+					span: fundef.span,
+				});
 			}
 		}
 	}
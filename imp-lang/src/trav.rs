@@ -54,6 +54,7 @@ pub trait Traverse<'ast> {
         match stmt {
             Assign(n) => self.trav_assign(n),
             Printf(n) => self.trav_printf(n),
+            Free(n) => self.trav_free(n),
         }
     }
 
@@ -65,6 +66,10 @@ pub trait Traverse<'ast> {
         self.trav_id(&mut printf.id);
     }
 
+    fn trav_free(&mut self, free: &mut Free<'ast, Self::Ast>) {
+        self.trav_id(&mut free.id);
+    }
+
     // Expressions
 
     fn trav_expr(&mut self, expr: &'ast Expr<'ast, Self::Ast>) -> Self::ExprOut {
@@ -87,6 +92,8 @@ pub trait Traverse<'ast> {
             Tensor(n) => self.trav_tensor_expr(n),
             Fold(n) => self.trav_fold_expr(n),
             Array(n) => self.trav_array_expr(n),
+            Record(n) => self.trav_record_expr(n),
+            Field(n) => self.trav_field_expr(n),
             Id(n) => self.trav_id_expr(n),
             Const(n) => self.trav_const_expr(n),
         }
@@ -134,11 +141,27 @@ pub trait Traverse<'ast> {
     }
 
     fn trav_tensor(&mut self, tensor: &mut Tensor<'ast, Self::Ast>) -> Self::ExprOut {
-        if let Some(lb) = &mut tensor.lb {
+        if let Some(target) = &mut tensor.target {
+            Self::Ast::trav_operand(self, target);
+        }
+        for partition in &mut tensor.partitions {
+            self.trav_partition(partition);
+        }
+        Self::EXPR_DEFAULT
+    }
+
+    fn trav_partition(&mut self, partition: &mut Partition<'ast, Self::Ast>) -> Self::ExprOut {
+        if let Some(lb) = &mut partition.lb {
             Self::Ast::trav_operand(self, lb);
         }
-        Self::Ast::trav_operand(self, &mut tensor.ub);
-        self.trav_body(&mut tensor.body);
+        Self::Ast::trav_operand(self, &mut partition.ub);
+        if let Some(step) = &mut partition.step {
+            Self::Ast::trav_operand(self, step);
+        }
+        if let Some(width) = &mut partition.width {
+            Self::Ast::trav_operand(self, width);
+        }
+        self.trav_body(&mut partition.body);
         Self::EXPR_DEFAULT
     }
 
@@ -161,7 +184,7 @@ pub trait Traverse<'ast> {
             }
         }
 
-        self.trav_tensor(&mut fold.selection);
+        self.trav_partition(&mut fold.selection);
         Self::EXPR_DEFAULT
     }
 
@@ -177,6 +200,28 @@ pub trait Traverse<'ast> {
         Self::EXPR_DEFAULT
     }
 
+    fn trav_record_expr(&mut self, mut record: Record<'ast, Self::Ast>) -> (Expr<'ast, Self::Ast>, Self::ExprOut) {
+        let out = self.trav_record(&mut record);
+        (Expr::Record(record), out)
+    }
+
+    fn trav_record(&mut self, record: &mut Record<'ast, Self::Ast>) -> Self::ExprOut {
+        for (_, value) in &mut record.fields {
+            Self::Ast::trav_operand(self, value);
+        }
+        Self::EXPR_DEFAULT
+    }
+
+    fn trav_field_expr(&mut self, mut field: Field<'ast, Self::Ast>) -> (Expr<'ast, Self::Ast>, Self::ExprOut) {
+        let out = self.trav_field(&mut field);
+        (Expr::Field(field), out)
+    }
+
+    fn trav_field(&mut self, field: &mut Field<'ast, Self::Ast>) -> Self::ExprOut {
+        Self::Ast::trav_operand(self, &mut field.record);
+        Self::EXPR_DEFAULT
+    }
+
     fn trav_id_expr(&mut self, mut id: Id<'ast, Self::Ast>) -> (Expr<'ast, Self::Ast>, Self::ExprOut) {
         let out = self.trav_id(&mut id);
         (Expr::Id(id), out)
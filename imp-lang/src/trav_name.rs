@@ -17,8 +17,12 @@ impl TravName {
             SSA => "ssa",
             TI => "ti",
             DR => "dr",
+            SP => "sp",
             CF => "cf",
+            WLF => "wlf",
+            WLS => "wls",
             DCR => "dcr",
+            IFR => "ifr",
             RNF => "rnf",
             CGC => "cgc",
             CGH => "cgh",
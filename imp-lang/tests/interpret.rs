@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use imp_lang::{parse_value, run, show_value, RunOptions};
+use parameterized::parameterized;
+
+/// Call `function` of `interpret.imp` with the interpreter.
+fn call(function: &str, args: &[&str]) -> String {
+    let options = RunOptions {
+        include: Vec::new(),
+        infile: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/interpret.imp"),
+        function: function.to_owned(),
+        args: args.iter().map(|arg| parse_value(arg).unwrap()).collect(),
+    };
+    show_value(&run(options).unwrap())
+}
+
+#[parameterized(
+    args = { &["7i32", "2i32"], &["-7i32", "2i32"] },
+    expected = { "11i32", "-11i32" },
+)]
+fn test_prf(args: &[&str], expected: &str) {
+    assert_eq!(call("prf", args), expected);
+}
+
+#[parameterized(
+    function = { "iota", "lower_bound", "strided", "reverse" },
+    args = { &["4"], &["5"], &["5"], &["[1i32, 2i32, 3i32]"] },
+    expected = { "[0, 1, 2, 3]", "[7, 7, 2, 3, 4]", "[0, 1, 0, 1, 0]", "[3i32, 2i32, 1i32]" },
+)]
+fn test_tensor(function: &str, args: &[&str], expected: &str) {
+    assert_eq!(call(function, args), expected);
+}
+
+#[parameterized(
+    function = { "sum", "sum", "dot" },
+    args = { &["[[1i32, 2i32], [3i32, 4i32]]"], &["[]"], &["2i32", "[1i32, 2i32, 3i32]"] },
+    expected = { "10i32", "0i32", "12i32" },
+)]
+fn test_fold(function: &str, args: &[&str], expected: &str) {
+    assert_eq!(call(function, args), expected);
+}
+
+#[parameterized(
+    function = { "max", "max", "clamp" },
+    args = { &["-3i32", "2i32"], &["3i32", "2i32"], &["2", "[0, 1, 2, 3]"] },
+    expected = { "2i32", "3i32", "[0, 1, 2, 2]" },
+)]
+fn test_cond(function: &str, args: &[&str], expected: &str) {
+    assert_eq!(call(function, args), expected);
+}
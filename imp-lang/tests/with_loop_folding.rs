@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use imp_lang::{compile, Options};
+use parameterized::parameterized;
+
+/// The generated C definition of `function` of `with_loop_folding.imp`.
+fn definition(function: &str) -> String {
+    let options = Options {
+        infile: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/with_loop_folding.imp"),
+        ..Default::default()
+    };
+    let c = compile(options).unwrap().c;
+    let start = c.lines()
+        .position(|line| line.starts_with(&format!("ImpArrayRaw IMP_{function}__")) && line.ends_with('{'))
+        .unwrap();
+    c.lines().skip(start).take_while(|line| *line != "}").collect::<Vec<_>>().join("\n")
+}
+
+#[parameterized(function = { "through_sel", "through_prf" })]
+fn test_fused(function: &str) {
+    // The producer is folded into the loop over `jv`, leaving no loop over its own `iv`
+    let definition = definition(function);
+    assert!(definition.contains("for (size_t jv_"));
+    assert!(!definition.contains("for (size_t iv_"));
+}
+
+#[test]
+fn test_split() {
+    // The part of the consumer below the lower bound of the producer gets a loop of its own
+    let definition = definition("below_lb");
+    assert!(definition.contains("for (size_t jv_wlf"));
+    assert!(!definition.contains("for (size_t iv_"));
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use imp_lang::{compile, Options};
+
+/// The generated C definition of `function` of `with_loop_scalarisation.imp`.
+fn definition(function: &str) -> String {
+    let options = Options {
+        infile: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/with_loop_scalarisation.imp"),
+        ..Default::default()
+    };
+    let c = compile(options).unwrap().c;
+    let start = c.lines()
+        .position(|line| line.starts_with(&format!("ImpArrayRaw IMP_{function}__")) && line.ends_with('{'))
+        .unwrap();
+    c.lines().skip(start).take_while(|line| *line != "}").collect::<Vec<_>>().join("\n")
+}
+
+/// The part of `definition` from the loop over `iv` onwards.
+fn iv_loop(definition: &str) -> &str {
+    &definition[definition.find("for (size_t iv_").unwrap()..]
+}
+
+#[test]
+fn test_merged() {
+    // A single loop over both index vectors, which are views of its index vector rather than allocations
+    let definition = definition("selecting");
+    assert!(iv_loop(&definition).starts_with("for (size_t iv_wls"));
+    assert!(!definition.contains("for (size_t jv_"));
+    assert!(!iv_loop(&definition).contains("malloc"));
+}
+
+#[test]
+fn test_not_merged() {
+    let definition = definition("computing");
+    assert!(definition.contains("for (size_t jv_"));
+    assert!(!definition.contains("_wls"));
+}
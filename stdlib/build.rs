@@ -10,7 +10,10 @@ fn main() {
     let cpath = options.c_path().unwrap();
     let opath = cpath.file_stem().unwrap().to_str().unwrap();
 
-    imp_lang::compile(options);
+    if let Err(e) = imp_lang::compile(options) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
 
     cc::Build::new()
         .file(&cpath)
@@ -13,9 +13,8 @@ mod tests {
         val = { -37, 0, 42 },
     )]
     fn test_genarray_i32(shp: Vec<usize>, val: i32) {
-        let imp_shp = ImpArrayOrScalar::Array(ImpArray { shp: vec![shp.len()], data: shp.clone() });
-        let imp_val = ImpArrayOrScalar::Scalar(val);
-        let arr = expect_array(genarray_usize_i32(imp_shp, imp_val));
+        let imp_shp = ImpArray { shp: vec![shp.len()], data: shp.clone() };
+        let arr = expect_array(genarray((&imp_shp, val)).unwrap());
         assert_eq!(arr.shp, shp);
         assert_eq!(arr.data, vec![val; arr.data.len()]);
     }
@@ -25,9 +24,8 @@ mod tests {
         val = { 37, 0, 42 },
     )]
     fn test_genarray_usize(shp: Vec<usize>, val: usize) {
-        let imp_shp = ImpArrayOrScalar::Array(ImpArray { shp: vec![shp.len()], data: shp.clone() });
-        let imp_val = ImpArrayOrScalar::Scalar(val);
-        let arr = expect_array(genarray_usize_usize(imp_shp, imp_val));
+        let imp_shp = ImpArray { shp: vec![shp.len()], data: shp.clone() };
+        let arr = expect_array(genarray((&imp_shp, val)).unwrap());
         assert_eq!(arr.shp, shp);
         assert_eq!(arr.data, vec![val; arr.data.len()]);
 
@@ -35,7 +33,7 @@ mod tests {
 
     #[parameterized(n = { 0, 1, 10 })]
     fn test_iota(n: usize) {
-        let arr = expect_array(iota(n));
+        let arr = iota(n).unwrap();
         assert_eq!(arr.shp, vec![n]);
         assert_eq!(arr.data, (0..n).collect::<Vec<_>>());
     }